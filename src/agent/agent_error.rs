@@ -0,0 +1,87 @@
+use std::fmt;
+use std::error;
+
+pub use self::AgentError::*;
+
+#[doc = "
+Use this for catching various errors that
+can happen when running or talking to an Agent.
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum AgentError {
+    /// Couldn't create the socket, e.g. the directory
+    /// doesn't exist
+    SocketErr,
+    /// Another agent listens on the socket already
+    RunningErr,
+    /// Couldn't lock the memory of the agent, probably
+    /// RLIMIT_MEMLOCK is too low
+    MlockErr,
+    /// Couldn't connect to the agent, probably it
+    /// doesn't run
+    ConnectErr,
+    /// Something went wrong while sending or receiving
+    IoErr,
+    /// Malformed request or response
+    ProtocolErr,
+    /// The user of the client isn't allowed to use
+    /// the agent
+    DeniedErr,
+    /// The agent is locked, unlock it first
+    LockedErr,
+    /// Wrong password and/or keyfile while unlocking
+    UnlockErr,
+    /// There is no entry with this path
+    NotFoundErr,
+}
+
+impl AgentError {
+    // Name of the error in the protocol
+    pub(crate) fn code(&self) -> &'static str {
+        match *self {
+            SocketErr => "socket",
+            RunningErr => "running",
+            MlockErr => "mlock",
+            ConnectErr => "connect",
+            IoErr => "io",
+            ProtocolErr => "protocol",
+            DeniedErr => "denied",
+            LockedErr => "locked",
+            UnlockErr => "unlock",
+            NotFoundErr => "not-found",
+        }
+    }
+
+    pub(crate) fn from_code(code: &str) -> AgentError {
+        match code {
+            "denied" => DeniedErr,
+            "locked" => LockedErr,
+            "unlock" => UnlockErr,
+            "not-found" => NotFoundErr,
+            _ => ProtocolErr,
+        }
+    }
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for AgentError {
+    fn description(&self) -> &str {
+        match *self {
+            SocketErr => "Couldn't create the socket of the agent",
+            RunningErr => "Another agent is running already",
+            MlockErr => "Couldn't lock the memory of the agent",
+            ConnectErr => "Couldn't connect to the agent",
+            IoErr => "Couldn't talk to the agent",
+            ProtocolErr => "Malformed message from or to the agent",
+            DeniedErr => "Access to the agent denied",
+            LockedErr => "The agent is locked",
+            UnlockErr => "Couldn't unlock the agent, wrong password or keyfile",
+            NotFoundErr => "No such entry",
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+use agent::agent_error::AgentError;
+use agent::protocol::{decode, default_socket_path, encode, Field, ERR, OK};
+use common::common::write_array_volatile;
+use super::super::sec_str::SecureString;
+
+#[doc = "
+A connection to an Agent. Entries are given by their path, i.e. the
+titles of their groups and their own title separated by slashes.
+"]
+pub struct AgentClient {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl AgentClient {
+    /// Connect to the agent listening on the socket at path
+    pub fn connect(path: &str) -> Result<AgentClient, AgentError> {
+        let stream = try!(UnixStream::connect(path).map_err(|_| AgentError::ConnectErr));
+        let reader = try!(stream.try_clone().map_err(|_| AgentError::ConnectErr));
+        Ok(AgentClient {
+            stream: stream,
+            reader: BufReader::new(reader),
+        })
+    }
+
+    /// Connect to the agent listening on default_socket_path()
+    pub fn connect_default() -> Result<AgentClient, AgentError> {
+        AgentClient::connect(&default_socket_path())
+    }
+
+    /// Check that the agent answers
+    pub fn ping(&mut self) -> Result<(), AgentError> {
+        self.request(&["PING"]).map(|_| ())
+    }
+
+    /// Paths of all entries
+    pub fn list(&mut self) -> Result<Vec<String>, AgentError> {
+        self.request(&["LIST"])
+    }
+
+    /// Paths of the entries with text in their title, URL, username
+    /// or comment, ignoring case
+    pub fn search(&mut self, text: &str) -> Result<Vec<String>, AgentError> {
+        self.request(&["SEARCH", text])
+    }
+
+    /// A field of the entry at path
+    pub fn get(&mut self, path: &str, field: Field) -> Result<SecureString, AgentError> {
+        let mut results = try!(self.request(&["GET", path, field.name()]));
+        if results.len() != 1 {
+            return Err(AgentError::ProtocolErr);
+        }
+        Ok(SecureString::new(results.remove(0)))
+    }
+
+    /// Drop the database in the agent until it's unlocked again
+    pub fn lock(&mut self) -> Result<(), AgentError> {
+        self.request(&["LOCK"]).map(|_| ())
+    }
+
+    /// Load the database in the agent again, password should be
+    /// a String for the same reasons as in V1Kpdb::new
+    pub fn unlock(&mut self, password: String) -> Result<(), AgentError> {
+        let result = self.request(&["UNLOCK", &password]).map(|_| ());
+        unsafe { write_array_volatile(password.as_ptr() as *mut u8, 0u8, password.len()) };
+        result
+    }
+
+    /// Stop the agent
+    pub fn stop(&mut self) -> Result<(), AgentError> {
+        self.request(&["STOP"]).map(|_| ())
+    }
+
+    // Sensitive data in this function:
+    //
+    // * the password for UNLOCK in the request
+    // * the results of GET in the response
+    //
+    // At the end of this function:
+    //
+    // * both lines are overwritten, the results are moved to the caller
+    fn request(&mut self, parts: &[&str]) -> Result<Vec<String>, AgentError> {
+        let request = encode(parts);
+        let written = self.stream.write_all(request.as_bytes());
+        unsafe { write_array_volatile(request.as_ptr() as *mut u8, 0u8, request.len()) };
+        try!(written.map_err(|_| AgentError::IoErr));
+
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return Err(AgentError::IoErr),
+            Ok(_) => {}
+        }
+        let mut response = decode(&line);
+        unsafe { write_array_volatile(line.as_ptr() as *mut u8, 0u8, line.len()) };
+        let status = response.remove(0);
+        match &status[..] {
+            OK => Ok(response),
+            ERR if response.len() == 1 => Err(AgentError::from_code(&response[0])),
+            _ => Err(AgentError::ProtocolErr),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod server;
+pub mod client;
+pub mod protocol;
+pub mod agent_error;
+
+#[cfg(test)]
+mod tests_agent;
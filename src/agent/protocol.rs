@@ -0,0 +1,141 @@
+use std::env;
+use std::ops::Range;
+
+use libc;
+
+// The protocol is line based: a request is a command followed by its
+// arguments, a response is OK followed by the results or ERR followed
+// by the code of an AgentError. All parts of a line are separated by
+// tabs, tabs, newlines and backslashes in them are escaped.
+
+pub(crate) const OK: &'static str = "OK";
+pub(crate) const ERR: &'static str = "ERR";
+
+#[doc = "
+A field of an entry which the agent hands out
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum Field {
+    Title,
+    Url,
+    Username,
+    Password,
+    Comment,
+}
+
+impl Field {
+    /// Name of the field in requests
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Field::Title => "title",
+            Field::Url => "url",
+            Field::Username => "username",
+            Field::Password => "password",
+            Field::Comment => "comment",
+        }
+    }
+
+    /// The field with this name, None if there isn't any
+    pub fn from_name(name: &str) -> Option<Field> {
+        match name {
+            "title" => Some(Field::Title),
+            "url" => Some(Field::Url),
+            "username" => Some(Field::Username),
+            "password" => Some(Field::Password),
+            "comment" => Some(Field::Comment),
+            _ => None,
+        }
+    }
+}
+
+/// Socket the agent listens on if no other one is given: the value
+/// of KEEPASS_AGENT_SOCK, otherwise keepass-agent.sock in
+/// XDG_RUNTIME_DIR or /tmp/keepass-agent-<uid>.sock
+pub fn default_socket_path() -> String {
+    if let Ok(path) = env::var("KEEPASS_AGENT_SOCK") {
+        return path;
+    }
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => format!("{}/keepass-agent.sock", dir),
+        Err(_) => format!("/tmp/keepass-agent-{}.sock", unsafe { libc::geteuid() }),
+    }
+}
+
+pub(crate) fn encode(parts: &[&str]) -> String {
+    let mut line = String::new();
+    for (index, part) in parts.iter().enumerate() {
+        if index > 0 {
+            line.push('\t');
+        }
+        for c in part.chars() {
+            match c {
+                '\\' => line.push_str("\\\\"),
+                '\t' => line.push_str("\\t"),
+                '\n' => line.push_str("\\n"),
+                '\r' => line.push_str("\\r"),
+                c => line.push(c),
+            }
+        }
+    }
+    line.push('\n');
+    line
+}
+
+pub(crate) fn decode(line: &str) -> Vec<String> {
+    let line = line.trim_end_matches(&['\n', '\r'][..]);
+    line.split('\t')
+        .map(|part| {
+            let mut decoded = String::with_capacity(part.len());
+            let mut chars = part.chars();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    decoded.push(c);
+                    continue;
+                }
+                match chars.next() {
+                    Some('t') => decoded.push('\t'),
+                    Some('n') => decoded.push('\n'),
+                    Some('r') => decoded.push('\r'),
+                    Some(c) => decoded.push(c),
+                    None => {}
+                }
+            }
+            decoded
+        })
+        .collect()
+}
+
+// Same as decode for a request without its newline, but the parts are
+// unescaped in place instead of being copied, as they may contain a
+// password. Returns where the parts are in line afterwards.
+pub(crate) fn decode_in_place(line: &mut [u8]) -> Vec<Range<usize>> {
+    let mut parts = vec![];
+    let mut start = 0;
+    while start <= line.len() {
+        let end = line[start..].iter().position(|&b| b == b'\t').map_or(line.len(), |i| start + i);
+        // Unescaping only shortens a part, so it's written over itself
+        let mut length = start;
+        let mut index = start;
+        while index < end {
+            let mut b = line[index];
+            index += 1;
+            if b == b'\\' {
+                if index == end {
+                    break;
+                }
+                b = match line[index] {
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b => b,
+                };
+                index += 1;
+            }
+            line[length] = b;
+            length += 1;
+        }
+        parts.push(start..length);
+        start = end + 1;
+    }
+    parts
+}
@@ -0,0 +1,357 @@
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::mem;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::str;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use agent::agent_error::AgentError;
+use agent::protocol::{decode_in_place, encode, Field, ERR, OK};
+use common::common::write_array_volatile;
+use kpdb::compositekey::{CompositeKey, KeyComponent};
+use kpdb::search::{Matcher, Search, SearchField};
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+// How long a client may take to send the next request
+const CLIENT_TIMEOUT: u64 = 10;
+
+// Longest request a client may send, including its newline. Requests
+// are read into a buffer of this size which never grows.
+const REQUEST_CAPACITY: usize = 8192;
+
+#[doc = "
+An agent holds an unlocked V1Kpdb and answers queries about it over a
+Unix domain socket, so that its key doesn't have to be derived again
+for every query. Only clients running as one of allowed_uids (checked
+through SO_PEERCRED) may talk to it. It locks itself after timeout
+without queries, i.e. drops the database with all its SecureStrings
+until a client unlocks it with the password again.
+"]
+pub struct Agent {
+    /// Filepath of the socket
+    pub socket_path: String,
+    /// Lock after this time without queries, None means never
+    pub timeout: Option<Duration>,
+    /// Users allowed to connect, only the owner of the agent by default
+    pub allowed_uids: Vec<u32>,
+    /// Lock all memory of the process with mlockall and disable
+    /// core dumps when the agent starts (true by default)
+    pub protect_memory: bool,
+    path: String,
+    keyfile: Option<String>,
+    // None while the agent is locked
+    db: Option<V1Kpdb>,
+    last_query: Instant,
+}
+
+impl Agent {
+    /// Create an agent for a loaded database. keyfile is used together
+    /// with the password given to unlock the agent.
+    pub fn new(db: V1Kpdb, keyfile: Option<String>, socket_path: String) -> Agent {
+        Agent {
+            socket_path: socket_path,
+            timeout: None,
+            allowed_uids: vec![unsafe { libc::geteuid() }],
+            protect_memory: true,
+            path: db.path.clone(),
+            keyfile: keyfile,
+            db: Some(db),
+            last_query: Instant::now(),
+        }
+    }
+
+    /// True if the database is dropped until the agent is unlocked
+    pub fn is_locked(&self) -> bool {
+        self.db.is_none()
+    }
+
+    /// Listen on socket_path and answer queries until a client stops
+    /// the agent. Returns RunningErr if another agent listens there.
+    pub fn run(&mut self) -> Result<(), AgentError> {
+        if self.protect_memory {
+            try!(protect_memory());
+        }
+        let listener = try!(bind(&self.socket_path));
+        let result = self.serve(&listener);
+        let _ = fs::remove_file(&self.socket_path);
+        result
+    }
+
+    fn serve(&mut self, listener: &UnixListener) -> Result<(), AgentError> {
+        self.last_query = Instant::now();
+        loop {
+            let wait = self.idle_time_left();
+            if !wait_for(listener.as_raw_fd(), wait) {
+                continue;
+            }
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(_) => continue,
+            };
+            if self.handle(stream) {
+                return Ok(());
+            }
+        }
+    }
+
+    // Answer the requests of a client, true if it stopped the agent
+    //
+    // Sensitive data in this function:
+    //
+    // * the requests, which contain the password for UNLOCK
+    //
+    // At the end of this function:
+    //
+    // * the requests are overwritten with zeroes, they are only read
+    //   and decoded in the locked line
+    fn handle(&mut self, mut stream: UnixStream) -> bool {
+        if !self.allowed(&stream) {
+            let _ = stream.write_all(encode(&[ERR, AgentError::DeniedErr.code()]).as_bytes());
+            return false;
+        }
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(CLIENT_TIMEOUT)));
+        let mut line = vec![0u8; REQUEST_CAPACITY];
+        unsafe {
+            libc::mlock(line.as_ptr() as *const libc::c_void, line.len());
+        }
+        let stop = self.serve_client(&mut stream, &mut line);
+        unsafe {
+            write_array_volatile(line.as_mut_ptr(), 0u8, line.len());
+            libc::munlock(line.as_ptr() as *const libc::c_void, line.len());
+        }
+        stop
+    }
+
+    fn serve_client(&mut self, stream: &mut UnixStream, line: &mut [u8]) -> bool {
+        loop {
+            let length = match read_request(stream, line) {
+                Some(length) => length,
+                None => return false,
+            };
+            // A client which stays connected doesn't keep the agent unlocked
+            self.idle_time_left();
+            let parts = decode_in_place(&mut line[..length]);
+            let request: Option<Vec<&str>> = parts.into_iter()
+                                                  .map(|part| str::from_utf8(&line[part]).ok())
+                                                  .collect();
+            let stop = request.as_ref().is_some_and(|request| request[0] == "STOP");
+            let result = match request {
+                Some(request) => self.answer(&request),
+                None => Err(AgentError::ProtocolErr),
+            };
+            unsafe { write_array_volatile(line.as_mut_ptr(), 0u8, length) };
+            let response = match result {
+                Ok(results) => {
+                    let mut parts = vec![OK];
+                    parts.extend(results.iter().map(|result| &result[..]));
+                    let response = encode(&parts);
+                    for result in results {
+                        wipe(result);
+                    }
+                    response
+                }
+                Err(e) => encode(&[ERR, e.code()]),
+            };
+            let written = stream.write_all(response.as_bytes());
+            wipe(response);
+            if stop {
+                return true;
+            }
+            if written.is_err() {
+                return false;
+            }
+        }
+    }
+
+    // Lock the agent if the timeout passed without queries. Returns the
+    // time until it locks itself, None if it doesn't.
+    fn idle_time_left(&mut self) -> Option<Duration> {
+        match self.timeout {
+            Some(timeout) if self.db.is_some() => {
+                let elapsed = self.last_query.elapsed();
+                if elapsed >= timeout {
+                    self.lock();
+                    return None;
+                }
+                Some(timeout - elapsed)
+            }
+            _ => None,
+        }
+    }
+
+    fn allowed(&self, stream: &UnixStream) -> bool {
+        peer_uid(stream).is_some_and(|uid| self.allowed_uids.contains(&uid))
+    }
+
+    // Sensitive data in this function:
+    //
+    // * the password for UNLOCK
+    // * the results of GET
+    //
+    // At the end of this function:
+    //
+    // * the password is still in the request, which the caller wipes
+    // * the results are moved to the caller, which wipes them
+    fn answer(&mut self, request: &[&str]) -> Result<Vec<String>, AgentError> {
+        let (command, request) = (request[0], &request[1..]);
+        match (command, request.len()) {
+            ("PING", 0) => return Ok(vec![]),
+            ("STOP", 0) => return Ok(vec![]),
+            ("LOCK", 0) => {
+                self.lock();
+                return Ok(vec![]);
+            }
+            ("UNLOCK", 1) => {
+                try!(self.unlock(request[0]));
+                return Ok(vec![]);
+            }
+            _ => {}
+        }
+
+        self.last_query = Instant::now();
+        let db = try!(self.db.as_ref().ok_or(AgentError::LockedErr));
+        match (command, request.len()) {
+            ("LIST", 0) => Ok(db.entries.iter().map(|&entry| db.entry_path(entry)).collect()),
+            ("SEARCH", 1) => {
+                let mut search = Search::new(Matcher::substring(request[0]));
+                search.fields = vec![SearchField::Title, SearchField::Url, SearchField::Username, SearchField::Comment];
+                Ok(search.iter(db).map(|entry| db.entry_path(entry)).collect())
+            }
+            ("GET", 2) => {
+                let field = try!(Field::from_name(request[1]).ok_or(AgentError::ProtocolErr));
+                let entry = try!(db.entry_by_path(request[0]).map_err(|_| AgentError::NotFoundErr));
+                let entry = &db[entry];
+                let value = match field {
+                    Field::Title => entry.title.clone(),
+                    Field::Url => entry.url.clone().unwrap_or_default(),
+                    Field::Username => reveal(&entry.username),
+                    Field::Password => reveal(&entry.password),
+                    Field::Comment => entry.comment.clone().unwrap_or_default(),
+                };
+                Ok(vec![value])
+            }
+            _ => Err(AgentError::ProtocolErr),
+        }
+    }
+
+    fn lock(&mut self) {
+        // Dropping the database deletes the key and all SecureStrings
+        self.db = None;
+    }
+
+    // The password goes straight into a SecureString, the only copy of it
+    // outside of the request
+    fn unlock(&mut self, password: &str) -> Result<(), AgentError> {
+        let mut key = CompositeKey::new();
+        if !password.is_empty() {
+            try!(key.add(KeyComponent::Password(SecureString::new(password.to_string())))
+                    .map_err(|_| AgentError::UnlockErr));
+        }
+        if let Some(ref keyfile) = self.keyfile {
+            try!(key.add(KeyComponent::KeyfilePath(SecureString::new(keyfile.clone())))
+                    .map_err(|_| AgentError::UnlockErr));
+        }
+        let mut db = try!(V1Kpdb::with_key(self.path.clone(), key).map_err(|_| AgentError::UnlockErr));
+        try!(db.load().map_err(|_| AgentError::UnlockErr));
+        self.db = Some(db);
+        self.last_query = Instant::now();
+        Ok(())
+    }
+}
+
+// Listen on a new socket at path, replacing one left behind
+pub(crate) fn bind(path: &str) -> Result<UnixListener, AgentError> {
+    if fs::metadata(path).is_ok() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(AgentError::RunningErr);
+        }
+        // Left behind by an agent which was killed
+        let _ = fs::remove_file(path);
+    }
+    let listener = try!(UnixListener::bind(path).map_err(|_| AgentError::SocketErr));
+    try!(fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(|_| AgentError::SocketErr));
+    Ok(listener)
+}
+
+// The user running the client on the other end, from SO_PEERCRED
+pub(crate) fn peer_uid(stream: &UnixStream) -> Option<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(stream.as_raw_fd(),
+                         libc::SOL_SOCKET,
+                         libc::SO_PEERCRED,
+                         &mut cred as *mut libc::ucred as *mut libc::c_void,
+                         &mut len)
+    };
+    if result == 0 { Some(cred.uid) } else { None }
+}
+
+// Keep the unlocked database out of swap and core dumps. Not being
+// dumpable also keeps other processes of the user from ptracing us.
+pub(crate) fn protect_memory() -> Result<(), AgentError> {
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+        return Err(AgentError::MlockErr);
+    }
+    unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) };
+    Ok(())
+}
+
+// Wait until a client connects, false on timeout
+pub(crate) fn wait_for(fd: libc::c_int, timeout: Option<Duration>) -> bool {
+    let timeout = match timeout {
+        Some(timeout) => (timeout.as_secs() * 1000) as i32 + timeout.subsec_millis() as i32 + 1,
+        None => -1,
+    };
+    let mut pollfd = libc::pollfd {
+        fd: fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // Interrupted by a signal counts as timeout, the caller checks again
+    unsafe { libc::poll(&mut pollfd, 1, timeout) > 0 }
+}
+
+// Read the next request into line without buffering it anywhere else.
+// Returns its length without the newline, None if the client closed
+// the connection, didn't send anything in time or sent a request
+// longer than line.
+fn read_request(stream: &mut UnixStream, line: &mut [u8]) -> Option<usize> {
+    let mut length = 0;
+    while length < line.len() {
+        match stream.read(&mut line[length..length + 1]) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        }
+        if line[length] == b'\n' {
+            while length > 0 && line[length - 1] == b'\r' {
+                length -= 1;
+            }
+            return Some(length);
+        }
+        length += 1;
+    }
+    None
+}
+
+fn wipe(string: String) {
+    unsafe { write_array_volatile(string.as_ptr() as *mut u8, 0u8, string.len()) };
+}
+
+fn reveal(string: &Option<SecureString>) -> String {
+    match *string {
+        Some(ref string) => string.with_unlocked(|string| string.to_string()).unwrap_or_default(),
+        None => "".to_string(),
+    }
+}
@@ -0,0 +1,104 @@
+use std::thread;
+use std::time::Duration;
+
+use agent::agent_error::AgentError;
+use agent::client::AgentClient;
+use agent::protocol::{decode, decode_in_place, encode, Field};
+use agent::server::Agent;
+use kpdb::v1kpdb::V1Kpdb;
+
+fn connect(socket: &str) -> AgentClient {
+    for _ in 0..50 {
+        if let Ok(client) = AgentClient::connect(socket) {
+            return client;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("agent didn't start");
+}
+
+fn get(client: &mut AgentClient, path: &str, field: Field) -> Result<String, AgentError> {
+    client.get(path, field).map(|mut value| {
+        value.unlock();
+        value.string.clone()
+    })
+}
+
+#[test]
+fn test_agent() {
+    let socket = "test/new_agent.sock";
+    let agent = thread::spawn(move || {
+        // V1Kpdb isn't Send, so load it in the thread of the agent
+        let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(), Some("test".to_string()), None)
+                         .ok()
+                         .unwrap();
+        assert!(db.load().is_ok());
+        let mut agent = Agent::new(db, None, socket.to_string());
+        agent.protect_memory = false;
+        agent.timeout = Some(Duration::from_secs(1));
+        agent.run()
+    });
+
+    let mut client = connect(socket);
+    assert!(client.ping().is_ok());
+    let entries = client.list().ok().unwrap();
+    assert_eq!(entries.len(), 5);
+    assert!(entries.contains(&"Internet/test1".to_string()));
+    assert_eq!(client.search("TEST3"), Ok(vec!["Internet/12/test3".to_string()]));
+    assert_eq!(get(&mut client, "Internet/test1", Field::Title), Ok("test1".to_string()));
+    assert_eq!(get(&mut client, "Internet/nothing", Field::Title), Err(AgentError::NotFoundErr));
+
+    assert!(client.lock().is_ok());
+    assert_eq!(client.list(), Err(AgentError::LockedErr));
+    assert_eq!(client.unlock("wrong".to_string()), Err(AgentError::UnlockErr));
+    assert!(client.unlock("test".to_string()).is_ok());
+    assert!(client.list().is_ok());
+
+    // The agent locks itself after the timeout
+    drop(client);
+    thread::sleep(Duration::from_millis(1500));
+    let mut client = connect(socket);
+    assert_eq!(client.list(), Err(AgentError::LockedErr));
+
+    assert!(client.stop().is_ok());
+    assert_eq!(agent.join().ok().unwrap(), Ok(()));
+    assert!(AgentClient::connect(socket).is_err());
+}
+
+#[test]
+fn test_agent_timeout_connected() {
+    let socket = "test/new_agent_connected.sock";
+    let agent = thread::spawn(move || {
+        let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(), Some("test".to_string()), None)
+                         .ok()
+                         .unwrap();
+        assert!(db.load().is_ok());
+        let mut agent = Agent::new(db, None, socket.to_string());
+        agent.protect_memory = false;
+        agent.timeout = Some(Duration::from_secs(1));
+        agent.run()
+    });
+
+    // Pings on the same connection don't count as queries
+    let mut client = connect(socket);
+    assert!(client.list().is_ok());
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(500));
+        assert!(client.ping().is_ok());
+    }
+    assert_eq!(client.list(), Err(AgentError::LockedErr));
+
+    assert!(client.stop().is_ok());
+    assert_eq!(agent.join().ok().unwrap(), Ok(()));
+}
+
+#[test]
+fn test_decode_in_place() {
+    let parts = ["GET", "Internet/a\tb\\c\nd", "", "pässword"];
+    let line = encode(&parts);
+    let mut request = line.trim_end_matches('\n').as_bytes().to_vec();
+    let decoded = decode_in_place(&mut request);
+    let decoded: Vec<&str> = decoded.into_iter().map(|part| ::std::str::from_utf8(&request[part]).unwrap()).collect();
+    assert_eq!(decoded, parts);
+    assert_eq!(decode(&line), parts);
+}
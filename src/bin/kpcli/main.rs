@@ -0,0 +1,714 @@
+extern crate chrono;
+extern crate keepass;
+extern crate libc;
+
+use std::env;
+use std::io::{self, BufRead, Read, Write};
+use std::process;
+use std::time::Duration;
+
+use chrono::Local;
+
+use keepass::kdbx::convert::{detect_format, v1_to_v2, v2_to_v1, ConversionReport, DatabaseFormat};
+use keepass::kdbx::v2error::V2KdbxError;
+use keepass::kdbx::v2kdbx::V2Kdbx;
+use keepass::agent::agent_error::AgentError;
+use keepass::agent::client::AgentClient;
+use keepass::agent::protocol::{default_socket_path, Field};
+use keepass::agent::server::Agent;
+use keepass::ssh_agent::server::{default_socket_path as default_ssh_socket_path, SshAgent};
+use keepass::common::common::write_array_volatile;
+use keepass::common::lock::LockMode;
+use keepass::fuse::vaultfs::VaultFs;
+use keepass::kpdb::diff::{diff, render};
+use keepass::kpdb::path::{join_path, split_path};
+use keepass::kpdb::query::Query;
+use keepass::kpdb::search::{Matcher, Search, SearchField};
+use keepass::kpdb::v1entry::{EntryId, V1Entry};
+use keepass::kpdb::v1error::V1KpdbError;
+use keepass::kpdb::v1group::GroupId;
+use keepass::kpdb::v1kpdb::V1Kpdb;
+use keepass::sec_str::SecureString;
+#[cfg(feature = "secret-service")]
+use keepass::secret_service::service::SecretService;
+
+mod readline;
+mod shell;
+
+const USAGE: &'static str = "Usage:
+    kpcli shell [--keyfile <keyfile>] [--timeout <seconds>] <database>
+    kpcli ls [--keyfile <keyfile>] <database>
+    kpcli show [--keyfile <keyfile>] [--password] <database> <entry>
+    kpcli search [--keyfile <keyfile>] <database> <text>
+    kpcli find [--keyfile <keyfile>] [--groups] <database> <query>
+    kpcli add-entry [--keyfile <keyfile>] [<fields>] <database> <group> <title>
+    kpcli edit-entry [--keyfile <keyfile>] [<fields>] [--new-password] <database> <entry>
+    kpcli rm-entry [--keyfile <keyfile>] <database> <entry>
+    kpcli add-group [--keyfile <keyfile>] [--parents] <database> <group>
+    kpcli rm-group [--keyfile <keyfile>] <database> <group>
+    kpcli passwd [--keyfile <keyfile>] [--keep-password]
+                 [--new-keyfile <keyfile> | --no-keyfile] <database>
+    kpcli convert [--keyfile <keyfile>] [--strict] <input> <output>
+    kpcli diff [--keyfile <keyfile>] <old> <new>
+    kpcli textconv [--keyfile <keyfile>] <database>
+    kpcli agent [--keyfile <keyfile>] [--timeout <seconds>] [--socket <socket>] <database>
+    kpcli get [--socket <socket>] <entry> [<field>]
+    kpcli agent-stop [--socket <socket>]
+    kpcli ssh-agent [--keyfile <keyfile>] [--timeout <seconds>] [--socket <socket>]
+                    [--confirm] <database>
+    kpcli secret-service [--keyfile <keyfile>] <database>
+    kpcli mount [--keyfile <keyfile>] [--writable] <database> <mountpoint>
+
+Groups are given by their path, e.g. \"Internet/Mail\", entries by the
+path of their group followed by their title, e.g. \"Internet/Mail/Work\".
+A / in a title is written as \\/ and a backslash as \\\\.
+<fields> are --title, --url, --username and --comment, each followed
+by the new value.
+
+Commands:
+    shell      Open the database once and work on it interactively, see
+               help in the shell. The database is locked after timeout
+               seconds (300 by default, 0 never) without input.
+    ls         List the group tree with the titles of the entries.
+    show       Show an entry, the password only with --password.
+    search     List the entries with text in their title, URL, username
+               or comment, ignoring case.
+    find       List the entries (or with --groups the groups) matching a
+               query like
+
+                   group:Servers/Prod AND url:*.example.com AND expires<30d
+
+               Conditions are <field>:<text> (contains, * and ? match
+               any text), <field>=<text> (is), <field>~/<regex>/ and
+               comparisons of dates like expires<30d (also <=, >, >=,
+               : or = for the same day), combined with AND, OR, NOT and
+               parentheses. A text
+               without field is looked for in the title, username, URL,
+               comment and attachment name. The fields are title,
+               username, url, comment, attachment, password and group
+               (the entry is in the group with this path or below it),
+               the date fields created, modified, accessed and expires.
+               Dates are 2024-01-31, 2024-01-31T12:00, now, never or
+               relative to now like 30d, -12h or 2w. Quote values with
+               spaces as \"a b\".
+    add-entry  Add an entry to a group, its password is asked for.
+    edit-entry Change fields of an entry, --new-password asks for a new
+               password.
+    rm-entry   Remove an entry.
+    add-group  Add a group, its parent group has to exist unless
+               --parents is given, which creates missing groups.
+    rm-group   Remove a group with its subgroups and entries.
+    passwd     Change the password of the database and, with
+               --new-keyfile or --no-keyfile, its keyfile.
+    convert    Convert a KeePass 1.x database (.kdb) into a KeePass 2.x
+               database (.kdbx) or the other way round. The new database
+               uses the same password and keyfile. Fields which can't be
+               converted are listed on stderr, --strict refuses to write
+               the output in this case.
+    diff       List the differences between two KeePass 1.x databases
+               with the same password and keyfile. Passwords are only
+               reported as changed, never shown.
+    textconv   Print a KeePass 1.x database as text without passwords.
+               To let git diff databases, add
+
+                   [diff \"kdb\"]
+                       textconv = kpcli textconv
+
+               to .git/config and \"*.kdb diff=kdb\" to .gitattributes.
+    agent      Keep the database open and answer get from other processes
+               of the same user over a Unix socket, in the foreground.
+               The agent is locked after timeout seconds (900 by
+               default, 0 never) without queries, get asks for the
+               password then. The socket defaults to KEEPASS_AGENT_SOCK,
+               otherwise keepass-agent.sock in XDG_RUNTIME_DIR.
+    get        Print a field of an entry from the agent: title, url,
+               username, password (the default) or comment.
+    agent-stop Stop the agent.
+    ssh-agent  Offer the SSH keys attached to entries with the line
+               \"ssh-agent: yes\" (or \"ssh-agent: confirm\" to ask through
+               SSH_ASKPASS before every use, --confirm does this for all
+               keys) in their comment to ssh, in the foreground. Point
+               SSH_AUTH_SOCK to the socket, by default
+               keepass-ssh-agent.sock in XDG_RUNTIME_DIR. The keys are
+               dropped after timeout seconds (900 by default, 0 never)
+               without use or by ssh-add -x, ssh-add -X brings them back
+               with the password of the database.
+    secret-service
+               Offer the database read-only as the default collection
+               of the Secret Service on the session bus (D-Bus), in the
+               foreground, for programs using libsecret. Only available
+               if kpcli was built with the secret-service feature.
+    mount      Mount the database as a directory tree through FUSE, in
+               the foreground until it is unmounted again. Groups and
+               entries are directories, an entry contains the files
+               username, password, url, comment and its attachment.
+               With --writable the files can be changed and the
+               database is saved whenever a changed file is closed.
+
+The password is read from the terminal, or from the environment
+variable KPCLI_PASSWORD if it is set (e.g. when run by git). Other
+processes of the same user may be able to read the environment, so
+kpcli warns when KPCLI_PASSWORD is used.";
+
+fn usage() -> ! {
+    let _ = writeln!(io::stderr(), "{}", USAGE);
+    process::exit(2);
+}
+
+fn fail(message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "kpcli: {}", message);
+    process::exit(1);
+}
+
+// Passwords are read into a buffer of this size, so it never has to
+// grow and leave copies of the password behind
+const PASSWORD_CAPACITY: usize = 1024;
+
+// Read a line from the terminal without echoing it. The line is read
+// into a locked buffer which is moved into the String, the String is
+// meant to be moved on into a SecureString. Errors are returned instead
+// of failing, a database may be locked already.
+fn read_password(prompt: &str) -> Result<String, String> {
+    let _ = write!(io::stderr(), "{}", prompt);
+    let _ = io::stderr().flush();
+
+    let fd = libc::STDIN_FILENO;
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    let is_tty = unsafe { libc::tcgetattr(fd, &mut term) } == 0;
+    if is_tty {
+        let mut no_echo = term;
+        no_echo.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &no_echo) };
+    }
+    let mut password: Vec<u8> = Vec::with_capacity(PASSWORD_CAPACITY);
+    unsafe { libc::mlock(password.as_ptr() as *const libc::c_void, PASSWORD_CAPACITY) };
+    // take keeps read_until within the capacity
+    let stdin = io::stdin();
+    let result = stdin.lock().take(PASSWORD_CAPACITY as u64).read_until(b'\n', &mut password);
+    if is_tty {
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &term) };
+        let _ = writeln!(io::stderr());
+    }
+    let too_long = password.len() == PASSWORD_CAPACITY && !password.ends_with(b"\n");
+    while password.ends_with(b"\n") || password.ends_with(b"\r") {
+        password.pop();
+    }
+    if result.is_err() || too_long {
+        wipe(&password);
+        return Err(if too_long { "password too long" } else { "couldn't read password" }.to_string());
+    }
+    String::from_utf8(password).map_err(|err| {
+        wipe(err.as_bytes());
+        "password isn't valid UTF-8".to_string()
+    })
+}
+
+// Overwrite a password which isn't needed anymore with zeroes
+fn wipe(password: &[u8]) {
+    unsafe { write_array_volatile(password.as_ptr() as *mut u8, 0u8, password.len()) };
+}
+
+// Ask twice for a new password
+fn read_new_password(prompt: &str) -> Result<String, String> {
+    let password = try!(read_password(prompt));
+    let repeated = match read_password("Repeat password: ") {
+        Ok(repeated) => repeated,
+        Err(e) => {
+            wipe(password.as_bytes());
+            return Err(e);
+        }
+    };
+    let matches = repeated == password;
+    wipe(repeated.as_bytes());
+    if !matches {
+        wipe(password.as_bytes());
+        return Err("passwords don't match".to_string());
+    }
+    Ok(password)
+}
+
+// KPCLI_PASSWORD is for non-interactive use like git diff, where there is
+// no terminal to ask. The environment isn't a safe place for a password,
+// so kpcli warns whenever it is used.
+fn get_password() -> Result<Option<String>, String> {
+    let password = match env::var("KPCLI_PASSWORD") {
+        Ok(password) => {
+            let _ = writeln!(io::stderr(),
+                             "kpcli: warning: using the password from KPCLI_PASSWORD");
+            password
+        }
+        Err(_) => try!(read_password("Password: ")),
+    };
+    Ok(if password.is_empty() { None } else { Some(password) })
+}
+
+fn convert(input: &str,
+           output: &str,
+           password: Option<String>,
+           keyfile: Option<String>,
+           strict: bool)
+           -> Result<ConversionReport, V2KdbxError> {
+    let format = try!(detect_format(input));
+
+    match format {
+        DatabaseFormat::Kdb => {
+            let mut db = try!(V1Kpdb::new(input.to_string(), password.clone(), keyfile.clone()));
+            try!(db.load());
+            let (mut kdbx, report) = try!(v1_to_v2(&db, output.to_string(), password, keyfile));
+            if !strict || report.is_lossless() {
+                try!(kdbx.save(None, None, None));
+            }
+            Ok(report)
+        }
+        DatabaseFormat::Kdbx => {
+            let mut db = try!(V2Kdbx::new(input.to_string(), password.clone(), keyfile.clone()));
+            try!(db.load());
+            let (mut kpdb, report) = try!(v2_to_v1(&db, output.to_string(), password, keyfile));
+            if !strict || report.is_lossless() {
+                try!(kpdb.save(None, None, None));
+            }
+            Ok(report)
+        }
+    }
+}
+
+fn load_kdb(path: &str, password: Option<String>, keyfile: Option<String>) -> Result<V1Kpdb, V1KpdbError> {
+    let mut db = try!(V1Kpdb::new(path.to_string(), password, keyfile));
+    try!(db.load());
+    Ok(db)
+}
+
+fn run_convert(paths: &[String], keyfile: Option<String>, strict: bool) -> Result<(), String> {
+    let password = try!(get_password());
+    let report = try!(convert(&paths[0], &paths[1], password, keyfile, strict).map_err(|e| e.to_string()));
+    if report.is_lossless() {
+        return Ok(());
+    }
+    let _ = writeln!(io::stderr(), "Couldn't convert:");
+    for issue in report.issues.iter() {
+        let _ = writeln!(io::stderr(), "    {}", issue);
+    }
+    if strict {
+        return Err(format!("{} not written", paths[1]));
+    }
+    Ok(())
+}
+
+fn run_diff(paths: &[String], keyfile: Option<String>) -> Result<(), String> {
+    let password = try!(get_password());
+    let old = try!(load_kdb(&paths[0], password.clone(), keyfile.clone())
+                       .map_err(|e| format!("{}: {}", paths[0], e)));
+    let new = try!(load_kdb(&paths[1], password, keyfile).map_err(|e| format!("{}: {}", paths[1], e)));
+    let result = diff(&old, &new);
+    print!("{}", result);
+    // Exit like diff(1): 1 if the databases differ
+    if !result.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_textconv(path: &str, keyfile: Option<String>) -> Result<(), String> {
+    let db = try!(load_kdb(path, try!(get_password()), keyfile).map_err(|e| format!("{}: {}", path, e)));
+    print!("{}", render(&db));
+    Ok(())
+}
+
+struct Options {
+    keyfile: Option<String>,
+    strict: bool,
+    show_password: bool,
+    new_password: bool,
+    keep_password: bool,
+    new_keyfile: Option<String>,
+    title: Option<String>,
+    url: Option<String>,
+    username: Option<String>,
+    comment: Option<String>,
+    timeout: Option<u64>,
+    socket: Option<String>,
+    confirm: bool,
+    writable: bool,
+    parents: bool,
+    groups: bool,
+}
+
+// The commands which change the database lock it like KeePass does. They
+// return errors instead of calling fail, so that the lock is released.
+fn open(path: &str, options: &Options, writable: bool) -> Result<V1Kpdb, String> {
+    let password = try!(get_password());
+    let mut db = try!(V1Kpdb::new(path.to_string(), password, options.keyfile.clone())
+                          .map_err(|e| e.to_string()));
+    if writable {
+        db.lock_mode = Some(LockMode::LockFile);
+    }
+    match db.load() {
+        Ok(_) => Ok(db),
+        Err(V1KpdbError::LockErr) => {
+            let holder = db.lock_holder().map(|info| format!("{}@{}", info.user, info.host));
+            Err(format!("{} is locked by {}", path, holder.unwrap_or_default()))
+        }
+        Err(e) => Err(format!("{}: {}", path, e)),
+    }
+}
+
+fn save(db: &mut V1Kpdb, password: Option<String>, keyfile: Option<String>) -> Result<(), String> {
+    db.save(None, password, keyfile).map_err(|e| format!("{}: {}", db.path, e))
+}
+
+fn find_group(db: &V1Kpdb, path: &str) -> Result<GroupId, String> {
+    match db.group_by_path(path) {
+        Ok(group) => Ok(group),
+        Err(V1KpdbError::AmbiguousErr) => Err(format!("several groups {}", path.trim_matches('/'))),
+        Err(_) => Err(format!("no group {}", path.trim_matches('/'))),
+    }
+}
+
+fn find_entry(db: &V1Kpdb, path: &str) -> Result<EntryId, String> {
+    match db.entry_by_path(path) {
+        Ok(entry) => Ok(entry),
+        Err(V1KpdbError::AmbiguousErr) => Err(format!("several entries {}", path.trim_matches('/'))),
+        Err(_) => Err(format!("no entry {}", path.trim_matches('/'))),
+    }
+}
+
+// Sensitive data in this function:
+//
+// * username and password (decrypted to be printed)
+//
+// At the end of this function:
+//
+// * the decrypted string is overwritten again, the copy is
+//   returned
+fn reveal(string: &Option<SecureString>) -> String {
+    match *string {
+        Some(ref string) => string.with_unlocked(|string| string.to_string()).unwrap_or_default(),
+        None => "".to_string(),
+    }
+}
+
+fn run_shell(path: &str, options: &Options) -> Result<(), String> {
+    let db = try!(open(path, options, true));
+    let timeout = match options.timeout.unwrap_or(300) {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    };
+    shell::run(db, options.keyfile.clone(), timeout)
+}
+
+fn run_agent(path: &str, options: &Options) -> Result<(), String> {
+    let db = try!(open(path, options, false));
+    let socket = options.socket.clone().unwrap_or_else(default_socket_path);
+    let mut agent = Agent::new(db, options.keyfile.clone(), socket);
+    agent.timeout = match options.timeout.unwrap_or(900) {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    };
+    agent.run().map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "secret-service")]
+fn run_secret_service(path: &str, options: &Options) -> Result<(), String> {
+    let db = try!(open(path, options, false));
+    SecretService::new(db).run().map_err(|e| e.to_string())
+}
+
+fn run_ssh_agent(path: &str, options: &Options) -> Result<(), String> {
+    let db = try!(open(path, options, false));
+    let socket = options.socket.clone().unwrap_or_else(default_ssh_socket_path);
+    let mut agent = try!(SshAgent::new(db, options.keyfile.clone(), socket).map_err(|e| format!("{}: {}", path, e)));
+    agent.confirm_all = options.confirm;
+    agent.timeout = match options.timeout.unwrap_or(900) {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    };
+    println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", agent.socket_path);
+    for comment in agent.key_comments() {
+        println!("# {}", comment);
+    }
+    agent.run().map_err(|e| e.to_string())
+}
+
+fn run_mount(path: &str, mountpoint: &str, options: &Options) -> Result<(), String> {
+    let db = try!(open(path, options, options.writable));
+    let mut fs = VaultFs::new(db);
+    fs.writable = options.writable;
+    fs.mount(mountpoint).map_err(|e| format!("{}: {}", mountpoint, e))
+}
+
+fn connect_agent(options: &Options) -> Result<AgentClient, String> {
+    let socket = options.socket.clone().unwrap_or_else(default_socket_path);
+    AgentClient::connect(&socket).map_err(|e| format!("{}: {}", socket, e))
+}
+
+fn run_get(entry: &str, field: &str, options: &Options) -> Result<(), String> {
+    let field = try!(Field::from_name(field).ok_or(format!("no field {}", field)));
+    let mut client = try!(connect_agent(options));
+    let mut value = match client.get(entry, field) {
+        Err(AgentError::LockedErr) => {
+            try!(client.unlock(try!(read_password("Password: "))).map_err(|e| e.to_string()));
+            try!(client.get(entry, field).map_err(|e| e.to_string()))
+        }
+        result => try!(result.map_err(|e| e.to_string())),
+    };
+    value.unlock();
+    println!("{}", value.string);
+    value.delete();
+    Ok(())
+}
+
+fn run_agent_stop(options: &Options) -> Result<(), String> {
+    let mut client = try!(connect_agent(options));
+    client.stop().map_err(|e| e.to_string())
+}
+
+fn run_ls(path: &str, options: &Options) -> Result<(), String> {
+    let db = try!(open(path, options, false));
+    for &group in db.groups.iter() {
+        let group = &db[group];
+        let depth = group.level as usize;
+        println!("{}{}/", "    ".repeat(depth), group.title);
+        for &entry in group.entries.iter() {
+            println!("{}{}", "    ".repeat(depth + 1), db[entry].title);
+        }
+    }
+    Ok(())
+}
+
+fn run_show(path: &str, entry: &str, options: &Options) -> Result<(), String> {
+    let db = try!(open(path, options, false));
+    let entry = try!(find_entry(&db, entry));
+    print_entry(&db[entry], options.show_password);
+    Ok(())
+}
+
+fn print_entry(entry: &V1Entry, show_password: bool) {
+    let password = if show_password {
+        reveal(&entry.password)
+    } else if entry.password.is_some() {
+        "********".to_string()
+    } else {
+        "".to_string()
+    };
+    println!("Title:      {}", entry.title);
+    println!("URL:        {}", entry.url.clone().unwrap_or_default());
+    println!("Username:   {}", reveal(&entry.username));
+    println!("Password:   {}", password);
+    println!("Comment:    {}", entry.comment.clone().unwrap_or_default().replace("\n", "\n            "));
+    if entry.binary.is_some() {
+        println!("Attachment: {}", entry.binary_desc.clone().unwrap_or_default());
+    }
+    println!("Created:    {}", entry.creation.format("%Y-%m-%d %H:%M:%S"));
+    println!("Modified:   {}", entry.last_mod.format("%Y-%m-%d %H:%M:%S"));
+    println!("Expires:    {}", entry.expire.format("%Y-%m-%d %H:%M:%S"));
+}
+
+fn run_search(path: &str, text: &str, options: &Options) -> Result<(), String> {
+    let db = try!(open(path, options, false));
+    for entry in search(&db, text) {
+        println!("{}", db.entry_path(entry));
+    }
+    Ok(())
+}
+
+// The query is parsed before the password is asked for, errors point
+// at their position in it
+fn run_find(path: &str, query: &str, options: &Options) -> Result<(), String> {
+    let parsed = try!(Query::parse(query).map_err(|e| {
+        format!("{}\n    {}\n    {}^", e, query, " ".repeat(e.position))
+    }));
+    let db = try!(open(path, options, false));
+    if options.groups {
+        for group in parsed.groups(&db) {
+            println!("{}/", db.group_path(group));
+        }
+    } else {
+        for entry in parsed.entries(&db) {
+            println!("{}", db.entry_path(entry));
+        }
+    }
+    Ok(())
+}
+
+// Entries with text in their title, URL, username or comment
+fn search(db: &V1Kpdb, text: &str) -> Vec<EntryId> {
+    let mut search = Search::new(Matcher::substring(text));
+    search.fields = vec![SearchField::Title, SearchField::Url, SearchField::Username, SearchField::Comment];
+    search.iter(db).collect()
+}
+
+fn run_add_entry(path: &str, group: &str, title: &str, options: &Options) -> Result<(), String> {
+    let mut db = try!(open(path, options, true));
+    let group = try!(find_group(&db, group));
+    let password = try!(read_new_password("Entry password: "));
+    try!(db.create_entry(group,
+                         title.to_string(),
+                         None,
+                         None,
+                         options.url.clone(),
+                         options.comment.clone(),
+                         options.username.clone(),
+                         Some(password))
+           .map_err(|e| e.to_string()));
+    save(&mut db, None, None)
+}
+
+fn run_edit_entry(path: &str, entry: &str, options: &Options) -> Result<(), String> {
+    let mut db = try!(open(path, options, true));
+    {
+        let entry = try!(find_entry(&db, entry));
+        let entry = &mut db[entry];
+        if let Some(ref title) = options.title {
+            entry.title = title.clone();
+        }
+        if let Some(ref url) = options.url {
+            entry.url = Some(url.clone());
+        }
+        if let Some(ref username) = options.username {
+            entry.username = Some(SecureString::new(username.clone()));
+        }
+        if let Some(ref comment) = options.comment {
+            entry.comment = Some(comment.clone());
+        }
+        if options.new_password {
+            entry.password = Some(SecureString::new(try!(read_new_password("Entry password: "))));
+        }
+        entry.last_mod = Local::now();
+    }
+    save(&mut db, None, None)
+}
+
+fn run_rm_entry(path: &str, entry: &str, options: &Options) -> Result<(), String> {
+    let mut db = try!(open(path, options, true));
+    let entry = try!(find_entry(&db, entry));
+    try!(db.remove_entry(entry).map_err(|e| e.to_string()));
+    save(&mut db, None, None)
+}
+
+fn run_add_group(path: &str, group: &str, options: &Options) -> Result<(), String> {
+    let mut db = try!(open(path, options, true));
+    try!(add_group(&mut db, group, options.parents));
+    save(&mut db, None, None)
+}
+
+// Create the group at path, with parents also the missing groups on
+// the way like mkdir -p
+fn add_group(db: &mut V1Kpdb, path: &str, parents: bool) -> Result<(), String> {
+    if parents {
+        return db.create_group_path(path).map(|_| ()).map_err(|e| format!("{}: {}", path, e));
+    }
+    let mut titles = split_path(path);
+    let title = try!(titles.pop().ok_or("no group title given".to_string()));
+    let parent = if titles.is_empty() {
+        None
+    } else {
+        Some(try!(find_group(db, &join_path(&titles))))
+    };
+    db.create_group(title, None, None, parent)
+      .map(|_| ())
+      .map_err(|e| e.to_string())
+}
+
+fn run_rm_group(path: &str, group: &str, options: &Options) -> Result<(), String> {
+    let mut db = try!(open(path, options, true));
+    let group = try!(find_group(&db, group));
+    try!(db.remove_group(group).map_err(|e| e.to_string()));
+    save(&mut db, None, None)
+}
+
+fn run_passwd(path: &str, options: &Options) -> Result<(), String> {
+    let mut db = try!(open(path, options, true));
+    // save removes the password if it's empty and keeps it if it's None
+    let password = if options.keep_password {
+        None
+    } else {
+        Some(try!(read_new_password("New password (empty for none): ")))
+    };
+    save(&mut db, password, options.new_keyfile.clone())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match args.first() {
+        Some(command) => command.clone(),
+        None => usage(),
+    };
+
+    let mut options = Options {
+        keyfile: None,
+        strict: false,
+        show_password: false,
+        new_password: false,
+        keep_password: false,
+        new_keyfile: None,
+        title: None,
+        url: None,
+        username: None,
+        comment: None,
+        timeout: None,
+        socket: None,
+        confirm: false,
+        writable: false,
+        parents: false,
+        groups: false,
+    };
+    let mut paths = vec![];
+    let mut iter = args.into_iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || Some(iter.next().unwrap_or_else(|| usage()));
+        match &arg[..] {
+            "-k" | "--keyfile" => options.keyfile = value(),
+            "--strict" => options.strict = true,
+            "--password" => options.show_password = true,
+            "--new-password" => options.new_password = true,
+            "--keep-password" => options.keep_password = true,
+            "--new-keyfile" => options.new_keyfile = value(),
+            "--no-keyfile" => options.new_keyfile = Some("".to_string()),
+            "--title" => options.title = value(),
+            "--url" => options.url = value(),
+            "--username" => options.username = value(),
+            "--comment" => options.comment = value(),
+            "--socket" => options.socket = value(),
+            "--confirm" => options.confirm = true,
+            "--writable" => options.writable = true,
+            "--parents" => options.parents = true,
+            "--groups" => options.groups = true,
+            "--timeout" => {
+                options.timeout = value().and_then(|timeout| timeout.parse().ok());
+                if options.timeout.is_none() {
+                    usage();
+                }
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let result = match (&command[..], paths.len()) {
+        ("shell", 1) => run_shell(&paths[0], &options),
+        ("ls", 1) => run_ls(&paths[0], &options),
+        ("show", 2) => run_show(&paths[0], &paths[1], &options),
+        ("search", 2) => run_search(&paths[0], &paths[1], &options),
+        ("find", 2) => run_find(&paths[0], &paths[1], &options),
+        ("add-entry", 3) => run_add_entry(&paths[0], &paths[1], &paths[2], &options),
+        ("edit-entry", 2) => run_edit_entry(&paths[0], &paths[1], &options),
+        ("rm-entry", 2) => run_rm_entry(&paths[0], &paths[1], &options),
+        ("add-group", 2) => run_add_group(&paths[0], &paths[1], &options),
+        ("rm-group", 2) => run_rm_group(&paths[0], &paths[1], &options),
+        ("passwd", 1) => run_passwd(&paths[0], &options),
+        ("convert", 2) => run_convert(&paths, options.keyfile, options.strict),
+        ("diff", 2) => run_diff(&paths, options.keyfile),
+        ("textconv", 1) => run_textconv(&paths[0], options.keyfile),
+        ("agent", 1) => run_agent(&paths[0], &options),
+        ("get", 1) => run_get(&paths[0], "password", &options),
+        ("get", 2) => run_get(&paths[0], &paths[1], &options),
+        ("agent-stop", 0) => run_agent_stop(&options),
+        ("ssh-agent", 1) => run_ssh_agent(&paths[0], &options),
+        #[cfg(feature = "secret-service")]
+        ("secret-service", 1) => run_secret_service(&paths[0], &options),
+        ("mount", 2) => run_mount(&paths[0], &paths[1], &options),
+        _ => usage(),
+    };
+    if let Err(message) = result {
+        fail(&message);
+    }
+}
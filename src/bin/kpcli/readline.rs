@@ -0,0 +1,179 @@
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+use libc;
+
+// What read_line got
+pub enum Input {
+    Line(String),
+    Eof,
+    // Nothing was typed for the whole timeout
+    Timeout,
+}
+
+// Puts the terminal into raw mode and restores it when dropped
+struct RawMode {
+    saved: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        let mut term: libc::termios = unsafe { ::std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut term) } != 0 {
+            return None;
+        }
+        let saved = term;
+        // No ISIG, Ctrl-C must not kill us while we hold the lock file
+        term.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        term.c_cc[libc::VMIN] = 1;
+        term.c_cc[libc::VTIME] = 0;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) };
+        Some(RawMode { saved: saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+// Wait until stdin is readable, false on timeout
+fn wait(timeout: Option<Duration>) -> bool {
+    let timeout = match timeout {
+        Some(timeout) => timeout.as_secs() as i32 * 1000 + timeout.subsec_millis() as i32,
+        None => -1,
+    };
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            0 => return false,
+            _ => return true,
+        }
+    }
+}
+
+fn read_byte() -> Option<u8> {
+    let mut byte = 0u8;
+    match unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+        1 => Some(byte),
+        _ => None,
+    }
+}
+
+fn redraw(prompt: &str, line: &[u8]) {
+    let mut out = io::stdout();
+    let _ = write!(out, "\r\x1b[K{}{}", prompt, String::from_utf8_lossy(line));
+    let _ = out.flush();
+}
+
+fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix = candidates[0].clone();
+    for candidate in candidates[1..].iter() {
+        while !candidate.starts_with(&prefix[..]) {
+            prefix.pop();
+        }
+    }
+    prefix
+}
+
+// Read a line from the terminal with simple editing: backspace, Ctrl-U
+// to clear the line, Ctrl-C to discard it, Ctrl-D to quit on an empty
+// line and Tab to complete the line. complete gets the line and returns
+// the position where completion starts and the candidates to put there.
+//
+// Returns Timeout if no key is pressed for timeout. Input which isn't
+// from a terminal is read line by line without a timeout.
+pub fn read_line<F>(prompt: &str, timeout: Option<Duration>, mut complete: F) -> Input
+    where F: FnMut(&str) -> (usize, Vec<String>)
+{
+    let _raw = match RawMode::enable() {
+        Some(raw) => raw,
+        None => return read_plain_line(prompt),
+    };
+    redraw(prompt, b"");
+
+    let mut line: Vec<u8> = vec![];
+    loop {
+        if !wait(timeout) {
+            println!();
+            return Input::Timeout;
+        }
+        let byte = match read_byte() {
+            Some(byte) => byte,
+            None => return Input::Eof,
+        };
+        match byte {
+            b'\r' | b'\n' => {
+                println!();
+                return Input::Line(String::from_utf8_lossy(&line).into_owned());
+            }
+            // Ctrl-D
+            4 if line.is_empty() => {
+                println!();
+                return Input::Eof;
+            }
+            // Ctrl-C
+            3 => {
+                println!("^C");
+                line.clear();
+            }
+            // Ctrl-U
+            21 => line.clear(),
+            // Backspace, remove a whole UTF-8 character
+            8 | 127 => {
+                while let Some(byte) = line.pop() {
+                    if byte & 0xC0 != 0x80 {
+                        break;
+                    }
+                }
+            }
+            b'\t' => {
+                let text = String::from_utf8_lossy(&line).into_owned();
+                let (start, candidates) = complete(&text);
+                if !candidates.is_empty() {
+                    let prefix = common_prefix(&candidates);
+                    if candidates.len() > 1 && prefix.len() <= text.len() - start {
+                        println!();
+                        println!("{}", candidates.join("  "));
+                    }
+                    line.truncate(start);
+                    line.extend_from_slice(prefix.as_bytes());
+                }
+            }
+            // Skip escape sequences like the cursor keys
+            0x1b if wait(Some(Duration::from_millis(50))) => {
+                if let Some(b'[') | Some(b'O') = read_byte() {
+                    while let Some(byte) = read_byte() {
+                        if (0x40..=0x7e).contains(&byte) {
+                            break;
+                        }
+                    }
+                }
+            }
+            byte if byte >= 0x20 => line.push(byte),
+            _ => {}
+        }
+        redraw(prompt, &line);
+    }
+}
+
+fn read_plain_line(prompt: &str) -> Input {
+    print!("{}", prompt);
+    let _ = io::stdout().flush();
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => Input::Eof,
+        Ok(_) => {
+            while line.ends_with('\n') || line.ends_with('\r') {
+                line.pop();
+            }
+            Input::Line(line)
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use std::time::Duration;
+
+use keepass::common::file::Fingerprint;
+use keepass::common::lock::LockMode;
+use keepass::kpdb::path::{escape_title, join_path, split_path};
+use keepass::kpdb::v1error::V1KpdbError;
+use keepass::kpdb::v1kpdb::V1Kpdb;
+
+use super::readline::{read_line, Input};
+use super::{add_group, find_entry, find_group, print_entry, read_password, save, search};
+
+const COMMANDS: [&'static str; 11] = ["cd", "exit", "find", "help", "lock", "ls", "mkdir", "mv", "rm", "save",
+                                      "show"];
+
+const HELP: &'static str = "Commands:
+    ls [<group>]               list the subgroups and entries of a group
+    cd [<group>]               change the current group, .. is the parent
+    show [-p] <entry>          show an entry, the password only with -p
+    find <text>                search the titles, URLs, usernames and comments
+    mv <path> <group>          move an entry or a group into another group
+    rm <entry or group>        remove an entry or a group with its content
+    mkdir [-p] <group>         create a group, with -p also missing parents
+    save                       save the changes
+    lock                       lock the database until the password is entered
+    exit                       leave the shell
+
+Paths are relative to the current group unless they start with /. Use
+quotes or backslashes for titles with spaces, Tab completes paths. A /
+in a title is written as \\\\/.";
+
+// How often the password is asked for to unlock the database
+const UNLOCK_ATTEMPTS: usize = 3;
+
+struct Shell {
+    keyfile: Option<String>,
+    path: String,
+    // None while the database is locked
+    db: Option<V1Kpdb>,
+    // The encrypted database with unsaved changes while it's locked
+    locked: Vec<u8>,
+    fingerprint: Option<Fingerprint>,
+    // Path of the current group, empty for the top
+    cwd: String,
+    modified: bool,
+    warned: bool,
+    timeout: Option<Duration>,
+}
+
+/// Run the interactive shell on an opened database. The database is
+/// locked after timeout without input: it's dropped with all its
+/// SecureStrings and loaded again when the password is entered.
+pub fn run(db: V1Kpdb, keyfile: Option<String>, timeout: Option<Duration>) -> Result<(), String> {
+    let mut shell = Shell {
+        keyfile: keyfile,
+        path: db.path.clone(),
+        db: Some(db),
+        locked: vec![],
+        fingerprint: None,
+        cwd: "".to_string(),
+        modified: false,
+        warned: false,
+        timeout: timeout,
+    };
+    shell.run()
+}
+
+impl Shell {
+    fn run(&mut self) -> Result<(), String> {
+        loop {
+            if self.db.is_none() {
+                try!(self.unlock());
+            }
+            let prompt = format!("kpcli:/{}> ", self.cwd);
+            let line = match read_line(&prompt, self.timeout, |line| self.complete(line)) {
+                Input::Line(line) => line,
+                Input::Eof => return Ok(()),
+                Input::Timeout => {
+                    self.lock();
+                    println!("Locked after {} seconds without input", self.timeout.unwrap().as_secs());
+                    continue;
+                }
+            };
+            let args = split(&line);
+            if args.is_empty() {
+                continue;
+            }
+            let warned = self.warned;
+            match self.execute(&args) {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(message) => println!("{}", message),
+            }
+            // A second exit leaves without saving
+            if warned {
+                self.warned = false;
+            }
+        }
+    }
+
+    fn execute(&mut self, args: &[String]) -> Result<bool, String> {
+        match (&args[0][..], args.len()) {
+            ("ls", 1) => try!(self.ls("")),
+            ("ls", 2) => try!(self.ls(&args[1])),
+            ("cd", 1) => self.cwd = "".to_string(),
+            ("cd", 2) => {
+                let path = self.resolve(&args[1]);
+                if !path.is_empty() {
+                    try!(find_group(self.db(), &path));
+                }
+                self.cwd = path;
+            }
+            ("show", 2) => try!(self.show(&args[1], false)),
+            ("show", 3) if args[1] == "-p" => try!(self.show(&args[2], true)),
+            ("find", 2) => {
+                for entry in search(self.db(), &args[1]) {
+                    println!("/{}", self.db().entry_path(entry));
+                }
+            }
+            ("mv", 3) => try!(self.mv(&args[1], &args[2])),
+            ("rm", 2) => try!(self.rm(&args[1])),
+            ("mkdir", 2) => try!(self.mkdir(&args[1], false)),
+            ("mkdir", 3) if args[1] == "-p" => try!(self.mkdir(&args[2], true)),
+            ("save", 1) => {
+                try!(save(self.db_mut(), None, None));
+                self.modified = false;
+            }
+            ("lock", 1) => self.lock(),
+            ("help", _) => println!("{}", HELP),
+            ("exit", 1) | ("quit", 1) => {
+                if self.modified && !self.warned {
+                    self.warned = true;
+                    return Err("There are unsaved changes, save them or exit again".to_string());
+                }
+                return Ok(true);
+            }
+            _ => return Err("Invalid command, try help".to_string()),
+        }
+        Ok(false)
+    }
+
+    fn db(&self) -> &V1Kpdb {
+        self.db.as_ref().expect("Database is locked")
+    }
+
+    fn db_mut(&mut self) -> &mut V1Kpdb {
+        self.db.as_mut().expect("Database is locked")
+    }
+
+    // Turn a path relative to the current group into an absolute one
+    fn resolve(&self, path: &str) -> String {
+        let mut titles: Vec<String> = vec![];
+        if !path.starts_with('/') {
+            titles = split_path(&self.cwd);
+        }
+        for title in split_path(path) {
+            match &title[..] {
+                "." => {}
+                ".." => {
+                    titles.pop();
+                }
+                _ => titles.push(title),
+            }
+        }
+        join_path(&titles)
+    }
+
+    // Titles of the subgroups and entries of the group at path
+    fn children(&self, path: &str) -> (Vec<String>, Vec<String>) {
+        let db = self.db();
+        let group = if path.is_empty() {
+            db.root_group
+        } else {
+            match db.group_by_path(path) {
+                Ok(group) => group,
+                Err(_) => return (vec![], vec![]),
+            }
+        };
+        let groups = db[group].children.iter().map(|&g| db[g].title.clone()).collect();
+        let entries = db[group].entries.iter().map(|&e| db[e].title.clone()).collect();
+        (groups, entries)
+    }
+
+    fn ls(&mut self, path: &str) -> Result<(), String> {
+        let path = self.resolve(path);
+        if !path.is_empty() {
+            try!(find_group(self.db(), &path));
+        }
+        let (groups, entries) = self.children(&path);
+        for group in groups {
+            println!("{}/", escape_title(&group));
+        }
+        for entry in entries {
+            println!("{}", escape_title(&entry));
+        }
+        Ok(())
+    }
+
+    fn show(&mut self, path: &str, show_password: bool) -> Result<(), String> {
+        let entry = try!(find_entry(self.db(), &self.resolve(path)));
+        print_entry(&self.db()[entry], show_password);
+        Ok(())
+    }
+
+    fn mv(&mut self, from: &str, to: &str) -> Result<(), String> {
+        let (from, to) = (self.resolve(from), self.resolve(to));
+        if let Ok(entry) = find_entry(self.db(), &from) {
+            let group = try!(find_group(self.db(), &to));
+            try!(self.db_mut().move_entry(entry, group).map_err(|e| e.to_string()));
+            self.modified = true;
+            return Ok(());
+        }
+        let group = try!(find_group(self.db(), &from));
+        let parent = if to.is_empty() { None } else { Some(try!(find_group(self.db(), &to))) };
+        let position = self.db()[parent.unwrap_or(self.db().root_group)].children.len();
+        try!(self.db_mut().move_group(group, parent, position).map_err(|e| e.to_string()));
+        // Follow the moved group
+        if self.cwd == from || self.cwd.starts_with(&format!("{}/", from)) {
+            let moved = self.db().group_path(group);
+            self.cwd = format!("{}{}", moved, &self.cwd[from.len()..]);
+        }
+        self.modified = true;
+        Ok(())
+    }
+
+    fn mkdir(&mut self, path: &str, parents: bool) -> Result<(), String> {
+        let path = self.resolve(path);
+        try!(add_group(self.db_mut(), &path, parents));
+        self.modified = true;
+        Ok(())
+    }
+
+    fn rm(&mut self, path: &str) -> Result<(), String> {
+        let path = self.resolve(path);
+        match find_entry(self.db(), &path) {
+            Ok(entry) => try!(self.db_mut().remove_entry(entry).map_err(|e| e.to_string())),
+            Err(_) => {
+                let group = try!(find_group(self.db(), &path));
+                try!(self.db_mut().remove_group(group).map_err(|e| e.to_string()));
+                // Leave the removed group
+                if self.cwd == path || self.cwd.starts_with(&format!("{}/", path)) {
+                    self.cwd = parent_path(&path);
+                }
+            }
+        }
+        self.modified = true;
+        Ok(())
+    }
+
+    // Unsaved changes are kept encrypted with the master key, all
+    // decrypted data goes away with the database
+    fn lock(&mut self) {
+        let mut db = match self.db.take() {
+            Some(db) => db,
+            None => return,
+        };
+        if self.modified {
+            let mut raw: Vec<u8> = vec![];
+            match db.save_to_writer(&mut raw, None, None) {
+                Ok(_) => {
+                    self.locked = raw;
+                    self.fingerprint = db.fingerprint.clone();
+                }
+                Err(e) => println!("Couldn't keep the unsaved changes: {}", e),
+            }
+        }
+    }
+
+    fn unlock(&mut self) -> Result<(), String> {
+        for _ in 0..UNLOCK_ATTEMPTS {
+            let password = try!(read_password("Password: "));
+            let password = if password.is_empty() { None } else { Some(password) };
+            let mut db = try!(V1Kpdb::new(self.path.clone(), password, self.keyfile.clone())
+                                  .map_err(|e| e.to_string()));
+            db.lock_mode = Some(LockMode::LockFile);
+            let result = if self.locked.is_empty() {
+                db.load()
+            } else {
+                db.lock(false).and_then(|_| db.load_from_bytes(&self.locked))
+            };
+            match result {
+                Ok(_) => {
+                    if !self.locked.is_empty() {
+                        db.fingerprint = self.fingerprint.take();
+                        self.locked = vec![];
+                    }
+                    self.modified = self.modified && db.fingerprint.is_some();
+                    self.db = Some(db);
+                    if !self.cwd.is_empty() && find_group(self.db(), &self.cwd).is_err() {
+                        self.cwd = "".to_string();
+                    }
+                    return Ok(());
+                }
+                Err(V1KpdbError::LockErr) => {
+                    let holder = db.lock_holder().map(|info| format!("{}@{}", info.user, info.host));
+                    return Err(format!("{} is locked by {}", self.path, holder.unwrap_or_default()));
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+        Err("Couldn't unlock the database".to_string())
+    }
+
+    fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        let start = word_start(line);
+        let word = unescape(&line[start..]);
+        if start == 0 {
+            let commands = COMMANDS.iter()
+                                   .filter(|command| command.starts_with(&word[..]))
+                                   .map(|command| format!("{} ", command))
+                                   .collect();
+            return (start, commands);
+        }
+
+        let (dir, partial) = match last_separator(&word) {
+            Some(index) => (&word[..index + 1], &word[index + 1..]),
+            None => ("", &word[..]),
+        };
+        let path = self.resolve(dir);
+        if !path.is_empty() && find_group(self.db(), &path).is_err() {
+            return (start, vec![]);
+        }
+        let (groups, entries) = self.children(&path);
+        let mut candidates: Vec<String> = groups.iter()
+                                                .map(|title| escape_title(title))
+                                                .filter(|title| title.starts_with(partial))
+                                                .map(|title| format!("{}/", escape(&format!("{}{}", dir, title))))
+                                                .collect();
+        if !line.starts_with("cd ") {
+            candidates.extend(entries.iter()
+                                     .map(|title| escape_title(title))
+                                     .filter(|title| title.starts_with(partial))
+                                     .map(|title| format!("{} ", escape(&format!("{}{}", dir, title)))));
+        }
+        (start, candidates)
+    }
+}
+
+fn parent_path(path: &str) -> String {
+    let mut titles = split_path(path);
+    titles.pop();
+    join_path(&titles)
+}
+
+// Index of the last / of a path which isn't part of a title
+fn last_separator(path: &str) -> Option<usize> {
+    let mut last = None;
+    let mut escaped = false;
+    for (index, c) in path.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '/' {
+            last = Some(index);
+        }
+    }
+    last
+}
+
+// Split a command line into words. Spaces can be escaped with a
+// backslash or by quoting.
+fn split(line: &str) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                if let Some(next) = chars.next() {
+                    word.get_or_insert_with(String::new).push(next);
+                }
+            }
+            (c, Some(q)) if c == q => quote = None,
+            ('"', None) | ('\'', None) => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (' ', None) | ('\t', None) => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            (c, _) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(word) = word {
+        words.push(word);
+    }
+    words
+}
+
+// Start of the last word of line, which Tab completes
+fn word_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == ' ' {
+            start = index + 1;
+        }
+    }
+    start
+}
+
+fn escape(word: &str) -> String {
+    let mut escaped = String::new();
+    for c in word.chars() {
+        if c == ' ' || c == '\\' || c == '"' || c == '\'' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn unescape(word: &str) -> String {
+    split(word).join(" ")
+}
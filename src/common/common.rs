@@ -22,6 +22,16 @@ pub fn slice_to_u32(slice: &[u8]) -> Result<u32, CommonError> {
     Ok(value | slice[0] as u32)
 }
 
+pub fn slice_to_u64(slice: &[u8]) -> Result<u64, CommonError> {
+    if slice.len() < 8 {
+        return Err(CommonError::ConvertErr);
+    }
+
+    let low = try!(slice_to_u32(&slice[0..4])) as u64;
+    let high = try!(slice_to_u32(&slice[4..8])) as u64;
+    Ok(high << 32 | low)
+}
+
 pub fn u16_to_vec_u8(value: u16) -> Vec<u8> {
     let mut ret: Vec<u8> = vec![0,0];
     ret[0] |= (value & 0xFF) as u8;
@@ -38,6 +48,12 @@ pub fn u32_to_vec_u8(value: u32) -> Vec<u8> {
     ret
 }
 
+pub fn u64_to_vec_u8(value: u64) -> Vec<u8> {
+    let mut ret = u32_to_vec_u8((value & 0xFFFFFFFF) as u32);
+    ret.append(&mut u32_to_vec_u8((value >> 32) as u32));
+    ret
+}
+
 pub unsafe fn write_array_volatile(dst: *mut u8, val: u8, count: usize) {
     for i in 0..count {
         ptr::write_volatile(dst.offset(i as isize), val);
@@ -11,6 +11,12 @@ can happen when using V1Kpdb.
 pub enum CommonError {
     /// Some error in parsing
     ConvertErr,
+    /// Couldn't create the temporary file
+    FileErr,
+    /// Couldn't write, sync or rename the temporary file
+    WriteErr,
+    /// Someone else holds the lock of the database
+    LockErr,
 }
 
 impl fmt::Display for CommonError {
@@ -23,6 +29,9 @@ impl error::Error for CommonError {
     fn description(&self) -> &str {
         match *self {
             ConvertErr => "Some error while converting datatypes",
+            FileErr => "Couldn't create temporary file",
+            WriteErr => "Couldn't write file",
+            LockErr => "Database is locked by someone else",
         }
     }
 }
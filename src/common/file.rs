@@ -0,0 +1,221 @@
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::Local;
+use openssl::hash;
+use rand;
+
+use common::common_error::CommonError;
+
+#[doc = "
+Fingerprint of a database file, taken when the database is loaded or
+saved. A database refuses to overwrite its file if the fingerprint
+of the file on disk doesn't match anymore.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Time of the last modification of the file
+    pub mtime: SystemTime,
+    /// SHA-256 hash of the content of the file
+    pub hash: Vec<u8>,
+}
+
+impl Fingerprint {
+    /// Fingerprint of data which was just read from or written to path
+    pub fn new(path: &str, data: &[u8]) -> Fingerprint {
+        let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Can't hash file!?");
+        hasher.write_all(data).expect("Can't hash file!?");
+        Fingerprint {
+            size: data.len() as u64,
+            mtime: fs::metadata(path).and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH),
+            hash: hasher.finish2().expect("Can't hash file!?").to_vec(),
+        }
+    }
+
+    /// Fingerprint of the file at path, None if it can't be read
+    pub fn of_file(path: &str) -> Option<Fingerprint> {
+        let mut data: Vec<u8> = vec![];
+        match File::open(path).and_then(|mut file| file.read_to_end(&mut data)) {
+            Ok(_) => Some(Fingerprint::new(path, &data)),
+            Err(_) => None,
+        }
+    }
+
+    /// True if both fingerprints belong to the same content. A
+    /// different mtime alone (e.g. through touch or a sync tool)
+    /// doesn't count as a modification.
+    pub fn same_content(&self, other: &Fingerprint) -> bool {
+        self.size == other.size && self.hash == other.hash
+    }
+}
+
+/// Write data to path without ever leaving a half written file behind:
+/// data is written to a temporary file in the same directory, synced to
+/// disk and renamed over path. An existing file keeps its permissions,
+/// a new one is only readable and writable by the owner.
+///
+/// If backups isn't 0, the previous version of path is kept as
+/// path.YYYYmmdd-HHMMSS.fff.bak (or .fff-2.bak and so on if path is
+/// saved more than once within a millisecond) and only the newest
+/// backups of these copies are kept.
+pub fn save_atomically(path: &str, data: &[u8], backups: usize) -> Result<(), CommonError> {
+    // Replace the file a symlink points to instead of the symlink itself
+    let target = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let name = try!(target.file_name().ok_or(CommonError::FileErr)).to_string_lossy().into_owned();
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let mode = fs::metadata(&target).map(|m| m.permissions().mode() & 0o7777).unwrap_or(0o600);
+
+    let temp = dir.join(format!(".{}.{:08x}.tmp", name, rand::random::<u32>()));
+    let mut file = try!(OpenOptions::new()
+                            .write(true)
+                            .create_new(true)
+                            .mode(0o600)
+                            .open(&temp)
+                            .map_err(|_| CommonError::FileErr));
+    let written = write_temp(&mut file, data, mode).and_then(|_| {
+        if backups > 0 && target.exists() {
+            try!(backup(&target, &dir, &name));
+        }
+        fs::rename(&temp, &target)
+    });
+    drop(file);
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+        return Err(CommonError::WriteErr);
+    }
+
+    // Make the rename itself durable
+    if let Ok(dir) = File::open(&dir) {
+        let _ = dir.sync_all();
+    }
+    if backups > 0 {
+        prune_backups(&dir, &name, backups);
+    }
+    Ok(())
+}
+
+/// The backups save_atomically kept of path, oldest first
+pub fn list_backups(path: &str) -> Vec<PathBuf> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    match target.file_name() {
+        Some(name) => find_backups(&dir, &name.to_string_lossy()),
+        None => vec![],
+    }
+}
+
+fn write_temp(file: &mut File, data: &[u8], mode: u32) -> io::Result<()> {
+    try!(file.write_all(data));
+    try!(file.set_permissions(Permissions::from_mode(mode)));
+    file.sync_all()
+}
+
+// An existing backup is never overwritten, a counter is appended to
+// the timestamp instead.
+fn backup(target: &Path, dir: &Path, name: &str) -> io::Result<()> {
+    let stamp = Local::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+    let mut count = 1;
+    loop {
+        let backup = if count == 1 {
+            dir.join(format!("{}.{}.bak", name, stamp))
+        } else {
+            dir.join(format!("{}.{}-{}.bak", name, stamp, count))
+        };
+        match create_backup(target, &backup) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => count += 1,
+            result => return result,
+        }
+    }
+}
+
+// The backup is a hard link to the old version, so it costs neither
+// time nor space. Filesystems without hard links get a copy.
+fn create_backup(target: &Path, backup: &Path) -> io::Result<()> {
+    match fs::hard_link(target, backup) {
+        Err(ref e) if e.kind() != io::ErrorKind::AlreadyExists => {
+            let mut old = try!(File::open(target));
+            let mut copy = try!(OpenOptions::new()
+                                    .write(true)
+                                    .create_new(true)
+                                    .mode(0o600)
+                                    .open(backup));
+            let copied = io::copy(&mut old, &mut copy)
+                             .and_then(|_| old.metadata())
+                             .and_then(|metadata| copy.set_permissions(metadata.permissions()))
+                             .and_then(|_| copy.sync_all());
+            if copied.is_err() {
+                let _ = fs::remove_file(backup);
+            }
+            copied
+        }
+        result => result,
+    }
+}
+
+fn find_backups(dir: &Path, name: &str) -> Vec<PathBuf> {
+    let prefix = format!("{}.", name);
+    let mut backups: Vec<(&str, u32, PathBuf)> = vec![];
+    let file_names: Vec<String> = match fs::read_dir(dir) {
+        Ok(entries) => {
+            entries.filter_map(|entry| entry.ok())
+                   .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                   .collect()
+        }
+        Err(_) => vec![],
+    };
+    for file_name in file_names.iter() {
+        if file_name.len() > prefix.len() + 4 && file_name.starts_with(&prefix) && file_name.ends_with(".bak") {
+            if let Some((stamp, count)) = parse_stamp(&file_name[prefix.len()..file_name.len() - 4]) {
+                backups.push((stamp, count, dir.join(file_name)));
+            }
+        }
+    }
+    // The timestamps have a fixed width, so they sort as strings
+    backups.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    backups.into_iter().map(|backup| backup.2).collect()
+}
+
+// YYYYmmdd-HHMMSS.fff, optionally followed by -count
+fn parse_stamp(text: &str) -> Option<(&str, u32)> {
+    if text.len() < 19 || !text.is_char_boundary(19) {
+        return None;
+    }
+    let (stamp, count) = text.split_at(19);
+    let valid = stamp.char_indices().all(|(i, c)| {
+        match i {
+            8 => c == '-',
+            15 => c == '.',
+            _ => c.is_ascii_digit(),
+        }
+    });
+    if !valid {
+        return None;
+    }
+    if count.is_empty() {
+        return Some((stamp, 1));
+    }
+    if !count.starts_with('-') || !count[1..].chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    count[1..].parse().ok().map(|count| (stamp, count))
+}
+
+fn prune_backups(dir: &Path, name: &str, keep: usize) {
+    let backups = find_backups(dir, name);
+    if backups.len() > keep {
+        for old in backups[..backups.len() - keep].iter() {
+            let _ = fs::remove_file(old);
+        }
+    }
+}
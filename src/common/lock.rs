@@ -0,0 +1,228 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+
+use chrono::Utc;
+use libc;
+use rand;
+use rustc_serialize::hex::ToHex;
+
+use common::common_error::CommonError;
+
+const LOCK_FILE_HEADER: &'static str = "KeePass Lock File";
+
+#[doc = "
+How a database is locked against concurrent editing
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum LockMode {
+    /// Create a <database>.lock file like KeePass and KeePassX do.
+    /// A lock file left behind by a crashed program has to be
+    /// overridden explicitly.
+    LockFile,
+    /// Like LockFile, but the lock file is flocked, too. The kernel
+    /// releases a flock if its holder dies, so lock files left behind
+    /// are taken over automatically. Lock files of programs which
+    /// don't use flock (like KeePass) are taken over as well, so only
+    /// use this if all programs accessing the database do.
+    Flock,
+}
+
+#[doc = "
+Who holds the lock of a database, read from its lock file. The fields
+are empty if the lock file doesn't say, e.g. KeePassX leaves it empty.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockInfo {
+    /// Random id of the lock
+    pub id: String,
+    /// Time the lock was taken (UTC, RFC 3339)
+    pub time: String,
+    /// User name of the holder
+    pub user: String,
+    /// Hostname of the holder
+    pub host: String,
+    /// Domain of the holder, used by KeePass on Windows
+    pub domain: String,
+}
+
+impl LockInfo {
+    fn new() -> LockInfo {
+        LockInfo {
+            id: (0..8).map(|_| rand::random::<u8>()).collect::<Vec<u8>>().to_hex().to_uppercase(),
+            time: Utc::now().to_rfc3339(),
+            user: env::var("USER").or_else(|_| env::var("LOGNAME")).unwrap_or_default(),
+            host: hostname(),
+            domain: "".to_string(),
+        }
+    }
+
+    // The lock file format of KeePass 2.x: a header line followed
+    // by id, time, user, host and domain on a line each
+    fn parse(content: &str) -> LockInfo {
+        let mut lines = content.lines();
+        let mut next = || lines.next().unwrap_or("").trim().to_string();
+        if next() != LOCK_FILE_HEADER {
+            return LockInfo {
+                id: "".to_string(),
+                time: "".to_string(),
+                user: "".to_string(),
+                host: "".to_string(),
+                domain: "".to_string(),
+            };
+        }
+        LockInfo {
+            id: next(),
+            time: next(),
+            user: next(),
+            host: next(),
+            domain: next(),
+        }
+    }
+
+    fn to_content(&self) -> String {
+        format!("{}\n{}\n{}\n{}\n{}\n{}\n",
+                LOCK_FILE_HEADER,
+                self.id,
+                self.time,
+                self.user,
+                self.host,
+                self.domain)
+    }
+}
+
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+    let result = unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) };
+    if result != 0 {
+        return "".to_string();
+    }
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+/// Filepath of the lock file of the database at path
+pub fn lock_path(path: &str) -> String {
+    format!("{}.lock", path)
+}
+
+/// Who holds the lock of the database at path, None if it isn't locked
+pub fn lock_holder(path: &str) -> Option<LockInfo> {
+    read_lock_file(&lock_path(path))
+}
+
+fn read_lock_file(lock_path: &str) -> Option<LockInfo> {
+    let mut content = String::new();
+    match File::open(lock_path) {
+        Ok(mut file) => {
+            let _ = file.read_to_string(&mut content);
+            Some(LockInfo::parse(&content))
+        }
+        Err(_) => None,
+    }
+}
+
+#[doc = "
+The lock of a database, it's released when dropped
+"]
+pub struct FileLock {
+    /// Filepath of the lock file
+    pub path: String,
+    /// What was written into the lock file
+    pub info: LockInfo,
+    // Holds the flock in LockMode::Flock
+    file: Option<File>,
+}
+
+impl FileLock {
+    /// Lock the database at path. Returns LockErr if someone else holds
+    /// the lock, use lock_holder to find out who. steal takes over a lock
+    /// file which was left behind, it can't take over a flock though.
+    pub fn acquire(path: &str, mode: LockMode, steal: bool) -> Result<FileLock, CommonError> {
+        let info = LockInfo::new();
+        let lock_path = lock_path(path);
+        let file = match mode {
+            LockMode::LockFile => {
+                let mut file = try!(FileLock::create(&lock_path, steal));
+                if file.write_all(info.to_content().as_bytes()).is_err() {
+                    // The lock file is ours, but doesn't say so
+                    let _ = fs::remove_file(&lock_path);
+                    return Err(CommonError::WriteErr);
+                }
+                None
+            }
+            LockMode::Flock => {
+                let mut file = try!(FileLock::flock(&lock_path));
+                if file.set_len(0).and_then(|_| file.write_all(info.to_content().as_bytes())).is_err() {
+                    // Removed before the flock is released, like on drop
+                    let _ = fs::remove_file(&lock_path);
+                    return Err(CommonError::WriteErr);
+                }
+                Some(file)
+            }
+        };
+        Ok(FileLock {
+            path: lock_path,
+            info: info,
+            file: file,
+        })
+    }
+
+    fn create(lock_path: &str, steal: bool) -> Result<File, CommonError> {
+        let mut options = OpenOptions::new();
+        options.write(true).mode(0o600);
+        if steal {
+            options.create(true).truncate(true);
+        } else {
+            options.create_new(true);
+        }
+        options.open(lock_path).map_err(|e| {
+            match e.kind() {
+                ErrorKind::AlreadyExists => CommonError::LockErr,
+                _ => CommonError::FileErr,
+            }
+        })
+    }
+
+    // Holders remove the lock file before releasing the flock, so the
+    // file we locked may be gone already. Try again in this case.
+    fn flock(lock_path: &str) -> Result<File, CommonError> {
+        loop {
+            let file = try!(OpenOptions::new()
+                                .read(true)
+                                .write(true)
+                                .create(true)
+                                .truncate(false)
+                                .mode(0o600)
+                                .open(lock_path)
+                                .map_err(|_| CommonError::FileErr));
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                return Err(CommonError::LockErr);
+            }
+            let locked = try!(file.metadata().map_err(|_| CommonError::FileErr));
+            match fs::metadata(lock_path) {
+                Ok(ref current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {
+                    return Ok(file)
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Don't remove a lock file someone else took over
+        let ours = match self.file {
+            Some(_) => true,
+            None => read_lock_file(&self.path).map(|info| info.id == self.info.id) == Some(true),
+        };
+        if ours {
+            let _ = fs::remove_file(&self.path);
+        }
+        // Closing the file releases the flock
+        self.file = None;
+    }
+}
@@ -1,5 +1,7 @@
 pub mod common;
 pub mod common_error;
+pub mod file;
+pub mod lock;
 
 //#[cfg(tests)]
 //mod tests_common;
@@ -0,0 +1,191 @@
+// The parts of the FUSE kernel protocol (linux/fuse.h) we speak, as
+// of version 7.31. Everything is in native byte order.
+
+pub const KERNEL_VERSION: u32 = 7;
+pub const KERNEL_MINOR_VERSION: u32 = 31;
+
+pub const FUSE_LOOKUP: u32 = 1;
+pub const FUSE_FORGET: u32 = 2;
+pub const FUSE_GETATTR: u32 = 3;
+pub const FUSE_SETATTR: u32 = 4;
+pub const FUSE_OPEN: u32 = 14;
+pub const FUSE_READ: u32 = 15;
+pub const FUSE_WRITE: u32 = 16;
+pub const FUSE_STATFS: u32 = 17;
+pub const FUSE_RELEASE: u32 = 18;
+pub const FUSE_FSYNC: u32 = 20;
+pub const FUSE_FLUSH: u32 = 25;
+pub const FUSE_INIT: u32 = 26;
+pub const FUSE_OPENDIR: u32 = 27;
+pub const FUSE_READDIR: u32 = 28;
+pub const FUSE_RELEASEDIR: u32 = 29;
+pub const FUSE_ACCESS: u32 = 34;
+pub const FUSE_INTERRUPT: u32 = 36;
+pub const FUSE_DESTROY: u32 = 38;
+pub const FUSE_BATCH_FORGET: u32 = 42;
+
+// Bypass the page cache for a file
+pub const FOPEN_DIRECT_IO: u32 = 1;
+
+// Fields of fuse_setattr_in to change
+pub const FATTR_SIZE: u32 = 1 << 3;
+
+pub const OUT_HEADER_LEN: usize = 16;
+
+// Largest write the kernel sends us, the buffer for requests has to
+// hold it together with the headers
+pub const MAX_WRITE: u32 = 128 * 1024;
+pub const BUFFER_LEN: usize = MAX_WRITE as usize + 4096;
+
+pub const ROOT_ID: u64 = 1;
+
+pub struct InHeader {
+    pub opcode: u32,
+    pub unique: u64,
+    pub nodeid: u64,
+}
+
+// Attributes of a file or directory (struct fuse_attr)
+pub struct Attr {
+    pub ino: u64,
+    pub size: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub atime: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data: data }
+    }
+
+    pub fn header(&mut self) -> Result<InHeader, ()> {
+        let _len = try!(self.u32());
+        let opcode = try!(self.u32());
+        let unique = try!(self.u64());
+        let nodeid = try!(self.u64());
+        // uid, gid, pid, total_extlen and padding
+        try!(self.bytes(16));
+        Ok(InHeader {
+            opcode: opcode,
+            unique: unique,
+            nodeid: nodeid,
+        })
+    }
+
+    pub fn u32(&mut self) -> Result<u32, ()> {
+        let bytes = try!(self.bytes(4));
+        Ok(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, ()> {
+        let bytes = try!(self.bytes(8));
+        let mut array = [0u8; 8];
+        array.copy_from_slice(bytes);
+        Ok(u64::from_ne_bytes(array))
+    }
+
+    // A name terminated by a zero byte
+    pub fn name(&mut self) -> Result<&'a str, ()> {
+        let end = try!(self.data.iter().position(|&byte| byte == 0).ok_or(()));
+        let name = try!(::std::str::from_utf8(&self.data[..end]).map_err(|_| ()));
+        self.data = &self.data[end + 1..];
+        Ok(name)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], ()> {
+        if self.data.len() < len {
+            return Err(());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+}
+
+pub fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+pub fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+pub fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+// struct fuse_out_header followed by the reply
+pub fn reply(unique: u64, error: i32, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(OUT_HEADER_LEN + payload.len());
+    put_u32(&mut out, (OUT_HEADER_LEN + payload.len()) as u32);
+    put_u32(&mut out, (-error) as u32);
+    put_u64(&mut out, unique);
+    out.extend_from_slice(payload);
+    out
+}
+
+pub fn put_attr(out: &mut Vec<u8>, attr: &Attr) {
+    put_u64(out, attr.ino);
+    put_u64(out, attr.size);
+    put_u64(out, attr.size.div_ceil(512));
+    put_u64(out, attr.atime);
+    put_u64(out, attr.mtime);
+    put_u64(out, attr.ctime);
+    // Nanoseconds of the times
+    put_u32(out, 0);
+    put_u32(out, 0);
+    put_u32(out, 0);
+    put_u32(out, attr.mode);
+    put_u32(out, attr.nlink);
+    put_u32(out, attr.uid);
+    put_u32(out, attr.gid);
+    // rdev, blksize and flags
+    put_u32(out, 0);
+    put_u32(out, 4096);
+    put_u32(out, 0);
+}
+
+// struct fuse_entry_out, the reply to LOOKUP
+pub fn put_entry(out: &mut Vec<u8>, attr: &Attr, valid: u64) {
+    put_u64(out, attr.ino);
+    // generation
+    put_u64(out, 0);
+    put_u64(out, valid);
+    put_u64(out, valid);
+    put_u32(out, 0);
+    put_u32(out, 0);
+    put_attr(out, attr);
+}
+
+// struct fuse_attr_out, the reply to GETATTR and SETATTR
+pub fn put_attr_out(out: &mut Vec<u8>, attr: &Attr, valid: u64) {
+    put_u64(out, valid);
+    put_u32(out, 0);
+    put_u32(out, 0);
+    put_attr(out, attr);
+}
+
+// struct fuse_dirent, padded to 8 bytes
+pub fn put_dirent(out: &mut Vec<u8>, ino: u64, offset: u64, kind: u32, name: &str) {
+    put_u64(out, ino);
+    put_u64(out, offset);
+    put_u32(out, name.len() as u32);
+    put_u32(out, kind);
+    out.extend_from_slice(name.as_bytes());
+    while !out.len().is_multiple_of(8) {
+        out.push(0);
+    }
+}
+
+pub fn dirent_len(name: &str) -> usize {
+    (24 + name.len()).div_ceil(8) * 8
+}
@@ -0,0 +1,36 @@
+use std::fmt;
+use std::error;
+
+pub use self::FuseError::*;
+
+#[doc = "
+Use this for catching various errors that
+can happen when mounting a VaultFs.
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum FuseError {
+    /// Couldn't open /dev/fuse, probably the fuse module
+    /// isn't loaded
+    DeviceErr,
+    /// Couldn't mount, e.g. the mountpoint doesn't exist or
+    /// fusermount isn't installed
+    MountErr,
+    /// Something went wrong while talking to the kernel
+    IoErr,
+}
+
+impl fmt::Display for FuseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for FuseError {
+    fn description(&self) -> &str {
+        match *self {
+            DeviceErr => "Couldn't open /dev/fuse",
+            MountErr => "Couldn't mount the filesystem",
+            IoErr => "Couldn't talk to the kernel",
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod vaultfs;
+pub mod mount;
+pub mod fuse_error;
+mod abi;
+
+#[cfg(test)]
+mod tests_fuse;
@@ -0,0 +1,142 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::process::Command;
+use std::ptr;
+
+use libc;
+
+use fuse::fuse_error::FuseError;
+
+// Helpers of libfuse which mount for users without CAP_SYS_ADMIN
+const FUSERMOUNT: [&'static str; 2] = ["fusermount3", "fusermount"];
+
+// The connection to the kernel for a mounted filesystem
+pub struct Mount {
+    pub device: File,
+    mountpoint: String,
+    fusermount: Option<&'static str>,
+}
+
+impl Mount {
+    // Mount with mount(2) if we may, otherwise through fusermount
+    pub fn new(mountpoint: &str, writable: bool) -> Result<Mount, FuseError> {
+        let device = try!(OpenOptions::new()
+                              .read(true)
+                              .write(true)
+                              .custom_flags(libc::O_CLOEXEC)
+                              .open("/dev/fuse")
+                              .map_err(|_| FuseError::DeviceErr));
+        let options = format!("fd={},rootmode=40000,user_id={},group_id={},default_permissions",
+                              device.as_raw_fd(),
+                              unsafe { libc::getuid() },
+                              unsafe { libc::getgid() });
+        let mut flags = libc::MS_NOSUID | libc::MS_NODEV;
+        if !writable {
+            flags |= libc::MS_RDONLY;
+        }
+        let target = try!(CString::new(mountpoint).map_err(|_| FuseError::MountErr));
+        let data = CString::new(options).unwrap();
+        let result = unsafe {
+            libc::mount(b"keepass\0".as_ptr() as *const libc::c_char,
+                        target.as_ptr(),
+                        b"fuse.keepass\0".as_ptr() as *const libc::c_char,
+                        flags,
+                        data.as_ptr() as *const libc::c_void)
+        };
+        if result == 0 {
+            return Ok(Mount {
+                device: device,
+                mountpoint: mountpoint.to_string(),
+                fusermount: None,
+            });
+        }
+        let options = format!("{},nosuid,nodev,default_permissions,fsname=keepass,subtype=keepass",
+                              if writable { "rw" } else { "ro" });
+        for program in &FUSERMOUNT {
+            if let Some(device) = fusermount(program, mountpoint, &options) {
+                return Ok(Mount {
+                    device: device,
+                    mountpoint: mountpoint.to_string(),
+                    fusermount: Some(program),
+                });
+            }
+        }
+        Err(FuseError::MountErr)
+    }
+
+    pub fn unmount(&self) {
+        match self.fusermount {
+            Some(program) => {
+                let _ = Command::new(program).args(["-u", "-z", "--", &self.mountpoint]).status();
+            }
+            None => {
+                let _ = unmount(&self.mountpoint);
+            }
+        }
+    }
+}
+
+/// Unmount the filesystem at mountpoint, which makes VaultFs::mount
+/// return
+pub fn unmount(mountpoint: &str) -> Result<(), FuseError> {
+    let target = try!(CString::new(mountpoint).map_err(|_| FuseError::MountErr));
+    if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } == 0 {
+        return Ok(());
+    }
+    for program in &FUSERMOUNT {
+        let status = Command::new(program).args(["-u", "-z", "--", mountpoint]).status();
+        if status.map(|status| status.success()).unwrap_or(false) {
+            return Ok(());
+        }
+    }
+    Err(FuseError::MountErr)
+}
+
+// fusermount mounts and passes the opened /dev/fuse back over the
+// socket in _FUSE_COMMFD
+fn fusermount(program: &str, mountpoint: &str, options: &str) -> Option<File> {
+    let mut sockets = [0 as libc::c_int; 2];
+    if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, sockets.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let (ours, theirs) = unsafe { (File::from_raw_fd(sockets[0]), File::from_raw_fd(sockets[1])) };
+    let child = Command::new(program)
+                    .args(["-o", options, "--", mountpoint])
+                    .env("_FUSE_COMMFD", theirs.as_raw_fd().to_string())
+                    .spawn();
+    drop(theirs);
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return None,
+    };
+    let device = receive_fd(ours.as_raw_fd());
+    let _ = child.wait();
+    device.map(|fd| unsafe { File::from_raw_fd(fd) })
+}
+
+fn receive_fd(socket: libc::c_int) -> Option<libc::c_int> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: 1,
+    };
+    let mut control = [0u8; 64];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = control.len() as _;
+    if unsafe { libc::recvmsg(socket, &mut message, 0) } <= 0 {
+        return None;
+    }
+    unsafe {
+        let header = libc::CMSG_FIRSTHDR(&message);
+        if header.is_null() || (*header).cmsg_level != libc::SOL_SOCKET || (*header).cmsg_type != libc::SCM_RIGHTS {
+            return None;
+        }
+        Some(ptr::read_unaligned(libc::CMSG_DATA(header) as *const libc::c_int))
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::thread;
+use std::time::Duration;
+
+use fuse::abi::ROOT_ID;
+use fuse::mount::unmount;
+use fuse::vaultfs::VaultFs;
+use kpdb::v1kpdb::V1Kpdb;
+
+fn load(path: &str) -> V1Kpdb {
+    let mut db = V1Kpdb::new(path.to_string(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+fn lookup(fs: &VaultFs, path: &str) -> Option<u64> {
+    let mut ino = ROOT_ID;
+    for name in path.split('/') {
+        ino = fs.lookup(ino, name)?;
+    }
+    Some(ino)
+}
+
+#[test]
+fn test_tree() {
+    let mut db = load("test/test_parsing.kdb");
+    let group = db.groups[0];
+    assert!(db.create_entry(group, "a/b".to_string(), None, None, None, None, None, None).is_ok());
+    let entry = db.create_entry(group,
+                                "a/b".to_string(),
+                                None,
+                                None,
+                                Some("http://foo".to_string()),
+                                None,
+                                Some("bar".to_string()),
+                                Some("foobar".to_string()))
+                  .ok()
+                  .unwrap();
+    db[entry].binary_desc = Some("notes.txt".to_string());
+    db[entry].binary = Some(b"attached".to_vec());
+    let fs = VaultFs::new(db);
+
+    let names: Vec<(String, bool)> = fs.read_dir(lookup(&fs, "Internet").unwrap())
+                                       .unwrap()
+                                       .into_iter()
+                                       .map(|(name, _, is_dir)| (name, is_dir))
+                                       .collect();
+    assert_eq!(names,
+               vec![("12".to_string(), true),
+                    ("11".to_string(), true),
+                    ("test1".to_string(), true),
+                    ("a_b".to_string(), true),
+                    ("a_b (2)".to_string(), true)]);
+    let names: Vec<String> = fs.read_dir(lookup(&fs, "Internet/a_b (2)").unwrap())
+                               .unwrap()
+                               .into_iter()
+                               .map(|(name, _, _)| name)
+                               .collect();
+    assert_eq!(names, vec!["username", "password", "url", "comment", "notes.txt"]);
+    assert_eq!(fs.contents(lookup(&fs, "Internet/a_b (2)/notes.txt").unwrap()),
+               Some(b"attached".to_vec()));
+    assert_eq!(fs.contents(lookup(&fs, "Internet/a_b (2)/password").unwrap()),
+               Some(b"foobar".to_vec()));
+    assert_eq!(fs.contents(lookup(&fs, "Internet/a_b (2)/url").unwrap()),
+               Some(b"http://foo".to_vec()));
+    assert_eq!(fs.contents(lookup(&fs, "Internet/11/21/test4/comment").unwrap()),
+               Some(vec![]));
+    assert!(lookup(&fs, "Internet/test1/notes.txt").is_none());
+    assert!(fs.read_dir(lookup(&fs, "Internet/test1/username").unwrap()).is_none());
+    assert!(fs.contents(lookup(&fs, "Internet/11").unwrap()).is_none());
+}
+
+fn wait_for_mount(path: &str) -> bool {
+    for _ in 0..50 {
+        if fs::metadata(path).is_ok() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    false
+}
+
+#[test]
+fn test_mount() {
+    let path = "test/new_fuse.kdb".to_string();
+    let mut db = load("test/test_parsing.kdb");
+    let group = db.groups[0];
+    assert!(db.create_entry(group,
+                            "mail".to_string(),
+                            None,
+                            None,
+                            None,
+                            None,
+                            Some("bar".to_string()),
+                            Some("foobar".to_string()))
+              .is_ok());
+    assert!(db.save(Some(path.clone()), Some("test".to_string()), None).is_ok());
+    let mountpoint = "test/new_mount";
+    let _ = fs::create_dir(mountpoint);
+
+    let db_path = path.clone();
+    let server = thread::spawn(move || {
+        let mut fs = VaultFs::new(load(&db_path));
+        fs.writable = true;
+        fs.mount(mountpoint)
+    });
+    if !wait_for_mount("test/new_mount/Internet") {
+        // Mounting needs /dev/fuse and permission to mount
+        println!("test_mount skipped, couldn't mount");
+        let _ = unmount(mountpoint);
+        return;
+    }
+
+    let password = "test/new_mount/Internet/mail/password";
+    assert_eq!(fs::read_to_string(password).unwrap(), "foobar");
+    assert_eq!(fs::metadata(password).unwrap().len(), 6);
+    let mut entries: Vec<String> = fs::read_dir("test/new_mount/Internet/11")
+                                       .unwrap()
+                                       .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                                       .collect();
+    entries.sort();
+    assert_eq!(entries, vec!["21", "22", "test2"]);
+    let mut file = fs::OpenOptions::new().write(true).truncate(true).open(password).unwrap();
+    file.write_all(b"changed\n").unwrap();
+    drop(file);
+    assert_eq!(fs::read_to_string(password).unwrap(), "changed");
+    let mut file = fs::OpenOptions::new().write(true).open(password).unwrap();
+    assert!(file.set_len(1 << 40).is_err());
+    assert!(file.seek(SeekFrom::Start(1 << 40)).is_ok());
+    assert!(file.write_all(b"x").is_err());
+    drop(file);
+    assert_eq!(fs::read_to_string(password).unwrap(), "changed");
+    assert!(fs::File::create("test/new_mount/Internet/new").is_err());
+
+    assert!(unmount(mountpoint).is_ok());
+    assert!(server.join().unwrap().is_ok());
+    let mut db = load(&path);
+    let entry = *db.entries.iter().find(|&&entry| db[entry].title == "mail").unwrap();
+    let entry = &mut db[entry];
+    let password = entry.password.as_mut().unwrap();
+    password.unlock();
+    assert_eq!(password.string, "changed");
+    let _ = fs::remove_dir(mountpoint);
+}
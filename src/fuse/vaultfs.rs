@@ -0,0 +1,616 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use chrono::{DateTime, Local};
+use libc;
+
+use common::common::write_array_volatile;
+use fuse::abi::*;
+use fuse::fuse_error::FuseError;
+use fuse::mount::Mount;
+use kpdb::v1entry::EntryId;
+use kpdb::v1group::GroupId;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+// How long the kernel may remember names and attributes, in seconds
+const ATTR_VALID: u64 = 1;
+
+// Largest file that can be written. Open files reserve this much up
+// front, so their contents are never reallocated and left behind.
+const MAX_FILE_SIZE: usize = 16 << 20;
+
+const DT_DIR: u32 = 4;
+const DT_REG: u32 = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+    Username,
+    Password,
+    Url,
+    Comment,
+    Attachment,
+}
+
+enum NodeKind {
+    Group(GroupId),
+    Entry(EntryId),
+    File(EntryId, Field),
+}
+
+struct Node {
+    parent: u64,
+    kind: NodeKind,
+    // Names and inodes
+    children: Vec<(String, u64)>,
+}
+
+// An open file
+struct Handle {
+    ino: u64,
+    // The new contents if the file is opened for writing
+    data: Option<Vec<u8>>,
+    dirty: bool,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Some(ref mut data) = self.data {
+            wipe(data);
+        }
+    }
+}
+
+#[doc = "
+Shows a V1Kpdb as a directory tree through FUSE. Groups are
+directories, and so are entries, containing the files username,
+password, url and comment and their attachment named after its
+description. Names are made unique by appending \" (2)\" and so on,
+slashes in titles become underscores.
+
+The view is read-only unless writable is set. Then the files (but not
+the tree) can be changed, and the database is saved every time a
+changed file is closed. A single trailing newline is dropped from the
+username, password and url written. Files are opened with direct I/O,
+so their contents never enter the page cache.
+"]
+pub struct VaultFs {
+    /// Allow changing the files and save the database when they are
+    /// closed (false by default)
+    pub writable: bool,
+    /// Owner of all files, the user mounting by default
+    pub uid: u32,
+    /// Group of all files, the group of the user mounting by default
+    pub gid: u32,
+    db: V1Kpdb,
+    // Node of inode i at i - 1
+    nodes: Vec<Node>,
+    handles: HashMap<u64, Handle>,
+    next_handle: u64,
+}
+
+impl VaultFs {
+    /// Create a view of a loaded database
+    pub fn new(db: V1Kpdb) -> VaultFs {
+        let root = db.root_group;
+        let mut fs = VaultFs {
+            writable: false,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            db: db,
+            nodes: vec![Node {
+                            parent: ROOT_ID,
+                            kind: NodeKind::Group(root),
+                            children: vec![],
+                        }],
+            handles: HashMap::new(),
+            next_handle: 1,
+        };
+        fs.add_group(ROOT_ID, root);
+        fs
+    }
+
+    /// Mount at mountpoint and answer the kernel until the filesystem
+    /// is unmounted again, e.g. with umount, fusermount -u or
+    /// fuse::mount::unmount
+    pub fn mount(&mut self, mountpoint: &str) -> Result<(), FuseError> {
+        let mount = try!(Mount::new(mountpoint, self.writable));
+        let fd = mount.device.as_raw_fd();
+        let mut buffer = vec![0u8; BUFFER_LEN];
+        loop {
+            let len = unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            if len < 0 {
+                match io::Error::last_os_error().raw_os_error() {
+                    Some(libc::ENOENT) | Some(libc::EINTR) | Some(libc::EAGAIN) => continue,
+                    // Unmounted
+                    Some(libc::ENODEV) => return Ok(()),
+                    _ => {
+                        mount.unmount();
+                        return Err(FuseError::IoErr);
+                    }
+                }
+            }
+            let len = len as usize;
+            let response = self.answer(&buffer[..len]);
+            // Written contents pass through the buffer
+            unsafe { write_array_volatile(buffer.as_mut_ptr(), 0u8, len) };
+            if let Some(mut response) = response {
+                // Fails if the request was interrupted, which is fine
+                unsafe { libc::write(fd, response.as_ptr() as *const libc::c_void, response.len()) };
+                wipe(&mut response);
+            }
+        }
+    }
+
+    fn add_node(&mut self, parent: u64, title: &str, kind: NodeKind) -> u64 {
+        let base = match title.replace(['/', '\0'], "_") {
+            ref name if name.is_empty() || name == "." || name == ".." => format!("_{}", name),
+            name => name,
+        };
+        let mut name = base.clone();
+        let mut count = 2;
+        while self.nodes[parent as usize - 1].children.iter().any(|child| child.0 == name) {
+            name = format!("{} ({})", base, count);
+            count += 1;
+        }
+        self.nodes.push(Node {
+            parent: parent,
+            kind: kind,
+            children: vec![],
+        });
+        let ino = self.nodes.len() as u64;
+        self.nodes[parent as usize - 1].children.push((name, ino));
+        ino
+    }
+
+    fn add_group(&mut self, ino: u64, group: GroupId) {
+        for child in self.db[group].children.clone() {
+            let title = self.db[child].title.clone();
+            let child_ino = self.add_node(ino, &title, NodeKind::Group(child));
+            self.add_group(child_ino, child);
+        }
+        for entry in self.db[group].entries.clone() {
+            let title = self.db[entry].title.clone();
+            let entry_ino = self.add_node(ino, &title, NodeKind::Entry(entry));
+            for &(name, field) in &[("username", Field::Username),
+                                    ("password", Field::Password),
+                                    ("url", Field::Url),
+                                    ("comment", Field::Comment)] {
+                self.add_node(entry_ino, name, NodeKind::File(entry, field));
+            }
+            if self.db[entry].binary.is_some() {
+                let name = self.db[entry].binary_desc.clone().unwrap_or_default();
+                let name = if name.is_empty() { "attachment".to_string() } else { name };
+                self.add_node(entry_ino, &name, NodeKind::File(entry, Field::Attachment));
+            }
+        }
+    }
+
+    fn node(&self, ino: u64) -> Option<&Node> {
+        if ino == 0 {
+            return None;
+        }
+        self.nodes.get(ino as usize - 1)
+    }
+
+    // Inode of name in the directory parent
+    pub(crate) fn lookup(&self, parent: u64, name: &str) -> Option<u64> {
+        self.node(parent)
+            .and_then(|node| node.children.iter().find(|child| child.0 == name))
+            .map(|child| child.1)
+    }
+
+    fn is_dir(&self, ino: u64) -> bool {
+        !matches!(self.nodes[ino as usize - 1].kind, NodeKind::File(..))
+    }
+
+    // Names, inodes and true for directories of the children of ino
+    pub(crate) fn read_dir(&self, ino: u64) -> Option<Vec<(String, u64, bool)>> {
+        let node = self.node(ino)?;
+        if let NodeKind::File(..) = node.kind {
+            return None;
+        }
+        Some(node.children
+                 .iter()
+                 .map(|&(ref name, child)| (name.clone(), child, self.is_dir(child)))
+                 .collect())
+    }
+
+    // Sensitive data in this function:
+    //
+    // * the username and password of the entry
+    //
+    // At the end of this function:
+    //
+    // * the copy is moved to the caller, which has to wipe it
+    pub(crate) fn contents(&self, ino: u64) -> Option<Vec<u8>> {
+        let (entry, field) = match self.node(ino).map(|node| &node.kind) {
+            Some(&NodeKind::File(entry, field)) => (&self.db[entry], field),
+            _ => return None,
+        };
+        Some(match field {
+            Field::Username => reveal(&entry.username),
+            Field::Password => reveal(&entry.password),
+            Field::Url => entry.url.clone().unwrap_or_default().into_bytes(),
+            Field::Comment => entry.comment.clone().unwrap_or_default().into_bytes(),
+            Field::Attachment => entry.binary.clone().unwrap_or_default(),
+        })
+    }
+
+    // Sensitive data in this function:
+    //
+    // * the new contents
+    //
+    // At the end of this function:
+    //
+    // * the contents are moved into the entry, as SecureString for
+    //   username and password
+    pub(crate) fn set_contents(&mut self, ino: u64, mut data: Vec<u8>) -> Result<(), i32> {
+        let (entry, field) = match self.node(ino).map(|node| &node.kind) {
+            Some(&NodeKind::File(entry, field)) => (entry, field),
+            _ => return Err(libc::EISDIR),
+        };
+        if field != Field::Comment && field != Field::Attachment && data.last() == Some(&b'\n') {
+            data.pop();
+        }
+        {
+            let entry = &mut self.db[entry];
+            if field == Field::Attachment {
+                entry.binary = Some(data);
+            } else {
+                let text = match String::from_utf8(data) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        wipe(&mut e.into_bytes());
+                        return Err(libc::EINVAL);
+                    }
+                };
+                match field {
+                    Field::Username => entry.username = Some(SecureString::new(text)),
+                    Field::Password => entry.password = Some(SecureString::new(text)),
+                    Field::Url => entry.url = Some(text),
+                    _ => entry.comment = Some(text),
+                }
+            }
+            entry.last_mod = Local::now();
+        }
+        self.db.save(None, None, None).map_err(|_| libc::EIO)
+    }
+
+    fn attr(&self, ino: u64) -> Option<Attr> {
+        let node = self.node(ino)?;
+        let (size, mode, times) = match node.kind {
+            NodeKind::Group(group) => {
+                let group = &self.db[group];
+                (0, libc::S_IFDIR | 0o500, (group.creation, group.last_mod, group.last_access))
+            }
+            NodeKind::Entry(entry) => {
+                let entry = &self.db[entry];
+                (0, libc::S_IFDIR | 0o500, (entry.creation, entry.last_mod, entry.last_access))
+            }
+            NodeKind::File(entry, _) => {
+                let mut contents = self.contents(ino).unwrap_or_default();
+                let size = contents.len() as u64;
+                wipe(&mut contents);
+                let mode = if self.writable { 0o600 } else { 0o400 };
+                let entry = &self.db[entry];
+                (size, libc::S_IFREG | mode, (entry.creation, entry.last_mod, entry.last_access))
+            }
+        };
+        let subdirs = node.children
+                          .iter()
+                          .filter(|&&(_, child)| self.is_dir(child))
+                          .count();
+        Some(Attr {
+            ino: ino,
+            size: size,
+            ctime: seconds(times.0),
+            mtime: seconds(times.1),
+            atime: seconds(times.2),
+            mode: mode,
+            nlink: if mode & libc::S_IFDIR != 0 { 2 + subdirs as u32 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+        })
+    }
+
+    // The reply to a request from the kernel, None for requests
+    // without reply
+    fn answer(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let mut reader = Reader::new(request);
+        let header = match reader.header() {
+            Ok(header) => header,
+            Err(_) => return None,
+        };
+        let result = match header.opcode {
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return None,
+            FUSE_INIT => self.init(&mut reader),
+            FUSE_DESTROY | FUSE_ACCESS | FUSE_RELEASEDIR => Ok(vec![]),
+            FUSE_LOOKUP => self.lookup_request(header.nodeid, &mut reader),
+            FUSE_GETATTR => self.getattr(header.nodeid),
+            FUSE_SETATTR => self.setattr(header.nodeid, &mut reader),
+            FUSE_OPENDIR => self.opendir(header.nodeid),
+            FUSE_READDIR => self.readdir(header.nodeid, &mut reader),
+            FUSE_OPEN => self.open(header.nodeid, &mut reader),
+            FUSE_READ => self.read(header.nodeid, &mut reader),
+            FUSE_WRITE => self.write(&mut reader),
+            FUSE_FLUSH | FUSE_FSYNC => {
+                let fh = reader.u64().unwrap_or(0);
+                self.commit(fh).map(|_| vec![])
+            }
+            FUSE_RELEASE => {
+                let fh = reader.u64().unwrap_or(0);
+                let result = self.commit(fh);
+                self.handles.remove(&fh);
+                result.map(|_| vec![])
+            }
+            FUSE_STATFS => Ok(statfs()),
+            _ => Err(libc::ENOSYS),
+        };
+        Some(match result {
+            Ok(mut payload) => {
+                let response = reply(header.unique, 0, &payload);
+                wipe(&mut payload);
+                response
+            }
+            Err(errno) => reply(header.unique, errno, &[]),
+        })
+    }
+
+    fn init(&mut self, reader: &mut Reader) -> Result<Vec<u8>, i32> {
+        let major = try!(reader.u32().map_err(|_| libc::EIO));
+        if major != KERNEL_VERSION {
+            return Err(libc::EPROTO);
+        }
+        let mut out = vec![];
+        put_u32(&mut out, KERNEL_VERSION);
+        put_u32(&mut out, KERNEL_MINOR_VERSION);
+        // max_readahead and flags
+        put_u32(&mut out, 0);
+        put_u32(&mut out, 0);
+        // max_background and congestion_threshold
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u32(&mut out, MAX_WRITE);
+        // time_gran, max_pages, map_alignment, flags2 and unused
+        put_u32(&mut out, 1);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        for _ in 0..8 {
+            put_u32(&mut out, 0);
+        }
+        Ok(out)
+    }
+
+    fn lookup_request(&self, parent: u64, reader: &mut Reader) -> Result<Vec<u8>, i32> {
+        let name = try!(reader.name().map_err(|_| libc::EINVAL));
+        let ino = try!(self.lookup(parent, name).ok_or(libc::ENOENT));
+        let attr = try!(self.attr(ino).ok_or(libc::ENOENT));
+        let mut out = vec![];
+        put_entry(&mut out, &attr, ATTR_VALID);
+        Ok(out)
+    }
+
+    fn getattr(&self, ino: u64) -> Result<Vec<u8>, i32> {
+        let attr = try!(self.attr(ino).ok_or(libc::ENOENT));
+        let mut out = vec![];
+        put_attr_out(&mut out, &attr, ATTR_VALID);
+        Ok(out)
+    }
+
+    // Only truncating is supported, times and modes stay as they are
+    fn setattr(&mut self, ino: u64, reader: &mut Reader) -> Result<Vec<u8>, i32> {
+        let valid = try!(reader.u32().map_err(|_| libc::EINVAL));
+        let _padding = try!(reader.u32().map_err(|_| libc::EINVAL));
+        let fh = try!(reader.u64().map_err(|_| libc::EINVAL));
+        let size = try!(reader.u64().map_err(|_| libc::EINVAL));
+        if valid & FATTR_SIZE != 0 {
+            if !self.writable {
+                return Err(libc::EROFS);
+            }
+            if size > MAX_FILE_SIZE as u64 {
+                return Err(libc::EFBIG);
+            }
+            let size = size as usize;
+            let open = self.handles.get_mut(&fh).is_some_and(|handle| handle.ino == ino && handle.data.is_some());
+            if open {
+                let handle = self.handles.get_mut(&fh).unwrap();
+                set_len(handle.data.as_mut().unwrap(), size);
+                handle.dirty = true;
+            } else {
+                let mut data = try!(reserved(try!(self.contents(ino).ok_or(libc::EISDIR))));
+                set_len(&mut data, size);
+                try!(self.set_contents(ino, data));
+            }
+        }
+        self.getattr(ino)
+    }
+
+    fn opendir(&self, ino: u64) -> Result<Vec<u8>, i32> {
+        try!(self.read_dir(ino).ok_or(libc::ENOTDIR));
+        let mut out = vec![];
+        put_u64(&mut out, 0);
+        put_u32(&mut out, 0);
+        put_u32(&mut out, 0);
+        Ok(out)
+    }
+
+    fn readdir(&self, ino: u64, reader: &mut Reader) -> Result<Vec<u8>, i32> {
+        let _fh = try!(reader.u64().map_err(|_| libc::EINVAL));
+        let offset = try!(reader.u64().map_err(|_| libc::EINVAL)) as usize;
+        let size = try!(reader.u32().map_err(|_| libc::EINVAL)) as usize;
+        let mut children = try!(self.read_dir(ino).ok_or(libc::ENOTDIR));
+        let parent = self.node(ino).map(|node| node.parent).unwrap_or(ROOT_ID);
+        children.insert(0, ("..".to_string(), parent, true));
+        children.insert(0, (".".to_string(), ino, true));
+        let mut out = vec![];
+        for (index, &(ref name, child, is_dir)) in children.iter().enumerate().skip(offset) {
+            if out.len() + dirent_len(name) > size {
+                break;
+            }
+            put_dirent(&mut out, child, index as u64 + 1, if is_dir { DT_DIR } else { DT_REG }, name);
+        }
+        Ok(out)
+    }
+
+    fn open(&mut self, ino: u64, reader: &mut Reader) -> Result<Vec<u8>, i32> {
+        let flags = try!(reader.u32().map_err(|_| libc::EINVAL)) as i32;
+        try!(self.contents(ino).map(|mut contents| wipe(&mut contents)).ok_or(libc::EISDIR));
+        let write = flags & libc::O_ACCMODE != libc::O_RDONLY;
+        if write && !self.writable {
+            return Err(libc::EROFS);
+        }
+        let data = if !write {
+            None
+        } else if flags & libc::O_TRUNC != 0 {
+            Some(try!(reserved(vec![])))
+        } else {
+            Some(try!(reserved(try!(self.contents(ino).ok_or(libc::EISDIR)))))
+        };
+        let fh = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(fh,
+                            Handle {
+                                ino: ino,
+                                dirty: flags & libc::O_TRUNC != 0 && write,
+                                data: data,
+                            });
+        let mut out = vec![];
+        put_u64(&mut out, fh);
+        put_u32(&mut out, FOPEN_DIRECT_IO);
+        put_u32(&mut out, 0);
+        Ok(out)
+    }
+
+    // Sensitive data in this function:
+    //
+    // * the contents of the file
+    //
+    // At the end of this function:
+    //
+    // * the contents are overwritten unless they belong to an open
+    //   file, the part read is moved to the caller, which wipes it
+    //   after replying
+    fn read(&self, ino: u64, reader: &mut Reader) -> Result<Vec<u8>, i32> {
+        let fh = try!(reader.u64().map_err(|_| libc::EINVAL));
+        let offset = try!(reader.u64().map_err(|_| libc::EINVAL));
+        let size = try!(reader.u32().map_err(|_| libc::EINVAL)) as u64;
+        let part = |data: &[u8]| {
+            let start = offset.min(data.len() as u64) as usize;
+            let end = offset.saturating_add(size).min(data.len() as u64) as usize;
+            data[start..end].to_vec()
+        };
+        match self.handles.get(&fh).and_then(|handle| handle.data.as_ref()) {
+            Some(data) => Ok(part(data)),
+            None => {
+                let mut contents = try!(self.contents(ino).ok_or(libc::EISDIR));
+                let out = part(&contents);
+                wipe(&mut contents);
+                Ok(out)
+            }
+        }
+    }
+
+    fn write(&mut self, reader: &mut Reader) -> Result<Vec<u8>, i32> {
+        let fh = try!(reader.u64().map_err(|_| libc::EINVAL));
+        let offset = try!(reader.u64().map_err(|_| libc::EINVAL));
+        let size = try!(reader.u32().map_err(|_| libc::EINVAL)) as usize;
+        // write_flags, lock_owner, flags and padding
+        try!(reader.bytes(20).map_err(|_| libc::EINVAL));
+        let bytes = try!(reader.bytes(size).map_err(|_| libc::EINVAL));
+        let handle = try!(self.handles.get_mut(&fh).ok_or(libc::EBADF));
+        let data = try!(handle.data.as_mut().ok_or(libc::EBADF));
+        let end = try!(offset.checked_add(size as u64).ok_or(libc::EINVAL));
+        if end > MAX_FILE_SIZE as u64 {
+            return Err(libc::EFBIG);
+        }
+        let (offset, end) = (offset as usize, end as usize);
+        if data.len() < end {
+            set_len(data, end);
+        }
+        data[offset..end].copy_from_slice(bytes);
+        handle.dirty = true;
+        let mut out = vec![];
+        put_u32(&mut out, size as u32);
+        put_u32(&mut out, 0);
+        Ok(out)
+    }
+
+    // Save the changes to an open file, which stays open for writing
+    // with the contents saved
+    fn commit(&mut self, fh: u64) -> Result<(), i32> {
+        let (ino, data) = match self.handles.get_mut(&fh) {
+            Some(handle) if handle.dirty => {
+                handle.dirty = false;
+                (handle.ino, handle.data.take().unwrap_or_default())
+            }
+            _ => return Ok(()),
+        };
+        let result = self.set_contents(ino, data);
+        let data = self.contents(ino).and_then(|contents| reserved(contents).ok());
+        if let Some(handle) = self.handles.get_mut(&fh) {
+            handle.data = data;
+        }
+        result
+    }
+}
+
+fn statfs() -> Vec<u8> {
+    let mut out = vec![];
+    // blocks, bfree, bavail, files and ffree
+    for _ in 0..5 {
+        put_u64(&mut out, 0);
+    }
+    put_u32(&mut out, 4096);
+    put_u32(&mut out, 255);
+    put_u32(&mut out, 4096);
+    // padding and spare
+    for _ in 0..7 {
+        put_u32(&mut out, 0);
+    }
+    out
+}
+
+fn seconds(time: DateTime<Local>) -> u64 {
+    time.timestamp().max(0) as u64
+}
+
+fn wipe(data: &mut Vec<u8>) {
+    unsafe { write_array_volatile(data.as_mut_ptr(), 0u8, data.len()) };
+}
+
+// Sensitive data in this function:
+//
+// * the contents of a file
+//
+// At the end of this function:
+//
+// * the contents are moved into a buffer reserved for MAX_FILE_SIZE,
+//   the old buffer is overwritten
+fn reserved(mut contents: Vec<u8>) -> Result<Vec<u8>, i32> {
+    if contents.len() > MAX_FILE_SIZE {
+        wipe(&mut contents);
+        return Err(libc::EFBIG);
+    }
+    let mut data = Vec::with_capacity(MAX_FILE_SIZE);
+    data.extend_from_slice(&contents);
+    wipe(&mut contents);
+    Ok(data)
+}
+
+// Resize contents within their reserved buffer, overwriting what is cut
+// off
+fn set_len(data: &mut Vec<u8>, size: usize) {
+    if size < data.len() {
+        unsafe { write_array_volatile(data.as_mut_ptr().add(size), 0u8, data.len() - size) };
+    }
+    data.resize(size, 0);
+}
+
+fn reveal(string: &Option<SecureString>) -> Vec<u8> {
+    match *string {
+        Some(ref string) => string.with_unlocked(|string| string.as_bytes().to_vec()).unwrap_or_default(),
+        None => vec![],
+    }
+}
@@ -0,0 +1,674 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::rc::Rc;
+
+use chrono::{DateTime, Local, TimeZone};
+use rand;
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use uuid::Uuid;
+
+use kdbx::v2entry::{V2Binary, V2BinaryRef, V2Entry, V2Value};
+use kdbx::v2error::V2KdbxError;
+use kdbx::v2group::V2Group;
+use kdbx::v2header::{V2Cipher, V2Kdf};
+use kdbx::v2kdbx::V2Kdbx;
+use kdbx::xmldom::XmlElement;
+use kpdb::v1entry::V1Entry;
+use kpdb::v1group::{GroupId, V1Group};
+use kpdb::v1header::V1Cipher;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+// KeePass 1.x groups are identified by a number. The UUID of a
+// converted group is this prefix followed by the id (big endian),
+// so that the id survives a conversion back.
+const V1_GROUP_UUID_PREFIX: [u8; 12] = [0x6b, 0x70, 0x64, 0x62, 0x2d, 0x67, 0x72, 0x6f, 0x75, 0x70,
+                                        0x2d, 0x69];
+
+// Meta-Info entries of KeePass 1.x databases (e.g. UI state or custom
+// icons of KeePass 1.x) are kept in the custom data of the meta data
+// under this prefix and the entry UUID as
+// "<group id>:<image>:<creation>:<last mod>:<last access>:<expire>:<base64 data>:<name>",
+// the timestamps in seconds since the epoch. The other fields of a
+// Meta-Info entry are always the same.
+const META_STREAM_PREFIX: &'static str = "KeePass1.MetaStream.";
+
+#[doc = "
+The file formats of KeePass databases
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum DatabaseFormat {
+    /// KeePass 1.x database (.kdb), see V1Kpdb
+    Kdb,
+    /// KeePass 2.x database (.kdbx), see V2Kdbx
+    Kdbx,
+}
+
+/// Find out the format of a database file by its signatures
+pub fn detect_format(path: &str) -> Result<DatabaseFormat, V2KdbxError> {
+    let mut file = try!(File::open(path).map_err(|_| V2KdbxError::FileErr));
+    let mut signatures = [0u8; 8];
+    try!(file.read_exact(&mut signatures).map_err(|_| V2KdbxError::FileErr));
+    if signatures[..4] != [0x03, 0xD9, 0xA2, 0x9A] {
+        return Err(V2KdbxError::SignatureErr);
+    }
+    match &signatures[4..] {
+        [0x65, 0xFB, 0x4B, 0xB5] => Ok(DatabaseFormat::Kdb),
+        [0x67, 0xFB, 0x4B, 0xB5] => Ok(DatabaseFormat::Kdbx),
+        _ => Err(V2KdbxError::SignatureErr),
+    }
+}
+
+#[doc = "
+A field of a group or entry (or of the database itself) which
+couldn't be represented in the target format of a conversion
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionIssue {
+    /// Path of the group or entry, e.g. Internet/foo. Empty for
+    /// the database itself
+    pub path: String,
+    /// Description of the dropped or changed field
+    pub field: String,
+}
+
+impl fmt::Display for ConversionIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.field)
+        } else {
+            write!(f, "{}: {}", self.path, self.field)
+        }
+    }
+}
+
+#[doc = "
+Lists everything which got lost or changed during a conversion
+between KeePass 1.x and 2.x databases
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionReport {
+    /// The fields which couldn't be converted
+    pub issues: Vec<ConversionIssue>,
+}
+
+impl ConversionReport {
+    fn new() -> ConversionReport {
+        ConversionReport { issues: vec![] }
+    }
+
+    fn add(&mut self, path: &str, field: String) {
+        self.issues.push(ConversionIssue {
+            path: path.to_string(),
+            field: field,
+        });
+    }
+
+    /// True if nothing got lost during the conversion
+    pub fn is_lossless(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for ConversionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in self.issues.iter() {
+            try!(writeln!(f, "{}", issue));
+        }
+        Ok(())
+    }
+}
+
+fn never_expires() -> DateTime<Local> {
+    Local.ymd(2999, 12, 28).and_hms(23, 59, 59)
+}
+
+fn join_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+fn group_uuid(id: u32) -> Uuid {
+    let mut bytes = V1_GROUP_UUID_PREFIX.to_vec();
+    bytes.extend_from_slice(&[(id >> 24) as u8, (id >> 16) as u8, (id >> 8) as u8, id as u8]);
+    Uuid::from_bytes(&bytes).unwrap()
+}
+
+fn group_id(uuid: &Uuid) -> Option<u32> {
+    let bytes = uuid.as_bytes();
+    if bytes[..12] != V1_GROUP_UUID_PREFIX {
+        return None;
+    }
+    Some((bytes[12] as u32) << 24 | (bytes[13] as u32) << 16 | (bytes[14] as u32) << 8 |
+         bytes[15] as u32)
+}
+
+// Sensitive data in this function:
+//
+// * secure string (decrypted and copied)
+//
+// At the end of this function:
+//
+// * the decrypted string is overwritten again, the copy is moved
+//   into the caller which has to move it into a SecureString again
+fn copy_secure_string(string: &SecureString) -> String {
+    string.with_unlocked(|string| string.to_string()).unwrap_or_default()
+}
+
+// Same as copy_secure_string for a string field of a KeePass 2.x entry
+fn copy_value(value: &V2Value) -> String {
+    match *value {
+        V2Value::Plain(ref string) => string.clone(),
+        V2Value::Protected(ref string) => copy_secure_string(string),
+    }
+}
+
+fn is_meta_stream(entry: &V1Entry) -> bool {
+    if entry.title != "Meta-Info" || entry.url != Some("$".to_string()) ||
+       entry.binary_desc != Some("bin-stream".to_string()) || entry.comment.is_none() {
+        return false;
+    }
+    let password_is_empty = match entry.password {
+        Some(ref password) => password.with_unlocked(|password| password.is_empty()).unwrap_or(false),
+        None => false,
+    };
+    match entry.username {
+        Some(ref username) => password_is_empty && username.with_unlocked(|username| username == "SYSTEM").unwrap_or(false),
+        None => false,
+    }
+}
+
+fn meta_stream_value(entry: &V1Entry) -> String {
+    format!("{}:{}:{}:{}:{}:{}:{}:{}",
+            entry.group_id,
+            entry.image,
+            entry.creation.timestamp(),
+            entry.last_mod.timestamp(),
+            entry.last_access.timestamp(),
+            entry.expire.timestamp(),
+            entry.binary.as_ref().map(|b| b.to_base64(STANDARD)).unwrap_or_default(),
+            entry.comment.as_ref().unwrap())
+}
+
+// The inverse of meta_stream_value, the entry isn't in a group yet
+fn meta_stream_entry(uuid: &str, value: &str) -> Result<V1Entry, V2KdbxError> {
+    let fields: Vec<&str> = value.splitn(8, ':').collect();
+    if fields.len() != 8 {
+        return Err(V2KdbxError::ConvertErr);
+    }
+    let number = |field: &str| field.parse::<i64>().map_err(|_| V2KdbxError::ConvertErr);
+    let time = |field: &str| {
+        number(field).and_then(|seconds| Local.timestamp_opt(seconds, 0).single().ok_or(V2KdbxError::ConvertErr))
+    };
+
+    let mut entry = V1Entry::new();
+    entry.uuid = try!(Uuid::parse_str(uuid).map_err(|_| V2KdbxError::ConvertErr));
+    entry.group_id = try!(fields[0].parse().map_err(|_| V2KdbxError::ConvertErr));
+    entry.image = try!(fields[1].parse().map_err(|_| V2KdbxError::ConvertErr));
+    entry.creation = try!(time(fields[2]));
+    entry.last_mod = try!(time(fields[3]));
+    entry.last_access = try!(time(fields[4]));
+    entry.expire = try!(time(fields[5]));
+    entry.binary = Some(try!(fields[6].from_base64().map_err(|_| V2KdbxError::ConvertErr)));
+    entry.comment = Some(fields[7].to_string());
+    entry.title = "Meta-Info".to_string();
+    entry.url = Some("$".to_string());
+    entry.username = Some(SecureString::new("SYSTEM".to_string()));
+    entry.password = Some(SecureString::new("".to_string()));
+    entry.binary_desc = Some("bin-stream".to_string());
+    Ok(entry)
+}
+
+/// Convert a loaded KeePass 1.x database into a KeePass 2.x database.
+/// UUIDs, the group tree, timestamps, icons, expiration dates,
+/// attachments and the Meta-Info entries are kept. Cipher and key
+/// transformation rounds are taken over from the header.
+///
+/// * db: the loaded KeePass 1.x database
+///
+/// * path, password, keyfile: see V2Kdbx::new. Save the returned
+///   database to write it to disk.
+///
+/// Returns the new database and a report of everything which
+/// couldn't be converted.
+pub fn v1_to_v2(db: &V1Kpdb,
+                path: String,
+                password: Option<String>,
+                keyfile: Option<String>)
+                -> Result<(V2Kdbx, ConversionReport), V2KdbxError> {
+    let mut kdbx = try!(V2Kdbx::new(path, password, keyfile));
+    let mut report = ConversionReport::new();
+
+    kdbx.header.set_cipher(match try!(db.header.cipher()) {
+        V1Cipher::Aes => V2Cipher::Aes,
+        V1Cipher::Twofish => V2Cipher::Twofish,
+    });
+    kdbx.header.kdf = V2Kdf::Aes {
+        seed: (0..32).map(|_| rand::random::<u8>()).collect(),
+        rounds: db.header.key_transf_rounds as u64,
+    };
+
+    // db.groups is sorted so that parents come before their children
+    let mut converted: Vec<(u32, Rc<RefCell<V2Group>>, String)> = vec![];
+    for &group in db.groups.iter() {
+        let group = &db[group];
+        let parent_id = group.parent.map(|p| db[p].id);
+        let (parent, parent_path) = match converted.iter().find(|c| Some(c.0) == parent_id) {
+            Some(&(_, ref parent, ref parent_path)) => (parent.clone(), parent_path.clone()),
+            None => (kdbx.root_group.clone(), "".to_string()),
+        };
+        let path = join_path(&parent_path, &group.title);
+
+        let new_group = kdbx.create_group(group.title.clone(), Some(parent));
+        {
+            let mut new_group = new_group.borrow_mut();
+            new_group.uuid = group_uuid(group.id);
+            new_group.icon_id = group.image;
+            new_group.times.creation = group.creation;
+            new_group.times.last_mod = group.last_mod;
+            new_group.times.last_access = group.last_access;
+            new_group.times.location_changed = group.last_mod;
+            new_group.times.expire = group.expire;
+            new_group.times.expires = group.expire != never_expires();
+        }
+        if group.flags != 0 {
+            report.add(&path, format!("Flags ({:#x})", group.flags));
+        }
+        converted.push((group.id, new_group, path));
+    }
+
+    let protection = kdbx.meta.memory_protection.clone();
+    for &entry in db.entries.iter() {
+        let entry = &db[entry];
+        if is_meta_stream(entry) {
+            kdbx.meta.custom_data.push((format!("{}{}", META_STREAM_PREFIX, entry.uuid.simple()),
+                                        meta_stream_value(entry)));
+            continue;
+        }
+
+        let group = match converted.iter().find(|c| c.0 == entry.group_id) {
+            Some(&(_, ref group, _)) => group.clone(),
+            None => {
+                report.add(&entry.title,
+                           format!("Group {} (doesn't exist, moved into the root group)", entry.group_id));
+                kdbx.root_group.clone()
+            }
+        };
+
+        let mut new_entry = V2Entry::new();
+        new_entry.uuid = entry.uuid;
+        new_entry.icon_id = entry.image;
+        new_entry.times.creation = entry.creation;
+        new_entry.times.last_mod = entry.last_mod;
+        new_entry.times.last_access = entry.last_access;
+        new_entry.times.location_changed = entry.last_mod;
+        new_entry.times.expire = entry.expire;
+        new_entry.times.expires = entry.expire != never_expires();
+
+        // A missing field of a KeePass 1.x entry means that the
+        // string field doesn't exist at all
+        new_entry.set_string("Title", entry.title.clone(), protection.protect_title);
+        if let Some(username) = entry.username.as_ref().map(copy_secure_string) {
+            new_entry.set_string("UserName", username, protection.protect_username);
+        }
+        if let Some(password) = entry.password.as_ref().map(copy_secure_string) {
+            new_entry.set_string("Password", password, protection.protect_password);
+        }
+        if let Some(ref url) = entry.url {
+            new_entry.set_string("URL", url.clone(), protection.protect_url);
+        }
+        if let Some(ref comment) = entry.comment {
+            new_entry.set_string("Notes", comment.clone(), protection.protect_notes);
+        }
+
+        let binary = entry.binary.clone().unwrap_or_default();
+        let binary_desc = entry.binary_desc.clone().unwrap_or_default();
+        if !binary.is_empty() || !binary_desc.is_empty() {
+            kdbx.binaries.push(V2Binary {
+                data: binary,
+                protected: false,
+            });
+            new_entry.binaries.push(V2BinaryRef {
+                key: binary_desc,
+                id: kdbx.binaries.len() - 1,
+            });
+        }
+
+        group.borrow_mut().entries.push(Rc::new(RefCell::new(new_entry)));
+    }
+
+    Ok((kdbx, report))
+}
+
+/// Convert a loaded KeePass 2.x database into a KeePass 1.x database.
+/// Groups and entries keep their UUIDs (groups created by v1_to_v2
+/// also their ids), the group tree, timestamps, icons, expiration dates,
+/// the first attachment and the Meta-Info entries. KeePass 1.x can't
+/// store custom string fields, further attachments, history, tags,
+/// colors, auto-type settings, when an item was moved the last time
+/// and most of the meta data. These are dropped and listed in the
+/// report. ChaCha20 and Argon2 are replaced by AES and AES-KDF.
+///
+/// * db: the loaded KeePass 2.x database
+///
+/// * path, password, keyfile: see V1Kpdb::new. Save the returned
+///   database to write it to disk.
+///
+/// Returns the new database and a report of everything which
+/// couldn't be converted.
+pub fn v2_to_v1(db: &V2Kdbx,
+                path: String,
+                password: Option<String>,
+                keyfile: Option<String>)
+                -> Result<(V1Kpdb, ConversionReport), V2KdbxError> {
+    let mut kpdb = try!(V1Kpdb::new(path, password, keyfile));
+    let mut report = ConversionReport::new();
+
+    kpdb.header.signature1 = 0x9AA2D903u32;
+    kpdb.header.signature2 = 0xB54BFB65u32;
+    kpdb.header.version = 0x00030002u32;
+    // SHA-2 flag plus the cipher
+    kpdb.header.enc_flag = 1;
+    kpdb.header.set_cipher(match try!(db.header.cipher()) {
+        V2Cipher::Aes => V1Cipher::Aes,
+        V2Cipher::Twofish => V1Cipher::Twofish,
+        V2Cipher::ChaCha20 => {
+            report.add("", "Cipher ChaCha20 (replaced by AES)".to_string());
+            V1Cipher::Aes
+        }
+    });
+    kpdb.header.transf_randomseed = (0..32).map(|_| rand::random::<u8>()).collect();
+    kpdb.header.key_transf_rounds = match db.header.kdf {
+        V2Kdf::Aes { rounds, .. } if rounds <= u32::max_value() as u64 => rounds as u32,
+        V2Kdf::Aes { rounds, .. } => {
+            report.add("", format!("Key transformation rounds {} (reduced to {})",
+                                   rounds,
+                                   u32::max_value()));
+            u32::max_value()
+        }
+        V2Kdf::Argon2d(_) | V2Kdf::Argon2id(_) => {
+            report.add("", "Key derivation function Argon2 (replaced by AES-KDF)".to_string());
+            // Default of KeePass for new databases
+            60000
+        }
+    };
+
+    report_meta(db, &mut report);
+
+    // Groups converted from KeePass 1.x keep their id, all
+    // others get new ones
+    let mut reserved_ids = vec![];
+    collect_group_ids(&db.root_group, &mut reserved_ids);
+    let mut builder = V1Builder {
+        kpdb: kpdb,
+        report: report,
+        reserved_ids: reserved_ids,
+        assigned_ids: vec![],
+    };
+
+    let root_group = builder.kpdb.root_group;
+    let root = db.root_group.borrow();
+    report_group(&root, &root.name, &mut builder.report);
+    if !root.entries.is_empty() {
+        // KeePass 1.x entries always live in a group
+        builder.report.add(&root.name,
+                           "Entries in the root group (moved into a group of the same name)"
+                               .to_string());
+        let mut group = V1Group::new();
+        group.id = builder.next_id(None);
+        group.title = root.name.clone();
+        group.image = root.icon_id;
+        convert_times(&root, &mut group);
+        let group = builder.kpdb.push_group(group, root_group);
+        for entry in root.entries.iter() {
+            try!(builder.convert_entry(db, entry, group, &root.name));
+        }
+    }
+    for child in root.groups.iter() {
+        try!(builder.convert_group(db, child, root_group, ""));
+    }
+    let V1Builder { mut kpdb, mut report, .. } = builder;
+
+    // Restore the Meta-Info entries into their groups, which kept
+    // their ids
+    for &(ref key, ref value) in db.meta.custom_data.iter() {
+        if !key.starts_with(META_STREAM_PREFIX) {
+            continue;
+        }
+        let entry = try!(meta_stream_entry(&key[META_STREAM_PREFIX.len()..], value));
+        let group = match kpdb.group_by_id(entry.group_id) {
+            Some(group) => group,
+            None => {
+                match kpdb.groups.first() {
+                    Some(&group) => {
+                        report.add("",
+                                   format!("Custom data {} (group {} doesn't exist, moved into the first group)",
+                                           key,
+                                           entry.group_id));
+                        group
+                    }
+                    None => {
+                        report.add("", format!("Custom data {} (no group to hold it)", key));
+                        continue;
+                    }
+                }
+            }
+        };
+        kpdb.push_entry(entry, Some(group));
+    }
+
+    Ok((kpdb, report))
+}
+
+// Builds the groups and entries of the KeePass 1.x database
+struct V1Builder {
+    kpdb: V1Kpdb,
+    report: ConversionReport,
+    // Ids of groups converted from KeePass 1.x
+    reserved_ids: Vec<u32>,
+    // Ids which are already used
+    assigned_ids: Vec<u32>,
+}
+
+impl V1Builder {
+    // Take the wanted id if it's still free, otherwise the
+    // smallest one which is neither used nor reserved
+    fn next_id(&mut self, wanted: Option<u32>) -> u32 {
+        let id = match wanted {
+            Some(id) if !self.assigned_ids.contains(&id) => id,
+            _ => {
+                let mut id = 1;
+                while self.reserved_ids.contains(&id) || self.assigned_ids.contains(&id) {
+                    id += 1;
+                }
+                id
+            }
+        };
+        self.assigned_ids.push(id);
+        id
+    }
+
+    // Append the group and its subgroups in tree order to kpdb.groups
+    fn convert_group(&mut self,
+                     db: &V2Kdbx,
+                     group: &Rc<RefCell<V2Group>>,
+                     parent: GroupId,
+                     parent_path: &str)
+                     -> Result<(), V2KdbxError> {
+        let group = group.borrow();
+        let path = join_path(parent_path, &group.name);
+        report_group(&group, &path, &mut self.report);
+
+        let mut new_group = V1Group::new();
+        new_group.id = self.next_id(group_id(&group.uuid));
+        new_group.title = group.name.clone();
+        new_group.image = group.icon_id;
+        convert_times(&group, &mut new_group);
+        let new_group = self.kpdb.push_group(new_group, parent);
+
+        for entry in group.entries.iter() {
+            try!(self.convert_entry(db, entry, new_group, &path));
+        }
+        for child in group.groups.iter() {
+            try!(self.convert_group(db, child, new_group, &path));
+        }
+        Ok(())
+    }
+
+    fn convert_entry(&mut self,
+                     db: &V2Kdbx,
+                     entry: &Rc<RefCell<V2Entry>>,
+                     group: GroupId,
+                     group_path: &str)
+                     -> Result<(), V2KdbxError> {
+        let entry = entry.borrow();
+        let path = join_path(group_path, &entry.title());
+        report_entry(&entry, &path, &mut self.report);
+
+        let mut new_entry = V1Entry::new();
+        new_entry.uuid = entry.uuid;
+        new_entry.image = entry.icon_id;
+        new_entry.creation = entry.times.creation;
+        new_entry.last_mod = entry.times.last_mod;
+        new_entry.last_access = entry.times.last_access;
+        new_entry.expire = if entry.times.expires {
+            entry.times.expire
+        } else {
+            never_expires()
+        };
+
+        for string in entry.strings.iter() {
+            match &string.key[..] {
+                "Title" => new_entry.title = copy_value(&string.value),
+                "UserName" => new_entry.username = Some(SecureString::new(copy_value(&string.value))),
+                "Password" => new_entry.password = Some(SecureString::new(copy_value(&string.value))),
+                "URL" => new_entry.url = Some(copy_value(&string.value)),
+                "Notes" => new_entry.comment = Some(copy_value(&string.value)),
+                key => self.report.add(&path, format!("String field {}", key)),
+            }
+        }
+
+        for (index, binary_ref) in entry.binaries.iter().enumerate() {
+            if index > 0 {
+                self.report.add(&path, format!("Attachment {}", binary_ref.key));
+                continue;
+            }
+            let binary = try!(db.binaries.get(binary_ref.id).ok_or(V2KdbxError::ConvertErr));
+            new_entry.binary_desc = Some(binary_ref.key.clone());
+            new_entry.binary = Some(binary.data.clone());
+        }
+
+        self.kpdb.push_entry(new_entry, Some(group));
+        Ok(())
+    }
+}
+
+fn collect_group_ids(group: &Rc<RefCell<V2Group>>, ids: &mut Vec<u32>) {
+    for child in group.borrow().groups.iter() {
+        if let Some(id) = group_id(&child.borrow().uuid) {
+            ids.push(id);
+        }
+        collect_group_ids(child, ids);
+    }
+}
+
+fn convert_times(group: &V2Group, new_group: &mut V1Group) {
+    new_group.creation = group.times.creation;
+    new_group.last_mod = group.times.last_mod;
+    new_group.last_access = group.times.last_access;
+    new_group.expire = if group.times.expires {
+        group.times.expire
+    } else {
+        never_expires()
+    };
+}
+
+fn report_unknown(unknown: &[XmlElement], path: &str, report: &mut ConversionReport) {
+    for element in unknown.iter() {
+        report.add(path, element.name.clone());
+    }
+}
+
+fn report_group(group: &V2Group, path: &str, report: &mut ConversionReport) {
+    if !group.notes.is_empty() {
+        report.add(path, "Notes".to_string());
+    }
+    if group.custom_icon_uuid.is_some() {
+        report.add(path, "CustomIconUUID".to_string());
+    }
+    if !group.default_autotype_sequence.is_empty() {
+        report.add(path, "DefaultAutoTypeSequence".to_string());
+    }
+    if group.enable_autotype.is_some() {
+        report.add(path, "EnableAutoType".to_string());
+    }
+    if group.enable_searching.is_some() {
+        report.add(path, "EnableSearching".to_string());
+    }
+    if group.times.usage_count > 0 {
+        report.add(path, "UsageCount".to_string());
+    }
+    // KeePass 1.x has no field for it, v1_to_v2 takes last_mod
+    if group.times.location_changed.timestamp() != group.times.last_mod.timestamp() {
+        report.add(path, "LocationChanged".to_string());
+    }
+    report_unknown(&group.unknown, path, report);
+}
+
+fn report_entry(entry: &V2Entry, path: &str, report: &mut ConversionReport) {
+    if entry.custom_icon_uuid.is_some() {
+        report.add(path, "CustomIconUUID".to_string());
+    }
+    if !entry.foreground_color.is_empty() {
+        report.add(path, "ForegroundColor".to_string());
+    }
+    if !entry.background_color.is_empty() {
+        report.add(path, "BackgroundColor".to_string());
+    }
+    if !entry.override_url.is_empty() {
+        report.add(path, "OverrideURL".to_string());
+    }
+    if !entry.tags.is_empty() {
+        report.add(path, "Tags".to_string());
+    }
+    if entry.times.usage_count > 0 {
+        report.add(path, "UsageCount".to_string());
+    }
+    if entry.times.location_changed.timestamp() != entry.times.last_mod.timestamp() {
+        report.add(path, "LocationChanged".to_string());
+    }
+    if !entry.history.is_empty() {
+        report.add(path, format!("History ({} versions)", entry.history.len()));
+    }
+    report_unknown(&entry.unknown, path, report);
+}
+
+fn report_meta(db: &V2Kdbx, report: &mut ConversionReport) {
+    let meta = &db.meta;
+    if !meta.database_name.is_empty() {
+        report.add("", "DatabaseName".to_string());
+    }
+    if !meta.database_description.is_empty() {
+        report.add("", "DatabaseDescription".to_string());
+    }
+    if !meta.default_username.is_empty() {
+        report.add("", "DefaultUserName".to_string());
+    }
+    if !meta.recycle_bin_uuid.is_nil() {
+        report.add("", "RecycleBinUUID".to_string());
+    }
+    for &(ref key, _) in meta.custom_data.iter() {
+        if !key.starts_with(META_STREAM_PREFIX) {
+            report.add("", format!("Custom data {}", key));
+        }
+    }
+    report_unknown(&meta.unknown, "", report);
+    if !db.deleted_objects.is_empty() {
+        report.add("", format!("DeletedObjects ({} objects)", db.deleted_objects.len()));
+    }
+}
@@ -0,0 +1,620 @@
+use libc::{c_void, mlock, munlock, size_t};
+use std::io::Write;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20::ChaCha20;
+use openssl::hash;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm;
+use salsa20::Salsa20;
+use salsa20::cipher::{KeyIvInit, StreamCipher};
+use salsa20::cipher::generic_array::GenericArray;
+
+use common::common::{slice_to_u32, u32_to_vec_u8, u64_to_vec_u8, write_array_volatile};
+use kdbx::v2error::V2KdbxError;
+use kdbx::v2header::{V2Argon2Params, V2Cipher, V2Header, V2Kdf};
+use kpdb::compositekey::CompositeKey;
+use kpdb::crypter::Crypter;
+
+// Fixed IV of the Salsa20 inner random stream
+const SALSA20_IV: [u8; 8] = [0xE8, 0x30, 0x09, 0x4B, 0x97, 0x20, 0x5D, 0x2A];
+// Size of the blocks of the hashed and HMAC block streams KeePass writes
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+// Implements a crypter to de- and encrypt the payload of a KeePass 2.x
+// database. The composite key is handled by the crypter of KeePass 1.x
+// databases as it's built the same way.
+//
+// Sensitive data in V2Crypter overall
+// * transformedkey: created in get_transformedkey, zeroed out in
+//   Crypter::get_finalkey_with
+// * finalkey: created in get_finalkey, zeroed out in en-/decrypt_raw
+// * hmackey: created in get_hmackey, zeroed out in en-/decrypt_payload_v4
+// * compositekey: created in Crypter::get_compositekey, zeroed out in
+//   the KDFs
+// * decrypted: created in decrypt_raw, zeroed out in decrypt_payload
+//   or moved out of decrypt_payload_v4
+// * content: created in read_hashed_blocks and moved out of decrypt_payload
+//   or moved into encrypt_payload and zeroed out there
+// * payload: created and zeroed out in encrypt_payload
+pub struct V2Crypter {
+    crypter: Crypter,
+}
+
+impl V2Crypter {
+    pub fn new(password: Option<String>,
+               keyfile: Option<String>)
+               -> Result<V2Crypter, V2KdbxError> {
+        Ok(V2Crypter { crypter: try!(Crypter::new(password, keyfile)) })
+    }
+
+    pub fn with_key(key: CompositeKey) -> Result<V2Crypter, V2KdbxError> {
+        Ok(V2Crypter { crypter: try!(Crypter::with_key(key)) })
+    }
+
+    pub fn change_password(&mut self, password: Option<String>) -> Result<(), V2KdbxError> {
+        Ok(try!(self.crypter.change_password(password)))
+    }
+
+    pub fn change_keyfile(&mut self, keyfile: Option<String>) -> Result<(), V2KdbxError> {
+        Ok(try!(self.crypter.change_keyfile(keyfile)))
+    }
+
+    // Decrypt the payload and return its content, i.e. the content of the
+    // hashed block stream for KDBX 3.1 and the decrypted HMAC block stream
+    // for KDBX 4.x. header_raw is the header as in the file which KDBX 4.x
+    // authenticates.
+    //
+    // Sensitive data in this function:
+    // * finalkey (locked: get_finalkey)
+    // * decrypted (locked: decrypt_raw)
+    //
+    // At the end of this function:
+    // * finalkey has moved to decrypt_raw
+    // * decrypted is zeroed out
+    // * content is moved out of function and locked
+    pub fn decrypt_payload(&mut self,
+                           header: &V2Header,
+                           header_raw: &[u8],
+                           encrypted: &[u8])
+                           -> Result<Vec<u8>, V2KdbxError> {
+        if header.major_version() >= 4 {
+            return self.decrypt_payload_v4(header, header_raw, encrypted);
+        }
+
+        let finalkey = try!(self.get_finalkey(header));
+        let decrypted = try!(V2Crypter::decrypt_raw(header, encrypted, finalkey));
+
+        let result = if decrypted.len() < 32 ||
+                        decrypted[0..32] != header.stream_start_bytes[..] {
+            Err(V2KdbxError::HashErr)
+        } else {
+            read_hashed_blocks(&decrypted[32..])
+        };
+        unsafe {
+            write_array_volatile(decrypted.as_ptr() as *mut u8, 0u8, decrypted.len());
+            munlock(decrypted.as_ptr() as *const c_void, decrypted.len() as size_t);
+        }
+
+        let content = try!(result);
+        unsafe {
+            mlock(content.as_ptr() as *const c_void, content.len() as size_t);
+        }
+        Ok(content)
+    }
+
+    // The header is followed by its SHA-256 and HMAC-SHA-256 and the
+    // encrypted payload is stored as HMAC block stream.
+    //
+    // Sensitive data in this function:
+    // * transformedkey (locked: get_transformedkey)
+    // * hmackey (locked: get_hmackey)
+    // * finalkey (locked: get_finalkey_with)
+    //
+    // At the end of this function:
+    // * transformedkey is zeroed out by get_finalkey_with
+    // * hmackey is zeroed out
+    // * finalkey has moved to decrypt_raw
+    // * decrypted is moved out of function
+    fn decrypt_payload_v4(&mut self,
+                          header: &V2Header,
+                          header_raw: &[u8],
+                          data: &[u8])
+                          -> Result<Vec<u8>, V2KdbxError> {
+        if data.len() < 64 {
+            return Err(V2KdbxError::FileErr);
+        }
+        if sha256(header_raw)[..] != data[0..32] {
+            return Err(V2KdbxError::HeaderHashErr);
+        }
+
+        let transformedkey = try!(self.get_transformedkey(header));
+        let hmackey = V2Crypter::get_hmackey(&header.master_seed, &transformedkey);
+        let finalkey = try!(Crypter::get_finalkey_with(&header.master_seed, transformedkey));
+
+        // The header HMAC is the first thing which depends on the key, so a
+        // mismatch means a wrong password and/or keyfile
+        let result = if !memcmp::eq(&hmac_sha256(&block_hmackey(u64::max_value(), &hmackey),
+                                                 &[header_raw]),
+                                    &data[32..64]) {
+            Err(V2KdbxError::HashErr)
+        } else {
+            read_hmac_blocks(&hmackey, &data[64..])
+        };
+        unsafe {
+            write_array_volatile(hmackey.as_ptr() as *mut u8, 0u8, hmackey.len());
+            munlock(hmackey.as_ptr() as *const c_void, hmackey.len() as size_t);
+        }
+        let encrypted = match result {
+            Ok(encrypted) => encrypted,
+            Err(e) => {
+                unsafe {
+                    write_array_volatile(finalkey.as_ptr() as *mut u8, 0u8, finalkey.len());
+                    munlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
+                }
+                return Err(e);
+            }
+        };
+
+        // The key is authenticated, so errors here mean a corrupted payload
+        V2Crypter::decrypt_raw(header, &encrypted, finalkey).map_err(|_| V2KdbxError::DecryptErr)
+    }
+
+    // Encrypt content and return everything which follows the header
+    // in the file.
+    //
+    // Sensitive data in this function:
+    // * content (locked outside of V2Crypter)
+    // * payload
+    // * finalkey (locked: get_finalkey)
+    //
+    // At the end of this function:
+    // * content and payload are zeroed out
+    // * finalkey has moved to encrypt_raw
+    pub fn encrypt_payload(&mut self,
+                           header: &V2Header,
+                           header_raw: &[u8],
+                           content: Vec<u8>)
+                           -> Result<Vec<u8>, V2KdbxError> {
+        if header.major_version() >= 4 {
+            return self.encrypt_payload_v4(header, header_raw, content);
+        }
+
+        let mut payload: Vec<u8> = Vec::with_capacity(content.len() + content.len() / BLOCK_SIZE * 40 + 112);
+        unsafe {
+            mlock(payload.as_ptr() as *const c_void, payload.capacity() as size_t);
+        }
+        payload.extend_from_slice(&header.stream_start_bytes);
+        write_hashed_blocks(&content, &mut payload);
+        unsafe {
+            write_array_volatile(content.as_ptr() as *mut u8, 0u8, content.len());
+            munlock(content.as_ptr() as *const c_void, content.len() as size_t);
+        }
+
+        let encrypted = match self.get_finalkey(header) {
+            Ok(finalkey) => V2Crypter::encrypt_raw(header, &payload, finalkey),
+            Err(e) => Err(e),
+        };
+        unsafe {
+            write_array_volatile(payload.as_ptr() as *mut u8, 0u8, payload.len());
+            munlock(payload.as_ptr() as *const c_void, payload.capacity() as size_t);
+        }
+        encrypted
+    }
+
+    // Sensitive data in this function:
+    // * content (locked outside of V2Crypter)
+    // * transformedkey (locked: get_transformedkey)
+    // * hmackey (locked: get_hmackey)
+    // * finalkey (locked: get_finalkey_with)
+    //
+    // At the end of this function:
+    // * content is zeroed out
+    // * transformedkey is zeroed out by get_finalkey_with
+    // * hmackey is zeroed out
+    // * finalkey has moved to encrypt_raw
+    fn encrypt_payload_v4(&mut self,
+                          header: &V2Header,
+                          header_raw: &[u8],
+                          content: Vec<u8>)
+                          -> Result<Vec<u8>, V2KdbxError> {
+        let keys = match self.get_transformedkey(header) {
+            Ok(transformedkey) => {
+                let hmackey = V2Crypter::get_hmackey(&header.master_seed, &transformedkey);
+                Crypter::get_finalkey_with(&header.master_seed, transformedkey)
+                    .map(|finalkey| (hmackey, finalkey))
+                    .map_err(|e| V2KdbxError::from(e))
+            }
+            Err(e) => Err(e),
+        };
+        let encrypted = match keys {
+            Ok((ref hmackey, ref finalkey)) => {
+                V2Crypter::encrypt_raw(header, &content, finalkey.clone()).map(|encrypted| {
+                    let mut out: Vec<u8> = Vec::with_capacity(encrypted.len() +
+                                                              encrypted.len() / BLOCK_SIZE * 36 +
+                                                              136);
+                    out.append(&mut sha256(header_raw));
+                    out.append(&mut hmac_sha256(&block_hmackey(u64::max_value(), hmackey),
+                                                &[header_raw]));
+                    write_hmac_blocks(hmackey, &encrypted, &mut out);
+                    out
+                })
+            }
+            Err(e) => Err(e),
+        };
+
+        unsafe {
+            write_array_volatile(content.as_ptr() as *mut u8, 0u8, content.len());
+            munlock(content.as_ptr() as *const c_void, content.len() as size_t);
+            if let Ok((ref hmackey, ref finalkey)) = keys {
+                write_array_volatile(hmackey.as_ptr() as *mut u8, 0u8, hmackey.len());
+                munlock(hmackey.as_ptr() as *const c_void, hmackey.len() as size_t);
+                write_array_volatile(finalkey.as_ptr() as *mut u8, 0u8, finalkey.len());
+                munlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
+            }
+        }
+        encrypted
+    }
+
+    // Sensitive data in this function:
+    // * transformedkey (locked: get_transformedkey)
+    // * finalkey (locked: get_finalkey_with)
+    //
+    // At the end of this function:
+    // * transformedkey is zeroed out by get_finalkey_with
+    // * finalkey is moved out of function
+    fn get_finalkey(&mut self, header: &V2Header) -> Result<Vec<u8>, V2KdbxError> {
+        let transformedkey = try!(self.get_transformedkey(header));
+        Ok(try!(Crypter::get_finalkey_with(&header.master_seed, transformedkey)))
+    }
+
+    // Transform the composite key with the KDF of the header
+    //
+    // Sensitive data in this function:
+    // * compositekey (locked: get_compositekey)
+    // * transformedkey
+    //
+    // At the end of this function:
+    // * compositekey is zeroed out by the KDF
+    // * transformedkey is locked and moved out of function
+    fn get_transformedkey(&mut self, header: &V2Header) -> Result<Vec<u8>, V2KdbxError> {
+        let compositekey = try!(self.crypter.get_compositekey());
+        match header.kdf {
+            V2Kdf::Aes { ref seed, rounds } => Ok(try!(Crypter::aes_kdf(compositekey, seed, rounds))),
+            V2Kdf::Argon2d(ref params) => V2Crypter::argon2_kdf(compositekey, Algorithm::Argon2d, params),
+            V2Kdf::Argon2id(ref params) => V2Crypter::argon2_kdf(compositekey, Algorithm::Argon2id, params),
+        }
+    }
+
+    // Sensitive data in this function:
+    // * compositekey (locked: get_compositekey)
+    // * transformedkey
+    //
+    // At the end of this function:
+    // * compositekey is zeroed out
+    // * transformedkey is locked and moved out of function
+    fn argon2_kdf(compositekey: Vec<u8>,
+                  algorithm: Algorithm,
+                  params: &V2Argon2Params)
+                  -> Result<Vec<u8>, V2KdbxError> {
+        let mut transformedkey = vec![0u8; 32];
+        unsafe {
+            mlock(transformedkey.as_ptr() as *const c_void, transformedkey.len() as size_t);
+        }
+
+        let version = match params.version {
+            0x10 => Ok(Version::V0x10),
+            0x13 => Ok(Version::V0x13),
+            _ => Err(V2KdbxError::KdfErr),
+        };
+        let result = version.and_then(|version| {
+            Params::new((params.memory / 1024) as u32,
+                        params.iterations as u32,
+                        params.parallelism,
+                        Some(32))
+                .map_err(|_| V2KdbxError::KdfErr)
+                .and_then(|argon2_params| {
+                    Argon2::new(algorithm, version, argon2_params)
+                        .hash_password_into(&compositekey, &params.salt, &mut transformedkey)
+                        .map_err(|_| V2KdbxError::KdfErr)
+                })
+        });
+
+        unsafe {
+            write_array_volatile(compositekey.as_ptr() as *mut u8, 0u8, compositekey.len());
+            munlock(compositekey.as_ptr() as *const c_void, compositekey.len() as size_t);
+        }
+        if let Err(e) = result {
+            unsafe {
+                write_array_volatile(transformedkey.as_ptr() as *mut u8, 0u8, transformedkey.len());
+                munlock(transformedkey.as_ptr() as *const c_void, transformedkey.len() as size_t);
+            }
+            return Err(e);
+        }
+        Ok(transformedkey)
+    }
+
+    // Key for the HMACs of KDBX 4.x
+    //
+    // Sensitive data in this function:
+    // * transformedkey (locked: get_transformedkey)
+    // * hmackey
+    //
+    // At the end of this function:
+    // * transformedkey hasn't changed (it's a reference)
+    // * hmackey is locked and moved out of function
+    fn get_hmackey(master_seed: &[u8], transformedkey: &[u8]) -> Vec<u8> {
+        let mut hasher = hash::Hasher::new(hash::MessageDigest::sha512()).expect("Can't create hasher!?");
+        hasher.write_all(master_seed).expect("Can't hash data!?");
+        hasher.write_all(transformedkey).expect("Can't hash data!?");
+        hasher.write_all(&[0x01]).expect("Can't hash data!?");
+        let hmackey = hasher.finish2().expect("Can't hash data!?").to_vec();
+        unsafe {
+            mlock(hmackey.as_ptr() as *const c_void, hmackey.len() as size_t);
+        }
+        hmackey
+    }
+
+    // Sensitive data in this function:
+    // * finalkey (locked: get_finalkey)
+    // * decrypted
+    //
+    // At the end of this function:
+    // * finalkey is zeroed out
+    // * decrypted is locked and moved out of function
+    fn decrypt_raw(header: &V2Header,
+                   encrypted: &[u8],
+                   finalkey: Vec<u8>)
+                   -> Result<Vec<u8>, V2KdbxError> {
+        let decryption = match header.cipher() {
+            Ok(V2Cipher::Aes) => symm::decrypt(symm::Cipher::aes_256_cbc(),
+                                               &finalkey,
+                                               Some(&header.encryption_iv),
+                                               encrypted).map_err(|_| V2KdbxError::HashErr),
+            Ok(V2Cipher::Twofish) => Crypter::twofish_cbc_decrypt(&finalkey,
+                                                                   &header.encryption_iv,
+                                                                   encrypted)
+                                         .map_err(|_| V2KdbxError::HashErr),
+            Ok(V2Cipher::ChaCha20) => chacha20_apply(&finalkey, &header.encryption_iv, encrypted),
+            Err(e) => Err(e),
+        };
+
+        unsafe {
+            write_array_volatile(finalkey.as_ptr() as *mut u8, 0u8, finalkey.len());
+            munlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
+        }
+
+        // A wrong key almost always results in wrong padding, so
+        // that's reported like wrong stream start bytes
+        let decrypted = try!(decryption);
+        unsafe {
+            mlock(decrypted.as_ptr() as *const c_void, decrypted.len() as size_t);
+        }
+        Ok(decrypted)
+    }
+
+    // Sensitive data in this function:
+    // * finalkey (locked: get_finalkey)
+    // * payload (locked: encrypt_payload)
+    //
+    // At the end of this function:
+    // * finalkey is zeroed out
+    // * payload hasn't changed (it's a reference)
+    fn encrypt_raw(header: &V2Header,
+                   payload: &[u8],
+                   finalkey: Vec<u8>)
+                   -> Result<Vec<u8>, V2KdbxError> {
+        let encryption = match header.cipher() {
+            Ok(V2Cipher::Aes) => symm::encrypt(symm::Cipher::aes_256_cbc(),
+                                               &finalkey,
+                                               Some(&header.encryption_iv),
+                                               payload).map_err(|_| V2KdbxError::EncryptErr),
+            Ok(V2Cipher::Twofish) => Crypter::twofish_cbc_encrypt(&finalkey,
+                                                                   &header.encryption_iv,
+                                                                   payload)
+                                         .map_err(|_| V2KdbxError::EncryptErr),
+            Ok(V2Cipher::ChaCha20) => chacha20_apply(&finalkey, &header.encryption_iv, payload),
+            Err(e) => Err(e),
+        };
+
+        unsafe {
+            write_array_volatile(finalkey.as_ptr() as *mut u8, 0u8, finalkey.len());
+            munlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
+        }
+        encryption
+    }
+}
+
+// ChaCha20 is a stream cipher, so en- and decryption are the same.
+// The chacha20 and salsa20 crates may depend on different versions of
+// the cipher traits, so call them explicitly.
+fn chacha20_apply(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, V2KdbxError> {
+    let mut cipher = try!(<ChaCha20 as chacha20::cipher::KeyIvInit>::new_from_slices(key, iv).map_err(|_| V2KdbxError::CipherErr));
+    let mut out = data.to_vec();
+    chacha20::cipher::StreamCipher::apply_keystream(&mut cipher, &mut out);
+    Ok(out)
+}
+
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Can't create hasher!?");
+    hasher.write_all(data).expect("Can't hash data!?");
+    hasher.finish2().expect("Can't hash data!?").to_vec()
+}
+
+// Read the hashed block stream. Every block is
+// [index: u32][SHA-256 of data: 32 bytes][size: u32][data] and the stream
+// ends with a block of size 0 and a zeroed hash.
+pub fn read_hashed_blocks(data: &[u8]) -> Result<Vec<u8>, V2KdbxError> {
+    // Reserve everything up front so that no plaintext is left behind
+    // by a reallocation
+    let mut content: Vec<u8> = Vec::with_capacity(data.len());
+    let mut pos = 0usize;
+    let mut index = 0u32;
+
+    loop {
+        if pos + 40 > data.len() {
+            return Err(V2KdbxError::BlockHashErr);
+        }
+        let block_index = try!(slice_to_u32(&data[pos..pos + 4]).map_err(|_| V2KdbxError::ConvertErr));
+        let block_hash = &data[pos + 4..pos + 36];
+        let block_size = try!(slice_to_u32(&data[pos + 36..pos + 40]).map_err(|_| V2KdbxError::ConvertErr)) as usize;
+        pos += 40;
+
+        if block_index != index {
+            return Err(V2KdbxError::BlockHashErr);
+        }
+        if block_size == 0 {
+            if block_hash.iter().any(|&b| b != 0) {
+                return Err(V2KdbxError::BlockHashErr);
+            }
+            break;
+        }
+        if pos + block_size > data.len() {
+            return Err(V2KdbxError::BlockHashErr);
+        }
+
+        let block = &data[pos..pos + block_size];
+        if sha256(block)[..] != block_hash[..] {
+            return Err(V2KdbxError::BlockHashErr);
+        }
+        content.extend_from_slice(block);
+        pos += block_size;
+        index += 1;
+    }
+
+    Ok(content)
+}
+
+// Write content as hashed block stream to out
+pub fn write_hashed_blocks(content: &[u8], out: &mut Vec<u8>) {
+    let mut index = 0u32;
+    for block in content.chunks(BLOCK_SIZE) {
+        out.append(&mut u32_to_vec_u8(index));
+        out.append(&mut sha256(block));
+        out.append(&mut u32_to_vec_u8(block.len() as u32));
+        out.extend_from_slice(block);
+        index += 1;
+    }
+    out.append(&mut u32_to_vec_u8(index));
+    out.append(&mut vec![0u8; 32]);
+    out.append(&mut u32_to_vec_u8(0));
+}
+
+// HMAC key of the block with the given index. The header uses
+// u64::max_value() as index.
+fn block_hmackey(index: u64, hmackey: &[u8]) -> Vec<u8> {
+    let mut hasher = hash::Hasher::new(hash::MessageDigest::sha512()).expect("Can't create hasher!?");
+    hasher.write_all(&u64_to_vec_u8(index)).expect("Can't hash data!?");
+    hasher.write_all(hmackey).expect("Can't hash data!?");
+    hasher.finish2().expect("Can't hash data!?").to_vec()
+}
+
+fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    let key = PKey::hmac(key).expect("Can't create HMAC key!?");
+    let mut signer = Signer::new(hash::MessageDigest::sha256(), &key).expect("Can't create signer!?");
+    for part in data.iter() {
+        signer.update(part).expect("Can't sign data!?");
+    }
+    signer.sign_to_vec().expect("Can't sign data!?")
+}
+
+// Read the HMAC block stream of KDBX 4.x. Every block is
+// [HMAC-SHA-256: 32 bytes][size: u32][data] and the stream ends with a
+// block of size 0. The HMAC is over [index: u64][size: u32][data].
+pub fn read_hmac_blocks(hmackey: &[u8], data: &[u8]) -> Result<Vec<u8>, V2KdbxError> {
+    let mut content: Vec<u8> = Vec::with_capacity(data.len());
+    let mut pos = 0usize;
+    let mut index = 0u64;
+
+    loop {
+        if pos + 36 > data.len() {
+            return Err(V2KdbxError::BlockHashErr);
+        }
+        let block_hmac = &data[pos..pos + 32];
+        let block_size = &data[pos + 32..pos + 36];
+        let size = try!(slice_to_u32(block_size).map_err(|_| V2KdbxError::ConvertErr)) as usize;
+        pos += 36;
+        if pos + size > data.len() {
+            return Err(V2KdbxError::BlockHashErr);
+        }
+
+        let block = &data[pos..pos + size];
+        let expected = hmac_sha256(&block_hmackey(index, hmackey),
+                                   &[&u64_to_vec_u8(index), block_size, block]);
+        if !memcmp::eq(&expected, block_hmac) {
+            return Err(V2KdbxError::BlockHashErr);
+        }
+        if size == 0 {
+            break;
+        }
+        content.extend_from_slice(block);
+        pos += size;
+        index += 1;
+    }
+
+    Ok(content)
+}
+
+// Write data as HMAC block stream to out
+pub fn write_hmac_blocks(hmackey: &[u8], data: &[u8], out: &mut Vec<u8>) {
+    let mut index = 0u64;
+    for block in data.chunks(BLOCK_SIZE).chain(Some(&[][..])) {
+        let block_size = u32_to_vec_u8(block.len() as u32);
+        out.append(&mut hmac_sha256(&block_hmackey(index, hmackey),
+                                    &[&u64_to_vec_u8(index), &block_size, block]));
+        out.extend_from_slice(&block_size);
+        out.extend_from_slice(block);
+        index += 1;
+    }
+}
+
+// Implements the inner random stream which protects values
+// like passwords inside the XML payload
+pub enum InnerStream {
+    Plain,
+    Salsa20(Salsa20),
+    ChaCha20(ChaCha20),
+}
+
+impl InnerStream {
+    pub fn new(id: u32, key: &[u8]) -> Result<InnerStream, V2KdbxError> {
+        match id {
+            0 => Ok(InnerStream::Plain),
+            2 => {
+                let key = sha256(key);
+                let cipher = Salsa20::new(GenericArray::from_slice(&key),
+                                          GenericArray::from_slice(&SALSA20_IV));
+                unsafe {
+                    write_array_volatile(key.as_ptr() as *mut u8, 0u8, key.len());
+                }
+                Ok(InnerStream::Salsa20(cipher))
+            }
+            3 => {
+                let mut hasher = hash::Hasher::new(hash::MessageDigest::sha512()).expect("Can't create hasher!?");
+                hasher.write_all(key).expect("Can't hash data!?");
+                let key = hasher.finish2().expect("Can't hash data!?");
+                let cipher = <ChaCha20 as chacha20::cipher::KeyIvInit>::new_from_slices(&key[0..32],
+                                                                                    &key[32..44]);
+                unsafe {
+                    write_array_volatile(key.as_ptr() as *mut u8, 0u8, key.len());
+                }
+                Ok(InnerStream::ChaCha20(try!(cipher.map_err(|_| V2KdbxError::InnerStreamErr))))
+            }
+            _ => Err(V2KdbxError::InnerStreamErr),
+        }
+    }
+
+    // XOR data with the next bytes of the key stream.
+    // The values have to be processed in document order.
+    pub fn process(&mut self, data: &mut [u8]) {
+        match *self {
+            InnerStream::Plain => {}
+            InnerStream::Salsa20(ref mut cipher) => cipher.apply_keystream(data),
+            InnerStream::ChaCha20(ref mut cipher) => {
+                chacha20::cipher::StreamCipher::apply_keystream(cipher, data)
+            }
+        }
+    }
+}
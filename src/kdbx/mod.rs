@@ -0,0 +1,17 @@
+pub mod v2kdbx;
+pub mod v2error;
+pub mod v2header;
+pub mod v2group;
+pub mod v2entry;
+pub mod v2meta;
+pub mod v2times;
+pub mod xmldom;
+pub mod convert;
+
+mod crypter;
+mod parser;
+
+#[cfg(test)]
+mod tests_kdbx;
+#[cfg(test)]
+mod tests_convert;
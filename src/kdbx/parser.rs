@@ -0,0 +1,1024 @@
+use libc::{c_void, munlock, size_t};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use uuid::Uuid;
+
+use common::common::{slice_to_u16, slice_to_u32, slice_to_u64, u16_to_vec_u8, u32_to_vec_u8,
+                     u64_to_vec_u8, write_array_volatile};
+use kdbx::crypter::InnerStream;
+use kdbx::v2entry::{V2Binary, V2BinaryRef, V2Entry, V2String, V2Value};
+use kdbx::v2error::V2KdbxError;
+use kdbx::v2group::V2Group;
+use kdbx::v2header::{V2Argon2Params, V2Header, V2Kdf, KDF_AES, KDF_ARGON2D, KDF_ARGON2ID};
+use kdbx::v2meta::{V2MemoryProtection, V2Meta};
+use kdbx::v2times::V2Times;
+use kdbx::xmldom::XmlElement;
+use sec_str::SecureString;
+
+pub struct HeaderLoadParser<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> HeaderLoadParser<'a> {
+    pub fn new(raw: &'a [u8]) -> HeaderLoadParser<'a> {
+        HeaderLoadParser {
+            raw: raw,
+        }
+    }
+
+    // Parse the header and return it together with its length in bytes.
+    // The header is a list of [id: u8][size: u16][data] fields which ends
+    // with id 0. KDBX 4.x uses an u32 for the size.
+    pub fn parse_header(&self) -> Result<(V2Header, usize), V2KdbxError> {
+        if self.raw.len() < 12 {
+            return Err(V2KdbxError::FileErr);
+        }
+
+        let mut header = V2Header {
+            signature1: try!(slice_to_u32(&self.raw[0..4]).map_err(|_| V2KdbxError::ConvertErr)),
+            signature2: try!(slice_to_u32(&self.raw[4..8]).map_err(|_| V2KdbxError::ConvertErr)),
+            version: try!(slice_to_u32(&self.raw[8..12]).map_err(|_| V2KdbxError::ConvertErr)),
+            cipher_id: vec![],
+            compression: 0,
+            master_seed: vec![],
+            kdf: V2Kdf::Aes {
+                seed: vec![],
+                rounds: 0,
+            },
+            encryption_iv: vec![],
+            protected_stream_key: vec![],
+            stream_start_bytes: vec![],
+            inner_random_stream: 0,
+        };
+        try!(header.check_signatures());
+        try!(header.check_version());
+        let kdbx4 = header.major_version() >= 4;
+        let size_len = if kdbx4 { 4 } else { 2 };
+
+        let mut transform_seed: Vec<u8> = vec![];
+        let mut transform_rounds = 0u64;
+        let mut has_kdf_parameters = false;
+        let mut pos = 12usize;
+        loop {
+            if pos + 1 + size_len > self.raw.len() {
+                return Err(V2KdbxError::HeaderErr);
+            }
+            let field_type = self.raw[pos];
+            let field_size = if kdbx4 {
+                try!(slice_to_u32(&self.raw[pos + 1..pos + 5]).map_err(|_| V2KdbxError::ConvertErr)) as usize
+            } else {
+                try!(slice_to_u16(&self.raw[pos + 1..pos + 3]).map_err(|_| V2KdbxError::ConvertErr)) as usize
+            };
+            pos += 1 + size_len;
+            if pos + field_size > self.raw.len() {
+                return Err(V2KdbxError::HeaderErr);
+            }
+            let field = &self.raw[pos..pos + field_size];
+            pos += field_size;
+
+            match field_type {
+                0 => break,
+                2 => header.cipher_id = field.to_vec(),
+                3 => header.compression = try!(slice_to_u32(field).map_err(|_| V2KdbxError::HeaderErr)),
+                4 => header.master_seed = field.to_vec(),
+                5 => transform_seed = field.to_vec(),
+                6 => transform_rounds = try!(slice_to_u64(field).map_err(|_| V2KdbxError::HeaderErr)),
+                7 => header.encryption_iv = field.to_vec(),
+                8 => header.protected_stream_key = field.to_vec(),
+                9 => header.stream_start_bytes = field.to_vec(),
+                10 => header.inner_random_stream = try!(slice_to_u32(field).map_err(|_| V2KdbxError::HeaderErr)),
+                11 => {
+                    header.kdf = try!(parse_kdf_parameters(field));
+                    has_kdf_parameters = true;
+                }
+                // Comment, public custom data and unknown fields
+                _ => (),
+            }
+        }
+
+        if kdbx4 {
+            if !has_kdf_parameters {
+                return Err(V2KdbxError::HeaderErr);
+            }
+        } else {
+            if transform_seed.len() != 32 || header.stream_start_bytes.len() != 32 {
+                return Err(V2KdbxError::HeaderErr);
+            }
+            header.kdf = V2Kdf::Aes {
+                seed: transform_seed,
+                rounds: transform_rounds,
+            };
+        }
+        if header.cipher_id.len() != 16 || header.master_seed.len() != 32 ||
+           header.encryption_iv.len() != header.iv_len() {
+            return Err(V2KdbxError::HeaderErr);
+        }
+
+        Ok((header, pos))
+    }
+}
+
+pub struct HeaderSaveParser {
+    header: V2Header,
+}
+
+impl HeaderSaveParser {
+    pub fn new(header: V2Header) -> HeaderSaveParser {
+        HeaderSaveParser {
+            header: header,
+        }
+    }
+
+    pub fn parse_header(&mut self) -> Vec<u8> {
+        let mut header_raw: Vec<u8> = vec![];
+        let kdbx4 = self.header.major_version() >= 4;
+
+        header_raw.append(&mut u32_to_vec_u8(self.header.signature1));
+        header_raw.append(&mut u32_to_vec_u8(self.header.signature2));
+        header_raw.append(&mut u32_to_vec_u8(self.header.version));
+        HeaderSaveParser::write_field(&mut header_raw, kdbx4, 2, &self.header.cipher_id);
+        HeaderSaveParser::write_field(&mut header_raw, kdbx4, 3, &u32_to_vec_u8(self.header.compression));
+        HeaderSaveParser::write_field(&mut header_raw, kdbx4, 4, &self.header.master_seed);
+        if !kdbx4 {
+            if let V2Kdf::Aes { ref seed, rounds } = self.header.kdf {
+                HeaderSaveParser::write_field(&mut header_raw, kdbx4, 5, seed);
+                HeaderSaveParser::write_field(&mut header_raw, kdbx4, 6, &u64_to_vec_u8(rounds));
+            }
+        }
+        HeaderSaveParser::write_field(&mut header_raw, kdbx4, 7, &self.header.encryption_iv);
+        if kdbx4 {
+            HeaderSaveParser::write_field(&mut header_raw, kdbx4, 11, &save_kdf_parameters(&self.header.kdf));
+        } else {
+            HeaderSaveParser::write_field(&mut header_raw, kdbx4, 8, &self.header.protected_stream_key);
+            HeaderSaveParser::write_field(&mut header_raw, kdbx4, 9, &self.header.stream_start_bytes);
+            HeaderSaveParser::write_field(&mut header_raw, kdbx4, 10, &u32_to_vec_u8(self.header.inner_random_stream));
+        }
+        HeaderSaveParser::write_field(&mut header_raw, kdbx4, 0, b"\r\n\r\n");
+
+        header_raw
+    }
+
+    fn write_field(header_raw: &mut Vec<u8>, kdbx4: bool, field_type: u8, data: &[u8]) {
+        header_raw.push(field_type);
+        if kdbx4 {
+            header_raw.append(&mut u32_to_vec_u8(data.len() as u32));
+        } else {
+            header_raw.append(&mut u16_to_vec_u8(data.len() as u16));
+        }
+        header_raw.extend_from_slice(data);
+    }
+}
+
+// Types of the values in a VariantDictionary
+const VARIANT_UINT32: u8 = 0x04;
+const VARIANT_UINT64: u8 = 0x05;
+const VARIANT_BYTES: u8 = 0x42;
+
+// Parse the KDF parameters of a KDBX 4.x header. They are stored as
+// VariantDictionary: [version: u16] followed by items of
+// [type: u8][name size: u32][name][value size: u32][value] which end
+// with type 0.
+fn parse_kdf_parameters(data: &[u8]) -> Result<V2Kdf, V2KdbxError> {
+    if data.len() < 2 || data[1] > 1 {
+        return Err(V2KdbxError::HeaderErr);
+    }
+
+    let mut items: Vec<(String, Vec<u8>)> = vec![];
+    let mut pos = 2usize;
+    loop {
+        if pos >= data.len() {
+            return Err(V2KdbxError::HeaderErr);
+        }
+        let value_type = data[pos];
+        pos += 1;
+        if value_type == 0 {
+            break;
+        }
+
+        let mut fields: Vec<&[u8]> = vec![];
+        for _ in 0..2 {
+            if pos + 4 > data.len() {
+                return Err(V2KdbxError::HeaderErr);
+            }
+            let size = try!(slice_to_u32(&data[pos..pos + 4]).map_err(|_| V2KdbxError::HeaderErr)) as usize;
+            pos += 4;
+            if pos + size > data.len() {
+                return Err(V2KdbxError::HeaderErr);
+            }
+            fields.push(&data[pos..pos + size]);
+            pos += size;
+        }
+        items.push((String::from_utf8_lossy(fields[0]).into_owned(), fields[1].to_vec()));
+    }
+
+    let get = |name: &str| -> Result<&[u8], V2KdbxError> {
+        items.iter()
+             .find(|item| item.0 == name)
+             .map(|item| &item.1[..])
+             .ok_or(V2KdbxError::HeaderErr)
+    };
+    let get_u64 = |name: &str| -> Result<u64, V2KdbxError> {
+        slice_to_u64(try!(get(name))).map_err(|_| V2KdbxError::HeaderErr)
+    };
+    let get_u32 = |name: &str| -> Result<u32, V2KdbxError> {
+        slice_to_u32(try!(get(name))).map_err(|_| V2KdbxError::HeaderErr)
+    };
+
+    let uuid = try!(get("$UUID"));
+    if uuid == &KDF_AES[..] {
+        Ok(V2Kdf::Aes {
+            seed: try!(get("S")).to_vec(),
+            rounds: try!(get_u64("R")),
+        })
+    } else if uuid == &KDF_ARGON2D[..] || uuid == &KDF_ARGON2ID[..] {
+        let params = V2Argon2Params {
+            salt: try!(get("S")).to_vec(),
+            iterations: try!(get_u64("I")),
+            memory: try!(get_u64("M")),
+            parallelism: try!(get_u32("P")),
+            version: try!(get_u32("V")),
+        };
+        if uuid == &KDF_ARGON2D[..] {
+            Ok(V2Kdf::Argon2d(params))
+        } else {
+            Ok(V2Kdf::Argon2id(params))
+        }
+    } else {
+        Err(V2KdbxError::KdfErr)
+    }
+}
+
+fn save_kdf_parameters(kdf: &V2Kdf) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![0x00, 0x01];
+    {
+        let mut write_item = |value_type: u8, name: &str, value: &[u8]| {
+            data.push(value_type);
+            data.append(&mut u32_to_vec_u8(name.len() as u32));
+            data.extend_from_slice(name.as_bytes());
+            data.append(&mut u32_to_vec_u8(value.len() as u32));
+            data.extend_from_slice(value);
+        };
+
+        write_item(VARIANT_BYTES, "$UUID", &kdf.uuid());
+        match *kdf {
+            V2Kdf::Aes { ref seed, rounds } => {
+                write_item(VARIANT_UINT64, "R", &u64_to_vec_u8(rounds));
+                write_item(VARIANT_BYTES, "S", seed);
+            }
+            V2Kdf::Argon2d(ref params) |
+            V2Kdf::Argon2id(ref params) => {
+                write_item(VARIANT_UINT64, "I", &u64_to_vec_u8(params.iterations));
+                write_item(VARIANT_UINT64, "M", &u64_to_vec_u8(params.memory));
+                write_item(VARIANT_UINT32, "P", &u32_to_vec_u8(params.parallelism));
+                write_item(VARIANT_BYTES, "S", &params.salt);
+                write_item(VARIANT_UINT32, "V", &u32_to_vec_u8(params.version));
+            }
+        }
+    }
+    data.push(0);
+    data
+}
+
+// Parse the inner header at the beginning of the decrypted payload
+// of a KDBX 4.x database. It holds the inner random stream and the
+// attachments as [id: u8][size: u32][data] fields which end with id 0.
+// Returns the attachments and the length of the inner header.
+pub fn parse_inner_header(header: &mut V2Header,
+                          content: &[u8])
+                          -> Result<(Vec<V2Binary>, usize), V2KdbxError> {
+    let mut binaries: Vec<V2Binary> = vec![];
+    let mut pos = 0usize;
+    loop {
+        if pos + 5 > content.len() {
+            return Err(V2KdbxError::HeaderErr);
+        }
+        let field_type = content[pos];
+        let field_size = try!(slice_to_u32(&content[pos + 1..pos + 5]).map_err(|_| V2KdbxError::ConvertErr)) as usize;
+        pos += 5;
+        if pos + field_size > content.len() {
+            return Err(V2KdbxError::HeaderErr);
+        }
+        let field = &content[pos..pos + field_size];
+        pos += field_size;
+
+        match field_type {
+            0 => break,
+            1 => header.inner_random_stream = try!(slice_to_u32(field).map_err(|_| V2KdbxError::HeaderErr)),
+            2 => header.protected_stream_key = field.to_vec(),
+            3 => {
+                if field.len() < 1 {
+                    return Err(V2KdbxError::HeaderErr);
+                }
+                binaries.push(V2Binary {
+                    data: field[1..].to_vec(),
+                    protected: field[0] & 0x01 == 0x01,
+                });
+            }
+            _ => (),
+        }
+    }
+
+    Ok((binaries, pos))
+}
+
+pub fn save_inner_header(header: &V2Header, binaries: &Vec<V2Binary>) -> Vec<u8> {
+    let mut content: Vec<u8> = vec![];
+    let write_field = |content: &mut Vec<u8>, field_type: u8, data: &[u8]| {
+        content.push(field_type);
+        content.append(&mut u32_to_vec_u8(data.len() as u32));
+        content.extend_from_slice(data);
+    };
+
+    write_field(&mut content, 1, &u32_to_vec_u8(header.inner_random_stream));
+    write_field(&mut content, 2, &header.protected_stream_key);
+    for binary in binaries.iter() {
+        let mut field = Vec::with_capacity(binary.data.len() + 1);
+        field.push(if binary.protected { 0x01 } else { 0x00 });
+        field.extend_from_slice(&binary.data);
+        write_field(&mut content, 3, &field);
+    }
+    write_field(&mut content, 0, &[]);
+    content
+}
+
+// Decompress the XML payload if needed.
+//
+// Sensitive data in this function:
+// * content (locked: decrypt_payload)
+// * document
+//
+// At the end of this function:
+// * content is zeroed out or moved out of function
+// * document is moved out of function
+pub fn decompress(compression: u32, content: Vec<u8>) -> Result<Vec<u8>, V2KdbxError> {
+    if compression == 0 {
+        return Ok(content);
+    }
+
+    let mut document: Vec<u8> = Vec::with_capacity(content.len() * 4);
+    let result = GzDecoder::new(&content[..]).read_to_end(&mut document);
+    unsafe {
+        write_array_volatile(content.as_ptr() as *mut u8, 0u8, content.len());
+        munlock(content.as_ptr() as *const c_void, content.len() as size_t);
+    }
+    try!(result.map_err(|_| V2KdbxError::CompressionErr));
+    Ok(document)
+}
+
+// Compress the XML payload if needed.
+//
+// Sensitive data in this function:
+// * document
+// * content
+//
+// At the end of this function:
+// * document is zeroed out or moved out of function
+// * content is moved out of function
+pub fn compress(compression: u32, document: Vec<u8>) -> Result<Vec<u8>, V2KdbxError> {
+    if compression == 0 {
+        return Ok(document);
+    }
+
+    let mut encoder = GzEncoder::new(Vec::with_capacity(document.len()), Compression::default());
+    let result = encoder.write_all(&document);
+    unsafe {
+        write_array_volatile(document.as_ptr() as *mut u8, 0u8, document.len());
+    }
+    try!(result.map_err(|_| V2KdbxError::CompressionErr));
+    encoder.finish().map_err(|_| V2KdbxError::CompressionErr)
+}
+
+// Implements a parser to create groups, entries and meta data from
+// the XML payload
+pub struct XmlLoadParser {
+    stream: InnerStream,
+    // Maps the IDs of the binary pool to indices of binaries
+    binary_ids: Vec<(String, usize)>,
+    pub meta: V2Meta,
+    pub binaries: Vec<V2Binary>,
+    pub root_group: Option<Rc<RefCell<V2Group>>>,
+    pub deleted_objects: Vec<(Uuid, DateTime<Local>)>,
+    pub header_hash: Option<Vec<u8>>,
+}
+
+impl XmlLoadParser {
+    // binaries are the attachments from the inner header of
+    // a KDBX 4.x database
+    pub fn new(header: &V2Header, binaries: Vec<V2Binary>) -> Result<XmlLoadParser, V2KdbxError> {
+        Ok(XmlLoadParser {
+            stream: try!(InnerStream::new(header.inner_random_stream,
+                                          &header.protected_stream_key)),
+            binary_ids: (0..binaries.len()).map(|i| (i.to_string(), i)).collect(),
+            meta: V2Meta::new(),
+            binaries: binaries,
+            root_group: None,
+            deleted_objects: vec![],
+            header_hash: None,
+        })
+    }
+
+    // The children have to be processed in document order as
+    // protected values consume the inner random stream
+    pub fn parse_document(&mut self, document: &XmlElement) -> Result<(), V2KdbxError> {
+        if document.name != "KeePassFile" {
+            return Err(V2KdbxError::XmlErr);
+        }
+
+        for child in document.children.iter() {
+            match &child.name[..] {
+                "Meta" => try!(self.parse_meta(child)),
+                "Root" => try!(self.parse_root(child)),
+                _ => (),
+            }
+        }
+
+        if self.root_group.is_none() {
+            return Err(V2KdbxError::XmlErr);
+        }
+        Ok(())
+    }
+
+    fn parse_meta(&mut self, element: &XmlElement) -> Result<(), V2KdbxError> {
+        for child in element.children.iter() {
+            match &child.name[..] {
+                "Generator" => self.meta.generator = child.text.clone(),
+                "HeaderHash" => {
+                    self.header_hash = Some(try!(child.text.from_base64()
+                                                     .map_err(|_| V2KdbxError::ConvertErr)))
+                }
+                "DatabaseName" => self.meta.database_name = child.text.clone(),
+                "DatabaseDescription" => self.meta.database_description = child.text.clone(),
+                "DefaultUserName" => self.meta.default_username = child.text.clone(),
+                "MaintenanceHistoryDays" => self.meta.maintenance_history_days = try!(parse_u32(&child.text)),
+                "MemoryProtection" => self.meta.memory_protection = parse_memory_protection(child),
+                "RecycleBinEnabled" => self.meta.recycle_bin_enabled = parse_bool(&child.text),
+                "RecycleBinUUID" => self.meta.recycle_bin_uuid = try!(parse_uuid(&child.text)),
+                "Binaries" => {
+                    for binary in child.children.iter() {
+                        try!(self.parse_pool_binary(binary));
+                    }
+                }
+                "CustomData" => {
+                    for item in child.children.iter() {
+                        let key = item.child("Key").map_or("".to_string(), |k| k.text.clone());
+                        let value = item.child("Value").map_or("".to_string(), |v| v.text.clone());
+                        self.meta.custom_data.push((key, value));
+                    }
+                }
+                _ => {
+                    let mut unknown = child.clone();
+                    try!(self.unprotect(&mut unknown));
+                    self.meta.unknown.push(unknown);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_pool_binary(&mut self, element: &XmlElement) -> Result<(), V2KdbxError> {
+        let id = element.attribute("ID").unwrap_or("").to_string();
+        let mut data = try!(element.text.from_base64().map_err(|_| V2KdbxError::ConvertErr));
+        let protected = element.is_protected();
+        if protected {
+            self.stream.process(&mut data);
+        }
+        if element.attribute("Compressed").map_or(false, |c| parse_bool(c)) {
+            data = try!(decompress(1, data));
+        }
+
+        self.binary_ids.push((id, self.binaries.len()));
+        self.binaries.push(V2Binary {
+            data: data,
+            protected: protected,
+        });
+        Ok(())
+    }
+
+    fn parse_root(&mut self, element: &XmlElement) -> Result<(), V2KdbxError> {
+        for child in element.children.iter() {
+            match &child.name[..] {
+                "Group" => {
+                    if self.root_group.is_some() {
+                        return Err(V2KdbxError::XmlErr);
+                    }
+                    self.root_group = Some(try!(self.parse_group(child)));
+                }
+                "DeletedObjects" => {
+                    for deleted in child.children.iter() {
+                        let uuid = try!(parse_uuid(&try!(deleted.child("UUID")
+                                                                .ok_or(V2KdbxError::XmlErr))
+                                                        .text));
+                        let time = match deleted.child("DeletionTime") {
+                            Some(t) => try!(parse_date(&t.text)),
+                            None => Local::now(),
+                        };
+                        self.deleted_objects.push((uuid, time));
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_group(&mut self, element: &XmlElement) -> Result<Rc<RefCell<V2Group>>, V2KdbxError> {
+        let mut group = V2Group::new();
+        group.is_expanded = false;
+
+        for child in element.children.iter() {
+            match &child.name[..] {
+                "UUID" => group.uuid = try!(parse_uuid(&child.text)),
+                "Name" => group.name = child.text.clone(),
+                "Notes" => group.notes = child.text.clone(),
+                "IconID" => group.icon_id = try!(parse_u32(&child.text)),
+                "CustomIconUUID" => group.custom_icon_uuid = Some(try!(parse_uuid(&child.text))),
+                "Times" => group.times = try!(parse_times(child)),
+                "IsExpanded" => group.is_expanded = parse_bool(&child.text),
+                "DefaultAutoTypeSequence" => group.default_autotype_sequence = child.text.clone(),
+                "EnableAutoType" => group.enable_autotype = parse_nullable_bool(&child.text),
+                "EnableSearching" => group.enable_searching = parse_nullable_bool(&child.text),
+                "LastTopVisibleEntry" => group.last_top_visible_entry = try!(parse_uuid(&child.text)),
+                "Entry" => group.entries.push(Rc::new(RefCell::new(try!(self.parse_entry(child))))),
+                "Group" => group.groups.push(try!(self.parse_group(child))),
+                _ => {
+                    let mut unknown = child.clone();
+                    try!(self.unprotect(&mut unknown));
+                    group.unknown.push(unknown);
+                }
+            }
+        }
+
+        Ok(Rc::new(RefCell::new(group)))
+    }
+
+    fn parse_entry(&mut self, element: &XmlElement) -> Result<V2Entry, V2KdbxError> {
+        let mut entry = V2Entry::new();
+
+        for child in element.children.iter() {
+            match &child.name[..] {
+                "UUID" => entry.uuid = try!(parse_uuid(&child.text)),
+                "IconID" => entry.icon_id = try!(parse_u32(&child.text)),
+                "CustomIconUUID" => entry.custom_icon_uuid = Some(try!(parse_uuid(&child.text))),
+                "ForegroundColor" => entry.foreground_color = child.text.clone(),
+                "BackgroundColor" => entry.background_color = child.text.clone(),
+                "OverrideURL" => entry.override_url = child.text.clone(),
+                "Tags" => entry.tags = child.text.clone(),
+                "Times" => entry.times = try!(parse_times(child)),
+                "String" => entry.strings.push(try!(self.parse_string(child))),
+                "Binary" => entry.binaries.push(try!(self.parse_binary_ref(child))),
+                "History" => {
+                    for old_entry in child.children.iter() {
+                        entry.history.push(try!(self.parse_entry(old_entry)));
+                    }
+                }
+                _ => {
+                    let mut unknown = child.clone();
+                    try!(self.unprotect(&mut unknown));
+                    entry.unknown.push(unknown);
+                }
+            }
+        }
+
+        Ok(entry)
+    }
+
+    // Sensitive data in this function:
+    // * value
+    //
+    // At the end of this function:
+    // * value is moved into a SecureString or zeroed out
+    fn parse_string(&mut self, element: &XmlElement) -> Result<V2String, V2KdbxError> {
+        let key = try!(element.child("Key").ok_or(V2KdbxError::XmlErr)).text.clone();
+        let value_element = match element.child("Value") {
+            Some(v) => v,
+            None => {
+                return Ok(V2String {
+                    key: key,
+                    value: V2Value::Plain("".to_string()),
+                })
+            }
+        };
+
+        if !value_element.is_protected() {
+            return Ok(V2String {
+                key: key,
+                value: V2Value::Plain(value_element.text.clone()),
+            });
+        }
+
+        let mut value = try!(value_element.text.from_base64().map_err(|_| V2KdbxError::ConvertErr));
+        self.stream.process(&mut value);
+        match String::from_utf8(value) {
+            Ok(s) => {
+                Ok(V2String {
+                    key: key,
+                    value: V2Value::Protected(SecureString::new(s)),
+                })
+            }
+            Err(e) => {
+                let value = e.into_bytes();
+                unsafe {
+                    write_array_volatile(value.as_ptr() as *mut u8, 0u8, value.len());
+                }
+                Err(V2KdbxError::ConvertErr)
+            }
+        }
+    }
+
+    fn parse_binary_ref(&mut self, element: &XmlElement) -> Result<V2BinaryRef, V2KdbxError> {
+        let key = try!(element.child("Key").ok_or(V2KdbxError::XmlErr)).text.clone();
+        let value = try!(element.child("Value").ok_or(V2KdbxError::XmlErr));
+
+        let id = match value.attribute("Ref") {
+            Some(reference) => {
+                try!(self.binary_ids
+                         .iter()
+                         .find(|b| b.0 == reference)
+                         .map(|b| b.1)
+                         .ok_or(V2KdbxError::XmlErr))
+            }
+            // Old databases store attachments inside the entry
+            None => {
+                let mut data = try!(value.text.from_base64().map_err(|_| V2KdbxError::ConvertErr));
+                let protected = value.is_protected();
+                if protected {
+                    self.stream.process(&mut data);
+                }
+                self.binaries.push(V2Binary {
+                    data: data,
+                    protected: protected,
+                });
+                self.binaries.len() - 1
+            }
+        };
+
+        Ok(V2BinaryRef {
+            key: key,
+            id: id,
+        })
+    }
+
+    // Decrypt protected values in elements which aren't modelled, so
+    // that they can be encrypted again on save
+    fn unprotect(&mut self, element: &mut XmlElement) -> Result<(), V2KdbxError> {
+        if element.is_protected() {
+            let mut value = try!(element.text.from_base64().map_err(|_| V2KdbxError::ConvertErr));
+            self.stream.process(&mut value);
+            element.text = String::from_utf8_lossy(&value).into_owned();
+            unsafe {
+                write_array_volatile(value.as_ptr() as *mut u8, 0u8, value.len());
+            }
+        }
+        for child in element.children.iter_mut() {
+            try!(self.unprotect(child));
+        }
+        Ok(())
+    }
+}
+
+// Implements a parser to create the XML payload from groups,
+// entries and meta data
+pub struct XmlSaveParser {
+    stream: InnerStream,
+    compression: u32,
+    // KDBX 4.x stores dates as binary and attachments
+    // in the inner header
+    kdbx4: bool,
+}
+
+impl XmlSaveParser {
+    pub fn new(header: &V2Header) -> Result<XmlSaveParser, V2KdbxError> {
+        Ok(XmlSaveParser {
+            stream: try!(InnerStream::new(header.inner_random_stream,
+                                          &header.protected_stream_key)),
+            compression: header.compression,
+            kdbx4: header.major_version() >= 4,
+        })
+    }
+
+    // The elements have to be created in document order as
+    // protected values consume the inner random stream
+    pub fn save_document(&mut self,
+                         meta: &V2Meta,
+                         binaries: &Vec<V2Binary>,
+                         root_group: &Rc<RefCell<V2Group>>,
+                         deleted_objects: &Vec<(Uuid, DateTime<Local>)>,
+                         header_hash: Option<Vec<u8>>)
+                         -> Result<XmlElement, V2KdbxError> {
+        let mut document = XmlElement::new("KeePassFile");
+        document.children.push(try!(self.save_meta(meta, binaries, header_hash)));
+
+        let mut root = XmlElement::new("Root");
+        root.children.push(try!(self.save_group(&mut root_group.borrow_mut())));
+        let mut deleted = XmlElement::new("DeletedObjects");
+        for &(ref uuid, ref time) in deleted_objects.iter() {
+            let mut object = XmlElement::new("DeletedObject");
+            object.children.push(save_uuid("UUID", uuid));
+            object.children.push(XmlElement::with_text("DeletionTime", save_date(time, self.kdbx4)));
+            deleted.children.push(object);
+        }
+        root.children.push(deleted);
+        document.children.push(root);
+
+        Ok(document)
+    }
+
+    fn save_meta(&mut self,
+                 meta: &V2Meta,
+                 binaries: &Vec<V2Binary>,
+                 header_hash: Option<Vec<u8>>)
+                 -> Result<XmlElement, V2KdbxError> {
+        let mut element = XmlElement::new("Meta");
+        element.children.push(XmlElement::with_text("Generator", meta.generator.clone()));
+        if let Some(hash) = header_hash {
+            element.children.push(XmlElement::with_text("HeaderHash", hash.to_base64(STANDARD)));
+        }
+        element.children.push(XmlElement::with_text("DatabaseName", meta.database_name.clone()));
+        element.children.push(XmlElement::with_text("DatabaseDescription",
+                                                    meta.database_description.clone()));
+        element.children.push(XmlElement::with_text("DefaultUserName", meta.default_username.clone()));
+        element.children.push(XmlElement::with_text("MaintenanceHistoryDays",
+                                                    meta.maintenance_history_days.to_string()));
+        element.children.push(save_memory_protection(&meta.memory_protection));
+        element.children.push(XmlElement::with_text("RecycleBinEnabled",
+                                                    save_bool(meta.recycle_bin_enabled)));
+        element.children.push(save_uuid("RecycleBinUUID", &meta.recycle_bin_uuid));
+
+        // KDBX 4.x stores the attachments in the inner header
+        if !self.kdbx4 {
+            let mut pool = XmlElement::new("Binaries");
+            for (id, binary) in binaries.iter().enumerate() {
+                let mut data = binary.data.clone();
+                let mut element = XmlElement::new("Binary");
+                element.attributes.push(("ID".to_string(), id.to_string()));
+                if binary.protected {
+                    element.attributes.push(("Protected".to_string(), "True".to_string()));
+                    self.stream.process(&mut data);
+                } else if self.compression == 1 {
+                    element.attributes.push(("Compressed".to_string(), "True".to_string()));
+                    data = try!(compress(1, data));
+                }
+                element.text = data.to_base64(STANDARD);
+                pool.children.push(element);
+            }
+            element.children.push(pool);
+        }
+
+        let mut custom_data = XmlElement::new("CustomData");
+        for &(ref key, ref value) in meta.custom_data.iter() {
+            let mut item = XmlElement::new("Item");
+            item.children.push(XmlElement::with_text("Key", key.clone()));
+            item.children.push(XmlElement::with_text("Value", value.clone()));
+            custom_data.children.push(item);
+        }
+        element.children.push(custom_data);
+
+        for unknown in meta.unknown.iter() {
+            element.children.push(self.protect(unknown.clone()));
+        }
+
+        Ok(element)
+    }
+
+    fn save_group(&mut self, group: &mut V2Group) -> Result<XmlElement, V2KdbxError> {
+        let mut element = XmlElement::new("Group");
+        element.children.push(save_uuid("UUID", &group.uuid));
+        element.children.push(XmlElement::with_text("Name", group.name.clone()));
+        element.children.push(XmlElement::with_text("Notes", group.notes.clone()));
+        element.children.push(XmlElement::with_text("IconID", group.icon_id.to_string()));
+        if let Some(ref uuid) = group.custom_icon_uuid {
+            element.children.push(save_uuid("CustomIconUUID", uuid));
+        }
+        element.children.push(save_times(&group.times, self.kdbx4));
+        element.children.push(XmlElement::with_text("IsExpanded", save_bool(group.is_expanded)));
+        element.children.push(XmlElement::with_text("DefaultAutoTypeSequence",
+                                                    group.default_autotype_sequence.clone()));
+        element.children.push(XmlElement::with_text("EnableAutoType",
+                                                    save_nullable_bool(group.enable_autotype)));
+        element.children.push(XmlElement::with_text("EnableSearching",
+                                                    save_nullable_bool(group.enable_searching)));
+        element.children.push(save_uuid("LastTopVisibleEntry", &group.last_top_visible_entry));
+        for unknown in group.unknown.iter() {
+            element.children.push(self.protect(unknown.clone()));
+        }
+
+        for entry in group.entries.iter() {
+            element.children.push(try!(self.save_entry(&mut entry.borrow_mut())));
+        }
+        for subgroup in group.groups.iter() {
+            element.children.push(try!(self.save_group(&mut subgroup.borrow_mut())));
+        }
+
+        Ok(element)
+    }
+
+    fn save_entry(&mut self, entry: &mut V2Entry) -> Result<XmlElement, V2KdbxError> {
+        let mut element = XmlElement::new("Entry");
+        element.children.push(save_uuid("UUID", &entry.uuid));
+        element.children.push(XmlElement::with_text("IconID", entry.icon_id.to_string()));
+        if let Some(ref uuid) = entry.custom_icon_uuid {
+            element.children.push(save_uuid("CustomIconUUID", uuid));
+        }
+        element.children.push(XmlElement::with_text("ForegroundColor", entry.foreground_color.clone()));
+        element.children.push(XmlElement::with_text("BackgroundColor", entry.background_color.clone()));
+        element.children.push(XmlElement::with_text("OverrideURL", entry.override_url.clone()));
+        element.children.push(XmlElement::with_text("Tags", entry.tags.clone()));
+        element.children.push(save_times(&entry.times, self.kdbx4));
+
+        for string in entry.strings.iter_mut() {
+            element.children.push(self.save_string(string));
+        }
+        for binary in entry.binaries.iter() {
+            let mut binary_element = XmlElement::new("Binary");
+            binary_element.children.push(XmlElement::with_text("Key", binary.key.clone()));
+            let mut value = XmlElement::new("Value");
+            value.attributes.push(("Ref".to_string(), binary.id.to_string()));
+            binary_element.children.push(value);
+            element.children.push(binary_element);
+        }
+        for unknown in entry.unknown.iter() {
+            element.children.push(self.protect(unknown.clone()));
+        }
+
+        if entry.history.len() > 0 {
+            let mut history = XmlElement::new("History");
+            for old_entry in entry.history.iter_mut() {
+                history.children.push(try!(self.save_entry(old_entry)));
+            }
+            element.children.push(history);
+        }
+
+        Ok(element)
+    }
+
+    // Sensitive data in this function:
+    // * string
+    // * value
+    //
+    // At the end of this function:
+    // * string is deleted
+    // * value is encrypted with the inner random stream
+    fn save_string(&mut self, string: &mut V2String) -> XmlElement {
+        let mut element = XmlElement::new("String");
+        element.children.push(XmlElement::with_text("Key", string.key.clone()));
+        let value_element = match string.value {
+            V2Value::Plain(ref value) => XmlElement::with_text("Value", value.clone()),
+            V2Value::Protected(ref mut value) => {
+                value.unlock();
+                let mut encrypted = value.string.as_bytes().to_vec();
+                value.delete();
+                self.stream.process(&mut encrypted);
+
+                let mut value_element = XmlElement::with_text("Value", encrypted.to_base64(STANDARD));
+                value_element.attributes.push(("Protected".to_string(), "True".to_string()));
+                value_element
+            }
+        };
+        element.children.push(value_element);
+        element
+    }
+
+    // Encrypt protected values of elements which aren't modelled
+    fn protect(&mut self, mut element: XmlElement) -> XmlElement {
+        if element.is_protected() {
+            let mut value = element.text.into_bytes();
+            self.stream.process(&mut value);
+            element.text = value.to_base64(STANDARD);
+        }
+        element.children = element.children
+                                  .into_iter()
+                                  .map(|child| self.protect(child))
+                                  .collect();
+        element
+    }
+}
+
+fn parse_times(element: &XmlElement) -> Result<V2Times, V2KdbxError> {
+    let mut times = V2Times::new();
+    for child in element.children.iter() {
+        match &child.name[..] {
+            "CreationTime" => times.creation = try!(parse_date(&child.text)),
+            "LastModificationTime" => times.last_mod = try!(parse_date(&child.text)),
+            "LastAccessTime" => times.last_access = try!(parse_date(&child.text)),
+            "ExpiryTime" => times.expire = try!(parse_date(&child.text)),
+            "Expires" => times.expires = parse_bool(&child.text),
+            "UsageCount" => times.usage_count = try!(parse_u32(&child.text)),
+            "LocationChanged" => times.location_changed = try!(parse_date(&child.text)),
+            _ => (),
+        }
+    }
+    Ok(times)
+}
+
+fn save_times(times: &V2Times, kdbx4: bool) -> XmlElement {
+    let mut element = XmlElement::new("Times");
+    element.children.push(XmlElement::with_text("CreationTime", save_date(&times.creation, kdbx4)));
+    element.children.push(XmlElement::with_text("LastModificationTime", save_date(&times.last_mod, kdbx4)));
+    element.children.push(XmlElement::with_text("LastAccessTime", save_date(&times.last_access, kdbx4)));
+    element.children.push(XmlElement::with_text("ExpiryTime", save_date(&times.expire, kdbx4)));
+    element.children.push(XmlElement::with_text("Expires", save_bool(times.expires)));
+    element.children.push(XmlElement::with_text("UsageCount", times.usage_count.to_string()));
+    element.children.push(XmlElement::with_text("LocationChanged", save_date(&times.location_changed, kdbx4)));
+    element
+}
+
+fn parse_memory_protection(element: &XmlElement) -> V2MemoryProtection {
+    let flag = |name: &str, default: bool| element.child(name).map_or(default, |c| parse_bool(&c.text));
+    V2MemoryProtection {
+        protect_title: flag("ProtectTitle", false),
+        protect_username: flag("ProtectUserName", false),
+        protect_password: flag("ProtectPassword", true),
+        protect_url: flag("ProtectURL", false),
+        protect_notes: flag("ProtectNotes", false),
+    }
+}
+
+fn save_memory_protection(protection: &V2MemoryProtection) -> XmlElement {
+    let mut element = XmlElement::new("MemoryProtection");
+    element.children.push(XmlElement::with_text("ProtectTitle", save_bool(protection.protect_title)));
+    element.children.push(XmlElement::with_text("ProtectUserName", save_bool(protection.protect_username)));
+    element.children.push(XmlElement::with_text("ProtectPassword", save_bool(protection.protect_password)));
+    element.children.push(XmlElement::with_text("ProtectURL", save_bool(protection.protect_url)));
+    element.children.push(XmlElement::with_text("ProtectNotes", save_bool(protection.protect_notes)));
+    element
+}
+
+fn parse_uuid(text: &str) -> Result<Uuid, V2KdbxError> {
+    let bytes = try!(text.from_base64().map_err(|_| V2KdbxError::ConvertErr));
+    Uuid::from_bytes(&bytes).map_err(|_| V2KdbxError::ConvertErr)
+}
+
+fn save_uuid(name: &str, uuid: &Uuid) -> XmlElement {
+    XmlElement::with_text(name, uuid.as_bytes().to_base64(STANDARD))
+}
+
+fn parse_u32(text: &str) -> Result<u32, V2KdbxError> {
+    text.trim().parse::<u32>().map_err(|_| V2KdbxError::ConvertErr)
+}
+
+fn parse_bool(text: &str) -> bool {
+    text.trim().eq_ignore_ascii_case("true")
+}
+
+fn save_bool(value: bool) -> String {
+    if value { "True".to_string() } else { "False".to_string() }
+}
+
+fn parse_nullable_bool(text: &str) -> Option<bool> {
+    if text.trim().eq_ignore_ascii_case("null") || text.trim().len() == 0 {
+        None
+    } else {
+        Some(parse_bool(text))
+    }
+}
+
+fn save_nullable_bool(value: Option<bool>) -> String {
+    match value {
+        Some(v) => save_bool(v),
+        None => "null".to_string(),
+    }
+}
+
+// Seconds from 0001-01-01 to 1970-01-01
+const KDBX4_EPOCH_OFFSET: i64 = 62135596800;
+
+// Dates are stored as UTC in ISO 8601 or in KDBX 4.x as base64 encoded
+// i64 of the seconds since 0001-01-01
+fn parse_date(text: &str) -> Result<DateTime<Local>, V2KdbxError> {
+    let text = text.trim();
+    if !text.contains('-') {
+        let bytes = try!(text.from_base64().map_err(|_| V2KdbxError::ConvertErr));
+        if bytes.len() != 8 {
+            return Err(V2KdbxError::ConvertErr);
+        }
+        let seconds = try!(slice_to_u64(&bytes).map_err(|_| V2KdbxError::ConvertErr)) as i64;
+        let date = try!(Utc.timestamp_opt(seconds - KDBX4_EPOCH_OFFSET, 0)
+                           .single()
+                           .ok_or(V2KdbxError::ConvertErr));
+        return Ok(date.with_timezone(&Local));
+    }
+    match DateTime::parse_from_rfc3339(text) {
+        Ok(date) => Ok(date.with_timezone(&Local)),
+        Err(_) => {
+            let date = try!(NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S")
+                                .map_err(|_| V2KdbxError::ConvertErr));
+            Ok(Local.from_utc_datetime(&date))
+        }
+    }
+}
+
+fn save_date(date: &DateTime<Local>, kdbx4: bool) -> String {
+    if kdbx4 {
+        let seconds = date.timestamp() + KDBX4_EPOCH_OFFSET;
+        u64_to_vec_u8(seconds as u64).to_base64(STANDARD)
+    } else {
+        date.with_timezone(&Utc).format("%Y-%m-%dT%H:%M:%SZ").to_string()
+    }
+}
@@ -0,0 +1,191 @@
+use chrono::{Local, TimeZone};
+
+use kdbx::convert::{detect_format, v1_to_v2, v2_to_v1, ConversionIssue, DatabaseFormat};
+use kdbx::v2error::V2KdbxError;
+use kdbx::v2kdbx::V2Kdbx;
+use kpdb::v1entry::V1Entry;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+fn unlock(string: &Option<SecureString>) -> Option<String> {
+    string.as_ref().and_then(|s| s.with_unlocked(|plain| plain.to_string()))
+}
+
+fn issue(path: &str, field: &str) -> ConversionIssue {
+    ConversionIssue {
+        path: path.to_string(),
+        field: field.to_string(),
+    }
+}
+
+#[test]
+fn test_detect_format() {
+    assert_eq!(detect_format("test/test_password.kdb"), Ok(DatabaseFormat::Kdb));
+    assert_eq!(detect_format("test/test_kdbx31.kdbx"), Ok(DatabaseFormat::Kdbx));
+    assert_eq!(detect_format("test/test_key"), Err(V2KdbxError::SignatureErr));
+    assert_eq!(detect_format("test/does_not_exist"), Err(V2KdbxError::FileErr));
+}
+
+#[test]
+fn test_v1_roundtrip() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+
+    // The test databases have neither attachments nor Meta-Info entries
+    {
+        let entry = db.entries[0];
+        let entry = &mut db[entry];
+        entry.binary_desc = Some("hello.txt".to_string());
+        entry.binary = Some(b"Hello, world!\n".to_vec());
+        entry.expire = Local.ymd(2020, 1, 2).and_hms(3, 4, 5);
+    }
+    let group = db.groups[2];
+    let mut meta_info = V1Entry::new();
+    meta_info.title = "Meta-Info".to_string();
+    meta_info.url = Some("$".to_string());
+    meta_info.username = Some(SecureString::new("SYSTEM".to_string()));
+    meta_info.password = Some(SecureString::new("".to_string()));
+    meta_info.comment = Some("KPX_GROUP_TREE_STATE".to_string());
+    meta_info.binary_desc = Some("bin-stream".to_string());
+    meta_info.binary = Some(vec![1, 0, 0, 0, 3, 0, 0, 0, 1]);
+    meta_info.image = 3;
+    meta_info.creation = Local.ymd(2014, 2, 26).and_hms(10, 0, 0);
+    db.push_entry(meta_info, Some(group));
+
+    let (mut kdbx, report) = v1_to_v2(&db,
+                                      "test/new_converted.kdbx".to_string(),
+                                      Some("test".to_string()),
+                                      None)
+                                 .ok()
+                                 .unwrap();
+    assert!(report.is_lossless());
+    {
+        let root = kdbx.root_group.borrow();
+        assert_eq!(root.groups.len(), 1);
+        let internet = root.groups[0].borrow();
+        assert_eq!(internet.name, "Internet");
+        assert_eq!(internet.groups.len(), 2);
+        assert_eq!(internet.groups[1].borrow().groups[1].borrow().groups.len(), 2);
+        let entry = internet.entries[0].borrow();
+        assert_eq!(entry.uuid, db[db.entries[0]].uuid);
+        assert_eq!(entry.binaries[0].key, "hello.txt");
+        assert!(entry.times.expires);
+        assert_eq!(kdbx.meta.custom_data.len(), 1);
+    }
+    assert!(kdbx.save(None, None, None).is_ok());
+
+    let mut kdbx = V2Kdbx::new("test/new_converted.kdbx".to_string(), Some("test".to_string()), None)
+                       .ok()
+                       .unwrap();
+    assert_eq!(kdbx.load(), Ok(()));
+    let (mut back, report) = v2_to_v1(&kdbx,
+                                      "test/new_converted.kdb".to_string(),
+                                      Some("test".to_string()),
+                                      None)
+                                 .ok()
+                                 .unwrap();
+    assert!(report.is_lossless());
+    assert_eq!(back.header.key_transf_rounds, 150000);
+    assert!(back.save(None, None, None).is_ok());
+
+    let mut back = V1Kpdb::new("test/new_converted.kdb".to_string(), Some("test".to_string()), None)
+                       .ok()
+                       .unwrap();
+    assert!(back.load().is_ok());
+
+    // Both formats store the timestamps in seconds
+    assert_eq!(back.groups.len(), db.groups.len());
+    for (&group, &original) in back.groups.iter().zip(db.groups.iter()) {
+        let group = &back[group];
+        let original = &db[original];
+        assert_eq!(group.id, original.id);
+        assert_eq!(group.title, original.title);
+        assert_eq!(group.level, original.level);
+        assert_eq!(group.image, original.image);
+        assert_eq!(group.creation.timestamp(), original.creation.timestamp());
+        assert_eq!(group.last_mod.timestamp(), original.last_mod.timestamp());
+        assert_eq!(group.last_access.timestamp(), original.last_access.timestamp());
+        assert_eq!(group.expire.timestamp(), original.expire.timestamp());
+        assert_eq!(back[group.parent.unwrap()].id,
+                   db[original.parent.unwrap()].id);
+    }
+
+    assert_eq!(back.entries.len(), db.entries.len());
+    for &original in db.entries.iter() {
+        let original = &db[original];
+        let entry = back.entries.iter().find(|&&e| back[e].uuid == original.uuid).unwrap();
+        let entry = &back[*entry];
+        assert_eq!(entry.group_id, original.group_id);
+        assert_eq!(entry.image, original.image);
+        assert_eq!(entry.title, original.title);
+        assert_eq!(entry.url, original.url);
+        assert_eq!(unlock(&entry.username), unlock(&original.username));
+        assert_eq!(unlock(&entry.password), unlock(&original.password));
+        assert_eq!(entry.comment, original.comment);
+        assert_eq!(entry.binary_desc, original.binary_desc);
+        assert_eq!(entry.binary, original.binary);
+        assert_eq!(entry.creation.timestamp(), original.creation.timestamp());
+        assert_eq!(entry.last_mod.timestamp(), original.last_mod.timestamp());
+        assert_eq!(entry.last_access.timestamp(), original.last_access.timestamp());
+        assert_eq!(entry.expire.timestamp(), original.expire.timestamp());
+    }
+}
+
+#[test]
+fn test_v2_to_v1_report() {
+    let mut db = V2Kdbx::new("test/test_kdbx31.kdbx".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    let moved = Local.ymd(2020, 1, 2).and_hms(3, 4, 5);
+    db.root_group.borrow().groups[0].borrow_mut().times.location_changed = moved;
+    let meta_stream = "KeePass1.MetaStream.0c31ac94234766368b8042815e5a1460";
+    db.meta.custom_data.push((meta_stream.to_string(), "999:0:0:0:0:0:AQ==:KPX_CUSTOM_ICONS_4".to_string()));
+    let (mut kpdb, report) = v2_to_v1(&db,
+                                      "test/new_converted_kdbx31.kdb".to_string(),
+                                      Some("test".to_string()),
+                                      None)
+                                 .ok()
+                                 .unwrap();
+    assert!(!report.is_lossless());
+    for expected in vec![issue("", "DatabaseName"),
+                         issue("", "Custom data KPXC_test"),
+                         issue("", "DeletedObjects (1 objects)"),
+                         issue("Root", "Entries in the root group (moved into a group of the same name)"),
+                         issue("Root/foo", "String field Extra"),
+                         issue("Root/foo", "Tags"),
+                         issue("Root/foo", "History (1 versions)"),
+                         issue("Root/foo", "AutoType"),
+                         issue("Internet", "Notes"),
+                         issue("Internet", "EnableAutoType"),
+                         issue("Internet", "LocationChanged"),
+                         issue("",
+                               &format!("Custom data {} (group 999 doesn't exist, moved into the first group)",
+                                        meta_stream))] {
+        assert!(report.issues.contains(&expected), "missing {}", expected);
+    }
+
+    assert!(kpdb.save(None, None, None).is_ok());
+    let mut kpdb = V1Kpdb::new("test/new_converted_kdbx31.kdb".to_string(),
+                               Some("test".to_string()),
+                               None)
+                       .ok()
+                       .unwrap();
+    assert!(kpdb.load().is_ok());
+    assert_eq!(kpdb.groups.len(), 2);
+    assert_eq!(kpdb[kpdb.groups[0]].title, "Root");
+    assert_eq!(kpdb[kpdb.groups[1]].title, "Internet");
+    let entry = &kpdb[kpdb.entries[0]];
+    assert_eq!(entry.title, "foo");
+    assert_eq!(entry.group_id, kpdb[kpdb.groups[0]].id);
+    assert_eq!(unlock(&entry.password), Some("DLE\"H<JZ|E".to_string()));
+    assert_eq!(entry.comment, Some("multi\nline".to_string()));
+    assert_eq!(entry.binary_desc, Some("hello.txt".to_string()));
+    assert_eq!(entry.binary, Some(b"Hello, world!\n".to_vec()));
+    let meta_info = &kpdb[kpdb.entries[kpdb.entries.len() - 1]];
+    assert_eq!(meta_info.title, "Meta-Info");
+    assert_eq!(meta_info.group_id, kpdb[kpdb.groups[0]].id);
+    assert_eq!(meta_info.binary, Some(vec![1]));
+}
@@ -0,0 +1,353 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+
+use chrono::{Datelike, Timelike, Utc};
+
+use kdbx::v2entry::V2Value;
+use kdbx::v2error::V2KdbxError;
+use kdbx::v2header::{V2Argon2Params, V2Cipher, V2Kdf};
+use kdbx::v2kdbx::V2Kdbx;
+
+fn unlock(value: Option<&mut V2Value>) -> String {
+    match value {
+        Some(&mut V2Value::Protected(ref mut s)) => {
+            s.unlock();
+            let plain = s.string.clone();
+            s.delete();
+            plain
+        }
+        Some(&mut V2Value::Plain(ref s)) => s.clone(),
+        None => panic!("missing string"),
+    }
+}
+
+#[test]
+fn test_load() {
+    let mut db = V2Kdbx::new("test/test_kdbx31.kdbx".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.header.cipher(), Ok(V2Cipher::Aes));
+    match db.header.kdf {
+        V2Kdf::Aes { rounds, .. } => assert_eq!(rounds, 6000),
+        _ => assert!(false),
+    }
+    check_content(&db);
+}
+
+// Checks the content all test/test_kdbx* databases share
+fn check_content(db: &V2Kdbx) {
+    assert_eq!(db.meta.database_name, "test db");
+    assert_eq!(db.meta.database_description, "A <test> database");
+    assert_eq!(db.meta.default_username, "foo");
+    assert_eq!(db.meta.maintenance_history_days, 365);
+    assert!(db.meta.memory_protection.protect_password);
+    assert!(db.meta.recycle_bin_enabled);
+    assert_eq!(db.meta.custom_data, vec![("KPXC_test".to_string(), "42".to_string())]);
+    assert_eq!(db.binaries.len(), 1);
+    assert_eq!(db.binaries[0].data, b"Hello, world!\n".to_vec());
+    assert_eq!(db.deleted_objects.len(), 1);
+
+    let root = db.root_group.borrow();
+    assert_eq!(root.name, "Root");
+    assert_eq!(root.uuid.to_string(), "11111111-2222-3333-4444-555555555555");
+    assert_eq!(root.icon_id, 48);
+    assert_eq!(root.entries.len(), 1);
+    assert_eq!(root.groups.len(), 1);
+
+    let mut entry = root.entries[0].borrow_mut();
+    assert_eq!(entry.title(), "foo");
+    assert_eq!(entry.uuid.to_string(), "0c31ac94-2347-6636-b8c0-42815e5a1460");
+    assert_eq!(unlock(entry.get_string_mut("UserName")), "foo");
+    assert_eq!(unlock(entry.get_string_mut("Password")), "DLE\"H<JZ|E");
+    assert_eq!(unlock(entry.get_string_mut("URL")), "http://foo");
+    assert_eq!(unlock(entry.get_string_mut("Notes")), "multi\nline");
+    assert_eq!(unlock(entry.get_string_mut("Extra")), "s3cr3t");
+    assert_eq!(entry.tags, "a;b");
+    assert_eq!(entry.times.usage_count, 3);
+    let creation = entry.times.creation.with_timezone(&Utc);
+    assert_eq!(creation.year(), 2014);
+    assert_eq!(creation.month(), 2);
+    assert_eq!(creation.day(), 26);
+    assert_eq!(creation.hour(), 10);
+    assert_eq!(entry.binaries.len(), 1);
+    assert_eq!(entry.binaries[0].key, "hello.txt");
+    assert_eq!(entry.binaries[0].id, 0);
+    assert_eq!(entry.unknown[0].name, "AutoType");
+    assert_eq!(entry.history.len(), 1);
+    assert_eq!(unlock(entry.history[0].get_string_mut("Password")), "old");
+
+    let group = root.groups[0].borrow();
+    assert_eq!(group.name, "Internet");
+    assert_eq!(group.notes, "net");
+    assert!(group.times.expires);
+    assert_eq!(group.enable_autotype, Some(false));
+    assert_eq!(group.enable_searching, Some(true));
+    let mut entry = group.entries[0].borrow_mut();
+    assert_eq!(entry.title(), "bar");
+    assert_eq!(unlock(entry.get_string_mut("UserName")), "bär");
+    assert_eq!(unlock(entry.get_string_mut("Password")), "pässword");
+    assert_eq!(unlock(entry.get_string_mut("URL")), "https://example.com/a?b=1&c=2");
+}
+
+#[test]
+fn test_load_w_keyfile() {
+    let mut db = V2Kdbx::new("test/test_kdbx31_both.kdbx".to_string(),
+                             Some("test".to_string()),
+                             Some("test/test_key".to_string()))
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.root_group.borrow().entries[0].borrow().title(), "foo");
+
+    let mut db = V2Kdbx::new("test/test_kdbx31_both.kdbx".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::HashErr));
+}
+
+#[test]
+fn test_load_errors() {
+    let mut db = V2Kdbx::new("test/test_kdbx31.kdbx".to_string(), Some("tes".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::HashErr));
+
+    let mut db = V2Kdbx::new("test/test_password.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::SignatureErr));
+
+    // Flip a bit in the last block of the payload. Because of CBC
+    // only this block decrypts to garbage, which the block hash catches.
+    let mut raw: Vec<u8> = vec![];
+    File::open("test/test_kdbx31.kdbx").unwrap().read_to_end(&mut raw).unwrap();
+    let len = raw.len();
+    raw[len - 100] ^= 1;
+    File::create("test/new_kdbx31_tampered.kdbx").unwrap().write_all(&raw).unwrap();
+    let mut db = V2Kdbx::new("test/new_kdbx31_tampered.kdbx".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::BlockHashErr));
+}
+
+#[test]
+fn test_save() {
+    let mut db = V2Kdbx::new("test/test_kdbx31.kdbx".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.save(Some("test/new_kdbx31.kdbx".to_string()), Some("new".to_string()), None),
+               Ok(()));
+
+    let mut db = V2Kdbx::new("test/new_kdbx31.kdbx".to_string(), Some("new".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.meta.database_description, "A <test> database");
+    assert_eq!(db.binaries[0].data, b"Hello, world!\n".to_vec());
+    assert_eq!(db.deleted_objects.len(), 1);
+    {
+        let root = db.root_group.borrow();
+        let mut entry = root.entries[0].borrow_mut();
+        assert_eq!(unlock(entry.get_string_mut("Password")), "DLE\"H<JZ|E");
+        assert_eq!(unlock(entry.get_string_mut("Extra")), "s3cr3t");
+        assert_eq!(entry.unknown[0].name, "AutoType");
+        assert_eq!(unlock(entry.history[0].get_string_mut("Password")), "old");
+        let group = root.groups[0].borrow();
+        assert_eq!(unlock(group.entries[0].borrow_mut().get_string_mut("Password")),
+                   "pässword");
+    }
+
+    // Twofish and no compression
+    db.header.set_cipher(V2Cipher::Twofish);
+    db.header.compression = 0;
+    assert_eq!(db.save(None, None, None), Ok(()));
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.header.cipher(), Ok(V2Cipher::Twofish));
+    assert_eq!(db.root_group.borrow().entries[0].borrow().title(), "foo");
+}
+
+#[test]
+fn test_in_memory() {
+    let mut raw: Vec<u8> = vec![];
+    File::open("test/test_kdbx40.kdbx").unwrap().read_to_end(&mut raw).unwrap();
+
+    // The path isn't used at all
+    let mut db = V2Kdbx::new("".to_string(), Some("test".to_string()), None).ok().unwrap();
+    assert_eq!(db.load_from_bytes(&raw), Ok(()));
+    assert_eq!(db.root_group.borrow().entries[0].borrow().title(), "foo");
+
+    let mut saved: Vec<u8> = vec![];
+    assert_eq!(db.save_to_writer(&mut saved, Some("new".to_string()), None), Ok(()));
+    assert_eq!(db.path, "");
+
+    let mut db = V2Kdbx::new("".to_string(), Some("new".to_string()), None).ok().unwrap();
+    assert_eq!(db.load_from_reader(&mut Cursor::new(saved)), Ok(()));
+    assert_eq!(db.root_group.borrow().entries[0].borrow().title(), "foo");
+
+    assert_eq!(db.load_from_bytes(&raw[..8]), Err(V2KdbxError::FileErr));
+}
+
+#[test]
+fn test_create() {
+    let mut db = V2Kdbx::new("test/new_kdbx31_created.kdbx".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    let root = db.root_group.clone();
+    let group = db.create_group("Internet".to_string(), None);
+    db.create_entry(group.clone(),
+                    "foo".to_string(),
+                    Some("http://foo".to_string()),
+                    None,
+                    Some("user".to_string()),
+                    Some("secret".to_string()));
+    assert_eq!(root.borrow().groups.len(), 1);
+    assert_eq!(db.save(None, None, None), Ok(()));
+
+    let mut db = V2Kdbx::new("test/new_kdbx31_created.kdbx".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    let root = db.root_group.borrow();
+    assert_eq!(root.name, "Root");
+    let group = root.groups[0].borrow();
+    assert_eq!(group.name, "Internet");
+    let mut entry = group.entries[0].borrow_mut();
+    assert_eq!(entry.title(), "foo");
+    assert_eq!(unlock(entry.get_string_mut("UserName")), "user");
+    assert_eq!(unlock(entry.get_string_mut("Password")), "secret");
+    match entry.get_string("Password") {
+        Some(&V2Value::Protected(_)) => (),
+        _ => assert!(false),
+    }
+}
+
+#[test]
+fn test_load_kdbx4() {
+    let mut db = V2Kdbx::new("test/test_kdbx40.kdbx".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.header.version, 0x00040000);
+    assert_eq!(db.header.cipher(), Ok(V2Cipher::Aes));
+    assert_eq!(db.header.inner_random_stream, 3);
+    match db.header.kdf {
+        V2Kdf::Aes { rounds, .. } => assert_eq!(rounds, 6000),
+        _ => assert!(false),
+    }
+    check_content(&db);
+
+    let mut db = V2Kdbx::new("test/test_kdbx41_argon2id.kdbx".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.header.version, 0x00040001);
+    assert_eq!(db.header.cipher(), Ok(V2Cipher::ChaCha20));
+    match db.header.kdf {
+        V2Kdf::Argon2id(ref params) => {
+            assert_eq!(params.iterations, 2);
+            assert_eq!(params.memory, 1024 * 1024);
+            assert_eq!(params.parallelism, 1);
+            assert_eq!(params.version, 0x13);
+        }
+        _ => assert!(false),
+    }
+    check_content(&db);
+
+    let mut db = V2Kdbx::new("test/test_kdbx41_argon2d_both.kdbx".to_string(),
+                             Some("test".to_string()),
+                             Some("test/test_key".to_string()))
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    match db.header.kdf {
+        V2Kdf::Argon2d(_) => (),
+        _ => assert!(false),
+    }
+    check_content(&db);
+}
+
+#[test]
+fn test_load_kdbx4_errors() {
+    let mut db = V2Kdbx::new("test/test_kdbx41_argon2id.kdbx".to_string(),
+                             Some("tes".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::HashErr));
+
+    let mut raw: Vec<u8> = vec![];
+    File::open("test/test_kdbx40.kdbx").unwrap().read_to_end(&mut raw).unwrap();
+
+    // A changed header doesn't match the hash behind it
+    let mut tampered = raw.clone();
+    tampered[50] ^= 1;
+    File::create("test/new_kdbx40_header.kdbx").unwrap().write_all(&tampered).unwrap();
+    let mut db = V2Kdbx::new("test/new_kdbx40_header.kdbx".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::HeaderHashErr));
+
+    // A changed block doesn't match its HMAC
+    let mut tampered = raw.clone();
+    let len = tampered.len();
+    tampered[len - 100] ^= 1;
+    File::create("test/new_kdbx40_block.kdbx").unwrap().write_all(&tampered).unwrap();
+    let mut db = V2Kdbx::new("test/new_kdbx40_block.kdbx".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::BlockHashErr));
+}
+
+#[test]
+fn test_save_kdbx4() {
+    let mut db = V2Kdbx::new("test/test_kdbx41_argon2id.kdbx".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.save(Some("test/new_kdbx41.kdbx".to_string()), None, None), Ok(()));
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.header.cipher(), Ok(V2Cipher::ChaCha20));
+    check_content(&db);
+
+    // Argon2 upgrades a KDBX 3.1 database
+    let mut db = V2Kdbx::new("test/test_kdbx31.kdbx".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    db.header.kdf = V2Kdf::Argon2d(V2Argon2Params {
+        salt: vec![],
+        iterations: 2,
+        memory: 1024 * 1024,
+        parallelism: 2,
+        version: 0x13,
+    });
+    assert_eq!(db.save(Some("test/new_kdbx31_upgraded.kdbx".to_string()), None, None),
+               Ok(()));
+    let mut db = V2Kdbx::new("test/new_kdbx31_upgraded.kdbx".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.header.major_version(), 4);
+    assert_eq!(db.header.inner_random_stream, 3);
+    check_content(&db);
+}
@@ -0,0 +1,149 @@
+use uuid::Uuid;
+
+use kdbx::v2times::V2Times;
+use kdbx::xmldom::XmlElement;
+use super::super::sec_str::SecureString;
+
+#[doc = "
+The value of a string field of an entry. Protected values are
+encrypted with the inner random stream in the database file and
+held as SecureString in memory.
+"]
+pub enum V2Value {
+    /// Value stored as plain text
+    Plain(String),
+    /// Value protected in file and memory
+    Protected(SecureString),
+}
+
+#[doc = "
+A string field of an entry, e.g. Title or Password
+"]
+pub struct V2String {
+    /// Name of the field
+    pub key: String,
+    /// Value of the field
+    pub value: V2Value,
+}
+
+#[doc = "
+Reference from an entry to an attachment in the binary pool of the database
+"]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct V2BinaryRef {
+    /// File name of the attachment
+    pub key: String,
+    /// Index into V2Kdbx::binaries
+    pub id: usize,
+}
+
+#[doc = "
+An attachment in the binary pool of the database
+"]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct V2Binary {
+    /// Content of the attachment
+    pub data: Vec<u8>,
+    /// Whether the content is protected by the inner random stream
+    pub protected: bool,
+}
+
+#[doc = "
+Implements an entry in a KeePass 2.x database.
+"]
+pub struct V2Entry {
+    /// UUID of the entry
+    pub uuid: Uuid,
+    /// Used to specify an icon for the entry
+    pub icon_id: u32,
+    /// Custom icon from the meta data, if any
+    pub custom_icon_uuid: Option<Uuid>,
+    /// Foreground color as #RRGGBB or empty
+    pub foreground_color: String,
+    /// Background color as #RRGGBB or empty
+    pub background_color: String,
+    /// URL override for the entry
+    pub override_url: String,
+    /// Tags separated by ; or ,
+    pub tags: String,
+    /// Timestamps of the entry
+    pub times: V2Times,
+    /// String fields like Title, UserName, Password, URL, Notes
+    /// and custom ones
+    pub strings: Vec<V2String>,
+    /// Attachments
+    pub binaries: Vec<V2BinaryRef>,
+    /// Older versions of the entry
+    pub history: Vec<V2Entry>,
+    /// Elements which aren't modelled here, e.g. AutoType.
+    /// They are written back on save unchanged.
+    pub unknown: Vec<XmlElement>,
+}
+
+impl V2Entry {
+    /// Don't use this to create an empty entry.
+    /// Normally you want to use the API
+    /// of V2Kdbx to do this
+    pub fn new() -> V2Entry {
+        V2Entry {
+            uuid: Uuid::new_v4(),
+            icon_id: 0,
+            custom_icon_uuid: None,
+            foreground_color: "".to_string(),
+            background_color: "".to_string(),
+            override_url: "".to_string(),
+            tags: "".to_string(),
+            times: V2Times::new(),
+            strings: vec![],
+            binaries: vec![],
+            history: vec![],
+            unknown: vec![],
+        }
+    }
+
+    /// Get a string field by its name
+    pub fn get_string(&self, key: &str) -> Option<&V2Value> {
+        self.strings.iter().find(|s| s.key == key).map(|s| &s.value)
+    }
+
+    /// Get a string field by its name for unlocking
+    pub fn get_string_mut(&mut self, key: &str) -> Option<&mut V2Value> {
+        self.strings.iter_mut().find(|s| s.key == key).map(|s| &mut s.value)
+    }
+
+    /// Set a string field. If protected is true the value is moved
+    /// into a SecureString.
+    pub fn set_string(&mut self, key: &str, value: String, protected: bool) {
+        let value = if protected {
+            V2Value::Protected(SecureString::new(value))
+        } else {
+            V2Value::Plain(value)
+        };
+        match self.strings.iter().position(|s| s.key == key) {
+            Some(index) => self.strings[index].value = value,
+            None => {
+                self.strings.push(V2String {
+                    key: key.to_string(),
+                    value: value,
+                })
+            }
+        }
+    }
+
+    /// Title of the entry. Returns an empty string if the
+    /// title is protected.
+    pub fn title(&self) -> String {
+        match self.get_string("Title") {
+            Some(&V2Value::Plain(ref title)) => title.clone(),
+            _ => "".to_string(),
+        }
+    }
+}
+
+impl PartialEq for V2Entry {
+    fn eq(&self, other: &V2Entry) -> bool {
+        self.uuid == other.uuid
+    }
+}
+
+impl Eq for V2Entry {}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::error;
+
+use kpdb::v1error::V1KpdbError;
+
+pub use self::V2KdbxError::*;
+
+#[doc = "
+Use this for catching various errors that
+can happen when using V2Kdbx.
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum V2KdbxError {
+    /// E.g. Couldn't open a file or file is to
+    /// small.
+    FileErr,
+    /// Something went wrong while the database is
+    /// readed in
+    ReadErr,
+    /// Something went wrong while the database was written
+    WriteErr,
+    /// The file signature in the header is wrong
+    SignatureErr,
+    /// Wrong or unsupported database version
+    VersionErr,
+    /// Not supported cipher used
+    CipherErr,
+    /// Not supported compression algorithm used
+    CompressionErr,
+    /// Not supported inner random stream used
+    InnerStreamErr,
+    /// Not supported key derivation function or
+    /// invalid parameters
+    KdfErr,
+    /// Some error in encryption
+    EncryptErr,
+    /// Some error in decryption
+    DecryptErr,
+    /// The stream start bytes (KDBX 3.1) or the HMAC of
+    /// the header (KDBX 4.x) are wrong.
+    /// Probably the wrong password and/or keyfile
+    /// was used
+    HashErr,
+    /// Hash or HMAC of a block of the payload is wrong.
+    /// The database is corrupted or was tampered with
+    BlockHashErr,
+    /// Hash of the header stored in the payload (KDBX 3.1) or
+    /// after the header (KDBX 4.x) is wrong.
+    /// The header is corrupted or was tampered with
+    HeaderHashErr,
+    /// Header field is missing or has a wrong size
+    HeaderErr,
+    /// The XML payload is malformed
+    XmlErr,
+    /// Some error in parsing
+    ConvertErr,
+    /// Password and/or keyfile needed but at least one of both
+    PassErr,
+    /// Hash of the key in a XML keyfile (version 2.0) is wrong,
+    /// i.e. the keyfile is corrupted
+    KeyfileHashErr,
+    /// The database file was changed by someone else since it was
+    /// loaded or saved, see force_save
+    ModifiedErr,
+}
+
+impl fmt::Display for V2KdbxError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for V2KdbxError {
+    fn description(&self) -> &str {
+        match *self {
+            FileErr => "Couldn't open file or database is too small",
+            ReadErr => "Couldn't read file",
+            WriteErr => "Couldn't write file",
+            SignatureErr => "File signature in header is wrong",
+            VersionErr => "Wrong or unsupported database version",
+            CipherErr => "Encryption algorithm not supported",
+            CompressionErr => "Compression algorithm not supported",
+            InnerStreamErr => "Inner random stream not supported",
+            KdfErr => "Key derivation function not supported or invalid parameters",
+            EncryptErr => "Something went wrong during encryption",
+            DecryptErr => "Something went wrong during decryption",
+            HashErr => "Stream start bytes or header HMAC are wrong, probably wrong password",
+            BlockHashErr => "Block hash is wrong, database is corrupted",
+            HeaderHashErr => "Header hash is wrong, header is corrupted",
+            HeaderErr => "Header field missing or malformed",
+            XmlErr => "Some error while parsing the XML payload",
+            ConvertErr => "Some error while parsing the database",
+            PassErr => "Password and/or keyfile needed but at least one of both",
+            KeyfileHashErr => "Hash of the key in the keyfile is wrong, keyfile is corrupted",
+            ModifiedErr => "Database file was modified by someone else since it was loaded",
+        }
+    }
+}
+
+// The key derivation is shared with KeePass 1.x databases
+impl From<V1KpdbError> for V2KdbxError {
+    fn from(err: V1KpdbError) -> V2KdbxError {
+        match err {
+            V1KpdbError::FileErr => V2KdbxError::FileErr,
+            V1KpdbError::ReadErr => V2KdbxError::ReadErr,
+            V1KpdbError::WriteErr => V2KdbxError::WriteErr,
+            V1KpdbError::EncryptErr => V2KdbxError::EncryptErr,
+            V1KpdbError::PassErr => V2KdbxError::PassErr,
+            V1KpdbError::ConvertErr => V2KdbxError::ConvertErr,
+            V1KpdbError::KeyfileHashErr => V2KdbxError::KeyfileHashErr,
+            V1KpdbError::ModifiedErr => V2KdbxError::ModifiedErr,
+            _ => V2KdbxError::DecryptErr,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use uuid::Uuid;
+
+use kdbx::v2entry::V2Entry;
+use kdbx::v2times::V2Times;
+use kdbx::xmldom::XmlElement;
+
+#[doc = "
+Implements a group of a KeePass 2.x database. Unlike in KeePass 1.x
+databases the group tree is stored as a tree, so a group owns its
+subgroups and entries.
+"]
+pub struct V2Group {
+    /// UUID of the group
+    pub uuid: Uuid,
+    /// Name of the group
+    pub name: String,
+    /// Some free-text-comment about the group
+    pub notes: String,
+    /// Number to specify a icon for the group
+    pub icon_id: u32,
+    /// Custom icon from the meta data, if any
+    pub custom_icon_uuid: Option<Uuid>,
+    /// Timestamps of the group
+    pub times: V2Times,
+    /// Whether the group is expanded in the tree view
+    pub is_expanded: bool,
+    /// Auto-type sequence for the entries of the group
+    pub default_autotype_sequence: String,
+    /// Auto-type for the entries, None means inherited from
+    /// the parent
+    pub enable_autotype: Option<bool>,
+    /// Searching for the entries, None means inherited from
+    /// the parent
+    pub enable_searching: Option<bool>,
+    /// UUID of the entry at the top of the list view
+    pub last_top_visible_entry: Uuid,
+    /// Subgroups
+    pub groups: Vec<Rc<RefCell<V2Group>>>,
+    /// Entries of the group
+    pub entries: Vec<Rc<RefCell<V2Entry>>>,
+    /// Elements which aren't modelled here. They are
+    /// written back on save unchanged.
+    pub unknown: Vec<XmlElement>,
+}
+
+impl V2Group {
+    /// Don't use this to create an empty group.
+    /// Normally you want to use the API
+    /// of V2Kdbx to do this.
+    pub fn new() -> V2Group {
+        V2Group {
+            uuid: Uuid::new_v4(),
+            name: "".to_string(),
+            notes: "".to_string(),
+            icon_id: 0,
+            custom_icon_uuid: None,
+            times: V2Times::new(),
+            is_expanded: true,
+            default_autotype_sequence: "".to_string(),
+            enable_autotype: None,
+            enable_searching: None,
+            last_top_visible_entry: Uuid::nil(),
+            groups: vec![],
+            entries: vec![],
+            unknown: vec![],
+        }
+    }
+}
+
+impl PartialEq for V2Group {
+    fn eq(&self, other: &V2Group) -> bool {
+        self.uuid == other.uuid
+    }
+}
+
+impl Eq for V2Group {}
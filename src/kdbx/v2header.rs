@@ -0,0 +1,221 @@
+use rand;
+
+use kdbx::v2error::V2KdbxError;
+
+/// UUID of AES-256 as outer cipher
+pub const CIPHER_AES: [u8; 16] = [0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50,
+                                  0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff];
+/// UUID of Twofish-256 as outer cipher
+pub const CIPHER_TWOFISH: [u8; 16] = [0xad, 0x68, 0xf2, 0x9f, 0x57, 0x6f, 0x4b, 0xb9,
+                                      0xa3, 0x6a, 0xd4, 0x7a, 0xf9, 0x65, 0x34, 0x6c];
+/// UUID of ChaCha20 as outer cipher (KDBX 4.x)
+pub const CIPHER_CHACHA20: [u8; 16] = [0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5,
+                                       0xa5, 0x24, 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a];
+
+/// UUID of AES-KDF
+pub const KDF_AES: [u8; 16] = [0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60,
+                               0xbf, 0x74, 0x0d, 0x08, 0xc1, 0x8a, 0x4f, 0xea];
+/// UUID of Argon2d
+pub const KDF_ARGON2D: [u8; 16] = [0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b,
+                                   0x91, 0xf7, 0xa9, 0xa4, 0x03, 0xe3, 0x0a, 0x0c];
+/// UUID of Argon2id
+pub const KDF_ARGON2ID: [u8; 16] = [0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73,
+                                    0xb2, 0x3d, 0xfc, 0x3e, 0xc6, 0xf0, 0xa1, 0xe6];
+
+#[doc = "
+The outer ciphers a KeePass 2.x database can be encrypted with.
+"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum V2Cipher {
+    /// AES-256 in CBC mode
+    Aes,
+    /// Twofish-256 in CBC mode
+    Twofish,
+    /// ChaCha20 with a 96 bit nonce. Needs KDBX 4.x
+    ChaCha20,
+}
+
+#[doc = "
+Parameters of the Argon2 key derivation
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V2Argon2Params {
+    /// Salt, regenerated on every save
+    pub salt: Vec<u8>,
+    /// Number of passes
+    pub iterations: u64,
+    /// Memory in bytes
+    pub memory: u64,
+    /// Number of lanes
+    pub parallelism: u32,
+    /// Argon2 version, 0x10 or 0x13
+    pub version: u32,
+}
+
+#[doc = "
+The key derivation functions which transform the composite key.
+KDBX 3.1 only knows AES-KDF.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum V2Kdf {
+    /// Encrypt the key rounds times with AES_ECB like KeePass 1.x does
+    Aes {
+        /// Key of AES_ECB, regenerated on every save
+        seed: Vec<u8>,
+        /// Number of rounds
+        rounds: u64,
+    },
+    /// Argon2d. Needs KDBX 4.x
+    Argon2d(V2Argon2Params),
+    /// Argon2id. Needs KDBX 4.x
+    Argon2id(V2Argon2Params),
+}
+
+impl V2Kdf {
+    /// Returns the UUID which identifies the KDF in the header
+    pub fn uuid(&self) -> [u8; 16] {
+        match *self {
+            V2Kdf::Aes { .. } => KDF_AES,
+            V2Kdf::Argon2d(_) => KDF_ARGON2D,
+            V2Kdf::Argon2id(_) => KDF_ARGON2ID,
+        }
+    }
+
+    /// Replace seed or salt with random bytes
+    pub fn randomize_seed(&mut self) {
+        let random: Vec<u8> = (0..32).map(|_| rand::random::<u8>()).collect();
+        match *self {
+            V2Kdf::Aes { ref mut seed, .. } => *seed = random,
+            V2Kdf::Argon2d(ref mut params) |
+            V2Kdf::Argon2id(ref mut params) => params.salt = random,
+        }
+    }
+}
+
+#[doc = "
+V2Header implements the outer header of a KeePass 2.x (KDBX) database.
+Normally you don't need to mess with this yourself.
+"]
+#[derive(Clone)]
+pub struct V2Header {
+    /// File signature
+    pub signature1: u32,
+    /// File signature
+    pub signature2: u32,
+    /// Version of the database. 0x00030001 is for KDBX 3.1,
+    /// 0x00040000 for KDBX 4.0 and 0x00040001 for KDBX 4.1
+    pub version: u32,
+    /// UUID of the outer cipher
+    pub cipher_id: Vec<u8>,
+    /// 0 for no compression, 1 for GZip
+    pub compression: u32,
+    /// A seed used to create the final key
+    pub master_seed: Vec<u8>,
+    /// Used to transform the composite key
+    pub kdf: V2Kdf,
+    /// IV for the outer cipher
+    pub encryption_iv: Vec<u8>,
+    /// Key of the inner random stream which protects
+    /// values like passwords inside the XML payload.
+    /// Stored in the inner header in KDBX 4.x
+    pub protected_stream_key: Vec<u8>,
+    /// First bytes of the decrypted payload to check
+    /// success of decryption. Empty in KDBX 4.x
+    pub stream_start_bytes: Vec<u8>,
+    /// Algorithm of the inner random stream. 0 is for none,
+    /// 2 is for Salsa20, 3 is for ChaCha20.
+    /// Stored in the inner header in KDBX 4.x
+    pub inner_random_stream: u32,
+}
+
+impl V2Header {
+    /// Use this to create a new header for a KDBX 3.1 database
+    /// with AES, GZip and Salsa20
+    pub fn new() -> V2Header {
+        V2Header {
+            signature1: 0x9AA2D903u32,
+            signature2: 0xB54BFB67u32,
+            version: 0x00030001u32,
+            cipher_id: CIPHER_AES.to_vec(),
+            compression: 1,
+            master_seed: (0..32).map(|_| rand::random::<u8>()).collect(),
+            kdf: V2Kdf::Aes {
+                seed: (0..32).map(|_| rand::random::<u8>()).collect(),
+                rounds: 60000,
+            },
+            encryption_iv: (0..16).map(|_| rand::random::<u8>()).collect(),
+            protected_stream_key: (0..32).map(|_| rand::random::<u8>()).collect(),
+            stream_start_bytes: (0..32).map(|_| rand::random::<u8>()).collect(),
+            inner_random_stream: 2,
+        }
+    }
+
+    // Checks file signatures
+    pub fn check_signatures(&self) -> Result<(), V2KdbxError> {
+        if self.signature1 != 0x9AA2D903u32 || self.signature2 != 0xB54BFB67u32 {
+            return Err(V2KdbxError::SignatureErr);
+        }
+        Ok(())
+    }
+
+    // Checks database version, only the major version is relevant
+    pub fn check_version(&self) -> Result<(), V2KdbxError> {
+        let major = self.major_version();
+        if major != 3 && major != 4 {
+            return Err(V2KdbxError::VersionErr);
+        }
+        Ok(())
+    }
+
+    // Checks compression flag
+    pub fn check_compression(&self) -> Result<(), V2KdbxError> {
+        if self.compression > 1 {
+            return Err(V2KdbxError::CompressionErr);
+        }
+        Ok(())
+    }
+
+    /// Returns the major version, e.g. 3 for KDBX 3.1 and 4 for KDBX 4.x
+    pub fn major_version(&self) -> u16 {
+        (self.version >> 16) as u16
+    }
+
+    /// Returns the cipher the database is encrypted with
+    pub fn cipher(&self) -> Result<V2Cipher, V2KdbxError> {
+        if self.cipher_id[..] == CIPHER_AES[..] {
+            Ok(V2Cipher::Aes)
+        } else if self.cipher_id[..] == CIPHER_TWOFISH[..] {
+            Ok(V2Cipher::Twofish)
+        } else if self.cipher_id[..] == CIPHER_CHACHA20[..] {
+            Ok(V2Cipher::ChaCha20)
+        } else {
+            Err(V2KdbxError::CipherErr)
+        }
+    }
+
+    /// Set the cipher which should be used on the next save
+    pub fn set_cipher(&mut self, cipher: V2Cipher) {
+        self.cipher_id = match cipher {
+            V2Cipher::Aes => CIPHER_AES.to_vec(),
+            V2Cipher::Twofish => CIPHER_TWOFISH.to_vec(),
+            V2Cipher::ChaCha20 => CIPHER_CHACHA20.to_vec(),
+        };
+    }
+
+    /// Length of the IV the cipher needs
+    pub fn iv_len(&self) -> usize {
+        match self.cipher() {
+            Ok(V2Cipher::ChaCha20) => 12,
+            _ => 16,
+        }
+    }
+
+    /// Whether the database uses features which KDBX 3.1 can't
+    /// store, i.e. Argon2 or ChaCha20
+    pub fn needs_kdbx4(&self) -> bool {
+        match self.kdf {
+            V2Kdf::Aes { .. } => self.cipher() == Ok(V2Cipher::ChaCha20),
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::io::{Read, Write};
+use std::fs::File;
+
+use chrono::{DateTime, Local};
+use rand;
+use uuid::Uuid;
+
+use common::common::write_array_volatile;
+use common::common_error::CommonError;
+use common::file::{save_atomically, Fingerprint};
+use kdbx::crypter::{sha256, V2Crypter};
+use kdbx::parser::{compress, decompress, parse_inner_header, save_inner_header, HeaderLoadParser,
+                   HeaderSaveParser, XmlLoadParser, XmlSaveParser};
+use kdbx::v2entry::{V2Binary, V2Entry};
+use kdbx::v2error::V2KdbxError;
+use kdbx::v2group::V2Group;
+use kdbx::v2header::V2Header;
+use kdbx::v2meta::V2Meta;
+use kdbx::xmldom::XmlElement;
+use kpdb::compositekey::CompositeKey;
+
+#[doc = "
+V2Kdbx implements a KeePass 2.x database (KDBX 3.1 and 4.x). Some notes on
+the file format:
+
+* Database is encrypted with AES, Twofish or ChaCha20 with a password and/or
+  a keyfile like a KeePass 1.x database. A loaded database is saved with the
+  same cipher again, use header.set_cipher() to choose another one.
+* The composite key is transformed with AES-KDF or with Argon2 (KDBX 4.x),
+  see header.kdf. Choosing Argon2 or ChaCha20 upgrades a KDBX 3.1 database
+  to KDBX 4.1 on save.
+* The payload is a (GZip compressed) XML document split into hashed blocks.
+  KDBX 4.x authenticates header and blocks with HMAC-SHA-256 and stores the
+  attachments in an inner header in front of the XML document.
+* Groups are stored as a tree which starts at a single root group.
+  Every group owns its subgroups and entries.
+* Entries hold an arbitrary number of string fields (Title, UserName,
+  Password, URL, Notes and custom ones), attachments and older versions
+  of themselves as history
+* Protected string fields are held as SecureString in memory
+* Elements of the XML document which aren't modelled (e.g. AutoType or
+  CustomIcons) are kept and written back unchanged on save
+"]
+pub struct V2Kdbx {
+    /// Filepath of the database
+    pub path: String,
+    /// Holds the header. Normally you don't need
+    /// to manipulate this yourself
+    pub header: V2Header,
+    /// Meta data like the database name
+    pub meta: V2Meta,
+    /// Attachments referenced by the entries
+    pub binaries: Vec<V2Binary>,
+    /// The group which holds all other groups and entries
+    pub root_group: Rc<RefCell<V2Group>>,
+    /// UUIDs and deletion dates of deleted groups and entries
+    pub deleted_objects: Vec<(Uuid, DateTime<Local>)>,
+    /// Number of timestamped backups of previous versions which
+    /// save keeps next to the database (path.YYYYmmdd-HHMMSS.fff.bak).
+    /// 0 keeps none
+    pub backups: usize,
+    /// Fingerprint of the file at path when the database was loaded
+    /// or saved the last time. None if the database wasn't loaded
+    /// from a file
+    pub fingerprint: Option<Fingerprint>,
+    // Used to de- and encrypt the database
+    crypter: V2Crypter,
+}
+
+impl V2Kdbx {
+    /// Call this to create a new database instance. You have to call load
+    /// to start decrypting and parsing of an existing database!
+    /// path is the filepath of the database, password is the database password
+    /// and keyfile is the filepath to the keyfile.
+    /// password should already lie on the heap as a String type and not &str
+    /// as it will be encrypted automatically and otherwise the plaintext
+    /// would lie in the memory though
+    pub fn new(path: String,
+               password: Option<String>,
+               keyfile: Option<String>)
+               -> Result<V2Kdbx, V2KdbxError> {
+        V2Kdbx::with_crypter(path, try!(V2Crypter::new(password, keyfile)))
+    }
+
+    /// Same as new, but the database is encrypted with a composite
+    /// key of arbitrary components, e.g. a password and a key from
+    /// a custom KeyProvider
+    pub fn with_key(path: String, key: CompositeKey) -> Result<V2Kdbx, V2KdbxError> {
+        V2Kdbx::with_crypter(path, try!(V2Crypter::with_key(key)))
+    }
+
+    fn with_crypter(path: String, crypter: V2Crypter) -> Result<V2Kdbx, V2KdbxError> {
+        let mut root_group = V2Group::new();
+        root_group.name = "Root".to_string();
+
+        Ok(V2Kdbx {
+            path: path,
+            header: V2Header::new(),
+            meta: V2Meta::new(),
+            binaries: vec![],
+            root_group: Rc::new(RefCell::new(root_group)),
+            deleted_objects: vec![],
+            backups: 0,
+            fingerprint: None,
+            crypter: crypter,
+        })
+    }
+
+    /// Decrypt and parse the database.
+    pub fn load(&mut self) -> Result<(), V2KdbxError> {
+        let raw = try!(self.read_in_file());
+        try!(self.load_from_bytes(&raw));
+        self.fingerprint = Some(Fingerprint::new(&self.path, &raw));
+        Ok(())
+    }
+
+    /// Same as load, but the encrypted database is read from reader
+    /// instead of the file at path
+    pub fn load_from_reader<R: Read>(&mut self, reader: &mut R) -> Result<(), V2KdbxError> {
+        let mut raw: Vec<u8> = vec![];
+        try!(reader.read_to_end(&mut raw).map_err(|_| V2KdbxError::ReadErr));
+        self.load_from_bytes(&raw)
+    }
+
+    /// Same as load, but the encrypted database is taken from raw,
+    /// so no filesystem is involved
+    pub fn load_from_bytes(&mut self, raw: &[u8]) -> Result<(), V2KdbxError> {
+        self.fingerprint = None;
+
+        // First read header and decrypt the payload
+        let header_parser = HeaderLoadParser::new(raw);
+        let (mut header, header_len) = try!(header_parser.parse_header());
+        try!(V2Kdbx::check_header(&header));
+        let content = try!(self.crypter.decrypt_payload(&header,
+                                                        &raw[..header_len],
+                                                        &raw[header_len..]));
+        let document = try!(decompress(header.compression, content));
+
+        // KDBX 4.x starts the payload with the inner header which holds
+        // the inner random stream and the attachments
+        let inner_header = if header.major_version() >= 4 {
+            parse_inner_header(&mut header, &document)
+        } else {
+            Ok((vec![], 0))
+        };
+
+        // Next parse the XML document and build the group tree
+        let xml = match inner_header {
+            Ok((_, xml_start)) => XmlElement::parse(&document[xml_start..]),
+            Err(e) => Err(e),
+        };
+        unsafe {
+            write_array_volatile(document.as_ptr() as *mut u8, 0u8, document.len());
+        }
+        let xml = try!(xml);
+        let (binaries, _) = try!(inner_header);
+        let mut parser = try!(XmlLoadParser::new(&header, binaries));
+        try!(parser.parse_document(&xml));
+
+        // The header isn't authenticated by the cipher in KDBX 3.1,
+        // so check the hash stored in the payload
+        if let Some(ref header_hash) = parser.header_hash {
+            if *header_hash != sha256(&raw[..header_len]) {
+                return Err(V2KdbxError::HeaderHashErr);
+            }
+        }
+
+        self.header = header;
+        self.meta = parser.meta;
+        self.binaries = parser.binaries;
+        self.root_group = try!(parser.root_group.ok_or(V2KdbxError::XmlErr));
+        self.deleted_objects = parser.deleted_objects;
+        Ok(())
+    }
+
+    fn read_in_file(&self) -> Result<Vec<u8>, V2KdbxError> {
+        let mut file = try!(File::open(&self.path).map_err(|_| V2KdbxError::FileErr));
+        let mut raw: Vec<u8> = vec![];
+        try!(file.read_to_end(&mut raw).map_err(|_| V2KdbxError::ReadErr));
+        Ok(raw)
+    }
+
+    fn check_header(header: &V2Header) -> Result<(), V2KdbxError> {
+        try!(header.check_signatures());
+        try!(header.check_version());
+        try!(header.check_compression());
+        try!(header.cipher());
+        Ok(())
+    }
+
+    /// Save the database. The file is written to a temporary file
+    /// first and renamed over the old one, so a crash never leaves a
+    /// half written database behind. Returns ModifiedErr if the file
+    /// was changed by someone else since it was loaded, use force_save
+    /// or load it again then.
+    ///
+    /// * path: new filepath of the database. None means the database is
+    ///         saved where it was loaded from
+    ///
+    /// * password: new password. None keeps the old one, an empty
+    ///             string removes it
+    ///
+    /// * keyfile: new keyfile. None keeps the old one, an empty
+    ///            string removes it
+    pub fn save(&mut self,
+                path: Option<String>,
+                password: Option<String>,
+                keyfile: Option<String>) -> Result<(), V2KdbxError> {
+        let same_file = path.is_none() || path.as_ref() == Some(&self.path);
+        if same_file && self.modified_on_disk() {
+            return Err(V2KdbxError::ModifiedErr);
+        }
+        self.force_save(path, password, keyfile)
+    }
+
+    /// Same as save, but the file is overwritten even if someone
+    /// else changed it in the meantime
+    pub fn force_save(&mut self,
+                      path: Option<String>,
+                      password: Option<String>,
+                      keyfile: Option<String>) -> Result<(), V2KdbxError> {
+        let (header, raw) = try!(self.encrypt(password, keyfile));
+
+        if let Some(new_path) = path {
+            self.path = new_path
+        }
+        try!(save_atomically(&self.path, &raw, self.backups).map_err(|e| {
+            match e {
+                CommonError::FileErr => V2KdbxError::FileErr,
+                _ => V2KdbxError::WriteErr,
+            }
+        }));
+        self.fingerprint = Some(Fingerprint::new(&self.path, &raw));
+
+        self.header = header;
+        Ok(())
+    }
+
+    /// True if the file at path was changed (or removed) by someone
+    /// else since the database was loaded or saved the last time
+    pub fn modified_on_disk(&self) -> bool {
+        match self.fingerprint {
+            Some(ref fingerprint) => {
+                match Fingerprint::of_file(&self.path) {
+                    Some(ref current) => !fingerprint.same_content(current),
+                    None => true,
+                }
+            }
+            None => false,
+        }
+    }
+
+    /// Same as save, but the encrypted database is written to writer
+    /// instead of a file. path stays untouched.
+    pub fn save_to_writer<W: Write>(&mut self,
+                                    writer: &mut W,
+                                    password: Option<String>,
+                                    keyfile: Option<String>) -> Result<(), V2KdbxError> {
+        let (header, raw) = try!(self.encrypt(password, keyfile));
+        try!(writer.write_all(&raw).map_err(|_| V2KdbxError::WriteErr));
+        try!(writer.flush().map_err(|_| V2KdbxError::WriteErr));
+
+        self.header = header;
+        Ok(())
+    }
+
+    // Encrypt the database with a new header and return the header
+    // together with its serialization followed by the encrypted payload
+    fn encrypt(&mut self,
+               password: Option<String>,
+               keyfile: Option<String>) -> Result<(V2Header, Vec<u8>), V2KdbxError> {
+        if let Some(new_password) = password {
+            if new_password == "".to_string() {
+                try!(self.crypter.change_password(None));
+            }
+            else {
+                try!(self.crypter.change_password(Some(new_password)));
+            }
+        }
+
+        if let Some(new_keyfile) = keyfile {
+            if new_keyfile == "".to_string() {
+                try!(self.crypter.change_keyfile(None));
+            }
+            else {
+                try!(self.crypter.change_keyfile(Some(new_keyfile)));
+            }
+        }
+
+        let mut header = self.header.clone();
+        // Argon2 and ChaCha20 need KDBX 4.x, so upgrade the file format
+        if header.major_version() < 4 && header.needs_kdbx4() {
+            header.version = 0x00040001;
+            header.inner_random_stream = 3;
+        }
+        let kdbx4 = header.major_version() >= 4;
+        header.master_seed = (0..32).map(|_| rand::random::<u8>()).collect();
+        header.kdf.randomize_seed();
+        header.encryption_iv = (0..header.iv_len()).map(|_| rand::random::<u8>()).collect();
+        if kdbx4 {
+            header.protected_stream_key = (0..64).map(|_| rand::random::<u8>()).collect();
+            header.stream_start_bytes = vec![];
+        } else {
+            header.protected_stream_key = (0..32).map(|_| rand::random::<u8>()).collect();
+            header.stream_start_bytes = (0..32).map(|_| rand::random::<u8>()).collect();
+        }
+
+        let mut header_parser = HeaderSaveParser::new(header.clone());
+        let header_raw = header_parser.parse_header();
+
+        // KDBX 4.x authenticates the header with a HMAC instead
+        // of storing its hash in the XML document
+        let header_hash = if kdbx4 { None } else { Some(sha256(&header_raw)) };
+        let mut parser = try!(XmlSaveParser::new(&header));
+        let xml = try!(parser.save_document(&self.meta,
+                                            &self.binaries,
+                                            &self.root_group,
+                                            &self.deleted_objects,
+                                            header_hash));
+        let xml = xml.to_document().into_bytes();
+        let mut document = if kdbx4 {
+            save_inner_header(&header, &self.binaries)
+        } else {
+            vec![]
+        };
+        document.reserve_exact(xml.len());
+        document.extend_from_slice(&xml);
+        unsafe {
+            write_array_volatile(xml.as_ptr() as *mut u8, 0u8, xml.len());
+        }
+        let content = try!(compress(header.compression, document));
+        let encrypted = try!(self.crypter.encrypt_payload(&header, &header_raw, content));
+
+        let mut raw = header_raw;
+        raw.extend_from_slice(&encrypted);
+        Ok((header, raw))
+    }
+
+    /// Create a new group and return it
+    ///
+    /// * name: name of the new group
+    ///
+    /// * parent: the group which should hold the new group.
+    ///           None means the root group
+    pub fn create_group(&mut self,
+                        name: String,
+                        parent: Option<Rc<RefCell<V2Group>>>)
+                        -> Rc<RefCell<V2Group>> {
+        let new_group = Rc::new(RefCell::new(V2Group::new()));
+        new_group.borrow_mut().name = name;
+
+        let parent = parent.unwrap_or(self.root_group.clone());
+        parent.borrow_mut().groups.push(new_group.clone());
+        new_group
+    }
+
+    /// Create a new entry and return it
+    ///
+    /// * group: group which should hold the entry
+    ///
+    /// * title: title of the new entry
+    ///
+    /// * url: URL from where the credentials are
+    ///
+    /// * notes: some free-text-comment about the entry
+    ///
+    /// * username: username for the URL
+    ///
+    /// * password: password for the URL. Protected if the meta data
+    ///             says so (the default)
+    ///
+    /// Note: username and password should be of type String at creation.
+    /// See V1Kpdb::create_entry for the reason.
+    pub fn create_entry(&mut self,
+                        group: Rc<RefCell<V2Group>>,
+                        title: String,
+                        url: Option<String>,
+                        notes: Option<String>,
+                        username: Option<String>,
+                        password: Option<String>)
+                        -> Rc<RefCell<V2Entry>> {
+        let protection = self.meta.memory_protection.clone();
+        let mut entry = V2Entry::new();
+        entry.set_string("Title", title, protection.protect_title);
+        entry.set_string("UserName", username.unwrap_or("".to_string()), protection.protect_username);
+        entry.set_string("Password", password.unwrap_or("".to_string()), protection.protect_password);
+        entry.set_string("URL", url.unwrap_or("".to_string()), protection.protect_url);
+        entry.set_string("Notes", notes.unwrap_or("".to_string()), protection.protect_notes);
+
+        let new_entry = Rc::new(RefCell::new(entry));
+        group.borrow_mut().entries.push(new_entry.clone());
+        new_entry
+    }
+}
@@ -0,0 +1,71 @@
+use uuid::Uuid;
+
+use kdbx::xmldom::XmlElement;
+
+#[doc = "
+Specifies which standard fields of new entries are protected
+"]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct V2MemoryProtection {
+    /// Protect the title
+    pub protect_title: bool,
+    /// Protect the username
+    pub protect_username: bool,
+    /// Protect the password
+    pub protect_password: bool,
+    /// Protect the URL
+    pub protect_url: bool,
+    /// Protect the notes
+    pub protect_notes: bool,
+}
+
+#[doc = "
+Implements the meta data of a KeePass 2.x database
+"]
+pub struct V2Meta {
+    /// Name of the application which wrote the database
+    pub generator: String,
+    /// Name of the database
+    pub database_name: String,
+    /// Description of the database
+    pub database_description: String,
+    /// Username for new entries
+    pub default_username: String,
+    /// Days after which history entries are deleted
+    pub maintenance_history_days: u32,
+    /// Which fields are protected
+    pub memory_protection: V2MemoryProtection,
+    /// Whether deleted items are moved into the recycle bin
+    pub recycle_bin_enabled: bool,
+    /// UUID of the recycle bin group, nil if there is none
+    pub recycle_bin_uuid: Uuid,
+    /// Custom key/value pairs of plugins and applications
+    pub custom_data: Vec<(String, String)>,
+    /// Elements which aren't modelled here, e.g. CustomIcons.
+    /// They are written back on save unchanged.
+    pub unknown: Vec<XmlElement>,
+}
+
+impl V2Meta {
+    /// Create meta data for a new database
+    pub fn new() -> V2Meta {
+        V2Meta {
+            generator: "rust-keepass".to_string(),
+            database_name: "".to_string(),
+            database_description: "".to_string(),
+            default_username: "".to_string(),
+            maintenance_history_days: 365,
+            memory_protection: V2MemoryProtection {
+                protect_title: false,
+                protect_username: false,
+                protect_password: true,
+                protect_url: false,
+                protect_notes: false,
+            },
+            recycle_bin_enabled: false,
+            recycle_bin_uuid: Uuid::nil(),
+            custom_data: vec![],
+            unknown: vec![],
+        }
+    }
+}
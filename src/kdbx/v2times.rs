@@ -0,0 +1,37 @@
+use chrono::{DateTime, Local, TimeZone};
+
+#[doc = "
+Implements the timestamps of groups and entries in a KeePass 2.x database
+"]
+#[derive(Clone, Debug, PartialEq)]
+pub struct V2Times {
+    /// Date of creation
+    pub creation: DateTime<Local>,
+    /// Date of last modification
+    pub last_mod: DateTime<Local>,
+    /// Date of last access
+    pub last_access: DateTime<Local>,
+    /// Expiration date, only relevant if expires is true
+    pub expire: DateTime<Local>,
+    /// Whether the item expires at all
+    pub expires: bool,
+    /// How often the item was used
+    pub usage_count: u32,
+    /// Date of the last move to another group
+    pub location_changed: DateTime<Local>,
+}
+
+impl V2Times {
+    /// Create timestamps for a new item
+    pub fn new() -> V2Times {
+        V2Times {
+            creation: Local::now(),
+            last_mod: Local::now(),
+            last_access: Local::now(),
+            expire: Local.ymd(2999, 12, 28).and_hms(23, 59, 59),
+            expires: false,
+            usage_count: 0,
+            location_changed: Local::now(),
+        }
+    }
+}
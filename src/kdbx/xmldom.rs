@@ -0,0 +1,149 @@
+use xml::reader::{EventReader, XmlEvent};
+
+use kdbx::v2error::V2KdbxError;
+
+#[doc = "
+A minimal XML element as used in the payload of a KeePass 2.x database.
+KeePass doesn't use mixed content, i.e. an element holds either child
+elements or text.
+"]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XmlElement {
+    /// Name of the element
+    pub name: String,
+    /// Attributes in document order
+    pub attributes: Vec<(String, String)>,
+    /// Child elements in document order
+    pub children: Vec<XmlElement>,
+    /// Text content, empty if the element has children
+    pub text: String,
+}
+
+impl XmlElement {
+    /// Create an empty element
+    pub fn new(name: &str) -> XmlElement {
+        XmlElement {
+            name: name.to_string(),
+            attributes: vec![],
+            children: vec![],
+            text: "".to_string(),
+        }
+    }
+
+    /// Create an element holding just text
+    pub fn with_text(name: &str, text: String) -> XmlElement {
+        let mut element = XmlElement::new(name);
+        element.text = text;
+        element
+    }
+
+    /// Get the value of an attribute
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|a| a.0 == name).map(|a| &a.1[..])
+    }
+
+    /// Get the first child with the given name
+    pub fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// Returns true if the value of this element is protected by
+    /// the inner random stream
+    pub fn is_protected(&self) -> bool {
+        match self.attribute("Protected") {
+            Some(value) => value.eq_ignore_ascii_case("true"),
+            None => false,
+        }
+    }
+
+    /// Parse a XML document and return its root element
+    pub fn parse(document: &[u8]) -> Result<XmlElement, V2KdbxError> {
+        let reader = EventReader::new(document);
+        let mut stack: Vec<XmlElement> = vec![];
+
+        for event in reader {
+            match try!(event.map_err(|_| V2KdbxError::XmlErr)) {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let mut element = XmlElement::new(&name.local_name);
+                    element.attributes = attributes.into_iter()
+                                                   .map(|a| (a.name.local_name, a.value))
+                                                   .collect();
+                    stack.push(element);
+                }
+                XmlEvent::EndElement { .. } => {
+                    let mut element = try!(stack.pop().ok_or(V2KdbxError::XmlErr));
+                    // Whitespace between child elements is just indentation
+                    if element.children.len() > 0 {
+                        element.text.clear();
+                    }
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                XmlEvent::Characters(text) | XmlEvent::CData(text) | XmlEvent::Whitespace(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&text);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Err(V2KdbxError::XmlErr)
+    }
+
+    /// Serialize the element into a XML document
+    pub fn to_document(&self) -> String {
+        let mut document = "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n".to_string();
+        self.write(&mut document, 0);
+        document
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        for _ in 0..depth {
+            out.push('\t');
+        }
+        out.push('<');
+        out.push_str(&self.name);
+        for &(ref name, ref value) in self.attributes.iter() {
+            out.push(' ');
+            out.push_str(name);
+            out.push_str("=\"");
+            escape(out, value);
+            out.push('"');
+        }
+
+        if self.children.len() > 0 {
+            out.push_str(">\n");
+            for child in self.children.iter() {
+                child.write(out, depth + 1);
+            }
+            for _ in 0..depth {
+                out.push('\t');
+            }
+        } else if self.text.len() > 0 {
+            out.push('>');
+            escape(out, &self.text);
+        } else {
+            out.push_str(" />\n");
+            return;
+        }
+        out.push_str("</");
+        out.push_str(&self.name);
+        out.push_str(">\n");
+    }
+}
+
+fn escape(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+}
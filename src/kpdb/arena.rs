@@ -0,0 +1,29 @@
+// Storage of the groups or entries of a V1Kpdb. Slots aren't reused,
+// so the handle of a removed item stays invalid instead of pointing
+// to another item later.
+pub struct Arena<T> {
+    slots: Vec<Option<T>>,
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Arena<T> {
+        Arena { slots: vec![] }
+    }
+
+    pub fn insert(&mut self, item: T) -> usize {
+        self.slots.push(Some(item));
+        self.slots.len() - 1
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.slots.get(index).and_then(|slot| slot.as_ref())
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.slots.get_mut(index).and_then(|slot| slot.as_mut())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        self.slots.get_mut(index).and_then(|slot| slot.take())
+    }
+}
@@ -0,0 +1,277 @@
+use libc::{c_void, mlock, munlock, size_t};
+use std::io::Write;
+
+use openssl::hash;
+
+use common::common::write_array_volatile;
+use kpdb::crypter::Crypter;
+use kpdb::v1error::V1KpdbError;
+use sec_str::SecureString;
+
+#[doc = "
+Implement this to plug a custom source of key material into a
+CompositeKey, e.g. a challenge-response token or a key fetched
+from a secrets service. Providers have to be Send and Sync, so that
+databases can be moved into or shared between threads.
+"]
+pub trait KeyProvider: Send + Sync {
+    /// Return the key of this component. It's hashed together with
+    /// the keys of the other components (or used as is if it's the
+    /// only component of a KeePass 1.x database) and overwritten with
+    /// zeroes afterwards.
+    fn get_key(&mut self) -> Result<Vec<u8>, V1KpdbError>;
+}
+
+#[doc = "
+A component of a CompositeKey
+"]
+pub enum KeyComponent {
+    /// A password, which is hashed with SHA-256
+    Password(SecureString),
+    /// Filepath of a keyfile, see KeyfileBytes
+    KeyfilePath(SecureString),
+    /// Content of a keyfile: a XML keyfile of KeePass 2.x, 32 bytes,
+    /// 64 hex characters or any other content which is hashed
+    /// with SHA-256
+    KeyfileBytes(Vec<u8>),
+    /// A 32 byte key which is used as is
+    RawKey(Vec<u8>),
+    /// A custom key provider
+    Provider(Box<dyn KeyProvider>),
+}
+
+impl KeyComponent {
+    // The bytes of KeyfileBytes and RawKey which have to be
+    // locked and deleted
+    fn bytes(&self) -> Option<&Vec<u8>> {
+        match *self {
+            KeyComponent::KeyfileBytes(ref bytes) | KeyComponent::RawKey(ref bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn is_password(&self) -> bool {
+        match *self {
+            KeyComponent::Password(_) => true,
+            _ => false,
+        }
+    }
+
+    fn is_keyfile(&self) -> bool {
+        match *self {
+            KeyComponent::KeyfilePath(_) | KeyComponent::KeyfileBytes(_) => true,
+            _ => false,
+        }
+    }
+
+    // Sensitive data in this function:
+    // * key
+    //
+    // At the end of this function:
+    // * key has moved out of function and is locked
+    fn get_key(&mut self) -> Result<Vec<u8>, V1KpdbError> {
+        let key = match *self {
+            KeyComponent::Password(ref mut password) => try!(Crypter::get_passwordkey(password)),
+            KeyComponent::KeyfilePath(ref mut keyfile) => try!(Crypter::get_keyfilekey(keyfile)),
+            KeyComponent::KeyfileBytes(ref bytes) => try!(Crypter::get_keyfilekey_from_bytes(bytes)),
+            KeyComponent::RawKey(ref key) => key.clone(),
+            KeyComponent::Provider(ref mut provider) => try!(provider.get_key()),
+        };
+        unsafe {
+            mlock(key.as_ptr() as *const c_void, key.len() as size_t);
+        }
+        Ok(key)
+    }
+}
+
+impl Drop for KeyComponent {
+    fn drop(&mut self) {
+        if let Some(bytes) = self.bytes() {
+            unsafe {
+                write_array_volatile(bytes.as_ptr() as *mut u8, 0u8, bytes.len());
+                munlock(bytes.as_ptr() as *const c_void, bytes.len() as size_t);
+            }
+        }
+    }
+}
+
+#[doc = "
+CompositeKey holds the ordered components of the key of a database,
+e.g. a password followed by a keyfile. The keys of the components
+are hashed together in this order. A KeePass 1.x database with a
+single component uses its key as is.
+
+Use V1Kpdb::with_key or V2Kdbx::with_key to open a database with it.
+"]
+pub struct CompositeKey {
+    components: Vec<KeyComponent>,
+}
+
+impl CompositeKey {
+    /// Create a composite key without any component
+    pub fn new() -> CompositeKey {
+        CompositeKey { components: vec![] }
+    }
+
+    /// Create a composite key from a password and/or the filepath of
+    /// a keyfile like V1Kpdb::new does. Returns PassErr if both are None.
+    pub fn from_password_and_keyfile(password: Option<String>,
+                                     keyfile: Option<String>)
+                                     -> Result<CompositeKey, V1KpdbError> {
+        let mut key = CompositeKey::new();
+        if let Some(p) = password {
+            try!(key.add(KeyComponent::Password(SecureString::new(p))));
+        }
+        if let Some(k) = keyfile {
+            try!(key.add(KeyComponent::KeyfilePath(SecureString::new(k))));
+        }
+        if key.is_empty() {
+            return Err(V1KpdbError::PassErr);
+        }
+        Ok(key)
+    }
+
+    /// Append a component. Returns ConvertErr if a RawKey
+    /// isn't 32 bytes long.
+    pub fn add(&mut self, component: KeyComponent) -> Result<(), V1KpdbError> {
+        if let KeyComponent::RawKey(ref key) = component {
+            if key.len() != 32 {
+                return Err(V1KpdbError::ConvertErr);
+            }
+        }
+        if let Some(bytes) = component.bytes() {
+            unsafe {
+                mlock(bytes.as_ptr() as *const c_void, bytes.len() as size_t);
+            }
+        }
+        self.components.push(component);
+        Ok(())
+    }
+
+    /// True if the key has no component at all
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// Replace the password, add it in front of the other components
+    /// or remove it (None). Returns PassErr if no component would
+    /// be left.
+    pub fn set_password(&mut self, password: Option<String>) -> Result<(), V1KpdbError> {
+        match password {
+            Some(p) => {
+                let component = KeyComponent::Password(SecureString::new(p));
+                match self.components.iter().position(|c| c.is_password()) {
+                    Some(index) => self.components[index] = component,
+                    None => self.components.insert(0, component),
+                }
+            }
+            None => {
+                if self.components.iter().all(|c| c.is_password()) {
+                    return Err(V1KpdbError::PassErr);
+                }
+                self.components.retain(|c| !c.is_password());
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the keyfile, append it to the other components or
+    /// remove it (None). Returns PassErr if no component would
+    /// be left.
+    pub fn set_keyfile(&mut self, keyfile: Option<String>) -> Result<(), V1KpdbError> {
+        match keyfile {
+            Some(k) => {
+                let component = KeyComponent::KeyfilePath(SecureString::new(k));
+                match self.components.iter().position(|c| c.is_keyfile()) {
+                    Some(index) => self.components[index] = component,
+                    None => self.components.push(component),
+                }
+            }
+            None => {
+                if self.components.iter().all(|c| c.is_keyfile()) {
+                    return Err(V1KpdbError::PassErr);
+                }
+                self.components.retain(|c| !c.is_keyfile());
+            }
+        }
+        Ok(())
+    }
+
+    // Collect the keys of all components
+    //
+    // Sensitive data in this function:
+    // * keys
+    //
+    // At the end of this function:
+    // * keys have moved out of function and are locked or are
+    //   zeroed out on error
+    fn get_keys(&mut self) -> Result<Vec<Vec<u8>>, V1KpdbError> {
+        if self.components.is_empty() {
+            return Err(V1KpdbError::PassErr);
+        }
+        let mut keys: Vec<Vec<u8>> = vec![];
+        for component in self.components.iter_mut() {
+            match component.get_key() {
+                Ok(key) => keys.push(key),
+                Err(e) => {
+                    CompositeKey::delete_keys(&keys);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    fn delete_keys(keys: &[Vec<u8>]) {
+        for key in keys.iter() {
+            unsafe {
+                write_array_volatile(key.as_ptr() as *mut u8, 0u8, key.len());
+                munlock(key.as_ptr() as *const c_void, key.len() as size_t);
+            }
+        }
+    }
+
+    // Hash the keys together
+    //
+    // Sensitive data in this function:
+    // * keys (locked: get_keys)
+    // * hashed_key
+    //
+    // At the end of this function:
+    // * keys are zeroed out
+    // * hashed_key is locked and moved out of function
+    fn hash_keys(keys: Vec<Vec<u8>>) -> Result<Vec<u8>, V1KpdbError> {
+        let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Can't hash keys!?");
+        let mut written = Ok(());
+        for key in keys.iter() {
+            if hasher.write_all(key).is_err() {
+                written = Err(V1KpdbError::DecryptErr);
+                break;
+            }
+        }
+        CompositeKey::delete_keys(&keys);
+        try!(written);
+        let hashed_key = hasher.finish2().expect("Can't hash keys!?").to_vec();
+        unsafe {
+            mlock(hashed_key.as_ptr() as *const c_void, hashed_key.len() as size_t);
+        }
+        Ok(hashed_key)
+    }
+
+    // The masterkey of a KeePass 1.x database: the key of a single
+    // component is used as is, several keys are hashed together
+    pub(crate) fn get_masterkey(&mut self) -> Result<Vec<u8>, V1KpdbError> {
+        let mut keys = try!(self.get_keys());
+        if keys.len() == 1 {
+            return Ok(keys.remove(0));
+        }
+        CompositeKey::hash_keys(keys)
+    }
+
+    // The composite key of a KeePass 2.x database: the keys are
+    // always hashed, even if there is only one
+    pub(crate) fn get_compositekey(&mut self) -> Result<Vec<u8>, V1KpdbError> {
+        let keys = try!(self.get_keys());
+        CompositeKey::hash_keys(keys)
+    }
+}
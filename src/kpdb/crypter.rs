@@ -1,39 +1,40 @@
 use libc::{c_void, mlock, munlock, size_t};
 use std::fs::File;
-use std::io::{Seek, SeekFrom, Read, Write};
+use std::io::{Read, Write};
+use std::str;
 
 use openssl::hash;
 use openssl::symm;
 use rustc_serialize::hex::FromHex;
+use twofish::Twofish;
+use twofish::cipher::{Block, BlockDecrypt, BlockEncrypt, KeyInit};
 
 use sec_str::SecureString;
 use common::common::write_array_volatile;
+use kpdb::compositekey::CompositeKey;
+use kpdb::keyfile::read_xml_keyfile;
 use kpdb::v1error::V1KpdbError;
-use kpdb::v1header::V1Header;
+use kpdb::v1header::{V1Cipher, V1Header};
 
 // implements a crypter to de- and encrypt a KeePass DB
 pub struct Crypter {
-    password: Option<SecureString>,
-    keyfile: Option<SecureString>,
+    key: CompositeKey,
 }
 
 // Sensitive data in Crypter overall
 // * finalkey: created in transform_key, zeroed out in en-/decrypt_raw
-// * masterkey: created in get_finalkey, lock depends on method, zeroed out in transform_key
+// * masterkey: created in CompositeKey::get_masterkey, locked, zeroed out in transform_key
 // * decrypted_database:
 // ** decryption: created in decrypt_raw, moved out of Crypted
 // ** encryption: created and locked outside of Crypter, zeroed out in encrypt_raw
-// * passwordkey: created in get_passwordkey, zeroed out in get_finalkey
-// * keyfilekey: created in get_keyfilekey, zeroed out in get_finalkey
-// * masterkey_tmp: created in get_finalkey, moved into masterkey
+// * passwordkey: created in get_passwordkey, zeroed out in CompositeKey
+// * keyfilekey: created in get_keyfilekey(_from_bytes), zeroed out in CompositeKey
 // * password: is a reference to a SecureString and is handled correctly in get_passwordkey 
 // * password_string: is a reference to password.string
 // * keyfile: is a reference to a SecureString and is handled correctly in get_keyfilekey 
-// * key: created in get_keyfilekey, moved into keyfilekey or is zeroed out in get_keyfile_key
-// * decoded_key: created in get_keyfilekey, moved into keyfilekey
-// * buf: created and zeroed out in get_keyfilekey
+// * raw: created and zeroed out in get_keyfilekey
+// * key: created in get_keyfilekey_from_bytes, moved into keyfilekey
 impl Crypter {
-    // Decrypt the database and return the raw data as Vec<u8>
     // Sensitive data in this function:
     //
     // * password
@@ -43,16 +44,15 @@ impl Crypter {
     pub fn new(password: Option<String>,
                keyfile: Option<String>)
                -> Result<Crypter, V1KpdbError> {
-        match (password, keyfile) {
-            (Some(p), None) => Ok(Crypter { password: Some(SecureString::new(p)),
-                                            keyfile: None, }),
-            (None, Some(k)) => Ok(Crypter { password: None,
-                                         keyfile: Some(SecureString::new(k)), }),
-            (Some(p), Some(k)) => Ok(Crypter { password: Some(SecureString::new(p)),
-                                               keyfile: Some(SecureString::new(k)), }),
-            (None, None) => Err(V1KpdbError::PassErr),
+        Crypter::with_key(try!(CompositeKey::from_password_and_keyfile(password, keyfile)))
+    }
+
+    // Use a composite key with arbitrary components
+    pub fn with_key(key: CompositeKey) -> Result<Crypter, V1KpdbError> {
+        if key.is_empty() {
+            return Err(V1KpdbError::PassErr);
         }
-        
+        Ok(Crypter { key: key })
     }
 
     // Sensitive data in this function:
@@ -61,19 +61,7 @@ impl Crypter {
     //
     // Old password's SecureString is deleted through Drop
     pub fn change_password(&mut self, password: Option<String>) -> Result<(), V1KpdbError> {
-        // Weird construct because self.keyfile in second match
-        // creates compiler errors due to invalid borrowing
-        let keyfile_option = match self.keyfile {
-            Some(_) => Some(()),
-            None => None,
-        };
-        match (password, keyfile_option) {
-            (None, None) => return Err(V1KpdbError::PassErr),
-            (None, Some(())) => self.password = None,
-            (Some(p), _) => self.password = Some(SecureString::new(p)),
-        };
-
-        Ok(())
+        self.key.set_password(password)
     }
 
     // Sensitive data in this function:
@@ -82,20 +70,7 @@ impl Crypter {
     //
     // Old keyfiles's SecureString is deleted through Drop
     pub fn change_keyfile(&mut self, keyfile: Option<String>) -> Result<(), V1KpdbError> {
-        // Weird construct because self.password in second match
-        // creates compiler errors due to invalid borrowing
-        let password_option = match self.password {
-            Some(_) => Some(()),
-            None => None,
-        };
-
-        match (password_option, keyfile) {
-            (None, None) => return Err(V1KpdbError::PassErr),
-            (Some(()), None) => self.keyfile = None,
-            (_, Some(k)) => self.keyfile = Some(SecureString::new(k)),
-        };
-
-        Ok(())
+        self.key.set_keyfile(keyfile)
     }
     
     // Sensitive data in this function:
@@ -129,66 +104,32 @@ impl Crypter {
     }
 
     // Sensitive data in this function:
-    // * masterkey
-    // * passwordkey (locked: get_passwordkey)
-    // * keyfilekey (locked: get_keyfilekey)
+    // * masterkey (locked: CompositeKey::get_masterkey)
     // * finalkey (locked: transform_key)
-    // * masterkey_tmp
     //
     // At the end of this function:
-    // * masterkey has moved to transform_key and is locked
-    // * passwordkey is zeroed out
-    // * keyfilekey is zeroed out
+    // * masterkey has moved to transform_key
     // * finalkey moved out of function
-    // * masterkey_tmp is locked and moved into masterkey
-    //
-    // passwordkey and keyfilekey are locked until procession
-    // p and k are locked through SecureString
     fn get_finalkey(&mut self, header: &V1Header) -> Result<Vec<u8>, V1KpdbError> {
-        let masterkey = match (&mut self.password, &mut self.keyfile) {
-            // Only password provided
-            (&mut Some(ref mut p), &mut None) => try!(Crypter::get_passwordkey(p)),
-            // Only keyfile provided
-            (&mut None, &mut Some(ref mut k)) => try!(Crypter::get_keyfilekey(k)),
-            // Both provided
-            (&mut Some(ref mut p), &mut Some(ref mut k)) => {
-                // Get hashed keys...
-                let passwordkey = try!(Crypter::get_passwordkey(p));
-
-                let keyfilekey = try!(Crypter::get_keyfilekey(k));
-
-                // ...and hash them together
-                let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Can't hash passwords!?");
-                try!(hasher.write_all(&passwordkey)
-                     .map_err(|_| V1KpdbError::DecryptErr));
-                try!(hasher.write_all(&keyfilekey)
-                     .map_err(|_| V1KpdbError::DecryptErr));
-
-                let masterkey_tmp = hasher.finish2().expect("Can't hash masterkey");
-                // Zero out unneeded keys and lock masterkey
-                unsafe {
-                    write_array_volatile(passwordkey.as_ptr() as *mut u8,
-                                         0u8,
-                                         passwordkey.len());
-                    write_array_volatile(keyfilekey.as_ptr() as *mut u8,
-                                         0u8,
-                                         keyfilekey.len());
-                    munlock(passwordkey.as_ptr() as *const c_void,
-                                  passwordkey.len() as size_t);
-                    munlock(keyfilekey.as_ptr() as *const c_void,
-                                  keyfilekey.len() as size_t);
-                    mlock(masterkey_tmp.as_ptr() as *const c_void,
-                                masterkey_tmp.len() as size_t);
-                }
-                masterkey_tmp.to_vec()
-            }
-            (&mut None, &mut None) => return Err(V1KpdbError::PassErr),
-        };
+        let masterkey = try!(self.key.get_masterkey());
         let finalkey = try!(Crypter::transform_key(masterkey, header));
 
         Ok(finalkey)
     }
     
+    // Create the composite key of a KeePass 2.x database. Unlike in
+    // get_finalkey the keys of the components are always hashed
+    // again, even if there is only one.
+    //
+    // Sensitive data in this function:
+    // * compositekey (locked: CompositeKey::get_compositekey)
+    //
+    // At the end of this function:
+    // * compositekey is moved out of function
+    pub fn get_compositekey(&mut self) -> Result<Vec<u8>, V1KpdbError> {
+        self.key.get_compositekey()
+    }
+
     // Hash the password string to create a decryption key from that
     // Sensitive data in this function:
     // * password
@@ -199,7 +140,7 @@ impl Crypter {
     // * password is zeroed out
     // * password_string is deleted (is a reference to password.string)
     // * passwordkey is moved out of function and locked
-    fn get_passwordkey(password: &mut SecureString) -> Result<Vec<u8>, V1KpdbError> {
+    pub fn get_passwordkey(password: &mut SecureString) -> Result<Vec<u8>, V1KpdbError> {
         password.unlock();
         let password_string = password.string.as_bytes();
 
@@ -217,136 +158,76 @@ impl Crypter {
         Ok(passwordkey.to_vec())
     }
 
-    // Get key from keyfile
+    // Get key from keyfile, see get_keyfilekey_from_bytes
     // Sensitive data in this function:
     // * keyfile
-    // * key
-    // * decoded_key
-    // * buf
-    // * file
+    // * raw
     //
     // At the end of this function:
     // * keyfile is deleted
-    // * key has moved out of function and is locked or is deleted (file_size==64)
-    // * decoded_key has moved out of function and is locked
-    // * buf is deleted
-    // * file ... TODO
+    // * raw is deleted
     //
-    // buf and key are locked during procession
-    fn get_keyfilekey(keyfile: &mut SecureString) -> Result<Vec<u8>, V1KpdbError> {
+    // raw is locked during procession
+    pub fn get_keyfilekey(keyfile: &mut SecureString) -> Result<Vec<u8>, V1KpdbError> {
         keyfile.unlock();
-
-        let mut file = try!(File::open(&keyfile.string).map_err(|_| V1KpdbError::FileErr));
-        // unsafe {
-        //     mlock(file.as_ptr() as *const c_void,
-        //                 file.len() as size_t);
-        // }
-
+        let file = File::open(&keyfile.string);
         keyfile.delete();
+        let mut file = try!(file.map_err(|_| V1KpdbError::FileErr));
 
-        let file_size = try!(file.seek(SeekFrom::End(0i64))
-                                 .map_err(|_| V1KpdbError::FileErr));
-        try!(file.seek(SeekFrom::Start(0u64))
-                 .map_err(|_| V1KpdbError::FileErr));
-
-        if file_size == 32 {
-            let mut key: Vec<u8> = vec![];
-            try!(file.read_to_end(&mut key).map_err(|_| V1KpdbError::ReadErr));
-            unsafe {
-                mlock(key.as_ptr() as *const c_void,
-                            key.len() as size_t);
-                // intrinsics::volatile_set_memory(&file as *mut c_void,
-                //                                 0u8,
-                //                                 mem::size_of::<File>());
-
-            }
-            return Ok(key);
-        } else if file_size == 64 {
-            // interpret characters as encoded hex if possible (e.g. "FF" => 0xff)
-            let mut key: String = "".to_string();
-            unsafe {
-                mlock(key.as_ptr() as *const c_void,
-                            key.len() as size_t);
-            }
-            match file.read_to_string(&mut key) {
-                Ok(_) => {
-                    match (&key[..]).from_hex() {
-                        Ok(decoded_key) => {
-                            unsafe {
-                                // intrinsics::volatile_set_memory(&file as *mut c_void,
-                                //                                 0u8,
-                                //                                 mem::size_of::<File>());
-                                mlock(decoded_key.as_ptr() as *const c_void,
-                                            decoded_key.len() as size_t);
-                                write_array_volatile(key.as_ptr() as *mut u8,
-                                                     0u8,
-                                                     key.len());
-                                munlock(key.as_ptr() as *const c_void,
-                                              key.len() as size_t);
-
-                            }
-                            return Ok(decoded_key)
-                        },
-                        Err(_) => {}
-                    }
-                }
-                Err(_) => {
-                    unsafe {
-                        write_array_volatile(key.as_ptr() as *mut u8,
-                                             0u8,
-                                             key.len());
-                        munlock(key.as_ptr() as *const c_void,
-                                      key.len() as size_t);
-                        
-                    }
-                    try!(file.seek(SeekFrom::Start(0u64))
-                         .map_err(|_| V1KpdbError::FileErr));
-                }
-            }
+        // Allocate the whole buffer at once, so that no copies
+        // of the content are left behind by reallocations
+        let file_size = try!(file.metadata().map_err(|_| V1KpdbError::FileErr)).len();
+        let mut raw: Vec<u8> = Vec::with_capacity(file_size as usize + 1);
+        unsafe {
+            mlock(raw.as_ptr() as *const c_void, raw.capacity() as size_t);
         }
-
-        // Read up to 2048 bytes and hash them
-        let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Can't create hasher!?");
-        let mut buf: Vec<u8> = vec![];
+        let key = file.read_to_end(&mut raw)
+                      .map_err(|_| V1KpdbError::ReadErr)
+                      .and_then(|_| Crypter::get_keyfilekey_from_bytes(&raw));
         unsafe {
-            mlock(buf.as_ptr() as *const c_void,
-                        buf.len() as size_t);
+            write_array_volatile(raw.as_ptr() as *mut u8, 0u8, raw.len());
+            munlock(raw.as_ptr() as *const c_void, raw.capacity() as size_t);
         }
+        key
+    }
 
-        loop {
-            buf = vec![0; 2048];
-            match file.read(&mut buf[..]) {
-                Ok(n) => {
-                    if n == 0 {
-                        break;
-                    };
-                    buf.truncate(n);
-                    try!(hasher.write_all(&buf[..])
-                         .map_err(|_| V1KpdbError::DecryptErr));
-                    unsafe {
-                        write_array_volatile(buf.as_ptr() as *mut u8, 0u8, buf.len())
-                    };
+    // Get key from the content of a keyfile: a XML keyfile (version 1.0
+    // or 2.0), 32 raw bytes, 64 hex characters or else the hash of the
+    // whole content
+    // Sensitive data in this function:
+    // * raw
+    // * key
+    //
+    // At the end of this function:
+    // * key has moved out of function and is locked
+    pub fn get_keyfilekey_from_bytes(raw: &[u8]) -> Result<Vec<u8>, V1KpdbError> {
+        // XML keyfiles of KeePass 2.x hold the key itself
+        if let Some(key) = try!(read_xml_keyfile(raw)) {
+            return Ok(key);
+        }
 
-                }
-                Err(_) => {
-                    return Err(V1KpdbError::ReadErr);
+        let key = if raw.len() == 32 {
+            raw.to_vec()
+        } else {
+            // interpret characters as encoded hex if possible (e.g. "FF" => 0xff)
+            let decoded_key = match str::from_utf8(raw) {
+                Ok(hex) if raw.len() == 64 => hex.from_hex().ok(),
+                _ => None,
+            };
+            match decoded_key {
+                Some(key) => key,
+                None => {
+                    let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256())
+                                         .expect("Can't create hasher!?");
+                    try!(hasher.write_all(raw).map_err(|_| V1KpdbError::DecryptErr));
+                    hasher.finish2().expect("Can't hash key!?").to_vec()
                 }
             }
-        }
-
-        let key = hasher.finish2().expect("Can't hash key!?");
+        };
         unsafe {
-            // intrinsics::volatile_set_memory(&file as *mut c_void,
-            //                                 0u8,
-            //                                 mem::size_of::<File>());
-            munlock(buf.as_ptr() as *const c_void,
-                          buf.len() as size_t);
-            mlock(key.as_ptr() as *const c_void,
-                        key.len() as size_t);
-            
+            mlock(key.as_ptr() as *const c_void, key.len() as size_t);
         }
-
-        Ok(key.to_vec())
+        Ok(key)
     }
 
     // Create the finalkey from the masterkey by encrypting it with some
@@ -359,13 +240,46 @@ impl Crypter {
     // At the end of this function:
     // * masterkey is zeroed out
     // * finalkey is locked and moved out of function
-    fn transform_key(mut masterkey: Vec<u8>, header: &V1Header) -> Result<Vec<u8>, V1KpdbError> {
+    fn transform_key(masterkey: Vec<u8>, header: &V1Header) -> Result<Vec<u8>, V1KpdbError> {
+        Crypter::transform_key_with(masterkey,
+                                    &header.transf_randomseed,
+                                    header.key_transf_rounds as u64,
+                                    &header.final_randomseed)
+    }
+
+    // Same as transform_key but with seeds and rounds given explicitly as
+    // KeePass 2.x databases (AES-KDF) use the same transformation.
+    //
+    // Sensitive data: see transform_key
+    pub fn transform_key_with(masterkey: Vec<u8>,
+                              transf_randomseed: &[u8],
+                              key_transf_rounds: u64,
+                              final_randomseed: &[u8])
+                              -> Result<Vec<u8>, V1KpdbError> {
+        let transformed_key = try!(Crypter::aes_kdf(masterkey, transf_randomseed, key_transf_rounds));
+        Crypter::get_finalkey_with(final_randomseed, transformed_key)
+    }
+
+    // Encrypt the masterkey key_transf_rounds times with AES_ECB and
+    // hash the result. KeePass 2.x calls this AES-KDF.
+    //
+    // Sensitive data in this function:
+    // * masterkey (locked: get_finalkey)
+    // * transformed_key
+    //
+    // At the end of this function:
+    // * masterkey is zeroed out
+    // * transformed_key is locked and moved out of function
+    pub fn aes_kdf(mut masterkey: Vec<u8>,
+                   transf_randomseed: &[u8],
+                   key_transf_rounds: u64)
+                   -> Result<Vec<u8>, V1KpdbError> {
         let mut crypter = symm::Crypter::new(symm::Cipher::aes_256_ecb(),
                                              symm::Mode::Encrypt,
-                                             &header.transf_randomseed,
+                                             transf_randomseed,
                                              None).expect("Can't create crypter!?");
         let mut transformed_key = vec![0; masterkey.len() + symm::Cipher::aes_256_cbc().block_size()];
-        for _ in 0..header.key_transf_rounds {
+        for _ in 0..key_transf_rounds {
             let _ = crypter.update(&masterkey, &mut transformed_key);
             transformed_key.truncate(masterkey.len());
             masterkey = transformed_key.clone();
@@ -377,13 +291,7 @@ impl Crypter {
         let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Could not create Hasher!?");
         try!(hasher.write_all(&masterkey)
              .map_err(|_| V1KpdbError::DecryptErr));
-        masterkey = hasher.finish2().expect("Could not hash masterkey!?").to_vec();
-        let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Could not create Hasher!?");
-        try!(hasher.write_all(&header.final_randomseed)
-             .map_err(|_| V1KpdbError::DecryptErr));
-        try!(hasher.write_all(&masterkey)
-             .map_err(|_| V1KpdbError::DecryptErr));
-        let finalkey = hasher.finish2().expect("Could not hash finalkey!?");
+        let transformed_key = hasher.finish2().expect("Could not hash masterkey!?").to_vec();
 
         // Zero out masterkey as it is not needed anymore
         unsafe {
@@ -392,8 +300,39 @@ impl Crypter {
                                  masterkey.len());
             munlock(masterkey.as_ptr() as *const c_void,
                           masterkey.len() as size_t);
-            mlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
+            mlock(transformed_key.as_ptr() as *const c_void, transformed_key.len() as size_t);
+        }
 
+        Ok(transformed_key)
+    }
+
+    // Hash the transformed key together with the final random seed
+    //
+    // Sensitive data in this function:
+    // * transformed_key (locked: aes_kdf)
+    // * finalkey
+    //
+    // At the end of this function:
+    // * transformed_key is zeroed out
+    // * finalkey is locked and moved out of function
+    pub fn get_finalkey_with(final_randomseed: &[u8],
+                             transformed_key: Vec<u8>)
+                             -> Result<Vec<u8>, V1KpdbError> {
+        let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Could not create Hasher!?");
+        let result = hasher.write_all(final_randomseed)
+                           .and_then(|_| hasher.write_all(&transformed_key));
+        unsafe {
+            write_array_volatile(transformed_key.as_ptr() as *mut u8,
+                                 0u8,
+                                 transformed_key.len());
+            munlock(transformed_key.as_ptr() as *const c_void,
+                    transformed_key.len() as size_t);
+        }
+        try!(result.map_err(|_| V1KpdbError::DecryptErr));
+        let finalkey = hasher.finish2().expect("Could not hash finalkey!?");
+
+        unsafe {
+            mlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
         }
 
         Ok(finalkey.to_vec())
@@ -411,23 +350,24 @@ impl Crypter {
     //
     // finalkey is locked through transform_key
     fn decrypt_raw(header: &V1Header, encrypted_database: Vec<u8>, finalkey: Vec<u8>) -> Result<Vec<u8>, V1KpdbError> {
-        let mut decrypted_database = try!(symm::decrypt(symm::Cipher::aes_256_cbc(),
-                                                        &finalkey,
-                                                        Some(header.iv.as_slice()),
-                                                        &encrypted_database).map_err(|_| V1KpdbError::DecryptErr));
+        let decryption = match header.cipher() {
+            Ok(V1Cipher::Aes) => symm::decrypt(symm::Cipher::aes_256_cbc(),
+                                               &finalkey,
+                                               Some(header.iv.as_slice()),
+                                               &encrypted_database).map_err(|_| V1KpdbError::DecryptErr),
+            Ok(V1Cipher::Twofish) => Crypter::twofish_cbc_decrypt(&finalkey,
+                                                                   &header.iv,
+                                                                   &encrypted_database),
+            Err(e) => Err(e),
+        };
 
         // Zero out finalkey as it is not needed anymore
         unsafe {
             write_array_volatile(finalkey.as_ptr() as *mut u8, 0u8, finalkey.len());
             munlock(finalkey.as_ptr() as *const c_void, finalkey.len() as size_t);
         }
-
-        // Delete padding from decrypted data
-        let padding = decrypted_database[decrypted_database.len() - 1] as usize;
-        let length = decrypted_database.len();
-
-        // resize() is safe as just padding is dropped
-        decrypted_database.resize(length - padding, 0);
+        // Both ciphers have removed the padding already
+        let decrypted_database = try!(decryption);
         unsafe {
             mlock(decrypted_database.as_ptr() as *const c_void, decrypted_database.len() as size_t);
         }
@@ -436,10 +376,16 @@ impl Crypter {
     }
 
     fn encrypt_raw(header: &V1Header, decrypted_database: Vec<u8>, finalkey: Vec<u8>) -> Result<Vec<u8>, V1KpdbError> {
-        let encrypted_database = try!(symm::encrypt(symm::Cipher::aes_256_cbc(),
-                                                    &finalkey,
-                                                    Some(header.iv.as_slice()),
-                                                    &decrypted_database).map_err(|_| V1KpdbError::EncryptErr));
+        let encryption = match header.cipher() {
+            Ok(V1Cipher::Aes) => symm::encrypt(symm::Cipher::aes_256_cbc(),
+                                               &finalkey,
+                                               Some(header.iv.as_slice()),
+                                               &decrypted_database).map_err(|_| V1KpdbError::EncryptErr),
+            Ok(V1Cipher::Twofish) => Crypter::twofish_cbc_encrypt(&finalkey,
+                                                                   &header.iv,
+                                                                   &decrypted_database),
+            Err(e) => Err(e),
+        };
         
         // Zero out finalkey as it is not needed anymore
         unsafe {
@@ -449,7 +395,87 @@ impl Crypter {
             munlock(decrypted_database.as_ptr() as *const c_void, decrypted_database.len() as size_t);
         }
 
-        Ok(encrypted_database)
+        encryption
+    }
+
+    // OpenSSL doesn't provide Twofish, so CBC mode and PKCS#7 padding
+    // are done here around the plain block cipher.
+    //
+    // Sensitive data in this function:
+    // * key (locked: transform_key)
+    // * decrypted
+    // * block
+    //
+    // At the end of this function:
+    // * key hasn't changed (it's a reference)
+    // * decrypted is moved out of function (locked in decrypt_raw)
+    // * block is zeroed out
+    //
+    // The key schedule of twofish is zeroed out on drop
+    pub fn twofish_cbc_decrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, V1KpdbError> {
+        if data.len() == 0 || data.len() % 16 != 0 || iv.len() != 16 {
+            return Err(V1KpdbError::DecryptErr);
+        }
+        let twofish = try!(Twofish::new_from_slice(key).map_err(|_| V1KpdbError::DecryptErr));
+
+        // Reserve everything up front so that no plaintext is left behind
+        // by a reallocation
+        let mut decrypted: Vec<u8> = Vec::with_capacity(data.len());
+        let mut prev = iv;
+        for chunk in data.chunks(16) {
+            let mut block = Block::<Twofish>::clone_from_slice(chunk);
+            twofish.decrypt_block(&mut block);
+            for i in 0..16 {
+                decrypted.push(block[i] ^ prev[i]);
+            }
+            unsafe {
+                write_array_volatile(block.as_mut_ptr(), 0u8, block.len());
+            }
+            prev = chunk;
+        }
+
+        let length = decrypted.len();
+        let padding = decrypted[length - 1] as usize;
+        if padding == 0 || padding > 16 ||
+           decrypted[length - padding..].iter().any(|&b| b as usize != padding) {
+            unsafe {
+                write_array_volatile(decrypted.as_ptr() as *mut u8, 0u8, length);
+            }
+            return Err(V1KpdbError::DecryptErr);
+        }
+        decrypted.truncate(length - padding);
+
+        Ok(decrypted)
+    }
+
+    // Sensitive data in this function:
+    // * key (locked: transform_key)
+    // * data (locked outside of Crypter)
+    // * block
+    //
+    // At the end of this function:
+    // * key and data haven't changed (they're references)
+    // * block holds the last ciphertext block only
+    pub fn twofish_cbc_encrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, V1KpdbError> {
+        if iv.len() != 16 {
+            return Err(V1KpdbError::EncryptErr);
+        }
+        let twofish = try!(Twofish::new_from_slice(key).map_err(|_| V1KpdbError::EncryptErr));
+
+        let padding = 16 - data.len() % 16;
+        let mut encrypted: Vec<u8> = Vec::with_capacity(data.len() + padding);
+        let mut block = Block::<Twofish>::clone_from_slice(iv);
+        for pos in 0..data.len() + padding {
+            // block still holds the previous ciphertext (or the IV)
+            let byte = if pos < data.len() { data[pos] } else { padding as u8 };
+            block[pos % 16] ^= byte;
+            if pos % 16 == 15 {
+                twofish.encrypt_block(&mut block);
+                encrypted.extend_from_slice(&block);
+            }
+        }
+
+        Ok(encrypted)
     }
 
     // Check some conditions
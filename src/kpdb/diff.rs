@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+
+use chrono::{DateTime, Datelike, Local};
+use openssl::hash;
+use rustc_serialize::hex::ToHex;
+use uuid::Uuid;
+
+use kpdb::v1entry::{EntryId, V1Entry};
+use kpdb::v1group::GroupId;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+// Shown instead of a username which can't be decrypted
+const UNREADABLE: &'static str = "(can't be decrypted)";
+
+#[doc = "
+A field of a group which diff compares
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum GroupField {
+    Title,
+    Image,
+    Expire,
+}
+
+#[doc = "
+A field of an entry which diff compares. Passwords are compared
+as well, but DiffChange::PasswordChanged never holds their values.
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum EntryField {
+    Title,
+    Url,
+    Username,
+    Comment,
+    Image,
+    Expire,
+    Attachment,
+}
+
+#[doc = "
+A difference between two databases. Groups and entries are given
+by their path, i.e. the titles of the groups above them.
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffChange {
+    /// The group is only in the new database
+    GroupAdded { id: u32, path: String },
+    /// The group is only in the old database
+    GroupRemoved { id: u32, path: String },
+    /// The group got another parent group
+    GroupMoved { id: u32, from: String, to: String },
+    /// A field of the group changed
+    GroupChanged { id: u32, path: String, field: GroupField, old: String, new: String },
+    /// The entry is only in the new database
+    EntryAdded { uuid: Uuid, path: String },
+    /// The entry is only in the old database
+    EntryRemoved { uuid: Uuid, path: String },
+    /// The entry is in another group now
+    EntryMoved { uuid: Uuid, from: String, to: String },
+    /// A field of the entry changed
+    EntryChanged { uuid: Uuid, path: String, field: EntryField, old: String, new: String },
+    /// The password of the entry changed
+    PasswordChanged { uuid: Uuid, path: String },
+}
+
+impl fmt::Display for DiffChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DiffChange::GroupAdded { ref path, .. } => write!(f, "+ group {}", path),
+            DiffChange::GroupRemoved { ref path, .. } => write!(f, "- group {}", path),
+            DiffChange::GroupMoved { ref from, ref to, .. } => write!(f, "~ group {} -> {}", from, to),
+            DiffChange::GroupChanged { ref path, field, ref old, ref new, .. } => {
+                write!(f, "~ group {}: {:?} {:?} -> {:?}", path, field, old, new)
+            }
+            DiffChange::EntryAdded { ref path, .. } => write!(f, "+ entry {}", path),
+            DiffChange::EntryRemoved { ref path, .. } => write!(f, "- entry {}", path),
+            DiffChange::EntryMoved { ref from, ref to, .. } => write!(f, "~ entry {} -> {}", from, to),
+            DiffChange::EntryChanged { ref path, field, ref old, ref new, .. } => {
+                write!(f, "~ entry {}: {:?} {:?} -> {:?}", path, field, old, new)
+            }
+            DiffChange::PasswordChanged { ref path, .. } => write!(f, "~ entry {}: Password changed", path),
+        }
+    }
+}
+
+#[doc = "
+All differences between two databases, see diff
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseDiff {
+    /// Changes of the groups followed by changes of the entries
+    pub changes: Vec<DiffChange>,
+}
+
+impl DatabaseDiff {
+    /// True if both databases hold the same groups and entries
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for DatabaseDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in self.changes.iter() {
+            try!(writeln!(f, "{}", change));
+        }
+        Ok(())
+    }
+}
+
+/// Compare two loaded databases. Groups are matched by id, entries by
+/// UUID. Timestamps other than the expiration dates are ignored.
+pub fn diff(old: &V1Kpdb, new: &V1Kpdb) -> DatabaseDiff {
+    let mut changes: Vec<DiffChange> = vec![];
+    let old_groups: HashMap<u32, GroupId> = old.groups.iter().map(|&g| (old[g].id, g)).collect();
+    let new_groups: HashMap<u32, GroupId> = new.groups.iter().map(|&g| (new[g].id, g)).collect();
+
+    for &group in old.groups.iter() {
+        let id = old[group].id;
+        if !new_groups.contains_key(&id) {
+            changes.push(DiffChange::GroupRemoved {
+                id: id,
+                path: old.group_path(group),
+            });
+        }
+    }
+    for &group in new.groups.iter() {
+        let id = new[group].id;
+        let old_group = match old_groups.get(&id) {
+            Some(&old_group) => old_group,
+            None => {
+                changes.push(DiffChange::GroupAdded {
+                    id: id,
+                    path: new.group_path(group),
+                });
+                continue;
+            }
+        };
+        let (old_path, path) = (old.group_path(old_group), new.group_path(group));
+        if parent_id(old, old_group) != parent_id(new, group) {
+            changes.push(DiffChange::GroupMoved {
+                id: id,
+                from: old_path,
+                to: path.clone(),
+            });
+        }
+        let (old_group, group) = (&old[old_group], &new[group]);
+        let fields = [(GroupField::Title, old_group.title.clone(), group.title.clone()),
+                      (GroupField::Image, old_group.image.to_string(), group.image.to_string()),
+                      (GroupField::Expire, format_expire(&old_group.expire), format_expire(&group.expire))];
+        for &(field, ref old, ref new) in fields.iter() {
+            if old != new {
+                changes.push(DiffChange::GroupChanged {
+                    id: id,
+                    path: path.clone(),
+                    field: field,
+                    old: old.clone(),
+                    new: new.clone(),
+                });
+            }
+        }
+    }
+
+    let old_entries: HashMap<Uuid, EntryId> = old.entries.iter().map(|&e| (old[e].uuid, e)).collect();
+    let new_entries: HashMap<Uuid, EntryId> = new.entries.iter().map(|&e| (new[e].uuid, e)).collect();
+    for &entry in old.entries.iter() {
+        let uuid = old[entry].uuid;
+        if !new_entries.contains_key(&uuid) {
+            changes.push(DiffChange::EntryRemoved {
+                uuid: uuid,
+                path: old.entry_path(entry),
+            });
+        }
+    }
+    for &entry in new.entries.iter() {
+        let uuid = new[entry].uuid;
+        let old_entry = match old_entries.get(&uuid) {
+            Some(&old_entry) => old_entry,
+            None => {
+                changes.push(DiffChange::EntryAdded {
+                    uuid: uuid,
+                    path: new.entry_path(entry),
+                });
+                continue;
+            }
+        };
+        let (old_path, path) = (old.entry_path(old_entry), new.entry_path(entry));
+        if old[old_entry].group_id != new[entry].group_id {
+            changes.push(DiffChange::EntryMoved {
+                uuid: uuid,
+                from: old_path,
+                to: path.clone(),
+            });
+        }
+        compare_entries(&old[old_entry], &new[entry], &path, &mut changes);
+    }
+
+    DatabaseDiff { changes: changes }
+}
+
+// Sensitive data in this function:
+//
+// * usernames and passwords (decrypted and compared)
+//
+// At the end of this function:
+//
+// * the decrypted strings are overwritten again, only the
+//   usernames are copied into the changes
+fn compare_entries(old: &V1Entry, new: &V1Entry, path: &str, changes: &mut Vec<DiffChange>) {
+    let fields = [(EntryField::Title, old.title.clone(), new.title.clone()),
+                  (EntryField::Url, old.url.clone().unwrap_or_default(), new.url.clone().unwrap_or_default()),
+                  (EntryField::Username, reveal(&old.username), reveal(&new.username)),
+                  (EntryField::Comment,
+                   old.comment.clone().unwrap_or_default(),
+                   new.comment.clone().unwrap_or_default()),
+                  (EntryField::Image, old.image.to_string(), new.image.to_string()),
+                  (EntryField::Expire, format_expire(&old.expire), format_expire(&new.expire)),
+                  (EntryField::Attachment, attachment(old), attachment(new))];
+    for &(field, ref old_value, ref new_value) in fields.iter() {
+        if old_value != new_value {
+            changes.push(DiffChange::EntryChanged {
+                uuid: new.uuid,
+                path: path.to_string(),
+                field: field,
+                old: old_value.clone(),
+                new: new_value.clone(),
+            });
+        }
+    }
+
+    if !same_secret(&old.password, &new.password) {
+        changes.push(DiffChange::PasswordChanged {
+            uuid: new.uuid,
+            path: path.to_string(),
+        });
+    }
+}
+
+fn reveal(string: &Option<SecureString>) -> String {
+    match *string {
+        Some(ref string) => {
+            string.with_unlocked(|string| string.to_string()).unwrap_or_else(|| UNREADABLE.to_string())
+        }
+        None => "".to_string(),
+    }
+}
+
+fn same_secret(old: &Option<SecureString>, new: &Option<SecureString>) -> bool {
+    match (old.as_ref(), new.as_ref()) {
+        (Some(old), Some(new)) => old.with_unlocked(|old| new.with_unlocked(|new| old == new)) == Some(Some(true)),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Render a database as text, e.g. for a git diff.textconv driver.
+/// Groups are listed in the order of the group tree, each followed by
+/// its entries. Passwords aren't shown, a changed password shows up
+/// through the modification time of its entry.
+pub fn render(db: &V1Kpdb) -> String {
+    let mut out = String::new();
+    for &group in db.groups.iter() {
+        let path = db.group_path(group);
+        let group = &db[group];
+        let _ = writeln!(out, "[{}]", path);
+        let _ = writeln!(out, "id: {}", group.id);
+        let _ = writeln!(out, "image: {}", group.image);
+        let _ = writeln!(out, "expires: {}", format_expire(&group.expire));
+        let _ = writeln!(out);
+
+        for entry in group.entries.iter().map(|&e| &db[e]) {
+            let _ = writeln!(out, "[{}] {}", path, entry.title);
+            let _ = writeln!(out, "uuid: {}", entry.uuid);
+            let _ = writeln!(out, "url: {}", entry.url.clone().unwrap_or_default());
+            let _ = writeln!(out, "username: {}", reveal(&entry.username));
+            let _ = writeln!(out, "password: {}", if entry.password.is_some() { "(hidden)" } else { "" });
+            let comment = entry.comment.clone().unwrap_or_default();
+            let _ = writeln!(out, "comment: {}", comment.replace("\n", "\n  "));
+            let _ = writeln!(out, "attachment: {}", attachment(entry));
+            let _ = writeln!(out, "image: {}", entry.image);
+            let _ = writeln!(out, "expires: {}", format_expire(&entry.expire));
+            let _ = writeln!(out, "modified: {}", entry.last_mod.format("%Y-%m-%d %H:%M:%S"));
+            let _ = writeln!(out);
+        }
+    }
+    out
+}
+
+fn parent_id(db: &V1Kpdb, group: GroupId) -> u32 {
+    db[group].parent.map(|parent| db[parent].id).unwrap_or(0)
+}
+
+fn format_expire(date: &DateTime<Local>) -> String {
+    // 28-12-2999 23:59:59 means the group or entry never expires
+    if date.year() == 2999 {
+        "never".to_string()
+    } else {
+        date.format("%Y-%m-%d %H:%M:%S").to_string()
+    }
+}
+
+// Name, size and the start of the SHA-256 hash of the attachment, so
+// that changes of its content are visible
+fn attachment(entry: &V1Entry) -> String {
+    match entry.binary {
+        Some(ref binary) => {
+            let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Can't hash attachment!?");
+            hasher.write_all(binary).expect("Can't hash attachment!?");
+            let hash = hasher.finish2().expect("Can't hash attachment!?");
+            format!("{} ({} bytes, sha256 {})",
+                    entry.binary_desc.clone().unwrap_or_default(),
+                    binary.len(),
+                    hash[..4].to_hex())
+        }
+        None => "".to_string(),
+    }
+}
@@ -0,0 +1,300 @@
+use libc::{c_void, mlock, munlock, size_t};
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::str;
+
+use openssl::hash;
+use rand;
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::hex::ToHex;
+use xml::reader::{EventReader, XmlEvent};
+
+use common::common::write_array_volatile;
+use kpdb::v1error::V1KpdbError;
+
+// Size of the buffer for the content of a new keyfile, a XML keyfile
+// takes about 250 bytes
+const KEYFILE_CAPACITY: usize = 512;
+
+// Read the key of a XML keyfile of KeePass 2.x. Version 1.0 stores the
+// key base64 encoded, version 2.0 hex encoded together with the first
+// four bytes of its SHA-256 hash. Returns None if raw isn't a XML
+// keyfile. The keyfile is read as a stream of XML events, so the
+// encoded key only ever ends up in the locked buffer data.
+//
+// Sensitive data in this function:
+// * raw (locked: get_keyfilekey)
+// * data
+// * key
+//
+// At the end of this function:
+// * data is overwritten and unlocked
+// * key has moved out of function and is locked
+pub(crate) fn read_xml_keyfile(raw: &[u8]) -> Result<Option<Vec<u8>>, V1KpdbError> {
+    // Look for a "<" (behind an UTF-8 BOM) first to avoid
+    // parsing binary keyfiles
+    let is_xml = match raw {
+        [b'<', ..] | [0xEF, 0xBB, 0xBF, b'<', ..] => true,
+        _ => false,
+    };
+    if !is_xml {
+        return Ok(None);
+    }
+
+    // The encoded key is never longer than the keyfile, so data isn't
+    // reallocated
+    let mut data: Vec<u8> = Vec::with_capacity(raw.len());
+    unsafe {
+        mlock(data.as_ptr() as *const c_void, data.capacity() as size_t);
+    }
+    let key = match read_xml_data(raw, &mut data) {
+        Ok(Some((version, hash))) => decode_xml_key(&data, &version, hash).map(Some),
+        other => other.map(|_| None),
+    };
+    unsafe {
+        write_array_volatile(data.as_mut_ptr(), 0u8, data.len());
+        munlock(data.as_ptr() as *const c_void, data.capacity() as size_t);
+    }
+    key
+}
+
+// Collect the text of KeyFile/Key/Data in data and return the version
+// and the Hash attribute of Data. Returns None if raw isn't a XML
+// keyfile, so the whole file is hashed.
+//
+// Sensitive data in this function:
+// * the texts of the events inside Data
+//
+// At the end of this function:
+// * the texts are overwritten, their content is in data
+fn read_xml_data(raw: &[u8], data: &mut Vec<u8>) -> Result<Option<(String, Option<String>)>, V1KpdbError> {
+    let mut path: Vec<String> = vec![];
+    let mut version: Option<String> = None;
+    let mut hash = None;
+    let mut has_data = false;
+    for event in EventReader::new(raw) {
+        match event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                if path.is_empty() && name.local_name != "KeyFile" {
+                    return Ok(None);
+                }
+                path.push(name.local_name);
+                if path == ["KeyFile", "Key", "Data"] {
+                    has_data = true;
+                    hash = attributes.into_iter()
+                                     .find(|attribute| attribute.name.local_name == "Hash")
+                                     .map(|attribute| attribute.value);
+                }
+            }
+            Ok(XmlEvent::EndElement { .. }) => {
+                path.pop();
+            }
+            Ok(XmlEvent::Characters(text)) | Ok(XmlEvent::CData(text)) | Ok(XmlEvent::Whitespace(text)) => {
+                if path == ["KeyFile", "Key", "Data"] {
+                    let fits = data.len() + text.len() <= data.capacity();
+                    if fits {
+                        data.extend_from_slice(text.as_bytes());
+                    }
+                    unsafe {
+                        write_array_volatile(text.as_ptr() as *mut u8, 0u8, text.len());
+                    }
+                    if !fits {
+                        return Err(V1KpdbError::ConvertErr);
+                    }
+                } else if path == ["KeyFile", "Meta", "Version"] {
+                    version.get_or_insert_with(String::new).push_str(&text);
+                }
+            }
+            Ok(_) => {}
+            Err(_) => return Ok(None),
+        }
+    }
+    match version {
+        Some(version) if has_data => Ok(Some((version, hash))),
+        _ => Err(V1KpdbError::ConvertErr),
+    }
+}
+
+// Sensitive data in this function:
+// * data (locked: read_xml_keyfile)
+// * key
+//
+// At the end of this function:
+// * key has moved out of function and is locked
+fn decode_xml_key(data: &[u8], version: &str, hash: Option<String>) -> Result<Vec<u8>, V1KpdbError> {
+    let text = try!(str::from_utf8(data).map_err(|_| V1KpdbError::ConvertErr));
+    match version.trim().split('.').next() {
+        Some("1") => {
+            let key = try!(text.trim().from_base64().map_err(|_| V1KpdbError::ConvertErr));
+            unsafe {
+                mlock(key.as_ptr() as *const c_void, key.len() as size_t);
+            }
+            Ok(key)
+        }
+        Some("2") => {
+            // The hex digits are split into groups and lines, they are
+            // decoded straight into the locked key
+            let mut key: Vec<u8> = Vec::with_capacity(text.len() / 2);
+            unsafe {
+                mlock(key.as_ptr() as *const c_void, key.capacity() as size_t);
+            }
+            let mut digits = text.chars().filter(|c| !c.is_whitespace()).map(|c| c.to_digit(16));
+            let mut valid = true;
+            while let Some(high) = digits.next() {
+                match (high, digits.next()) {
+                    (Some(high), Some(Some(low))) => key.push((high << 4 | low) as u8),
+                    _ => {
+                        valid = false;
+                        break;
+                    }
+                }
+            }
+            let error = if !valid {
+                Some(V1KpdbError::ConvertErr)
+            } else if hash.is_some_and(|hash| !hash.eq_ignore_ascii_case(&key_hash(&key))) {
+                Some(V1KpdbError::KeyfileHashErr)
+            } else {
+                None
+            };
+            if let Some(error) = error {
+                unsafe {
+                    write_array_volatile(key.as_mut_ptr(), 0u8, key.len());
+                    munlock(key.as_ptr() as *const c_void, key.capacity() as size_t);
+                }
+                return Err(error);
+            }
+            Ok(key)
+        }
+        _ => Err(V1KpdbError::ConvertErr),
+    }
+}
+
+// First four bytes of the SHA-256 hash of the key as upper case hex
+fn key_hash(key: &[u8]) -> String {
+    let mut hasher = hash::Hasher::new(hash::MessageDigest::sha256()).expect("Can't create hasher!?");
+    hasher.write_all(key).expect("Can't hash key!?");
+    let hash = hasher.finish2().expect("Can't hash key!?");
+    hash[..4].to_hex().to_uppercase()
+}
+
+#[doc = "
+The formats of keyfiles create_keyfile can write. All of them hold
+a random 256 bit key which is used as is.
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum KeyfileFormat {
+    /// The 32 bytes of the key
+    Raw,
+    /// The key as 64 hex characters
+    Hex,
+    /// XML keyfile (version 2.0) of KeePass 2.x
+    Xml,
+}
+
+/// Create a new keyfile with a random 256 bit key. Only the owner may
+/// read and write the keyfile. Use the path of the keyfile as keyfile
+/// parameter of V1Kpdb or V2Kdbx.
+///
+/// Returns ExistsErr if there is a file at path already. It's never
+/// overwritten, as that would lock out of the databases using it.
+//
+// Sensitive data in this function:
+// * key
+// * content
+//
+// At the end of this function:
+// * key and content are deleted
+//
+// key and content are locked during procession. content is large
+// enough for every format, so it's never reallocated.
+pub fn create_keyfile(path: &str, format: KeyfileFormat) -> Result<(), V1KpdbError> {
+    let mut file = match OpenOptions::new().write(true).create_new(true).mode(0o600).open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => return Err(V1KpdbError::ExistsErr),
+        Err(_) => return Err(V1KpdbError::WriteErr),
+    };
+
+    let mut key: Vec<u8> = vec![0u8; 32];
+    let mut content: Vec<u8> = Vec::with_capacity(KEYFILE_CAPACITY);
+    unsafe {
+        mlock(key.as_ptr() as *const c_void, key.len() as size_t);
+        mlock(content.as_ptr() as *const c_void, content.capacity() as size_t);
+    }
+    for byte in key.iter_mut() {
+        *byte = rand::random::<u8>();
+    }
+    match format {
+        KeyfileFormat::Raw => content.extend_from_slice(&key),
+        KeyfileFormat::Hex => push_hex(&mut content, &key, false),
+        KeyfileFormat::Xml => xml_keyfile(&mut content, &key),
+    }
+    debug_assert_eq!(content.capacity(), KEYFILE_CAPACITY);
+
+    // The umask may have cleared bits of mode
+    let written = file.set_permissions(Permissions::from_mode(0o600))
+                      .and_then(|_| file.write_all(&content))
+                      .and_then(|_| file.flush());
+
+    unsafe {
+        write_array_volatile(key.as_ptr() as *mut u8, 0u8, key.len());
+        munlock(key.as_ptr() as *const c_void, key.len() as size_t);
+        write_array_volatile(content.as_ptr() as *mut u8, 0u8, content.len());
+        munlock(content.as_ptr() as *const c_void, content.capacity() as size_t);
+    }
+    if written.is_err() {
+        // Don't leave a broken keyfile behind
+        drop(file);
+        let _ = fs::remove_file(path);
+        return Err(V1KpdbError::WriteErr);
+    }
+    Ok(())
+}
+
+/// Create a new XML keyfile (version 2.0) like KeePass 2.x does,
+/// see create_keyfile
+pub fn create_xml_keyfile(path: &str) -> Result<(), V1KpdbError> {
+    create_keyfile(path, KeyfileFormat::Xml)
+}
+
+// Append a XML keyfile (version 2.0) holding key to content. The hex
+// digits of the key are split into groups and lines like KeePass 2.x
+// does. The key is only written into content, which the caller locks.
+fn xml_keyfile(content: &mut Vec<u8>, key: &[u8]) {
+    content.extend_from_slice(b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+                                <KeyFile>\n\
+                                \t<Meta>\n\
+                                \t\t<Version>2.0</Version>\n\
+                                \t</Meta>\n\
+                                \t<Key>\n\
+                                \t\t<Data Hash=\"");
+    content.extend_from_slice(key_hash(key).as_bytes());
+    content.extend_from_slice(b"\">\n");
+    for line in key.chunks(16) {
+        content.extend_from_slice(b"\t\t\t");
+        for (index, group) in line.chunks(4).enumerate() {
+            if index > 0 {
+                content.push(b' ');
+            }
+            push_hex(content, group, true);
+        }
+        content.push(b'\n');
+    }
+    content.extend_from_slice(b"\t\t</Data>\n\
+                                \t</Key>\n\
+                                </KeyFile>\n");
+}
+
+// Append the hex digits of bytes to content without an intermediate
+// string
+fn push_hex(content: &mut Vec<u8>, bytes: &[u8], upper_case: bool) {
+    let digits: &[u8; 16] = if upper_case {
+        b"0123456789ABCDEF"
+    } else {
+        b"0123456789abcdef"
+    };
+    for &byte in bytes.iter() {
+        content.push(digits[(byte >> 4) as usize]);
+        content.push(digits[(byte & 0x0f) as usize]);
+    }
+}
@@ -0,0 +1,610 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+
+use chrono::{DateTime, Local};
+use uuid::Uuid;
+
+use kpdb::v1entry::{EntryId, V1Entry};
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1group::{GroupId, V1Group};
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+#[doc = "
+A change merge applied to the database merged into
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeChange {
+    /// A group of the other database was added
+    GroupAdded { id: u32, title: String },
+    /// The fields of a group were taken from the other database
+    GroupUpdated { id: u32, title: String },
+    /// A group got another parent (0 is the root)
+    GroupMoved { id: u32, title: String, from: u32, to: u32 },
+    /// A group was deleted in the other database
+    GroupDeleted { id: u32, title: String },
+    /// A group deleted in this database was restored, because it was
+    /// changed in the other one or still holds entries or groups
+    GroupRestored { id: u32, title: String },
+    /// A new group of the other database had the id of a different
+    /// new group of this database and got a new id
+    GroupRenumbered { title: String, from: u32, to: u32 },
+    /// An entry of the other database was added
+    EntryAdded { uuid: Uuid, title: String },
+    /// The fields of an entry were taken from the other database
+    EntryUpdated { uuid: Uuid, title: String },
+    /// An entry was moved into another group
+    EntryMoved { uuid: Uuid, title: String, from: u32, to: u32 },
+    /// An entry was deleted in the other database
+    EntryDeleted { uuid: Uuid, title: String },
+    /// An entry deleted in this database was restored, because it
+    /// was changed in the other one
+    EntryRestored { uuid: Uuid, title: String },
+}
+
+impl fmt::Display for MergeChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MergeChange::GroupAdded { id, ref title } => write!(f, "Added group {} ({})", title, id),
+            MergeChange::GroupUpdated { id, ref title } => write!(f, "Updated group {} ({})", title, id),
+            MergeChange::GroupMoved { id, ref title, from, to } => {
+                write!(f, "Moved group {} ({}) from group {} to {}", title, id, from, to)
+            }
+            MergeChange::GroupDeleted { id, ref title } => write!(f, "Deleted group {} ({})", title, id),
+            MergeChange::GroupRestored { id, ref title } => write!(f, "Restored group {} ({})", title, id),
+            MergeChange::GroupRenumbered { ref title, from, to } => {
+                write!(f, "Renumbered group {} from {} to {}", title, from, to)
+            }
+            MergeChange::EntryAdded { uuid, ref title } => write!(f, "Added entry {} ({})", title, uuid),
+            MergeChange::EntryUpdated { uuid, ref title } => write!(f, "Updated entry {} ({})", title, uuid),
+            MergeChange::EntryMoved { uuid, ref title, from, to } => {
+                write!(f, "Moved entry {} ({}) from group {} to {}", title, uuid, from, to)
+            }
+            MergeChange::EntryDeleted { uuid, ref title } => write!(f, "Deleted entry {} ({})", title, uuid),
+            MergeChange::EntryRestored { uuid, ref title } => {
+                write!(f, "Restored entry {} ({})", title, uuid)
+            }
+        }
+    }
+}
+
+#[doc = "
+Lists everything merge changed in the database merged into
+"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeReport {
+    /// The changes in the order they were applied
+    pub changes: Vec<MergeChange>,
+}
+
+impl MergeReport {
+    fn new() -> MergeReport {
+        MergeReport { changes: vec![] }
+    }
+
+    /// True if merge didn't change anything
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for MergeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in self.changes.iter() {
+            try!(writeln!(f, "{}", change));
+        }
+        Ok(())
+    }
+}
+
+// Where the merged version of a group or entry comes from, the handle
+// is one of theirs if from_theirs is set, otherwise one of ours
+#[derive(Clone, Copy)]
+struct Merged<T> {
+    item: T,
+    from_theirs: bool,
+}
+
+/// Merge theirs into ours. Groups are matched by id, entries by UUID.
+/// If both databases changed a group or entry the one with the newer
+/// last_mod wins, including the group it belongs to.
+///
+/// * base: the common ancestor of both databases, e.g. the version
+///         both were loaded from. It's needed to tell deletions from
+///         additions and to find new groups of both sides which got
+///         the same id. Without it nothing is deleted.
+///
+/// * ours: the database which is changed, its header is kept
+///
+/// * theirs: the other database
+///
+/// A group or entry deleted on one side but changed after base on the
+/// other is kept. So are deleted groups which still hold entries or
+/// groups. Returns the list of changes applied to ours. Groups and
+/// entries of ours which survive keep their handles. Returns
+/// DecryptErr without changing ours if a username or password of
+/// theirs can't be decrypted.
+pub fn merge(base: Option<&V1Kpdb>,
+             ours: &mut V1Kpdb,
+             theirs: &V1Kpdb)
+             -> Result<MergeReport, V1KpdbError> {
+    // Check the secrets which may be copied before anything is changed
+    for &entry in theirs.entries.iter() {
+        let entry = &theirs[entry];
+        for secret in entry.username.iter().chain(entry.password.iter()) {
+            if secret.with_unlocked(|_| ()).is_none() {
+                return Err(V1KpdbError::DecryptErr);
+            }
+        }
+    }
+
+    let mut report = MergeReport::new();
+    let base_groups: HashMap<u32, DateTime<Local>> = base.map(|base| {
+                                                             base.groups
+                                                                 .iter()
+                                                                 .map(|&g| (base[g].id, base[g].last_mod))
+                                                                 .collect()
+                                                         })
+                                                         .unwrap_or_default();
+    let base_entries: HashMap<Uuid, DateTime<Local>> = base.map(|base| {
+                                                               base.entries
+                                                                   .iter()
+                                                                   .map(|&e| (base[e].uuid, base[e].last_mod))
+                                                                   .collect()
+                                                           })
+                                                           .unwrap_or_default();
+    let our_groups = groups_by_id(ours);
+    let our_entries = entries_by_uuid(ours);
+
+    let remap = renumber_groups(base.is_some(), &base_groups, ours, theirs, &mut report);
+    let their_id = |group: GroupId| remap[&theirs[group].id];
+    let mut their_groups: HashMap<u32, GroupId> = HashMap::new();
+    for &group in theirs.groups.iter() {
+        their_groups.insert(their_id(group), group);
+    }
+    let their_entries = entries_by_uuid(theirs);
+
+    // Decide which version of every group and entry survives
+    let mut groups: HashMap<u32, Merged<GroupId>> = HashMap::new();
+    let mut deleted_groups: HashMap<u32, Merged<GroupId>> = HashMap::new();
+    let mut restored_groups: HashSet<u32> = HashSet::new();
+    for (&id, &our_group) in our_groups.iter() {
+        let last_mod = ours[our_group].last_mod;
+        match their_groups.get(&id) {
+            Some(&their_group) => {
+                if theirs[their_group].last_mod > last_mod {
+                    groups.insert(id, merged(their_group, true));
+                } else {
+                    groups.insert(id, merged(our_group, false));
+                }
+            }
+            None => {
+                match base_groups.get(&id) {
+                    Some(&base_mod) if last_mod <= base_mod => {
+                        deleted_groups.insert(id, merged(our_group, false));
+                    }
+                    _ => {
+                        groups.insert(id, merged(our_group, false));
+                    }
+                }
+            }
+        }
+    }
+    for (&id, &their_group) in their_groups.iter() {
+        if our_groups.contains_key(&id) {
+            continue;
+        }
+        match base_groups.get(&id) {
+            Some(&base_mod) => {
+                if theirs[their_group].last_mod > base_mod {
+                    restored_groups.insert(id);
+                    groups.insert(id, merged(their_group, true));
+                } else {
+                    deleted_groups.insert(id, merged(their_group, true));
+                }
+            }
+            None => {
+                groups.insert(id, merged(their_group, true));
+            }
+        }
+    }
+
+    let mut entries: HashMap<Uuid, Merged<EntryId>> = HashMap::new();
+    let mut deleted_entries: Vec<EntryId> = vec![];
+    let mut restored_entries: HashSet<Uuid> = HashSet::new();
+    for &our_entry in ours.entries.iter() {
+        let uuid = ours[our_entry].uuid;
+        let last_mod = ours[our_entry].last_mod;
+        match their_entries.get(&uuid) {
+            Some(&their_entry) => {
+                if theirs[their_entry].last_mod > last_mod {
+                    entries.insert(uuid, merged(their_entry, true));
+                } else {
+                    entries.insert(uuid, merged(our_entry, false));
+                }
+            }
+            None => {
+                match base_entries.get(&uuid) {
+                    Some(&base_mod) if last_mod <= base_mod => {
+                        deleted_entries.push(our_entry);
+                    }
+                    _ => {
+                        entries.insert(uuid, merged(our_entry, false));
+                    }
+                }
+            }
+        }
+    }
+    for (uuid, &their_entry) in their_entries.iter() {
+        if our_entries.contains_key(uuid) {
+            continue;
+        }
+        match base_entries.get(uuid) {
+            Some(&base_mod) => {
+                if theirs[their_entry].last_mod > base_mod {
+                    restored_entries.insert(*uuid);
+                    entries.insert(*uuid, merged(their_entry, true));
+                }
+            }
+            None => {
+                entries.insert(*uuid, merged(their_entry, true));
+            }
+        }
+    }
+
+    // The group ids of the surviving groups and entries, as
+    // their groups were renumbered
+    let new_id = |id: u32, from_theirs: bool| -> u32 {
+        if from_theirs { *remap.get(&id).unwrap_or(&id) } else { id }
+    };
+    let parent_id = |ours: &V1Kpdb, merged: &Merged<GroupId>| -> u32 {
+        let db = if merged.from_theirs { theirs } else { ours };
+        match parent_of(db, merged.item) {
+            0 => 0,
+            id => new_id(id, merged.from_theirs),
+        }
+    };
+    let group_id = |ours: &V1Kpdb, merged: &Merged<EntryId>| -> u32 {
+        let db = if merged.from_theirs { theirs } else { ours };
+        new_id(db[merged.item].group_id, merged.from_theirs)
+    };
+
+    // Restore deleted groups which are still needed
+    loop {
+        let mut needed: Vec<u32> = vec![];
+        for merged in groups.values() {
+            let parent = parent_id(ours, merged);
+            if parent != 0 && !groups.contains_key(&parent) {
+                needed.push(parent);
+            }
+        }
+        for merged in entries.values() {
+            let id = group_id(ours, merged);
+            if !groups.contains_key(&id) {
+                needed.push(id);
+            }
+        }
+        if needed.is_empty() {
+            break;
+        }
+        for id in needed {
+            if groups.contains_key(&id) {
+                continue;
+            }
+            match deleted_groups.remove(&id) {
+                Some(group) => {
+                    if group.from_theirs {
+                        restored_groups.insert(id);
+                    }
+                    groups.insert(id, group);
+                }
+                None => return Err(V1KpdbError::TreeErr),
+            }
+        }
+    }
+
+    // Sort the groups into a tree. Ours keep their order, the new
+    // ones follow in the order of theirs.
+    let mut order: Vec<u32> = vec![];
+    for &group in ours.groups.iter() {
+        let id = ours[group].id;
+        if groups.contains_key(&id) {
+            order.push(id);
+        }
+    }
+    for &group in theirs.groups.iter() {
+        let id = their_id(group);
+        if groups.contains_key(&id) && !our_groups.contains_key(&id) {
+            order.push(id);
+        }
+    }
+    let mut parents: HashMap<u32, u32> = HashMap::new();
+    for &id in order.iter() {
+        parents.insert(id, parent_id(ours, &groups[&id]));
+    }
+    let mut sorted: Vec<(u32, u16)> = vec![];
+    add_children(0, 0, &order, &parents, &mut sorted);
+    // Groups which moved into each other on both sides form a
+    // cycle, put them on top level
+    while sorted.len() < order.len() {
+        let id = *order.iter().find(|id| !sorted.iter().any(|&(s, _)| s == **id)).unwrap();
+        parents.insert(id, 0);
+        sorted.clear();
+        add_children(0, 0, &order, &parents, &mut sorted);
+    }
+
+    // Take the groups and entries out of the tree of ours, the
+    // surviving ones are linked again
+    let old_groups = mem::take(&mut ours.groups);
+    let old_entries = mem::take(&mut ours.entries);
+    let root = ours.root_group;
+    ours[root].children.clear();
+    ours[root].entries.clear();
+    for &group in old_groups.iter() {
+        ours[group].children.clear();
+        ours[group].entries.clear();
+    }
+
+    // Build the new group tree of ours
+    let mut new_by_id: HashMap<u32, GroupId> = HashMap::new();
+    for &(id, _) in sorted.iter() {
+        let merged = groups[&id];
+        let parent = match parents[&id] {
+            0 => root,
+            parent => new_by_id[&parent],
+        };
+        let index = ours.groups.len();
+        let group = match our_groups.get(&id) {
+            Some(&group) => {
+                let old_parent = parent_of(ours, group);
+                if merged.from_theirs {
+                    copy_group(&mut ours[group], &theirs[merged.item]);
+                    report.changes.push(MergeChange::GroupUpdated {
+                        id: id,
+                        title: ours[group].title.clone(),
+                    });
+                }
+                if old_parent != parents[&id] {
+                    report.changes.push(MergeChange::GroupMoved {
+                        id: id,
+                        title: ours[group].title.clone(),
+                        from: old_parent,
+                        to: parents[&id],
+                    });
+                }
+                ours.link_group(group, parent, index);
+                group
+            }
+            None => {
+                let mut group = V1Group::new();
+                copy_group(&mut group, &theirs[merged.item]);
+                group.id = id;
+                let title = group.title.clone();
+                report.changes.push(if restored_groups.contains(&id) {
+                    MergeChange::GroupRestored { id: id, title: title }
+                } else {
+                    MergeChange::GroupAdded { id: id, title: title }
+                });
+                ours.push_group(group, parent)
+            }
+        };
+        new_by_id.insert(id, group);
+    }
+    let mut deleted: Vec<(u32, String)> = deleted_groups.iter()
+                                                        .filter(|&(_, g)| !g.from_theirs)
+                                                        .map(|(&id, g)| (id, ours[g.item].title.clone()))
+                                                        .collect();
+    deleted.sort();
+    for (id, title) in deleted {
+        report.changes.push(MergeChange::GroupDeleted { id: id, title: title });
+    }
+
+    // Sort the entries into their groups. Ours keep their order,
+    // the new ones follow in the order of theirs.
+    let mut uuids: Vec<Uuid> = old_entries.iter()
+                                          .map(|&e| ours[e].uuid)
+                                          .filter(|uuid| entries.contains_key(uuid))
+                                          .collect();
+    for &entry in theirs.entries.iter() {
+        let uuid = theirs[entry].uuid;
+        if entries.contains_key(&uuid) && !our_entries.contains_key(&uuid) {
+            uuids.push(uuid);
+        }
+    }
+    for uuid in uuids.iter() {
+        let merged = entries[uuid];
+        let id = group_id(ours, &merged);
+        let group = new_by_id[&id];
+        match our_entries.get(uuid) {
+            Some(&entry) => {
+                let old_group = ours[entry].group_id;
+                if merged.from_theirs {
+                    try!(copy_entry(&mut ours[entry], &theirs[merged.item]));
+                    report.changes.push(MergeChange::EntryUpdated {
+                        uuid: *uuid,
+                        title: ours[entry].title.clone(),
+                    });
+                }
+                if old_group != id {
+                    report.changes.push(MergeChange::EntryMoved {
+                        uuid: *uuid,
+                        title: ours[entry].title.clone(),
+                        from: old_group,
+                        to: id,
+                    });
+                }
+                ours.link_entry(entry, Some(group));
+            }
+            None => {
+                let mut entry = V1Entry::new();
+                try!(copy_entry(&mut entry, &theirs[merged.item]));
+                let title = entry.title.clone();
+                report.changes.push(if restored_entries.contains(uuid) {
+                    MergeChange::EntryRestored { uuid: *uuid, title: title }
+                } else {
+                    MergeChange::EntryAdded { uuid: *uuid, title: title }
+                });
+                ours.push_entry(entry, Some(group));
+            }
+        }
+    }
+    for &entry in deleted_entries.iter() {
+        report.changes.push(MergeChange::EntryDeleted {
+            uuid: ours[entry].uuid,
+            title: ours[entry].title.clone(),
+        });
+    }
+
+    // Drop what didn't survive
+    let linked: HashSet<EntryId> = ours.entries.iter().cloned().collect();
+    for entry in old_entries {
+        if !linked.contains(&entry) {
+            ours.drop_entry(entry);
+        }
+    }
+    for group in old_groups {
+        if new_by_id.get(&ours[group].id) != Some(&group) {
+            ours.drop_group(group);
+        }
+    }
+    Ok(report)
+}
+
+fn merged<T>(item: T, from_theirs: bool) -> Merged<T> {
+    Merged {
+        item: item,
+        from_theirs: from_theirs,
+    }
+}
+
+fn groups_by_id(db: &V1Kpdb) -> HashMap<u32, GroupId> {
+    db.groups.iter().map(|&g| (db[g].id, g)).collect()
+}
+
+fn entries_by_uuid(db: &V1Kpdb) -> HashMap<Uuid, EntryId> {
+    db.entries.iter().map(|&e| (db[e].uuid, e)).collect()
+}
+
+// The id of the parent of a group, 0 for the root group
+fn parent_of(db: &V1Kpdb, group: GroupId) -> u32 {
+    match db[group].parent {
+        Some(parent) => db[parent].id,
+        None => 0,
+    }
+}
+
+// Give new groups of theirs which got the id of a different new group
+// of ours a new id, unless they were merged into ours before already.
+// Without base every group with the same id is the same group. Returns
+// the new ids of all groups of theirs.
+fn renumber_groups(has_base: bool,
+                   base_groups: &HashMap<u32, DateTime<Local>>,
+                   ours: &V1Kpdb,
+                   theirs: &V1Kpdb,
+                   report: &mut MergeReport)
+                   -> HashMap<u32, u32> {
+    let mut used: HashSet<u32> = HashSet::new();
+    used.extend(base_groups.keys());
+    used.extend(ours.groups.iter().map(|&g| ours[g].id));
+    used.extend(theirs.groups.iter().map(|&g| theirs[g].id));
+    let our_groups = groups_by_id(ours);
+
+    let same = |ours: &V1Group, theirs: &V1Group| {
+        ours.title == theirs.title && ours.creation.timestamp() == theirs.creation.timestamp()
+    };
+    let mut remap: HashMap<u32, u32> = HashMap::new();
+    for &group in theirs.groups.iter() {
+        let group = &theirs[group];
+        let collides = match our_groups.get(&group.id) {
+            Some(&our_group) if has_base && !base_groups.contains_key(&group.id) => !same(&ours[our_group], group),
+            _ => false,
+        };
+        let merged_before = ours.groups.iter().find(|&&our_group| {
+            let id = ours[our_group].id;
+            !base_groups.contains_key(&id) && !remap.values().any(|&v| v == id) &&
+            same(&ours[our_group], group)
+        });
+        if !collides {
+            remap.insert(group.id, group.id);
+        } else if let Some(&our_group) = merged_before {
+            remap.insert(group.id, ours[our_group].id);
+        } else {
+            let mut id = 1;
+            while used.contains(&id) {
+                id += 1;
+            }
+            used.insert(id);
+            remap.insert(group.id, id);
+            report.changes.push(MergeChange::GroupRenumbered {
+                title: group.title.clone(),
+                from: group.id,
+                to: id,
+            });
+        }
+    }
+    remap
+}
+
+// Append the children of parent to sorted in the given order,
+// each followed by its own children
+fn add_children(parent: u32,
+                level: u16,
+                order: &[u32],
+                parents: &HashMap<u32, u32>,
+                sorted: &mut Vec<(u32, u16)>) {
+    for &id in order.iter() {
+        if parents[&id] == parent && id != parent {
+            sorted.push((id, level));
+            add_children(id, level + 1, order, parents, sorted);
+        }
+    }
+}
+
+fn copy_group(group: &mut V1Group, from: &V1Group) {
+    group.title = from.title.clone();
+    group.image = from.image;
+    group.creation = from.creation;
+    group.last_mod = from.last_mod;
+    group.last_access = from.last_access;
+    group.expire = from.expire;
+    group.flags = from.flags;
+}
+
+// Sensitive data in this function:
+//
+// * username and password of from (decrypted and copied)
+//
+// At the end of this function:
+//
+// * the decrypted strings are overwritten, the copies are
+//   moved into new SecureStrings
+fn copy_entry(entry: &mut V1Entry, from: &V1Entry) -> Result<(), V1KpdbError> {
+    let username = match from.username {
+        Some(ref username) => Some(try!(copy_secure_string(username))),
+        None => None,
+    };
+    let password = match from.password {
+        Some(ref password) => Some(try!(copy_secure_string(password))),
+        None => None,
+    };
+    entry.uuid = from.uuid;
+    entry.image = from.image;
+    entry.title = from.title.clone();
+    entry.url = from.url.clone();
+    entry.username = username;
+    entry.password = password;
+    entry.comment = from.comment.clone();
+    entry.binary_desc = from.binary_desc.clone();
+    entry.binary = from.binary.clone();
+    entry.creation = from.creation;
+    entry.last_mod = from.last_mod;
+    entry.last_access = from.last_access;
+    entry.expire = from.expire;
+    Ok(())
+}
+
+fn copy_secure_string(string: &SecureString) -> Result<SecureString, V1KpdbError> {
+    string.with_unlocked(|string| SecureString::new(string.to_string()))
+          .ok_or(V1KpdbError::DecryptErr)
+}
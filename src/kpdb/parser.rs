@@ -1,6 +1,4 @@
 use libc::{c_void, munlock, size_t};
-use std::cell::{RefCell, RefMut};
-use std::rc::Rc;
 use std::str;
 
 use chrono::{DateTime, Local, TimeZone, Datelike, Timelike};
@@ -8,7 +6,7 @@ use rustc_serialize::hex::FromHex;
 use uuid::Uuid;
 
 use kpdb::v1entry::V1Entry;
-use kpdb::v1group::V1Group;
+use kpdb::v1group::{GroupId, V1Group};
 use kpdb::v1header::V1Header;
 use kpdb::v1kpdb::V1Kpdb;
 use kpdb::v1error::V1KpdbError;
@@ -111,11 +109,11 @@ impl LoadParser {
     }
     
     // Parse the groups and put them into a vector
-    pub fn parse_groups(&mut self) -> Result<(Vec<Rc<RefCell<V1Group>>>, Vec<u16>), V1KpdbError> {
+    pub fn parse_groups(&mut self) -> Result<(Vec<V1Group>, Vec<u16>), V1KpdbError> {
         let mut group_number: u32 = 0;
         let mut levels: Vec<u16> = vec![];
-        let mut cur_group = Rc::new(RefCell::new(V1Group::new()));
-        let mut groups: Vec<Rc<RefCell<V1Group>>> = vec![];
+        let mut cur_group = V1Group::new();
+        let mut groups: Vec<V1Group> = vec![];
 
         let mut field_type: u16;
         let mut field_size: u32;
@@ -135,17 +133,17 @@ impl LoadParser {
                 return Err(V1KpdbError::OffsetErr);
             }
 
-            let _ = self.read_group_field(cur_group.borrow_mut(), field_type, field_size);
+            let _ = self.read_group_field(&mut cur_group, field_type, field_size);
 
             if field_type == 0x0008 {
-                levels.push(cur_group.borrow().level);
+                levels.push(cur_group.level);
             } else if field_type == 0xFFFF {
                 groups.push(cur_group);
                 group_number += 1;
                 if group_number == self.num_groups {
                     break;
                 };
-                cur_group = Rc::new(RefCell::new(V1Group::new()));
+                cur_group = V1Group::new();
             }
 
             self.pos += field_size as usize;
@@ -159,10 +157,10 @@ impl LoadParser {
     }
 
     // Parse the entries and put them into a vector
-    pub fn parse_entries(&mut self) -> Result<Vec<Rc<RefCell<V1Entry>>>, V1KpdbError> {
+    pub fn parse_entries(&mut self) -> Result<Vec<V1Entry>, V1KpdbError> {
         let mut entry_number: u32 = 0;
-        let mut cur_entry = Rc::new(RefCell::new(V1Entry::new()));
-        let mut entries: Vec<Rc<RefCell<V1Entry>>> = vec![];
+        let mut cur_entry = V1Entry::new();
+        let mut entries: Vec<V1Entry> = vec![];
 
         let mut field_type: u16;
         let mut field_size: u32;
@@ -182,7 +180,7 @@ impl LoadParser {
                 return Err(V1KpdbError::OffsetErr);
             }
 
-            let _ = self.read_entry_field(cur_entry.borrow_mut(), field_type, field_size);
+            let _ = self.read_entry_field(&mut cur_entry, field_type, field_size);
 
             if field_type == 0xFFFF {
                 entries.push(cur_entry);
@@ -190,7 +188,7 @@ impl LoadParser {
                 if entry_number == self.num_entries {
                     break;
                 };
-                cur_entry = Rc::new(RefCell::new(V1Entry::new()));
+                cur_entry = V1Entry::new();
             }
 
             self.pos += field_size as usize;
@@ -205,7 +203,7 @@ impl LoadParser {
 
     // Read a group field from the raw data by it's field type
     fn read_group_field(&mut self,
-                        mut group: RefMut<V1Group>,
+                        group: &mut V1Group,
                         field_type: u16,
                         field_size: u32)
                         -> Result<(), V1KpdbError> {
@@ -237,7 +235,7 @@ impl LoadParser {
 
     // Read an entry field from the raw data by it's field type
     fn read_entry_field(&mut self,
-                        mut entry: RefMut<V1Entry>,
+                        entry: &mut V1Entry,
                         field_type: u16,
                         field_size: u32)
                         -> Result<(), V1KpdbError> {
@@ -311,21 +309,24 @@ impl LoadParser {
         Local.ymd(year, month, day).and_hms(hour, minute, second)
     }
 
-    // Create the group tree from the level data
-    pub fn create_group_tree(db: &mut V1Kpdb, levels: Vec<u16>) -> Result<(), V1KpdbError> {
-        if levels[0] != 0 {
+    // Create the group tree from the level data and sort the entries
+    // to their groups
+    pub fn create_group_tree(db: &mut V1Kpdb,
+                             groups: Vec<V1Group>,
+                             levels: Vec<u16>,
+                             entries: Vec<V1Entry>)
+                             -> Result<(), V1KpdbError> {
+        if levels.len() != groups.len() || levels.first().is_some_and(|&level| level != 0) {
             return Err(V1KpdbError::TreeErr);
         }
 
-        for i in 0..db.groups.len() {
+        let mut ids: Vec<GroupId> = vec![];
+        for (i, group) in groups.into_iter().enumerate() {
             // level 0 means that the group is not a sub group. Hence add it as a children
             // of the root
             if levels[i] == 0 {
-                db.groups[i].borrow_mut().parent = Some(db.root_group.clone());
-                db.root_group
-                  .borrow_mut()
-                  .children
-                  .push(Rc::downgrade(&(db.groups[i].clone())));
+                let root = db.root_group;
+                ids.push(db.push_group(group, root));
                 continue;
             }
 
@@ -337,11 +338,8 @@ impl LoadParser {
                     if levels[i] - levels[j] != 1 {
                         return Err(V1KpdbError::TreeErr);
                     }
-                    db.groups[i].borrow_mut().parent = Some(db.groups[j].clone());
-                    db.groups[j]
-                        .borrow_mut()
-                        .children
-                        .push(Rc::downgrade(&(db.groups[i].clone())));
+                    let parent = ids[j];
+                    ids.push(db.push_group(group, parent));
                     break;
                 }
                 // It's not possible that a group which comes after another
@@ -354,16 +352,9 @@ impl LoadParser {
             }
         }
 
-        // Sort entries to their groups
-        // iter is secure as it is just obfuscated
-        // pointer arithmetic to the entries vector
-        for e in db.entries.iter() {
-            for g in db.groups.iter() {
-                if e.borrow().group_id == g.borrow().id {
-                    g.borrow_mut().entries.push(Rc::downgrade(&e.clone()));
-                    e.borrow_mut().group = Some(g.clone());
-                }
-            }
+        for entry in entries {
+            let group = db.group_by_id(entry.group_id);
+            db.push_entry(entry, group);
         }
 
         Ok(())
@@ -399,18 +390,27 @@ impl SaveParser {
         }
     }
 
-    pub fn prepare(&mut self, database: &V1Kpdb) {
+    /// Serialize the groups and entries of database. Returns DecryptErr
+    /// if a username or password can't be decrypted, what was
+    /// serialized so far is overwritten with zeroes then.
+    pub fn prepare(&mut self, database: &V1Kpdb) -> Result<(), V1KpdbError> {
         self.save_groups(database);
-        self.save_entries(database);
+        let result = self.save_entries(database);
+        if result.is_err() {
+            unsafe {
+                write_array_volatile(self.database.as_ptr() as *mut u8, 0u8, self.database.len());
+            }
+        }
+        result
     }
     
     fn save_groups(&mut self,
                    database: &V1Kpdb) {
         let mut ret: Vec<u8>;
         let mut ret_len: u32;
-        for group in &database.groups {
+        for &group in &database.groups {
             for field_type in 1..10 as u16 {
-                ret = SaveParser::save_group_field(group.clone(), field_type);
+                ret = SaveParser::save_group_field(&database[group], field_type);
                 ret_len = ret.len() as u32;
                 if ret_len > 0 {
                     self.database.append(&mut u16_to_vec_u8(field_type));
@@ -424,12 +424,12 @@ impl SaveParser {
     }
 
     fn save_entries(&mut self,
-                    database: &V1Kpdb) {
+                    database: &V1Kpdb) -> Result<(), V1KpdbError> {
         let mut ret: Vec<u8>;
         let mut ret_len: u32;
-        for entry in &database.entries {
+        for &entry in &database.entries {
             for field_type in 1..15 as u16 {
-                ret = SaveParser::save_entry_field(entry.clone(), field_type);
+                ret = try!(SaveParser::save_entry_field(&database[entry], field_type));
                 ret_len = ret.len() as u32;
                 if ret_len > 0 {
                     self.database.append(&mut u16_to_vec_u8(field_type));
@@ -440,91 +440,93 @@ impl SaveParser {
             self.database.append(&mut vec![0xFFu8, 0xFFu8]);
             self.database.append(&mut vec![0u8, 0u8, 0u8, 0u8]);
         }        
+        Ok(())
     }
     
-    fn save_group_field(group: Rc<RefCell<V1Group>>,
+    fn save_group_field(group: &V1Group,
                         field_type: u16) -> Vec<u8> {
         match field_type {
-            0x0001 => return u32_to_vec_u8(group.borrow().id),
+            0x0001 => return u32_to_vec_u8(group.id),
             0x0002 => {
-                let mut title = group.borrow().title.clone().into_bytes();
+                let mut title = group.title.clone().into_bytes();
                 title.push(0);
                 return title;
             },
-            0x0003 => return SaveParser::pack_date(&group.borrow().creation),
-            0x0004 => return SaveParser::pack_date(&group.borrow().last_mod),
-            0x0005 => return SaveParser::pack_date(&group.borrow().last_access),
-            0x0006 => return SaveParser::pack_date(&group.borrow().expire),
-            0x0007 => return u32_to_vec_u8(group.borrow().image),
-            0x0008 => return u16_to_vec_u8(group.borrow().level),
-            0x0009 => return u32_to_vec_u8(group.borrow().flags),
+            0x0003 => return SaveParser::pack_date(&group.creation),
+            0x0004 => return SaveParser::pack_date(&group.last_mod),
+            0x0005 => return SaveParser::pack_date(&group.last_access),
+            0x0006 => return SaveParser::pack_date(&group.expire),
+            0x0007 => return u32_to_vec_u8(group.image),
+            0x0008 => return u16_to_vec_u8(group.level),
+            0x0009 => return u32_to_vec_u8(group.flags),
             _ => (),
         }
 
         return vec![];
     }
 
-    fn save_entry_field(entry: Rc<RefCell<V1Entry>>,
-                        field_type: u16) -> Vec<u8> {
+    fn save_entry_field(entry: &V1Entry,
+                        field_type: u16) -> Result<Vec<u8>, V1KpdbError> {
         match field_type {
-            0x0001 => return (&entry.borrow().uuid.simple().to_string()[..]).from_hex().unwrap(), //Should never fail
-            0x0002 => return u32_to_vec_u8(entry.borrow().group_id),
-            0x0003 => return u32_to_vec_u8(entry.borrow().image),
+            0x0001 => return Ok((&entry.uuid.simple().to_string()[..]).from_hex().unwrap()), //Should never fail
+            0x0002 => return Ok(u32_to_vec_u8(entry.group_id)),
+            0x0003 => return Ok(u32_to_vec_u8(entry.image)),
             0x0004 => {
-                let mut ret = entry.borrow().title.clone().into_bytes();
+                let mut ret = entry.title.clone().into_bytes();
                 ret.push(0);
-                return ret;
+                return Ok(ret);
             },
             0x0005 => {
-                if let Some(ref url) = entry.borrow().url {
+                if let Some(ref url) = entry.url {
                     let mut ret = url.clone().into_bytes();
                     ret.push(0);
-                    return ret;                    
+                    return Ok(ret);                    
                 }
             },
             0x0006 => {
-                if let Some(ref mut username) = entry.borrow_mut().username {
-                    username.unlock();
-                    let mut ret = username.string.clone().into_bytes();
+                if let Some(ref username) = entry.username {
+                    // Never save an empty username instead of one which can't be decrypted
+                    let mut ret = try!(username.with_unlocked(|username| username.as_bytes().to_vec())
+                                               .ok_or(V1KpdbError::DecryptErr));
                     ret.push(0);
-                    return ret;
+                    return Ok(ret);
                 }
             },
             0x0007 => {
-                if let Some(ref mut password) = entry.borrow_mut().password {
-                    password.unlock();
-                    let mut ret = password.string.clone().into_bytes();
+                if let Some(ref password) = entry.password {
+                    let mut ret = try!(password.with_unlocked(|password| password.as_bytes().to_vec())
+                                               .ok_or(V1KpdbError::DecryptErr));
                     ret.push(0);
-                    return ret;
+                    return Ok(ret);
                 }
             },
             0x0008 => {
-                if let Some(ref comment) = entry.borrow().comment {
+                if let Some(ref comment) = entry.comment {
                     let mut ret = comment.clone().into_bytes();
                     ret.push(0);
-                    return ret;                    
+                    return Ok(ret);                    
                 }
             },
-            0x0009 => return SaveParser::pack_date(&entry.borrow().creation),
-            0x000A => return SaveParser::pack_date(&entry.borrow().last_mod),
-            0x000B => return SaveParser::pack_date(&entry.borrow().last_access),
-            0x000C => return SaveParser::pack_date(&entry.borrow().expire),
+            0x0009 => return Ok(SaveParser::pack_date(&entry.creation)),
+            0x000A => return Ok(SaveParser::pack_date(&entry.last_mod)),
+            0x000B => return Ok(SaveParser::pack_date(&entry.last_access)),
+            0x000C => return Ok(SaveParser::pack_date(&entry.expire)),
             0x000D => {
-                if let Some(ref binary_desc) = entry.borrow().binary_desc {
+                if let Some(ref binary_desc) = entry.binary_desc {
                     let mut ret = binary_desc.clone().into_bytes();
                     ret.push(0);
-                    return ret;                    
+                    return Ok(ret);                    
                 }
             },
             0x000E => {
-                if let Some(ref binary) = entry.borrow().binary {
-                    return binary.clone();
+                if let Some(ref binary) = entry.binary {
+                    return Ok(binary.clone());
                 }
             },
             _ => (),
         }
 
-        return vec![];        
+        Ok(vec![])
     }
     
     fn pack_date(date: &DateTime<Local>) -> Vec<u8> {
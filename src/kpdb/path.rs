@@ -0,0 +1,46 @@
+// Paths of groups and entries like "Internet/Email/Gmail": the
+// titles from the top of the group tree down, separated by "/".
+// A "/" in a title is written as "\/" and a backslash as "\\".
+
+/// Escape a title to be used as part of a path
+pub fn escape_title(title: &str) -> String {
+    let mut escaped = String::with_capacity(title.len());
+    for c in title.chars() {
+        if c == '/' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Split a path into its unescaped titles. Leading, trailing and
+/// repeated slashes are ignored, so "/Internet//Email/" is the same
+/// as "Internet/Email".
+pub fn split_path(path: &str) -> Vec<String> {
+    let mut titles: Vec<String> = vec![];
+    let mut title = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // A trailing backslash stands for itself
+            '\\' => title.push(chars.next().unwrap_or('\\')),
+            '/' => {
+                if !title.is_empty() {
+                    titles.push(title);
+                    title = String::new();
+                }
+            }
+            c => title.push(c),
+        }
+    }
+    if !title.is_empty() {
+        titles.push(title);
+    }
+    titles
+}
+
+/// Join titles into a path, the inverse of split_path
+pub fn join_path<S: AsRef<str>>(titles: &[S]) -> String {
+    titles.iter().map(|title| escape_title(title.as_ref())).collect::<Vec<String>>().join("/")
+}
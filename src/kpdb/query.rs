@@ -0,0 +1,507 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use regex;
+
+use kpdb::path::{join_path, split_path};
+use kpdb::query_error::{QueryError, QueryErrorKind};
+use kpdb::search::{Matcher, SearchField};
+use kpdb::v1entry::{EntryId, V1Entry};
+use kpdb::v1group::{GroupId, V1Group};
+use kpdb::v1kpdb::V1Kpdb;
+
+#[doc = "
+A parsed query which finds entries or groups of a V1Kpdb, e.g.
+
+```text
+group:\"Servers/Prod\" AND url:*.example.com AND expires<30d
+title~/^db-/ OR (mail AND NOT comment:old)
+```
+
+A query is made of conditions combined with AND, OR, NOT and
+parentheses. NOT binds strongest, then AND, then OR. The conditions
+are:
+
+```text
+mail                 a default field (title, username, url, comment
+                     or attachment) contains mail
+title:db             the title contains db, * and ? make it a pattern
+                     for the whole title, e.g. url:*.example.com
+title=db             the title is db
+title~/^db-[0-9]+$/  the regular expression matches the title
+group:Servers/Prod   in the group with this path or a subgroup of it,
+                     group=... only in the group itself, group~...
+                     matches the regular expression against the path
+expires<30d          expires in less than 30 days from now, the date
+                     fields can also be compared with <=, >, >= and
+                     : or = (on the same day)
+```
+
+The text fields are title, username, url, comment, attachment (its
+name) and password, the date fields created, modified, accessed and
+expires. Text is compared ignoring case, group paths are not. Values
+with spaces or parentheses are quoted like \"a \\\"b\\\"\", regular
+expressions containing / are written like /a\\/b/. Dates are given as
+2024-01-31, 2024-01-31T12:00, now, never or relative to now with a
+unit h (hours), d (days) or w (weeks), e.g. 30d or -12h.
+
+Groups only have a title and the date fields, conditions on other
+fields are false for them.
+"]
+#[derive(Debug, Clone)]
+pub struct Query {
+    node: Node,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    // The matcher matches one of the fields
+    Text(Vec<SearchField>, Matcher),
+    Group(GroupCondition),
+    Date(DateField, Operator, DateTime<Local>),
+}
+
+#[derive(Debug, Clone)]
+enum GroupCondition {
+    // The group with this path or one of its subgroups
+    Subtree(String),
+    Exact(String),
+    Path(Matcher),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateField {
+    Created,
+    Modified,
+    Accessed,
+    Expires,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Text(SearchField),
+    Group,
+    Date(DateField),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Contains,
+    Equals,
+    Matches,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+// What a query is evaluated on
+#[derive(Clone, Copy)]
+enum Item<'a> {
+    Entry(&'a V1Entry),
+    Group(GroupId, &'a V1Group),
+}
+
+impl Query {
+    /// Parse a query, relative dates like 30d are relative to the
+    /// time of parsing
+    pub fn parse(query: &str) -> Result<Query, QueryError> {
+        Query::parse_at(query, Local::now())
+    }
+
+    /// Same as parse, but relative dates are relative to now
+    pub fn parse_at(query: &str, now: DateTime<Local>) -> Result<Query, QueryError> {
+        let mut parser = Parser {
+            chars: query.chars().collect(),
+            position: 0,
+            now: now,
+        };
+        let node = try!(parser.or());
+        parser.skip_whitespace();
+        if parser.peek().is_some() {
+            return Err(QueryError::new(QueryErrorKind::TrailingErr, parser.position));
+        }
+        Ok(Query { node: node })
+    }
+
+    /// Whether the entry of db matches, false if it was removed
+    pub fn matches_entry(&self, db: &V1Kpdb, entry: EntryId) -> bool {
+        match db.entry(entry) {
+            Some(entry) => self.node.eval(db, Item::Entry(entry)),
+            None => false,
+        }
+    }
+
+    /// Whether the group of db matches, false if it was removed
+    pub fn matches_group(&self, db: &V1Kpdb, group: GroupId) -> bool {
+        match db.group(group) {
+            Some(found) => self.node.eval(db, Item::Group(group, found)),
+            None => false,
+        }
+    }
+
+    /// Iterate lazily over the entries of db which match, in the
+    /// order of the file
+    pub fn entries<'a>(&'a self, db: &'a V1Kpdb) -> impl Iterator<Item = EntryId> + 'a {
+        db.entries.iter().cloned().filter(move |&entry| self.matches_entry(db, entry))
+    }
+
+    /// Iterate lazily over the groups of db which match, in the order
+    /// of the file
+    pub fn groups<'a>(&'a self, db: &'a V1Kpdb) -> impl Iterator<Item = GroupId> + 'a {
+        db.groups.iter().cloned().filter(move |&group| self.matches_group(db, group))
+    }
+}
+
+impl Node {
+    fn eval(&self, db: &V1Kpdb, item: Item) -> bool {
+        match *self {
+            Node::And(ref left, ref right) => left.eval(db, item) && right.eval(db, item),
+            Node::Or(ref left, ref right) => left.eval(db, item) || right.eval(db, item),
+            Node::Not(ref node) => !node.eval(db, item),
+            Node::Text(ref fields, ref matcher) => {
+                match item {
+                    Item::Entry(entry) => fields.iter().any(|field| field.matches(entry, matcher)),
+                    Item::Group(_, group) => {
+                        fields.contains(&SearchField::Title) && matcher.is_match(&group.title)
+                    }
+                }
+            }
+            Node::Group(ref condition) => {
+                let group = match item {
+                    Item::Entry(entry) => entry.group,
+                    Item::Group(group, _) => Some(group),
+                };
+                let path = match group {
+                    Some(group) if db.group(group).is_some() => db.group_path(group),
+                    _ => return false,
+                };
+                match *condition {
+                    GroupCondition::Subtree(ref top) => path == *top || path.starts_with(&format!("{}/", top)),
+                    GroupCondition::Exact(ref exact) => path == *exact,
+                    GroupCondition::Path(ref matcher) => matcher.is_match(&path),
+                }
+            }
+            Node::Date(field, operator, ref date) => {
+                let value = match (item, field) {
+                    (Item::Entry(entry), DateField::Created) => entry.creation,
+                    (Item::Entry(entry), DateField::Modified) => entry.last_mod,
+                    (Item::Entry(entry), DateField::Accessed) => entry.last_access,
+                    (Item::Entry(entry), DateField::Expires) => entry.expire,
+                    (Item::Group(_, group), DateField::Created) => group.creation,
+                    (Item::Group(_, group), DateField::Modified) => group.last_mod,
+                    (Item::Group(_, group), DateField::Accessed) => group.last_access,
+                    (Item::Group(_, group), DateField::Expires) => group.expire,
+                };
+                match operator {
+                    Operator::Less => value < *date,
+                    Operator::LessEqual => value <= *date,
+                    Operator::Greater => value > *date,
+                    Operator::GreaterEqual => value >= *date,
+                    _ => value.date_naive() == date.date_naive(),
+                }
+            }
+        }
+    }
+}
+
+// A recursive descent parser working on characters, so that the
+// positions of errors are columns
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    now: DateTime<Local>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map(char::is_whitespace) == Some(true) {
+            self.position += 1;
+        }
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> String {
+        let start = self.position;
+        while self.peek().map(&f) == Some(true) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().cloned().collect()
+    }
+
+    // Consume keyword if it comes next as a whole word
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespace();
+        let end = self.position + keyword.len();
+        if end > self.chars.len() || !self.chars[self.position..end].iter().cloned().eq(keyword.chars()) {
+            return false;
+        }
+        match self.chars.get(end) {
+            Some(&c) if !c.is_whitespace() && c != '(' && c != '"' => false,
+            _ => {
+                self.position = end;
+                true
+            }
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, QueryError> {
+        let mut node = try!(self.and());
+        while self.keyword("OR") {
+            node = Node::Or(Box::new(node), Box::new(try!(self.and())));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, QueryError> {
+        let mut node = try!(self.not());
+        while self.keyword("AND") {
+            node = Node::And(Box::new(node), Box::new(try!(self.not())));
+        }
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node, QueryError> {
+        if self.keyword("NOT") {
+            return Ok(Node::Not(Box::new(try!(self.not()))));
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Node, QueryError> {
+        self.skip_whitespace();
+        let start = self.position;
+        match self.peek() {
+            None | Some(')') => return Err(QueryError::new(QueryErrorKind::TermErr, start)),
+            Some('(') => {
+                self.position += 1;
+                let node = try!(self.or());
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err(QueryError::new(QueryErrorKind::ParenErr, self.position));
+                }
+                self.position += 1;
+                return Ok(node);
+            }
+            Some('"') => {
+                let text = try!(self.quoted());
+                return Ok(Node::Text(SearchField::defaults(), text_matcher(&text, Operator::Contains)));
+            }
+            _ => {}
+        }
+        let word = self.take_while(|c| !c.is_whitespace() && !"()\":=~<>".contains(c));
+        let operator_start = self.position;
+        match self.operator() {
+            Some(operator) => self.condition(&word, start, operator, operator_start),
+            None if word == "AND" || word == "OR" => Err(QueryError::new(QueryErrorKind::TermErr, start)),
+            None => Ok(Node::Text(SearchField::defaults(), text_matcher(&word, Operator::Contains))),
+        }
+    }
+
+    fn operator(&mut self) -> Option<Operator> {
+        let (operator, len) = match (self.peek(), self.chars.get(self.position + 1).cloned()) {
+            (Some('<'), Some('=')) => (Operator::LessEqual, 2),
+            (Some('>'), Some('=')) => (Operator::GreaterEqual, 2),
+            (Some('<'), _) => (Operator::Less, 1),
+            (Some('>'), _) => (Operator::Greater, 1),
+            (Some(':'), _) => (Operator::Contains, 1),
+            (Some('='), _) => (Operator::Equals, 1),
+            (Some('~'), _) => (Operator::Matches, 1),
+            _ => return None,
+        };
+        self.position += len;
+        Some(operator)
+    }
+
+    // name, operator and the value following them
+    fn condition(&mut self,
+                 name: &str,
+                 start: usize,
+                 operator: Operator,
+                 operator_start: usize)
+                 -> Result<Node, QueryError> {
+        let field = try!(field(name).ok_or(QueryError::new(QueryErrorKind::FieldErr, start)));
+        let supported = match field {
+            Field::Text(_) | Field::Group => {
+                operator == Operator::Contains || operator == Operator::Equals || operator == Operator::Matches
+            }
+            Field::Date(_) => operator != Operator::Matches,
+        };
+        if !supported {
+            return Err(QueryError::new(QueryErrorKind::OperatorErr, operator_start));
+        }
+
+        let value_start = self.position;
+        let value = try!(self.value(operator == Operator::Matches));
+        let regex_error = QueryError::new(QueryErrorKind::RegexErr, value_start);
+        match field {
+            Field::Text(field) => {
+                let matcher = match operator {
+                    Operator::Matches => try!(Matcher::regex(&value).map_err(|_| regex_error)),
+                    _ => text_matcher(&value, operator),
+                };
+                Ok(Node::Text(vec![field], matcher))
+            }
+            Field::Group => {
+                let condition = match operator {
+                    Operator::Matches => GroupCondition::Path(try!(Matcher::regex(&value).map_err(|_| regex_error))),
+                    _ => {
+                        let path = join_path(&split_path(&value));
+                        if path.is_empty() {
+                            return Err(QueryError::new(QueryErrorKind::ValueErr, value_start));
+                        }
+                        if operator == Operator::Contains {
+                            GroupCondition::Subtree(path)
+                        } else {
+                            GroupCondition::Exact(path)
+                        }
+                    }
+                };
+                Ok(Node::Group(condition))
+            }
+            Field::Date(field) => {
+                let date = try!(parse_date(&value, &self.now)
+                                    .ok_or(QueryError::new(QueryErrorKind::DateErr, value_start)));
+                Ok(Node::Date(field, operator, date))
+            }
+        }
+    }
+
+    // A quoted string, a /regular expression/ if allowed or a word
+    fn value(&mut self, regex: bool) -> Result<String, QueryError> {
+        match self.peek() {
+            Some('"') => self.quoted(),
+            Some('/') if regex => self.delimited(),
+            _ => {
+                let start = self.position;
+                let value = self.take_while(|c| !c.is_whitespace() && c != ')');
+                if value.is_empty() {
+                    return Err(QueryError::new(QueryErrorKind::ValueErr, start));
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    // "..." in which a backslash escapes the next character
+    fn quoted(&mut self) -> Result<String, QueryError> {
+        let start = self.position;
+        self.position += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.position += 1;
+                    return Ok(value);
+                }
+                Some('\\') if self.position + 1 < self.chars.len() => {
+                    value.push(self.chars[self.position + 1]);
+                    self.position += 2;
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.position += 1;
+                }
+                None => return Err(QueryError::new(QueryErrorKind::UnclosedErr, start)),
+            }
+        }
+    }
+
+    // /.../ in which \/ stands for / and other escapes are kept for
+    // the regular expression
+    fn delimited(&mut self) -> Result<String, QueryError> {
+        let start = self.position;
+        self.position += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                Some('/') => {
+                    self.position += 1;
+                    return Ok(value);
+                }
+                Some('\\') if self.position + 1 < self.chars.len() => {
+                    let next = self.chars[self.position + 1];
+                    if next != '/' {
+                        value.push('\\');
+                    }
+                    value.push(next);
+                    self.position += 2;
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.position += 1;
+                }
+                None => return Err(QueryError::new(QueryErrorKind::UnclosedErr, start)),
+            }
+        }
+    }
+}
+
+fn field(name: &str) -> Option<Field> {
+    let field = match &name.to_lowercase()[..] {
+        "title" => Field::Text(SearchField::Title),
+        "username" => Field::Text(SearchField::Username),
+        "url" => Field::Text(SearchField::Url),
+        "comment" => Field::Text(SearchField::Comment),
+        "attachment" => Field::Text(SearchField::BinaryDesc),
+        "password" => Field::Text(SearchField::Password),
+        "group" => Field::Group,
+        "created" => Field::Date(DateField::Created),
+        "modified" => Field::Date(DateField::Modified),
+        "accessed" => Field::Date(DateField::Accessed),
+        "expires" => Field::Date(DateField::Expires),
+        _ => return None,
+    };
+    Some(field)
+}
+
+// Equals compares the whole field, Contains a part of it unless the
+// text has wildcards
+fn text_matcher(text: &str, operator: Operator) -> Matcher {
+    if operator == Operator::Contains && !text.contains('*') && !text.contains('?') {
+        return Matcher::substring(text);
+    }
+    let mut pattern = "(?s)^".to_string();
+    for c in text.chars() {
+        match c {
+            '*' if operator == Operator::Contains => pattern.push_str(".*"),
+            '?' if operator == Operator::Contains => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Matcher::regex(&pattern).expect("Can't compile escaped text!?")
+}
+
+// 2024-01-31 (at midnight), 2024-01-31T12:00[:00], now, never or an
+// offset to now like 30d, -12h or +2w
+fn parse_date(value: &str, now: &DateTime<Local>) -> Option<DateTime<Local>> {
+    match value {
+        "now" => return Some(*now),
+        // Like KeePass
+        "never" => return Local.with_ymd_and_hms(2999, 12, 28, 23, 59, 59).single(),
+        _ => {}
+    }
+    let unit = value.chars().last().unwrap_or(' ');
+    if unit == 'h' || unit == 'd' || unit == 'w' {
+        let number = value[..value.len() - 1].trim_start_matches('+');
+        let offset = match (number.parse::<i64>().ok(), unit) {
+            (Some(hours), 'h') => Duration::try_hours(hours),
+            (Some(days), 'd') => Duration::try_days(days),
+            (Some(weeks), 'w') => Duration::try_weeks(weeks),
+            _ => None,
+        };
+        return offset.and_then(|offset| now.checked_add_signed(offset));
+    }
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+                    .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+                    .ok()
+                    .or_else(|| {
+                        NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0))
+                    });
+    naive.and_then(|naive| Local.from_local_datetime(&naive).earliest())
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::error;
+
+pub use self::QueryErrorKind::*;
+
+#[doc = "
+What is wrong with a query, see QueryError
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum QueryErrorKind {
+    /// Expected a condition, NOT or (, e.g. at the end of the query
+    TermErr,
+    /// The name before an operator isn't a field
+    FieldErr,
+    /// The field can't be compared with this operator, e.g.
+    /// title<x
+    OperatorErr,
+    /// The operator isn't followed by a value
+    ValueErr,
+    /// The value of a date field isn't a date
+    DateErr,
+    /// The value of ~ isn't a valid regular expression
+    RegexErr,
+    /// A quoted value or a regular expression isn't closed
+    UnclosedErr,
+    /// A ( isn't closed
+    ParenErr,
+    /// A complete condition is followed by something else than AND,
+    /// OR or )
+    TrailingErr,
+}
+
+#[doc = "
+Use this for catching errors in the syntax of a Query. position is
+the index of the character (not byte) of the query where the error
+was found, starting at 0.
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub struct QueryError {
+    pub kind: QueryErrorKind,
+    pub position: usize,
+}
+
+impl QueryError {
+    pub fn new(kind: QueryErrorKind, position: usize) -> QueryError {
+        QueryError {
+            kind: kind,
+            position: position,
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} at column {}", error::Error::description(self), self.position + 1)
+    }
+}
+
+impl error::Error for QueryError {
+    fn description(&self) -> &str {
+        match self.kind {
+            TermErr => "Expected a condition, NOT or (",
+            FieldErr => "Unknown field",
+            OperatorErr => "Operator can't be used with this field",
+            ValueErr => "Expected a value",
+            DateErr => "Expected a date like 2024-01-31, 30d, -12h, now or never",
+            RegexErr => "Invalid regular expression",
+            UnclosedErr => "Quote or regular expression isn't closed",
+            ParenErr => "Expected )",
+            TrailingErr => "Expected AND, OR or the end of the query",
+        }
+    }
+}
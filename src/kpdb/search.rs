@@ -0,0 +1,246 @@
+use std::slice;
+
+use chrono::{DateTime, Local};
+use regex::{self, Regex, RegexBuilder};
+
+use kpdb::v1entry::{EntryId, V1Entry};
+use kpdb::v1group::GroupId;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+#[doc = "
+The fields of an entry a Search looks at
+"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SearchField {
+    Title,
+    Username,
+    Url,
+    Comment,
+    /// The name of the attachment
+    BinaryDesc,
+    Password,
+}
+
+impl SearchField {
+    /// All fields but the password, which are searched by default
+    pub fn defaults() -> Vec<SearchField> {
+        vec![SearchField::Title,
+             SearchField::Username,
+             SearchField::Url,
+             SearchField::Comment,
+             SearchField::BinaryDesc]
+    }
+
+    /// Whether matcher matches this field of entry. A missing field
+    /// is matched as an empty string, a secret which can't be
+    /// decrypted doesn't match.
+    //
+    // Sensitive data in this function:
+    // * username and password (decrypted to be matched)
+    //
+    // At the end of this function:
+    // * the decrypted copy is zeroed out by with_unlocked right after
+    //   it was matched, before the next field is decrypted
+    pub fn matches(self, entry: &V1Entry, matcher: &Matcher) -> bool {
+        let text = |field: &Option<String>| matcher.is_match(field.as_ref().map(|field| &field[..]).unwrap_or(""));
+        let secret = |field: &Option<SecureString>| match *field {
+            Some(ref field) => field.with_unlocked(|field| matcher.is_match(field)).unwrap_or(false),
+            None => matcher.is_match(""),
+        };
+        match self {
+            SearchField::Title => matcher.is_match(&entry.title),
+            SearchField::Username => secret(&entry.username),
+            SearchField::Url => text(&entry.url),
+            SearchField::Comment => text(&entry.comment),
+            SearchField::BinaryDesc => text(&entry.binary_desc),
+            SearchField::Password => secret(&entry.password),
+        }
+    }
+}
+
+#[doc = "
+How the text of a Search is matched against a field, always ignoring
+case. The text is compiled into a regular expression once, so that
+decrypted fields are matched without making (lowercase) copies of
+them.
+"]
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    regex: Regex,
+    whole_word: bool,
+}
+
+impl Matcher {
+    /// Match fields which contain text
+    pub fn substring(text: &str) -> Matcher {
+        Matcher {
+            regex: Matcher::build(&regex::escape(text)).expect("Can't compile escaped text!?"),
+            whole_word: false,
+        }
+    }
+
+    /// Match fields which contain text as a whole word, i.e. not
+    /// preceded or followed by a letter, digit or _
+    pub fn word(text: &str) -> Matcher {
+        Matcher { whole_word: true, ..Matcher::substring(text) }
+    }
+
+    /// Match fields in which the regular expression pattern matches,
+    /// see the regex crate for its syntax. (?-i) makes it case
+    /// sensitive.
+    pub fn regex(pattern: &str) -> Result<Matcher, regex::Error> {
+        Ok(Matcher {
+            regex: try!(Matcher::build(pattern)),
+            whole_word: false,
+        })
+    }
+
+    fn build(pattern: &str) -> Result<Regex, regex::Error> {
+        RegexBuilder::new(pattern).case_insensitive(true).build()
+    }
+
+    /// Whether text matches
+    pub fn is_match(&self, text: &str) -> bool {
+        if !self.whole_word {
+            return self.regex.is_match(text);
+        }
+        let is_word = |c: Option<char>| c.map(|c| c.is_alphanumeric() || c == '_').unwrap_or(false);
+        let mut start = 0;
+        while let Some(found) = self.regex.find_at(text, start) {
+            if !is_word(text[..found.start()].chars().next_back()) && !is_word(text[found.end()..].chars().next()) {
+                return true;
+            }
+            // Retry one character later, occurrences may overlap
+            match text[found.start()..].chars().next() {
+                Some(c) => start = found.start() + c.len_utf8(),
+                None => return false,
+            }
+        }
+        false
+    }
+}
+
+#[doc = "
+A search for entries of a V1Kpdb. An entry is found if the matcher
+matches one of the fields and it passes all filters which are set,
+e.g. to find the entries below Internet whose title or URL contains
+mail:
+
+```no_run
+# use keepass::kpdb::search::{Matcher, Search, SearchField};
+# use keepass::kpdb::v1kpdb::V1Kpdb;
+# let mut db = V1Kpdb::new(\"test.kdb\".to_string(), Some(\"test\".to_string()), None).unwrap();
+# db.load().unwrap();
+let mut search = Search::new(Matcher::substring(\"mail\"));
+search.fields = vec![SearchField::Title, SearchField::Url];
+search.group = db.group_by_path(\"Internet\").ok();
+for entry in search.iter(&db) {
+    println!(\"{}\", db.entry_path(entry));
+}
+```
+
+The entries are found lazily in the order of the file while iterating.
+"]
+#[derive(Debug, Clone)]
+pub struct Search {
+    /// How fields are matched
+    pub matcher: Matcher,
+    /// The fields to search, SearchField::defaults() by new
+    pub fields: Vec<SearchField>,
+    /// Only entries of this group or its subgroups
+    pub group: Option<GroupId>,
+    /// Some(true) for expired entries only, Some(false) for entries
+    /// which haven't expired yet
+    pub expired: Option<bool>,
+    /// Only entries modified at this time or later
+    pub modified_since: Option<DateTime<Local>>,
+    /// Only entries modified before this time
+    pub modified_before: Option<DateTime<Local>>,
+}
+
+impl Search {
+    /// A search of the default fields without filters
+    pub fn new(matcher: Matcher) -> Search {
+        Search {
+            matcher: matcher,
+            fields: SearchField::defaults(),
+            group: None,
+            expired: None,
+            modified_since: None,
+            modified_before: None,
+        }
+    }
+
+    /// Iterate over the entries of db which are found
+    pub fn iter<'a>(&'a self, db: &'a V1Kpdb) -> SearchIter<'a> {
+        SearchIter {
+            search: self,
+            db: db,
+            entries: db.entries.iter(),
+            now: Local::now(),
+        }
+    }
+
+    /// Whether entry of db is found, now decides which entries are
+    /// expired
+    pub fn matches(&self, db: &V1Kpdb, entry: EntryId, now: &DateTime<Local>) -> bool {
+        let entry = match db.entry(entry) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        // The cheap filters first, fields may have to be decrypted
+        if let Some(expired) = self.expired {
+            if (entry.expire <= *now) != expired {
+                return false;
+            }
+        }
+        if let Some(since) = self.modified_since {
+            if entry.last_mod < since {
+                return false;
+            }
+        }
+        if let Some(before) = self.modified_before {
+            if entry.last_mod >= before {
+                return false;
+            }
+        }
+        if let Some(group) = self.group {
+            if !in_subtree(db, entry.group, group) {
+                return false;
+            }
+        }
+        self.fields.iter().any(|field| field.matches(entry, &self.matcher))
+    }
+}
+
+#[doc = "
+The entries found by a Search, see Search::iter
+"]
+pub struct SearchIter<'a> {
+    search: &'a Search,
+    db: &'a V1Kpdb,
+    entries: slice::Iter<'a, EntryId>,
+    now: DateTime<Local>,
+}
+
+impl<'a> Iterator for SearchIter<'a> {
+    type Item = EntryId;
+
+    fn next(&mut self) -> Option<EntryId> {
+        let (search, db, now) = (self.search, self.db, &self.now);
+        self.entries.find(|&&entry| search.matches(db, entry, now)).cloned()
+    }
+}
+
+/// Whether group is ancestor or one of its subgroups
+pub fn in_subtree(db: &V1Kpdb, group: Option<GroupId>, ancestor: GroupId) -> bool {
+    let mut current = group;
+    while let Some(group) = current {
+        if group == ancestor {
+            return true;
+        }
+        current = db.group(group).and_then(|group| group.parent);
+    }
+    false
+}
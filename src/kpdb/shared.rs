@@ -0,0 +1,62 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use kpdb::v1kpdb::V1Kpdb;
+
+#[doc = "
+A loaded V1Kpdb which can be shared between threads, e.g. by the
+workers of a server. Clones are handles to the same database.
+
+Any number of threads can read at the same time, writes wait for all
+readers and are serialized. Secrets are read through
+SecureString::with_unlocked, which decrypts into a temporary copy, so
+readers never see each other's plaintext. SecureString::unlock needs
+the write lock.
+
+```no_run
+# use std::thread;
+# use keepass::kpdb::shared::SharedKpdb;
+# use keepass::kpdb::v1kpdb::V1Kpdb;
+# let mut db = V1Kpdb::new(\"test.kdb\".to_string(), Some(\"test\".to_string()), None).unwrap();
+# db.load().unwrap();
+let db = SharedKpdb::new(db);
+let worker = db.clone();
+thread::spawn(move || {
+    let db = worker.read();
+    for &entry in db.entries.iter() {
+        println!(\"{}\", db[entry].title);
+    }
+});
+db.write().create_group(\"new\".to_string(), None, None, None).unwrap();
+```
+"]
+#[derive(Clone)]
+pub struct SharedKpdb {
+    db: Arc<RwLock<V1Kpdb>>,
+}
+
+impl SharedKpdb {
+    /// Share a loaded database
+    pub fn new(db: V1Kpdb) -> SharedKpdb {
+        SharedKpdb { db: Arc::new(RwLock::new(db)) }
+    }
+
+    /// Read access, blocks while another thread writes
+    pub fn read(&self) -> RwLockReadGuard<'_, V1Kpdb> {
+        self.db.read().unwrap()
+    }
+
+    /// Exclusive access to change or save the database, blocks until
+    /// all readers and writers are done
+    pub fn write(&self) -> RwLockWriteGuard<'_, V1Kpdb> {
+        self.db.write().unwrap()
+    }
+
+    /// Get the database back if this is the last handle, otherwise
+    /// the handle is returned
+    pub fn into_inner(self) -> Result<V1Kpdb, SharedKpdb> {
+        match Arc::try_unwrap(self.db) {
+            Ok(db) => Ok(db.into_inner().unwrap()),
+            Err(db) => Err(SharedKpdb { db: db }),
+        }
+    }
+}
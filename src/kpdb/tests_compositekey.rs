@@ -0,0 +1,117 @@
+use std::fs::File;
+use std::io::Read;
+
+use kdbx::v2error::V2KdbxError;
+use kdbx::v2kdbx::V2Kdbx;
+use kpdb::compositekey::{CompositeKey, KeyComponent, KeyProvider};
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+struct FixedKey(Vec<u8>);
+
+impl KeyProvider for FixedKey {
+    fn get_key(&mut self) -> Result<Vec<u8>, V1KpdbError> {
+        Ok(self.0.clone())
+    }
+}
+
+struct FailingKey;
+
+impl KeyProvider for FailingKey {
+    fn get_key(&mut self) -> Result<Vec<u8>, V1KpdbError> {
+        Err(V1KpdbError::ReadErr)
+    }
+}
+
+fn password(password: &str) -> KeyComponent {
+    KeyComponent::Password(SecureString::new(password.to_string()))
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    let mut raw = vec![];
+    File::open(path).unwrap().read_to_end(&mut raw).unwrap();
+    raw
+}
+
+fn load_v1(path: &str, components: Vec<KeyComponent>) -> Result<(), V1KpdbError> {
+    let mut key = CompositeKey::new();
+    for component in components {
+        try!(key.add(component));
+    }
+    let mut db = try!(V1Kpdb::with_key(path.to_string(), key));
+    db.load()
+}
+
+#[test]
+fn test_pass_err() {
+    assert!(CompositeKey::from_password_and_keyfile(None, None).err() == Some(V1KpdbError::PassErr));
+    assert!(V1Kpdb::with_key("test/test_password.kdb".to_string(), CompositeKey::new()).err() ==
+            Some(V1KpdbError::PassErr));
+    assert!(V2Kdbx::with_key("test/test_kdbx31.kdbx".to_string(), CompositeKey::new()).err() ==
+            Some(V2KdbxError::PassErr));
+
+    let mut key = CompositeKey::from_password_and_keyfile(Some("test".to_string()), None)
+                      .ok()
+                      .unwrap();
+    assert_eq!(key.set_password(None), Err(V1KpdbError::PassErr));
+    assert_eq!(key.set_keyfile(None), Ok(()));
+    assert_eq!(key.set_keyfile(Some("test/test_key".to_string())), Ok(()));
+    assert_eq!(key.set_password(None), Ok(()));
+    assert!(!key.is_empty());
+}
+
+#[test]
+fn test_components() {
+    assert_eq!(load_v1("test/test_both.kdb",
+                       vec![password("test"),
+                            KeyComponent::KeyfilePath(SecureString::new("test/test_key".to_string()))]),
+               Ok(()));
+    assert_eq!(load_v1("test/test_both.kdb",
+                       vec![password("test"), KeyComponent::KeyfileBytes(read_file("test/test_key"))]),
+               Ok(()));
+    assert_eq!(load_v1("test/test_both.kdb", vec![password("test")]),
+               Err(V1KpdbError::DecryptErr));
+
+    // A keyfile of 32 bytes holds the raw key
+    assert_eq!(load_v1("test/test_32B_key.kdb",
+                       vec![KeyComponent::RawKey(read_file("test/32Bkey"))]),
+               Ok(()));
+    assert_eq!(load_v1("test/test_32B_key.kdb", vec![KeyComponent::RawKey(vec![0; 16])]),
+               Err(V1KpdbError::ConvertErr));
+    assert_eq!(load_v1("test/test_password.kdb",
+                       vec![password("test"), KeyComponent::Provider(Box::new(FailingKey))]),
+               Err(V1KpdbError::ReadErr));
+}
+
+fn provider_key(byte: u8) -> CompositeKey {
+    let mut key = CompositeKey::new();
+    assert_eq!(key.add(password("test")), Ok(()));
+    assert_eq!(key.add(KeyComponent::Provider(Box::new(FixedKey(vec![byte; 32])))), Ok(()));
+    key
+}
+
+#[test]
+fn test_provider() {
+    let mut db = V2Kdbx::with_key("test/new_provider.kdbx".to_string(), provider_key(42))
+                     .ok()
+                     .unwrap();
+    let root = db.root_group.clone();
+    db.create_entry(root, "foo".to_string(), None, None, None, Some("bar".to_string()));
+    assert_eq!(db.save(None, None, None), Ok(()));
+
+    let mut db = V2Kdbx::with_key("test/new_provider.kdbx".to_string(), provider_key(42))
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.root_group.borrow().entries[0].borrow().title(), "foo");
+
+    let mut db = V2Kdbx::with_key("test/new_provider.kdbx".to_string(), provider_key(43))
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::HashErr));
+    let mut db = V2Kdbx::new("test/new_provider.kdbx".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::HashErr));
+}
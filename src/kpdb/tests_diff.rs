@@ -0,0 +1,116 @@
+use kpdb::diff::{diff, render, DiffChange, EntryField};
+use kpdb::v1entry::EntryId;
+use kpdb::v1group::GroupId;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+fn load(path: &str) -> V1Kpdb {
+    let mut db = V1Kpdb::new(path.to_string(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+fn group(db: &V1Kpdb, title: &str) -> GroupId {
+    *db.groups.iter().find(|&&g| db[g].title == title).unwrap()
+}
+
+fn entry_in(db: &V1Kpdb, group_title: &str) -> EntryId {
+    let id = db[group(db, group_title)].id;
+    *db.entries.iter().find(|&&e| db[e].group_id == id).unwrap()
+}
+
+#[test]
+fn test_diff() {
+    let mut db = load("test/test_parsing.kdb");
+    assert!(db.save(Some("test/new_diff.kdb".to_string()), None, None).is_ok());
+    let old = load("test/new_diff.kdb");
+    let mut new = load("test/new_diff.kdb");
+    assert!(diff(&old, &new).is_empty());
+
+    // Change some fields and the password of an entry
+    let changed = entry_in(&new, "Internet");
+    let uuid = new[changed].uuid;
+    let path = format!("Internet/{}", new[changed].title);
+    new[changed].url = Some("https://example.org".to_string());
+    new[changed].password = Some(SecureString::new("secret".to_string()));
+    new[changed].binary_desc = Some("notes.txt".to_string());
+    new[changed].binary = Some(b"attached".to_vec());
+
+    // Move an entry and remove a group
+    let moved = entry_in(&new, "12");
+    let moved_uuid = new[moved].uuid;
+    let target = group(&new, "Internet");
+    assert!(new.move_entry(moved, target).is_ok());
+    let removed = group(&new, "32");
+    assert!(new.remove_group(removed).is_ok());
+    let parent = group(&new, "11");
+    assert!(new.create_entry(parent,
+                             "added".to_string(),
+                             None,
+                             None,
+                             None,
+                             None,
+                             None,
+                             None)
+               .is_ok());
+
+    let result = diff(&old, &new);
+    assert!(result.changes.iter().any(|c| {
+        match *c {
+            DiffChange::EntryChanged { uuid: u, field: EntryField::Url, ref new, .. } => {
+                u == uuid && new == "https://example.org"
+            }
+            _ => false,
+        }
+    }));
+    assert!(result.changes.iter().any(|c| {
+        match *c {
+            DiffChange::EntryChanged { field: EntryField::Attachment, ref new, .. } => {
+                new.starts_with("notes.txt (8 bytes, sha256 ")
+            }
+            _ => false,
+        }
+    }));
+    assert!(result.changes.contains(&DiffChange::PasswordChanged {
+        uuid: uuid,
+        path: path,
+    }));
+    assert!(result.changes.iter().any(|c| {
+        match *c {
+            DiffChange::EntryMoved { uuid, ref from, ref to } => {
+                uuid == moved_uuid && from.starts_with("Internet/12/") && !to.contains("12/")
+            }
+            _ => false,
+        }
+    }));
+    assert!(result.changes.iter().any(|c| {
+        match *c {
+            DiffChange::GroupRemoved { ref path, .. } => path.ends_with("/32"),
+            _ => false,
+        }
+    }));
+    assert!(result.changes.iter().any(|c| {
+        match *c {
+            DiffChange::EntryAdded { ref path, .. } => path == "Internet/11/added",
+            _ => false,
+        }
+    }));
+
+    // The password itself is never shown
+    let text = format!("{}", result);
+    assert!(!text.contains("secret"));
+    assert!(!render(&new).contains("secret"));
+}
+
+#[test]
+fn test_render() {
+    let mut db = load("test/test_parsing.kdb");
+    assert!(db.save(Some("test/new_render.kdb".to_string()), None, None).is_ok());
+    let text = render(&load("test/new_render.kdb"));
+
+    // Rendering is stable, so it can be used as textconv driver
+    assert_eq!(text, render(&load("test/new_render.kdb")));
+    assert!(text.starts_with("[Internet]\n"));
+    assert!(text.contains("password: (hidden)\n"));
+    assert_eq!(text.matches("\nuuid: ").count(), db.entries.len());
+}
@@ -0,0 +1,156 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use kdbx::v2error::V2KdbxError;
+use kdbx::v2kdbx::V2Kdbx;
+use kpdb::crypter::Crypter;
+use kpdb::keyfile::{create_keyfile, create_xml_keyfile, KeyfileFormat};
+use kpdb::v1error::V1KpdbError;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+fn keyfilekey(path: &str) -> Result<Vec<u8>, V1KpdbError> {
+    Crypter::get_keyfilekey(&mut SecureString::new(path.to_string()))
+}
+
+#[test]
+fn test_xml_keyfile_v1() {
+    let key: Vec<u8> = (1..33).collect();
+    assert_eq!(keyfilekey("test/xml_v1_key.key"), Ok(key));
+}
+
+#[test]
+fn test_xml_keyfile_v2() {
+    let key: Vec<u8> = vec![0x03, 0x0A, 0x11, 0x18, 0x1F, 0x26, 0x2D, 0x34, 0x3B, 0x42, 0x49, 0x50,
+                            0x57, 0x5E, 0x65, 0x6C, 0x73, 0x7A, 0x81, 0x88, 0x8F, 0x96, 0x9D, 0xA4,
+                            0xAB, 0xB2, 0xB9, 0xC0, 0xC7, 0xCE, 0xD5, 0xDC];
+    assert_eq!(keyfilekey("test/xml_v2_key.keyx"), Ok(key));
+    assert_eq!(keyfilekey("test/xml_v2_key_wrong_hash.keyx"),
+               Err(V1KpdbError::KeyfileHashErr));
+}
+
+#[test]
+fn test_xml_keyfile_invalid() {
+    let raw = b"<KeyFile><Meta><Version>2.0</Version></Meta><Key><Data>0A1</Data></Key></KeyFile>";
+    assert_eq!(Crypter::get_keyfilekey_from_bytes(raw), Err(V1KpdbError::ConvertErr));
+    let raw = b"<KeyFile><Meta><Version>2.0</Version></Meta><Key><Data>0A 1G</Data></Key></KeyFile>";
+    assert_eq!(Crypter::get_keyfilekey_from_bytes(raw), Err(V1KpdbError::ConvertErr));
+    let raw = b"<KeyFile><Meta><Version>2.0</Version></Meta></KeyFile>";
+    assert_eq!(Crypter::get_keyfilekey_from_bytes(raw), Err(V1KpdbError::ConvertErr));
+
+    // Other XML documents are hashed as a whole
+    let raw = b"<Other><Key><Data>0A1B</Data></Key></Other>";
+    assert_eq!(Crypter::get_keyfilekey_from_bytes(raw).map(|key| key.len()), Ok(32));
+}
+
+#[test]
+fn test_create_xml_keyfile() {
+    let _ = fs::remove_file("test/new_xml_key.keyx");
+    let _ = fs::remove_file("test/new_xml_key2.keyx");
+    assert_eq!(create_xml_keyfile("test/new_xml_key.keyx"), Ok(()));
+    let key = keyfilekey("test/new_xml_key.keyx").ok().unwrap();
+    assert_eq!(key.len(), 32);
+    assert_eq!(create_xml_keyfile("test/new_xml_key2.keyx"), Ok(()));
+    assert!(keyfilekey("test/new_xml_key2.keyx").ok().unwrap() != key);
+
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert!(db.save(Some("test/new_xml_key.kdb".to_string()),
+                    None,
+                    Some("test/new_xml_key.keyx".to_string()))
+              .is_ok());
+    let mut db = V1Kpdb::new("test/new_xml_key.kdb".to_string(),
+                             Some("test".to_string()),
+                             Some("test/new_xml_key.keyx".to_string()))
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let mut db = V1Kpdb::new("test/new_xml_key.kdb".to_string(),
+                             Some("test".to_string()),
+                             Some("test/new_xml_key2.keyx".to_string()))
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_err());
+
+    let mut db = V2Kdbx::new("test/test_kdbx31.kdbx".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    assert_eq!(db.save(Some("test/new_xml_key.kdbx".to_string()),
+                       None,
+                       Some("test/new_xml_key.keyx".to_string())),
+               Ok(()));
+    let mut db = V2Kdbx::new("test/new_xml_key.kdbx".to_string(),
+                             Some("test".to_string()),
+                             Some("test/new_xml_key.keyx".to_string()))
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Ok(()));
+    let mut db = V2Kdbx::new("test/new_xml_key.kdbx".to_string(),
+                             Some("test".to_string()),
+                             Some("test/xml_v2_key_wrong_hash.keyx".to_string()))
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V2KdbxError::KeyfileHashErr));
+}
+
+#[test]
+fn test_create_keyfile() {
+    for &(format, path, size) in [(KeyfileFormat::Raw, "test/new_raw_key", 32),
+                                  (KeyfileFormat::Hex, "test/new_hex_key", 64),
+                                  (KeyfileFormat::Xml, "test/new_xml_key3.keyx", 0)]
+                                     .iter() {
+        let _ = fs::remove_file(path);
+        assert_eq!(create_keyfile(path, format), Ok(()));
+        let metadata = fs::metadata(path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        if size > 0 {
+            assert_eq!(metadata.len(), size);
+        }
+        assert_eq!(keyfilekey(path).ok().unwrap().len(), 32);
+
+        let mut db = V1Kpdb::new("test/test_password.kdb".to_string(),
+                                 Some("test".to_string()),
+                                 None)
+                         .ok()
+                         .unwrap();
+        assert!(db.load().is_ok());
+        assert!(db.save(Some("test/new_generated_key.kdb".to_string()),
+                        None,
+                        Some(path.to_string()))
+                  .is_ok());
+        let mut db = V1Kpdb::new("test/new_generated_key.kdb".to_string(),
+                                 Some("test".to_string()),
+                                 Some(path.to_string()))
+                         .ok()
+                         .unwrap();
+        assert!(db.load().is_ok());
+
+        let mut db = V2Kdbx::new("test/test_kdbx40.kdbx".to_string(),
+                                 Some("test".to_string()),
+                                 None)
+                         .ok()
+                         .unwrap();
+        assert_eq!(db.load(), Ok(()));
+        assert_eq!(db.save(Some("test/new_generated_key.kdbx".to_string()),
+                           None,
+                           Some(path.to_string())),
+                   Ok(()));
+        let mut db = V2Kdbx::new("test/new_generated_key.kdbx".to_string(),
+                                 Some("test".to_string()),
+                                 Some(path.to_string()))
+                         .ok()
+                         .unwrap();
+        assert_eq!(db.load(), Ok(()));
+    }
+
+    // An existing keyfile is never overwritten
+    let key = keyfilekey("test/new_raw_key").ok().unwrap();
+    assert_eq!(create_keyfile("test/new_raw_key", KeyfileFormat::Raw),
+               Err(V1KpdbError::ExistsErr));
+    assert_eq!(keyfilekey("test/new_raw_key").ok().unwrap(), key);
+    assert_eq!(create_keyfile("test/missing/key", KeyfileFormat::Raw),
+               Err(V1KpdbError::WriteErr));
+}
@@ -0,0 +1,199 @@
+use chrono::{Duration, Local};
+
+use kpdb::merge::{merge, MergeChange};
+use kpdb::v1entry::EntryId;
+use kpdb::v1group::GroupId;
+use kpdb::v1kpdb::V1Kpdb;
+
+fn load(path: &str) -> V1Kpdb {
+    let mut db = V1Kpdb::new(path.to_string(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+// test_parsing.kdb has no timestamps for its groups, so save it once
+// to get the same timestamps in all three databases
+fn setup(path: &str) -> (V1Kpdb, V1Kpdb, V1Kpdb) {
+    let mut db = load("test/test_parsing.kdb");
+    assert!(db.save(Some(path.to_string()), None, None).is_ok());
+    (load(path), load(path), load(path))
+}
+
+fn group(db: &V1Kpdb, title: &str) -> GroupId {
+    *db.groups.iter().find(|&&g| db[g].title == title).unwrap()
+}
+
+fn entry_in(db: &V1Kpdb, group_title: &str) -> EntryId {
+    let id = db[group(db, group_title)].id;
+    *db.entries.iter().find(|&&e| db[e].group_id == id).unwrap()
+}
+
+fn touch_entry(db: &mut V1Kpdb, entry: EntryId, title: &str, hours: i64) {
+    let entry = &mut db[entry];
+    entry.title = title.to_string();
+    entry.last_mod = Local::now() + Duration::hours(hours);
+}
+
+fn check_tree(db: &V1Kpdb) {
+    assert_eq!(db.header.num_groups as usize, db.groups.len());
+    assert_eq!(db.header.num_entries as usize, db.entries.len());
+    for &group in db.groups.iter() {
+        let group = &db[group];
+        let parent = &db[group.parent.unwrap()];
+        assert!(parent.id == 0 && group.level == 0 || parent.level + 1 == group.level);
+    }
+    for &entry in db.entries.iter() {
+        assert_eq!(db[db[entry].group.unwrap()].id, db[entry].group_id);
+    }
+}
+
+#[test]
+fn test_merge() {
+    let (base, mut ours, mut theirs) = setup("test/new_merge.kdb");
+    let max_id = base.groups.iter().map(|&g| base[g].id).max().unwrap();
+
+    // Both change the same entry, theirs later
+    let changed = entry_in(&ours, "Internet");
+    let uuid = ours[changed].uuid;
+    touch_entry(&mut ours, changed, "ours", 1);
+    let changed = entry_in(&theirs, "Internet");
+    touch_entry(&mut theirs, changed, "theirs", 2);
+
+    // Ours deletes an entry, theirs moves another one
+    let removed = entry_in(&ours, "11");
+    let removed_uuid = ours[removed].uuid;
+    assert!(ours.remove_entry(removed).is_ok());
+    let moved = entry_in(&theirs, "12");
+    let moved_uuid = theirs[moved].uuid;
+    theirs[moved].group_id = theirs[group(&theirs, "Internet")].id;
+    theirs[moved].last_mod = Local::now() + Duration::hours(1);
+
+    // Both create a group with the same id, theirs deletes a group
+    assert!(ours.create_group("ours new".to_string(), None, None, None).is_ok());
+    let parent = group(&theirs, "Internet");
+    assert!(theirs.create_group("theirs new".to_string(), None, None, Some(parent)).is_ok());
+    let deleted = group(&theirs, "22");
+    let deleted_id = theirs[deleted].id;
+    let deleted_entry = theirs[entry_in(&theirs, "22")].uuid;
+    assert!(theirs.remove_group(deleted).is_ok());
+
+    let report = merge(Some(&base), &mut ours, &theirs).ok().unwrap();
+    let internet = ours[group(&ours, "Internet")].id;
+    for expected in vec![MergeChange::GroupRenumbered {
+                             title: "theirs new".to_string(),
+                             from: max_id + 1,
+                             to: max_id + 2,
+                         },
+                         MergeChange::GroupAdded {
+                             id: max_id + 2,
+                             title: "theirs new".to_string(),
+                         },
+                         MergeChange::GroupDeleted {
+                             id: deleted_id,
+                             title: "22".to_string(),
+                         },
+                         MergeChange::EntryUpdated {
+                             uuid: uuid,
+                             title: "theirs".to_string(),
+                         }] {
+        assert!(report.changes.contains(&expected), "missing {}", expected);
+    }
+    assert!(report.changes.iter().any(|c| {
+        match *c {
+            MergeChange::EntryMoved { uuid, to, .. } => uuid == moved_uuid && to == internet,
+            _ => false,
+        }
+    }));
+    assert!(report.changes.iter().any(|c| {
+        match *c {
+            MergeChange::EntryDeleted { uuid, .. } => uuid == deleted_entry,
+            _ => false,
+        }
+    }));
+    assert!(!report.changes.iter().any(|c| {
+        match *c {
+            MergeChange::EntryRestored { .. } | MergeChange::GroupRestored { .. } => true,
+            _ => false,
+        }
+    }));
+
+    check_tree(&ours);
+    assert_eq!(ours[group(&ours, "ours new")].id, max_id + 1);
+    assert_eq!(ours[ours[group(&ours, "theirs new")].parent.unwrap()].id,
+               internet);
+    assert_eq!(ours[group(&ours, "theirs new")].level, 1);
+    assert!(ours.groups.iter().all(|&g| ours[g].title != "22"));
+    assert!(ours.entries.iter().all(|&e| ours[e].uuid != removed_uuid));
+    assert!(ours.entries.iter().all(|&e| ours[e].uuid != deleted_entry));
+    assert_eq!(ours[*ours.entries.iter().find(|&&e| ours[e].uuid == uuid).unwrap()].title,
+               "theirs");
+
+    // The merged database can be saved and loaded again
+    assert!(ours.save(Some("test/new_merged.kdb".to_string()), None, None).is_ok());
+    let merged = load("test/new_merged.kdb");
+    check_tree(&merged);
+    assert_eq!(merged.groups.len(), base.groups.len() + 1);
+    assert_eq!(merged.entries.len(), base.entries.len() - 2);
+
+    // Merging again changes nothing
+    let report = merge(Some(&base), &mut ours, &theirs).ok().unwrap();
+    assert!(report.is_empty(), "{}", report);
+}
+
+#[test]
+fn test_merge_conflicts() {
+    let (base, mut ours, mut theirs) = setup("test/new_merge_conflicts.kdb");
+
+    // Theirs deletes a group holding an entry ours changed
+    let kept_group = group(&theirs, "21");
+    let kept_id = theirs[kept_group].id;
+    assert!(theirs.remove_group(kept_group).is_ok());
+    let changed = entry_in(&ours, "21");
+    touch_entry(&mut ours, changed, "changed", 1);
+
+    // Ours deletes an entry theirs changed
+    let restored = entry_in(&ours, "12");
+    let restored_uuid = ours[restored].uuid;
+    assert!(ours.remove_entry(restored).is_ok());
+    let restored = entry_in(&theirs, "12");
+    touch_entry(&mut theirs, restored, "restored", 1);
+
+    // The subgroups of the deleted group are deleted, the group itself
+    // is kept for the changed entry
+    let report = merge(Some(&base), &mut ours, &theirs).ok().unwrap();
+    let subgroup = |title: &str| {
+        MergeChange::GroupDeleted {
+            id: base[group(&base, title)].id,
+            title: title.to_string(),
+        }
+    };
+    assert_eq!(report.changes,
+               vec![subgroup("31"),
+                    subgroup("32"),
+                    MergeChange::EntryRestored {
+                        uuid: restored_uuid,
+                        title: "restored".to_string(),
+                    }]);
+    check_tree(&ours);
+    assert_eq!(ours[group(&ours, "21")].id, kept_id);
+    assert_eq!(ours[entry_in(&ours, "21")].title, "changed");
+    assert_eq!(ours[entry_in(&ours, "12")].title, "restored");
+}
+
+#[test]
+fn test_merge_without_base() {
+    let (_, mut ours, mut theirs) = setup("test/new_merge_without_base.kdb");
+    let removed = entry_in(&theirs, "11");
+    assert!(theirs.remove_entry(removed).is_ok());
+    let removed = group(&theirs, "22");
+    assert!(theirs.remove_group(removed).is_ok());
+    let entries = ours.entries.len();
+    let groups = ours.groups.len();
+
+    // Nothing is deleted without base
+    let report = merge(None, &mut ours, &theirs).ok().unwrap();
+    assert!(report.is_empty());
+    assert_eq!(ours.entries.len(), entries);
+    assert_eq!(ours.groups.len(), groups);
+    check_tree(&ours);
+}
@@ -7,7 +7,8 @@ use uuid::Uuid;
 
 use kpdb::crypter::Crypter;
 use kpdb::parser::{HeaderLoadParser, LoadParser,SaveParser};
-use kpdb::v1header::V1Header;
+use kpdb::v1header::{V1Cipher, V1Header};
+use kpdb::v1error::V1KpdbError;
 use kpdb::v1kpdb::V1Kpdb;
 use super::super::sec_str::SecureString;
 
@@ -46,18 +47,18 @@ fn test_parse_groups() {
         Err(_) => assert!(false),
     }
 
-    assert_eq!(groups[0].borrow().id, 1);
-    assert_eq!(groups[0].borrow().title, "Internet");
-    assert_eq!(groups[0].borrow().image, 1);
-    assert_eq!(groups[0].borrow().level, 0);
-
-    assert_eq!(groups[1].borrow().id, 2);
-    assert_eq!(groups[1].borrow().title, "test");
-    assert_eq!(groups[1].borrow().image, 1);
-    assert_eq!(groups[1].borrow().level, 0);
-    assert_eq!(groups[1].borrow().creation.year(), 2014);
-    assert_eq!(groups[1].borrow().creation.month(), 2);
-    assert_eq!(groups[1].borrow().creation.day(), 26);
+    assert_eq!(groups[0].id, 1);
+    assert_eq!(groups[0].title, "Internet");
+    assert_eq!(groups[0].image, 1);
+    assert_eq!(groups[0].level, 0);
+
+    assert_eq!(groups[1].id, 2);
+    assert_eq!(groups[1].title, "test");
+    assert_eq!(groups[1].image, 1);
+    assert_eq!(groups[1].level, 0);
+    assert_eq!(groups[1].creation.year(), 2014);
+    assert_eq!(groups[1].creation.month(), 2);
+    assert_eq!(groups[1].creation.day(), 26);
 }
 
 #[test]
@@ -83,39 +84,35 @@ fn test_parse_entries() {
         Err(_) => assert!(false),
     }
 
-    entries[0].borrow_mut().username.as_mut().unwrap().unlock();
-    entries[0].borrow_mut().password.as_mut().unwrap().unlock();
+    entries[0].username.as_mut().unwrap().unlock();
+    entries[0].password.as_mut().unwrap().unlock();
 
-    assert_eq!(entries[0].borrow().uuid, uuid);
-    assert_eq!(entries[0].borrow().title, "foo");
-    assert_eq!(entries[0].borrow().url, Some("foo".to_string()));
-    assert_eq!(entries[0].borrow().username.as_ref().unwrap().string, "foo");
-    assert_eq!(entries[0].borrow().password.as_ref().unwrap().string,
+    assert_eq!(entries[0].uuid, uuid);
+    assert_eq!(entries[0].title, "foo");
+    assert_eq!(entries[0].url, Some("foo".to_string()));
+    assert_eq!(entries[0].username.as_ref().unwrap().string, "foo");
+    assert_eq!(entries[0].password.as_ref().unwrap().string,
                "DLE\"H<JZ|E");
-    assert_eq!(entries[0].borrow().image, 1);
-    assert_eq!(entries[0].borrow().group_id, 1);
-    assert_eq!(entries[0].borrow().creation.year(), 2014);
-    assert_eq!(entries[0].borrow().creation.month(), 2);
-    assert_eq!(entries[0].borrow().creation.day(), 26);
+    assert_eq!(entries[0].image, 1);
+    assert_eq!(entries[0].group_id, 1);
+    assert_eq!(entries[0].creation.year(), 2014);
+    assert_eq!(entries[0].creation.month(), 2);
+    assert_eq!(entries[0].creation.day(), 26);
 }
 
 fn get_parent_title(index: usize, db: &V1Kpdb) -> String {
-    let mut group = db.groups[index].borrow_mut();
-    let parent = group.parent.as_mut().unwrap().borrow();
-    parent.title.clone()
+    let group = &db[db.groups[index]];
+    db[group.parent.unwrap()].title.clone()
 }
 
 fn get_children_title(parent_index: usize, children_index: usize, db: &V1Kpdb) -> String {
-    let group = db.groups[parent_index].borrow_mut();
-    let children_ref = group.children[children_index].upgrade().unwrap();
-    let children = children_ref.borrow();
-    children.title.clone()
+    let group = &db[db.groups[parent_index]];
+    db[group.children[children_index]].title.clone()
 }
 
 fn get_entry_parent_title(index: usize, db: &V1Kpdb) -> String {
-    let mut entry = db.entries[index].borrow_mut();
-    let group = entry.group.as_mut().unwrap().borrow();
-    group.title.clone()
+    let entry = &db[db.entries[index]];
+    db[entry.group.unwrap()].title.clone()
 }
 #[test]
 fn test_create_group_tree() {
@@ -178,6 +175,28 @@ fn test_read_header() {
     assert_eq!(header.transf_randomseed[15], 0x9Fu8);
 }
 
+#[test]
+fn test_enc_flag() {
+    let mut header = V1Header::new();
+    assert_eq!(header.check_enc_flag(), Err(V1KpdbError::EncFlagErr));
+
+    // SHA-2 and Twofish
+    header.enc_flag = 9;
+    assert_eq!(header.cipher(), Ok(V1Cipher::Twofish));
+
+    // ArcFour is not supported
+    header.enc_flag = 5;
+    assert_eq!(header.check_enc_flag(), Err(V1KpdbError::EncFlagErr));
+
+    header.set_cipher(V1Cipher::Aes);
+    assert_eq!(header.enc_flag, 7);
+    assert_eq!(header.cipher(), Ok(V1Cipher::Aes));
+
+    header.set_cipher(V1Cipher::Twofish);
+    assert_eq!(header.enc_flag, 13);
+    assert!(header.check_enc_flag().is_ok());
+}
+
 #[test]
 fn test_prepare_save() {
     let test_1 = vec![0x01, 0x00, 0x04, 0x00,
@@ -229,7 +248,7 @@ fn test_prepare_save() {
     assert_eq!(db.load().is_ok(), true);
 
     let mut parser = SaveParser::new();
-    parser.prepare(&db);
+    assert!(parser.prepare(&db).is_ok());
 
     println!("{:?}", parser.database);
     assert_eq!(test_1[..], parser.database[0..36]);
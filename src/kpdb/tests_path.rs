@@ -0,0 +1,17 @@
+use kpdb::path::{escape_title, join_path, split_path};
+
+#[test]
+fn test_split_path() {
+    assert_eq!(split_path("Internet/Email"), vec!["Internet", "Email"]);
+    assert_eq!(split_path("/Internet//Email/"), vec!["Internet", "Email"]);
+    assert_eq!(split_path("a\\/b/c\\\\/d\\"), vec!["a/b", "c\\", "d\\"]);
+    assert!(split_path("/").is_empty());
+}
+
+#[test]
+fn test_join_path() {
+    assert_eq!(escape_title("a/b\\c"), "a\\/b\\\\c");
+    let titles = vec!["Internet", "a/b", "c\\"];
+    assert_eq!(join_path(&titles), "Internet/a\\/b/c\\\\");
+    assert_eq!(split_path(&join_path(&titles)), titles);
+}
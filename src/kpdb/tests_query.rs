@@ -0,0 +1,117 @@
+use chrono::{Duration, Local, TimeZone};
+
+use kpdb::query::Query;
+use kpdb::query_error::{QueryError, QueryErrorKind};
+use kpdb::v1kpdb::V1Kpdb;
+
+fn load() -> V1Kpdb {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+fn find(db: &V1Kpdb, query: &str) -> Vec<String> {
+    let query = Query::parse(query).unwrap();
+    query.entries(db).map(|entry| db[entry].title.clone()).collect()
+}
+
+fn error(query: &str) -> QueryError {
+    Query::parse(query).err().unwrap()
+}
+
+#[test]
+fn test_text() {
+    let mut db = load();
+    let internet = db.groups[0];
+    db.create_entry(internet,
+                    "db-1".to_string(),
+                    None,
+                    None,
+                    Some("https://db1.prod.example.com".to_string()),
+                    Some("primary \"main\" database".to_string()),
+                    Some("admin".to_string()),
+                    Some("secret".to_string()))
+      .unwrap();
+
+    assert_eq!(find(&db, "DB"), vec!["db-1"]);
+    assert_eq!(find(&db, "title:test1"), vec!["test1"]);
+    assert_eq!(find(&db, "title=TEST"), Vec::<String>::new());
+    assert_eq!(find(&db, "title=TEST1"), vec!["test1"]);
+    assert_eq!(find(&db, "url:*.example.com"), vec!["db-1"]);
+    assert_eq!(find(&db, "url:*.example"), Vec::<String>::new());
+    assert_eq!(find(&db, "title~/^db-[0-9]+$/"), vec!["db-1"]);
+    assert_eq!(find(&db, "title~^test[12]"), vec!["test1", "test2"]);
+    assert_eq!(find(&db, "comment:\"\\\"main\\\" database\""), vec!["db-1"]);
+    assert_eq!(find(&db, "username=admin"), vec!["db-1"]);
+    assert_eq!(find(&db, "secret"), Vec::<String>::new());
+    assert_eq!(find(&db, "password:secret"), vec!["db-1"]);
+}
+
+#[test]
+fn test_operators() {
+    let db = load();
+    assert_eq!(find(&db, "title:test1 OR title:test2 AND title:x"), vec!["test1"]);
+    assert_eq!(find(&db, "(title:test1 OR title:test2) AND NOT title:test1"), vec!["test2"]);
+    assert_eq!(find(&db, "NOT NOT title:test3"), vec!["test3"]);
+    assert_eq!(find(&db, "test AND NOT(title:test1 OR title:test2)"), vec!["test3", "test4", "test5"]);
+}
+
+#[test]
+fn test_groups() {
+    let db = load();
+    assert_eq!(find(&db, "group:Internet/11"), vec!["test2", "test4", "test5"]);
+    assert_eq!(find(&db, "group:\"/Internet/11/\""), vec!["test2", "test4", "test5"]);
+    assert_eq!(find(&db, "group=Internet/11"), vec!["test2"]);
+    assert_eq!(find(&db, "group:Internet/1"), Vec::<String>::new());
+    assert_eq!(find(&db, "group~/2$/"), vec!["test3", "test5"]);
+
+    let query = Query::parse("group:Internet/11 AND NOT title=22").unwrap();
+    let groups: Vec<String> = query.groups(&db).map(|group| db[group].title.clone()).collect();
+    assert_eq!(groups, vec!["11", "21", "32", "31"]);
+    // Groups have no URL
+    assert_eq!(Query::parse("url:*").unwrap().groups(&db).count(), 0);
+}
+
+#[test]
+fn test_dates() {
+    let mut db = load();
+    let now = Local.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+    let (test1, test2) = (db.entries[0], db.entries[1]);
+    db[test1].expire = now + Duration::days(10);
+    db[test2].expire = now - Duration::hours(1);
+    let find_at = |db: &V1Kpdb, query: &str| -> Vec<String> {
+        let query = Query::parse_at(query, now).unwrap();
+        query.entries(db).map(|entry| db[entry].title.clone()).collect()
+    };
+
+    assert_eq!(find_at(&db, "expires<30d"), vec!["test1", "test2"]);
+    assert_eq!(find_at(&db, "expires<30d AND expires>=now"), vec!["test1"]);
+    assert_eq!(find_at(&db, "expires<now"), vec!["test2"]);
+    assert_eq!(find_at(&db, "expires<-2h"), Vec::<String>::new());
+    assert_eq!(find_at(&db, "expires>2024-06-20 AND expires<2024-06-25T12:30"), vec!["test1"]);
+    assert_eq!(find_at(&db, "expires:2024-06-25"), vec!["test1"]);
+    assert_eq!(find_at(&db, "expires=never").len(), 3);
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(error(""), QueryError::new(QueryErrorKind::TermErr, 0));
+    assert_eq!(error("title:a AND"), QueryError::new(QueryErrorKind::TermErr, 11));
+    assert_eq!(error("titel:a"), QueryError::new(QueryErrorKind::FieldErr, 0));
+    assert_eq!(error("a OR title<x"), QueryError::new(QueryErrorKind::OperatorErr, 10));
+    assert_eq!(error("expires~x"), QueryError::new(QueryErrorKind::OperatorErr, 7));
+    assert_eq!(error("title: a"), QueryError::new(QueryErrorKind::ValueErr, 6));
+    assert_eq!(error("expires<soon"), QueryError::new(QueryErrorKind::DateErr, 8));
+    assert_eq!(error("title~/(/"), QueryError::new(QueryErrorKind::RegexErr, 6));
+    assert_eq!(error("comment:\"abc"), QueryError::new(QueryErrorKind::UnclosedErr, 8));
+    assert_eq!(error("(a OR b"), QueryError::new(QueryErrorKind::ParenErr, 7));
+    assert_eq!(error("a b"), QueryError::new(QueryErrorKind::TrailingErr, 2));
+    assert_eq!(error("a)"), QueryError::new(QueryErrorKind::TrailingErr, 1));
+    // Positions count characters, not bytes
+    assert_eq!(error("ä ö"), QueryError::new(QueryErrorKind::TrailingErr, 2));
+    assert_eq!(error("titel:a").to_string(), "Unknown field at column 1");
+}
@@ -0,0 +1,101 @@
+use chrono::{Duration, Local};
+
+use kpdb::search::{Matcher, Search, SearchField};
+use kpdb::v1entry::EntryId;
+use kpdb::v1kpdb::V1Kpdb;
+
+fn load() -> V1Kpdb {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+fn titles(db: &V1Kpdb, search: &Search) -> Vec<String> {
+    search.iter(db).map(|entry: EntryId| db[entry].title.clone()).collect()
+}
+
+#[test]
+fn test_matcher() {
+    let substring = Matcher::substring("Mail.");
+    assert!(substring.is_match("webmail.example.com"));
+    assert!(!substring.is_match("mailbox"));
+
+    let word = Matcher::word("db");
+    assert!(word.is_match("Prod DB server"));
+    assert!(word.is_match("db-1"));
+    assert!(!word.is_match("mongodb"));
+    assert!(!word.is_match("db_1"));
+    assert!(Matcher::word("a-a").is_match("xa-a-a"));
+
+    let regex = Matcher::regex("^db-[0-9]+$").unwrap();
+    assert!(regex.is_match("DB-12"));
+    assert!(!regex.is_match("db-12a"));
+    assert!(!Matcher::regex("(?-i)^db").unwrap().is_match("DB"));
+    assert!(Matcher::regex("(").is_err());
+}
+
+#[test]
+fn test_fields() {
+    let mut db = load();
+    let internet = db.groups[0];
+    db.create_entry(internet,
+                    "Mail".to_string(),
+                    None,
+                    None,
+                    Some("https://mail.example.com".to_string()),
+                    Some("work account".to_string()),
+                    Some("alice".to_string()),
+                    Some("secret".to_string()))
+      .unwrap();
+
+    let mut search = Search::new(Matcher::substring("EXAMPLE"));
+    assert_eq!(titles(&db, &search), vec!["Mail"]);
+    search.matcher = Matcher::substring("alice");
+    assert_eq!(titles(&db, &search), vec!["Mail"]);
+    search.fields = vec![SearchField::Title, SearchField::Comment];
+    assert!(titles(&db, &search).is_empty());
+
+    // The password is only searched if asked for
+    search.matcher = Matcher::word("secret");
+    search.fields = SearchField::defaults();
+    assert!(titles(&db, &search).is_empty());
+    search.fields.push(SearchField::Password);
+    assert_eq!(titles(&db, &search), vec!["Mail"]);
+}
+
+#[test]
+fn test_filters() {
+    let mut db = load();
+    let mut search = Search::new(Matcher::substring("test"));
+    assert_eq!(titles(&db, &search), vec!["test1", "test2", "test3", "test4", "test5"]);
+
+    // 11 holds test2, 22 with test5 and 21 with test4
+    search.group = Some(db.groups[2]);
+    assert_eq!(titles(&db, &search), vec!["test2", "test4", "test5"]);
+    search.group = Some(db.groups[3]);
+    assert_eq!(titles(&db, &search), vec!["test5"]);
+    search.group = None;
+
+    let (test1, test2) = (db.entries[0], db.entries[1]);
+    db[test1].expire = Local::now() - Duration::days(1);
+    search.expired = Some(true);
+    assert_eq!(titles(&db, &search), vec!["test1"]);
+    search.expired = Some(false);
+    assert_eq!(titles(&db, &search).len(), 4);
+    search.expired = None;
+
+    let now = Local::now();
+    db[test2].last_mod = now;
+    search.modified_since = Some(now - Duration::hours(1));
+    assert_eq!(titles(&db, &search), vec!["test2"]);
+    search.modified_since = None;
+    search.modified_before = Some(now);
+    assert_eq!(titles(&db, &search).len(), 4);
+
+    // Lazily: the first entry is found without looking at the rest
+    assert_eq!(search.iter(&db).next(), Some(test1));
+}
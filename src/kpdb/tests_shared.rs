@@ -0,0 +1,71 @@
+use std::thread;
+
+use kpdb::shared::SharedKpdb;
+use kpdb::v1kpdb::V1Kpdb;
+use super::super::sec_str::SecureString;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_send_sync() {
+    assert_send_sync::<V1Kpdb>();
+    assert_send_sync::<SecureString>();
+    assert_send_sync::<SharedKpdb>();
+}
+
+#[test]
+fn test_threads() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let group = db.groups[0];
+    let entry = db.create_entry(group,
+                                "shared".to_string(),
+                                None,
+                                None,
+                                None,
+                                None,
+                                Some("bar".to_string()),
+                                Some("foobar".to_string()))
+                  .ok()
+                  .unwrap();
+    let db = SharedKpdb::new(db);
+
+    let readers: Vec<_> = (0..4)
+                              .map(|_| {
+                                  let db = db.clone();
+                                  thread::spawn(move || {
+                                      for _ in 0..100 {
+                                          let db = db.read();
+                                          let titles: Vec<String> =
+                                              db.entries.iter().map(|&e| db[e].title.clone()).collect();
+                                          assert!(titles.contains(&"test1".to_string()));
+                                          let password = db[entry].password.as_ref().unwrap();
+                                          assert!(password.with_unlocked(|password| password == "foobar") == Some(true));
+                                      }
+                                  })
+                              })
+                              .collect();
+    let writer = {
+        let db = db.clone();
+        thread::spawn(move || {
+            for i in 0..10 {
+                let mut db = db.write();
+                assert!(db.create_group(format!("new {}", i), None, None, None).is_ok());
+            }
+        })
+    };
+    for reader in readers {
+        assert!(reader.join().is_ok());
+    }
+    assert!(writer.join().is_ok());
+
+    let db = db.into_inner().ok().unwrap();
+    assert_eq!(db.groups.len(), 17);
+    assert_eq!(db.header.num_groups, 17);
+    // The readers never decrypted the password in place
+    assert!(db[entry].password.as_ref().unwrap().string.bytes().all(|byte| byte == 0));
+}
@@ -1,7 +1,16 @@
+use std::fs::{self, File, Permissions};
+use std::io::{Cursor, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::thread;
+use std::time::Duration;
+
 use chrono::{Timelike, Local, TimeZone, Datelike};
 
+use common::file::{list_backups, save_atomically};
+use common::lock::LockMode;
 use kpdb::v1kpdb::V1Kpdb;
 use kpdb::v1error::V1KpdbError;
+use kpdb::v1header::V1Cipher;
 
 #[test]
 fn test_new() {
@@ -61,6 +70,274 @@ fn test_save() {
 
 }
 
+#[test]
+fn test_save_keyfile_only() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+
+    // Removing the only component fails and doesn't save anything
+    let _ = fs::remove_file("test/new_keyfile_only.kdb");
+    assert_eq!(db.save(Some("test/new_keyfile_only.kdb".to_string()), Some("".to_string()), None),
+               Err(V1KpdbError::PassErr));
+    assert!(fs::metadata("test/new_keyfile_only.kdb").is_err());
+
+    // The keyfile is added before the password is removed
+    assert!(db.save(Some("test/new_keyfile_only.kdb".to_string()),
+                    Some("".to_string()),
+                    Some("test/64Bkey".to_string()))
+              .is_ok());
+    let mut db = V1Kpdb::new("test/new_keyfile_only.kdb".to_string(), None, Some("test/64Bkey".to_string()))
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let mut db = V1Kpdb::new("test/new_keyfile_only.kdb".to_string(),
+                             Some("test".to_string()),
+                             Some("test/64Bkey".to_string()))
+                     .ok()
+                     .unwrap();
+    assert_eq!(db.load(), Err(V1KpdbError::DecryptErr));
+
+    // Neither can the keyfile be removed together with the password
+    let mut db = V1Kpdb::new("test/new_keyfile_only.kdb".to_string(), None, Some("test/64Bkey".to_string()))
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.save(None, Some("".to_string()), Some("".to_string())), Err(V1KpdbError::PassErr));
+}
+
+#[test]
+fn test_save_twofish() {
+    let mut db = V1Kpdb::new("test/test_password.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.header.cipher(), Ok(V1Cipher::Aes));
+
+    db.header.set_cipher(V1Cipher::Twofish);
+    assert!(db.save(Some("test/new_twofish.kdb".to_string()), None, None).is_ok());
+
+    let mut db = V1Kpdb::new("test/new_twofish.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.header.cipher(), Ok(V1Cipher::Twofish));
+    assert_eq!(db[db.entries[0]].title, "foo");
+
+    // The cipher is kept on save
+    assert!(db.save(None, None, None).is_ok());
+    assert!(db.load().is_ok());
+    assert_eq!(db.header.cipher(), Ok(V1Cipher::Twofish));
+
+    let mut db = V1Kpdb::new("test/new_twofish.kdb".to_string(), Some("tes".to_string()), None)
+                     .ok()
+                     .unwrap();
+    match db.load() {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::DecryptErr),
+    };
+}
+
+#[test]
+fn test_load_twofish() {
+    // test_parsing.kdb encrypted with Twofish by libgcrypt instead of
+    // KeePass, which isn't available to create it
+    let mut db = V1Kpdb::new("test/test_twofish.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(db.header.cipher(), Ok(V1Cipher::Twofish));
+    let titles: Vec<String> = db.groups.iter().map(|&group| db[group].title.clone()).collect();
+    assert_eq!(titles, vec!["Internet", "12", "11", "22", "21", "32", "31"]);
+    let titles: Vec<String> = db.entries.iter().map(|&entry| db[entry].title.clone()).collect();
+    assert_eq!(titles, vec!["test1", "test2", "test3", "test4", "test5"]);
+
+    let mut db = V1Kpdb::new("test/test_twofish.kdb".to_string(), Some("tes".to_string()), None)
+                     .ok()
+                     .unwrap();
+    match db.load() {
+        Ok(_) => assert!(false),
+        Err(e) => assert_eq!(e, V1KpdbError::DecryptErr),
+    };
+}
+
+#[test]
+fn test_save_atomically() {
+    let _ = fs::remove_dir_all("test/new_atomic");
+    fs::create_dir("test/new_atomic").unwrap();
+    fs::copy("test/test_password.kdb", "test/new_atomic/db.kdb").unwrap();
+    fs::set_permissions("test/new_atomic/db.kdb", Permissions::from_mode(0o640)).unwrap();
+
+    let mut db = V1Kpdb::new("test/new_atomic/db.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    db.backups = 2;
+    for _ in 0..3 {
+        // The backups are named after the time in milliseconds
+        thread::sleep(Duration::from_millis(5));
+        assert!(db.save(None, None, None).is_ok());
+    }
+    assert!(db.load().is_ok());
+    let mode = fs::metadata("test/new_atomic/db.kdb").unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+
+    // Only the backups and the database itself, no temporary files
+    let backups = list_backups("test/new_atomic/db.kdb");
+    assert_eq!(backups.len(), 2);
+    assert_eq!(fs::read_dir("test/new_atomic").unwrap().count(), 3);
+    let mut backup = V1Kpdb::new(backups[0].to_string_lossy().into_owned(),
+                                 Some("test".to_string()),
+                                 None)
+                         .ok()
+                         .unwrap();
+    assert!(backup.load().is_ok());
+    assert_eq!(backup[backup.entries[0]].title, "foo");
+
+    // A failed save leaves nothing behind
+    assert_eq!(db.save(Some("test/new_atomic/missing/db.kdb".to_string()), None, None),
+               Err(V1KpdbError::FileErr));
+    assert_eq!(fs::read_dir("test/new_atomic").unwrap().count(), 3);
+}
+
+#[test]
+fn test_save_backups_same_millisecond() {
+    let _ = fs::remove_dir_all("test/new_backups");
+    fs::create_dir("test/new_backups").unwrap();
+    fs::write("test/new_backups/data", b"old").unwrap();
+    for i in 0..5 {
+        assert!(save_atomically("test/new_backups/data", format!("{}", i).as_bytes(), 10).is_ok());
+    }
+
+    // None of the backups overwrote another, the newest is listed last
+    let backups: Vec<Vec<u8>> = list_backups("test/new_backups/data")
+                                    .iter()
+                                    .map(|backup| fs::read(backup).unwrap())
+                                    .collect();
+    assert_eq!(backups,
+               vec![b"old".to_vec(), b"0".to_vec(), b"1".to_vec(), b"2".to_vec(), b"3".to_vec()]);
+}
+
+#[test]
+fn test_modified_on_disk() {
+    fs::copy("test/test_password.kdb", "test/new_modified.kdb").unwrap();
+    let mut mine = V1Kpdb::new("test/new_modified.kdb".to_string(), Some("test".to_string()), None)
+                       .ok()
+                       .unwrap();
+    assert!(mine.load().is_ok());
+    let mut theirs = V1Kpdb::new("test/new_modified.kdb".to_string(), Some("test".to_string()), None)
+                         .ok()
+                         .unwrap();
+    assert!(theirs.load().is_ok());
+    assert!(!mine.modified_on_disk());
+
+    // Both sides change the database, theirs is saved first
+    assert!(theirs.create_group("theirs".to_string(), None, None, None).is_ok());
+    let entry = theirs.entries[0];
+    theirs[entry].title = "changed by them".to_string();
+    theirs[entry].last_mod = Local::now();
+    assert!(theirs.save(None, None, None).is_ok());
+    let group = mine.groups[0].clone();
+    mine.create_entry(group, "mine".to_string(), None, None, None, None, None, None);
+    assert!(mine.modified_on_disk());
+    assert_eq!(mine.save(None, None, None), Err(V1KpdbError::ModifiedErr));
+
+    assert!(mine.reload_and_merge().is_ok());
+    assert!(mine.save(None, None, None).is_ok());
+    let mut merged = V1Kpdb::new("test/new_modified.kdb".to_string(), Some("test".to_string()), None)
+                         .ok()
+                         .unwrap();
+    assert!(merged.load().is_ok());
+    assert_eq!(merged.groups.len(), theirs.groups.len());
+    assert!(merged.groups.iter().any(|&g| merged[g].title == "theirs"));
+    assert_eq!(merged.entries.len(), theirs.entries.len() + 1);
+    assert!(merged.entries.iter().any(|&e| merged[e].title == "mine"));
+    assert!(merged.entries.iter().any(|&e| merged[e].title == "changed by them"));
+
+    // force_save overwrites changes of others
+    assert_eq!(theirs.save(None, None, None), Err(V1KpdbError::ModifiedErr));
+    assert!(theirs.force_save(None, None, None).is_ok());
+    assert!(!theirs.modified_on_disk());
+    assert!(merged.modified_on_disk());
+}
+
+fn locked_db(path: &str, mode: LockMode) -> V1Kpdb {
+    let mut db = V1Kpdb::new(path.to_string(), Some("test".to_string()), None).ok().unwrap();
+    db.lock_mode = Some(mode);
+    db
+}
+
+#[test]
+fn test_lock() {
+    fs::copy("test/test_password.kdb", "test/new_locked.kdb").unwrap();
+    let _ = fs::remove_file("test/new_locked.kdb.lock");
+
+    let mut first = locked_db("test/new_locked.kdb", LockMode::LockFile);
+    assert!(first.load().is_ok());
+    assert!(first.is_locked());
+    let mut second = locked_db("test/new_locked.kdb", LockMode::LockFile);
+    assert_eq!(second.load(), Err(V1KpdbError::LockErr));
+    let holder = second.lock_holder().unwrap();
+    assert!(!holder.id.is_empty());
+    assert!(!holder.host.is_empty());
+
+    // The lock file of KeePassX is empty
+    File::create("test/new_locked.kdb.lock").unwrap();
+    assert_eq!(second.lock_holder().unwrap().id, "");
+
+    // A lock file someone else took over stays when the old holder is dropped
+    assert!(second.lock(true).is_ok());
+    assert!(second.load().is_ok());
+    let stolen = second.lock_holder().unwrap().id;
+    assert!(!stolen.is_empty());
+    drop(first);
+    assert_eq!(second.lock_holder().unwrap().id, stolen);
+    drop(second);
+    assert!(fs::metadata("test/new_locked.kdb.lock").is_err());
+
+    let mut first = locked_db("test/new_locked.kdb", LockMode::Flock);
+    assert!(first.load().is_ok());
+    let mut second = locked_db("test/new_locked.kdb", LockMode::Flock);
+    assert_eq!(second.load(), Err(V1KpdbError::LockErr));
+    assert_eq!(second.lock(true), Err(V1KpdbError::LockErr));
+    first.unlock();
+    assert!(second.load().is_ok());
+    // Lock files honour flocks and the other way round
+    assert_eq!(first.load(), Err(V1KpdbError::LockErr));
+    first.lock_mode = Some(LockMode::LockFile);
+    assert_eq!(first.load(), Err(V1KpdbError::LockErr));
+
+    // A lock taken by a failing load is released again
+    drop(second);
+    let mut wrong = V1Kpdb::new("test/new_locked.kdb".to_string(), Some("tes".to_string()), None).ok().unwrap();
+    wrong.lock_mode = Some(LockMode::LockFile);
+    assert_eq!(wrong.load(), Err(V1KpdbError::DecryptErr));
+    assert!(!wrong.is_locked());
+    assert!(fs::metadata("test/new_locked.kdb.lock").is_err());
+}
+
+#[test]
+fn test_in_memory() {
+    let mut raw: Vec<u8> = vec![];
+    File::open("test/test_password.kdb").unwrap().read_to_end(&mut raw).unwrap();
+
+    // The path isn't used at all
+    let mut db = V1Kpdb::new("".to_string(), Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load_from_bytes(&raw).is_ok());
+    assert_eq!(db[db.entries[0]].title, "foo");
+
+    let mut saved: Vec<u8> = vec![];
+    assert!(db.save_to_writer(&mut saved, Some("new".to_string()), None).is_ok());
+    assert_eq!(db.path, "");
+
+    let mut db = V1Kpdb::new("".to_string(), Some("new".to_string()), None).ok().unwrap();
+    assert!(db.load_from_reader(&mut Cursor::new(saved)).is_ok());
+    assert_eq!(db[db.entries[0]].title, "foo");
+
+    assert_eq!(db.load_from_bytes(&raw[..100]), Err(V1KpdbError::FileErr));
+}
+
 #[test]
 fn test_create_group_w_title_only() {
     let mut result = V1Kpdb::new("test/test_password.kdb".to_string(),
@@ -77,7 +354,7 @@ fn test_create_group_w_title_only() {
     assert_eq!(db.create_group("test".to_string(), None, None, None).is_ok(),
                true);
 
-    let mut new_group = db.groups[db.groups.len() - 1].borrow_mut();
+    let new_group = &db[db.groups[db.groups.len() - 1]];
     assert_eq!(new_group.title, "test");
     assert_eq!((new_group.expire.year(),
                 new_group.expire.month(),
@@ -89,8 +366,8 @@ fn test_create_group_w_title_only() {
                (23, 59, 59));
     assert_eq!(new_group.image, 0);
 
-    let parent = new_group.parent.as_mut().unwrap();
-    assert_eq!(parent.borrow().id, 0);
+    let parent = new_group.parent.unwrap();
+    assert_eq!(db[parent].id, 0);
 
     assert_eq!(db.header.num_groups, num_groups_before + 1);
 }
@@ -116,7 +393,7 @@ fn test_create_group_w_everything() {
                  .is_ok(),
                true);
 
-    let mut new_group = db.groups[2].borrow_mut();
+    let new_group = &db[db.groups[2]];
     assert_eq!(new_group.title, "test");
     assert_eq!((new_group.expire.year(),
                 new_group.expire.month(),
@@ -124,12 +401,28 @@ fn test_create_group_w_everything() {
                (2015, 2, 28));
     assert_eq!(new_group.image, 2);
 
-    let parent = new_group.parent.as_mut().unwrap();
-    assert_eq!(parent.borrow().title, "12");
+    let parent = new_group.parent.unwrap();
+    assert_eq!(db[parent].title, "12");
 
     assert_eq!(db.header.num_groups, num_groups_before + 1);
 }
 
+#[test]
+fn test_create_group_level() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+
+    // 11 is on level 1, so its new subgroup must be saved on level 2
+    let parent = db.groups[2].clone();
+    assert_eq!(db[parent].level, 1);
+    assert!(db.create_group("test".to_string(), None, None, Some(parent)).is_ok());
+    assert_eq!(db[db.groups[3]].level, 2);
+}
+
 #[test]
 fn test_create_entry() {
     let mut result = V1Kpdb::new("test/test_password.kdb".to_string(),
@@ -153,7 +446,8 @@ fn test_create_entry() {
                     Some("bar".to_string()),
                     Some("foobar".to_string()));
 
-    let mut new_entry = db.entries[db.entries.len() - 1].borrow_mut();
+    let new_entry = db.entries[db.entries.len() - 1];
+    let new_entry = &mut db[new_entry];
     new_entry.username.as_mut().unwrap().unlock();
     new_entry.password.as_mut().unwrap().unlock();
     assert_eq!(new_entry.title, "test");
@@ -187,14 +481,14 @@ fn test_remove_group() {
 
     let group = db.groups[2].clone();
     let num_groups_before = db.header.num_groups;
-    let num_groups_in_root = db.groups[0].borrow().children.len();
+    let num_groups_in_root = db[db.groups[0]].children.len();
     let num_entries_before = db.header.num_entries;
     let num_entries_in_db = db.entries.len();
 
     assert_eq!(db.remove_group(group).is_ok(), true);
     assert_eq!(db.groups.len() as u32, num_groups_before - 5);
     assert_eq!(db.header.num_groups, num_groups_before - 5);
-    assert_eq!(db.groups[0].borrow().children.len(), num_groups_in_root - 1);
+    assert_eq!(db[db.groups[0]].children.len(), num_groups_in_root - 1);
     assert_eq!(db.header.num_entries, num_entries_before - 3);
     assert_eq!(db.entries.len(), num_entries_in_db - 3);
 }
@@ -211,7 +505,7 @@ fn test_remove_group_easy() {
     let mut db = result.unwrap();
 
     let num_groups_before = db.header.num_groups;
-    let num_groups_in_root = db.root_group.borrow().children.len();
+    let num_groups_in_root = db[db.root_group].children.len();
     let num_entries_before = db.header.num_entries;
     let num_entries_in_db = db.entries.len();
 
@@ -219,7 +513,7 @@ fn test_remove_group_easy() {
     assert_eq!(db.remove_group(group).is_ok(), true);
     assert_eq!(db.groups.len() as u32, num_groups_before - 1);
     assert_eq!(db.header.num_groups, num_groups_before - 1);
-    assert_eq!(db.root_group.borrow().children.len(),
+    assert_eq!(db[db.root_group].children.len(),
                num_groups_in_root - 1);
     assert_eq!(db.header.num_entries, num_entries_before - 1);
     assert_eq!(db.entries.len(), num_entries_in_db - 1);
@@ -239,11 +533,185 @@ fn test_remove_entry() {
     let entry = db.entries[0].clone();
     let num_entries_before = db.header.num_entries;
     let num_entries_in_db = db.entries.len();
-    let num_entries_in_group = db.groups[0].borrow().entries.len();
+    let num_entries_in_group = db[db.groups[0]].entries.len();
 
     assert_eq!(db.remove_entry(entry).is_ok(), true);
     assert_eq!(db.header.num_entries, num_entries_before - 1);
     assert_eq!(db.entries.len(), num_entries_in_db - 1);
-    assert_eq!(db.groups[0].borrow().entries.len(),
+    assert_eq!(db[db.groups[0]].entries.len(),
                num_entries_in_group - 1);
 }
+
+#[test]
+fn test_handles() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+
+    let group = db.groups[2];
+    let id = db[group].id;
+    let entry = db.entries[4];
+    let uuid = db[entry].uuid;
+    assert_eq!(db.group_by_id(id), Some(group));
+    assert_eq!(db.entry_by_uuid(&uuid), Some(entry));
+
+    // Handles of other groups and entries survive a removal
+    let removed = db.groups[1];
+    let removed_entry = db[removed].entries[0];
+    assert!(db.remove_group(removed).is_ok());
+    assert_eq!(db[group].id, id);
+    assert_eq!(db[entry].uuid, uuid);
+    assert!(db.group(removed).is_none());
+    assert!(db.entry(removed_entry).is_none());
+    assert_eq!(db.remove_group(removed), Err(V1KpdbError::IndexErr));
+    assert_eq!(db.remove_entry(removed_entry), Err(V1KpdbError::IndexErr));
+
+    // New groups don't reuse the handle of a removed one
+    let new_group = db.create_group("new".to_string(), None, None, None).ok().unwrap();
+    assert!(new_group != removed);
+    assert_eq!(db.group_by_id(db[new_group].id), Some(new_group));
+}
+
+#[test]
+fn test_move_entry() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+
+    let entry = db.entries[0];
+    let (old_group, new_group) = (db.groups[0], db.groups[3]);
+    let (uuid, creation) = (db[entry].uuid, db[entry].creation);
+    assert_eq!(db.move_entry(entry, new_group), Ok(()));
+    assert_eq!(db[entry].group, Some(new_group));
+    assert_eq!(db[entry].group_id, db[new_group].id);
+    assert!(!db[old_group].entries.contains(&entry));
+    assert!(db[new_group].entries.contains(&entry));
+    assert_eq!(db.move_entry(entry, db.root_group), Err(V1KpdbError::IndexErr));
+
+    assert!(db.save(Some("test/new_move_entry.kdb".to_string()), None, None).is_ok());
+    let mut db = V1Kpdb::new("test/new_move_entry.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let entry = db.entry_by_uuid(&uuid).unwrap();
+    assert_eq!(db[db[entry].group.unwrap()].title, "22");
+    assert_eq!(db[entry].creation, creation);
+}
+
+#[test]
+fn test_move_group() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let titles = |db: &V1Kpdb| -> Vec<(String, u16)> {
+        db.groups.iter().map(|&g| (db[g].title.clone(), db[g].level)).collect()
+    };
+    let (internet, group_11, group_21) = (db.groups[0], db.groups[2], db.groups[4]);
+
+    // 21 with its subgroups becomes the first subgroup of Internet
+    assert_eq!(db.move_group(group_21, Some(internet), 0), Ok(()));
+    assert_eq!(titles(&db),
+               vec![("Internet".to_string(), 0),
+                    ("21".to_string(), 1),
+                    ("32".to_string(), 2),
+                    ("31".to_string(), 2),
+                    ("12".to_string(), 1),
+                    ("11".to_string(), 1),
+                    ("22".to_string(), 2)]);
+    assert_eq!(db[internet].children[0], group_21);
+    assert_eq!(db[group_11].children.len(), 1);
+
+    // 11 becomes a top level group after Internet
+    assert_eq!(db.move_group(group_11, None, 10), Ok(()));
+    assert_eq!(titles(&db)[5..].to_vec(),
+               vec![("11".to_string(), 0), ("22".to_string(), 1)]);
+    let leaf = db.groups[2];
+    assert_eq!(db.move_group(internet, Some(leaf), 0), Err(V1KpdbError::MoveErr));
+
+    // The file order is still valid
+    let expected = titles(&db);
+    assert!(db.save(Some("test/new_move_group.kdb".to_string()), None, None).is_ok());
+    let mut db = V1Kpdb::new("test/new_move_group.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    assert_eq!(titles(&db), expected);
+    assert_eq!(db[db.groups[1]].entries.len(), 1);
+    assert_eq!(db[db[db.groups[1]].entries[0]].title, "test4");
+}
+
+#[test]
+fn test_paths() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let group_22 = db.groups[3];
+    assert_eq!(db.group_path(group_22), "Internet/11/22");
+    assert_eq!(db.group_by_path("Internet/11/22"), Ok(group_22));
+    assert_eq!(db.group_by_path("/Internet/11/22/"), Ok(group_22));
+    assert_eq!(db.group_by_path("Internet/22"), Err(V1KpdbError::PathErr));
+    assert_eq!(db.group_by_path(""), Err(V1KpdbError::PathErr));
+    let test5 = db.entry_by_path("Internet/11/22/test5").unwrap();
+    assert_eq!(db[test5].title, "test5");
+    assert_eq!(db.entry_path(test5), "Internet/11/22/test5");
+    assert_eq!(db.entry_by_path("Internet/11/test5"), Err(V1KpdbError::PathErr));
+
+    // Slashes and backslashes in titles are escaped
+    db[group_22].title = "a/b\\c".to_string();
+    assert_eq!(db.group_path(group_22), "Internet/11/a\\/b\\\\c");
+    assert_eq!(db.group_by_path("Internet/11/a\\/b\\\\c"), Ok(group_22));
+    assert_eq!(db.entry_by_path("Internet/11/a\\/b\\\\c/test5"), Ok(test5));
+
+    // Sibling groups and entries with the same title
+    let group_12 = db.groups[1];
+    db[group_12].title = "11".to_string();
+    assert_eq!(db.group_by_path("Internet/11"), Err(V1KpdbError::AmbiguousErr));
+    assert_eq!(db.entry_by_path("Internet/11/test2"), Err(V1KpdbError::AmbiguousErr));
+    db[group_12].title = "12".to_string();
+    db[test5].title = "test6".to_string();
+    let internet = db.groups[0];
+    let test1 = db[internet].entries[0];
+    db[test1].title = "twice".to_string();
+    assert!(db.create_entry(internet, "twice".to_string(), None, None, None, None, None, None).is_ok());
+    assert_eq!(db.entry_by_path("Internet/twice"), Err(V1KpdbError::AmbiguousErr));
+}
+
+#[test]
+fn test_create_group_path() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(),
+                             Some("test".to_string()),
+                             None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let group_11 = db.groups[2];
+    assert_eq!(db.create_group_path("Internet/11"), Ok(group_11));
+    assert_eq!(db.groups.len(), 7);
+
+    let new = db.create_group_path("Internet/11/new/a\\/b").unwrap();
+    assert_eq!(db.groups.len(), 9);
+    assert_eq!(db[new].title, "a/b");
+    assert_eq!(db[new].level, 3);
+    assert_eq!(db.group_path(new), "Internet/11/new/a\\/b");
+    assert_eq!(db.group_by_path("Internet/11/new/a\\/b"), Ok(new));
+
+    let top = db.create_group_path("Top").unwrap();
+    assert_eq!(db[top].level, 0);
+    assert_eq!(db.create_group_path("/"), Err(V1KpdbError::PathErr));
+}
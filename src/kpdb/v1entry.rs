@@ -1,12 +1,16 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-
 use chrono::{DateTime, Local, TimeZone};
 use uuid::Uuid;
 
-use super::v1group::V1Group;
+use super::v1group::GroupId;
 use super::super::sec_str::SecureString;
 
+#[doc = "
+Handle of an entry in a V1Kpdb, see V1Kpdb::entry. It stays valid
+until the entry is removed and means nothing to other databases.
+"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntryId(pub(crate) usize);
+
 #[doc = "
 Implements an entry in a KeePass v1.x database.
 "]
@@ -15,8 +19,8 @@ pub struct V1Entry {
     pub uuid: Uuid,
     /// ID of the group holding the entry
     pub group_id: u32,
-    /// Reference to the group holding the entry
-    pub group: Option<Rc<RefCell<V1Group>>>,
+    /// The group holding the entry
+    pub group: Option<GroupId>,
     /// Used to specify an icon for the entry
     pub image: u32,
     /// Title of the entry
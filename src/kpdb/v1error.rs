@@ -39,10 +39,30 @@ pub enum V1KpdbError {
     TreeErr,
     /// Password and/or keyfile needed but at least one of both
     PassErr,
-    /// Can't find item in Vec
+    /// The group or entry isn't (or no longer) in the database
     IndexErr,
-    /// Tried upgrade of weak reference without strong one
+    /// Not returned anymore, the groups and entries aren't weak
+    /// references since they are handles into the database
+    #[deprecated(note = "groups and entries are GroupId and EntryId handles now, see IndexErr")]
     WeakErr,
+    /// Hash of the key in a XML keyfile (version 2.0) is wrong,
+    /// i.e. the keyfile is corrupted
+    KeyfileHashErr,
+    /// The database file was changed by someone else since it was
+    /// loaded or saved, see force_save
+    ModifiedErr,
+    /// Someone else holds the lock of the database, see
+    /// V1Kpdb::lock_holder
+    LockErr,
+    /// Tried to move a group into itself or one of its subgroups
+    MoveErr,
+    /// No group or entry has the given path
+    PathErr,
+    /// Several sibling groups or entries have the title given in a
+    /// path
+    AmbiguousErr,
+    /// The file to create exists already
+    ExistsErr,
 }
 
 impl fmt::Display for V1KpdbError {
@@ -67,8 +87,16 @@ impl error::Error for V1KpdbError {
             OffsetErr => "Some error while parsing the database. Probably a corrupted file",
             TreeErr => "Group tree is corrupted",
             PassErr => "Password and/or keyfile needed but at least one of both",
-            IndexErr => "Can't find item in Vec",
+            IndexErr => "Group or entry isn't in the database",
+            #[allow(deprecated)]
             WeakErr => "Tried upgrade of weak reference without strong one",
+            KeyfileHashErr => "Hash of the key in the keyfile is wrong, keyfile is corrupted",
+            ModifiedErr => "Database file was modified by someone else since it was loaded",
+            LockErr => "Database is locked by someone else",
+            MoveErr => "Can't move a group into itself or one of its subgroups",
+            PathErr => "No group or entry with this path",
+            AmbiguousErr => "Several groups or entries with this path",
+            ExistsErr => "File exists already",
         }
     }
 }
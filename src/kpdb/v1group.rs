@@ -1,11 +1,13 @@
-use std::cell::RefCell;
-use std::rc::{Rc, Weak};
-
 use chrono::{DateTime, Local, TimeZone};
 
-use kpdb::GetIndex;
-use kpdb::v1entry::V1Entry;
-use kpdb::v1error::V1KpdbError;
+use kpdb::v1entry::EntryId;
+
+#[doc = "
+Handle of a group in a V1Kpdb, see V1Kpdb::group. It stays valid
+until the group is removed and means nothing to other databases.
+"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GroupId(pub(crate) usize);
 
 #[doc = "
 Implements a group of a KeePass v1.x database
@@ -29,12 +31,12 @@ pub struct V1Group {
     pub expire: DateTime<Local>,
     /// ??
     pub flags: u32,
-    /// Pointer to the parent group
-    pub parent: Option<Rc<RefCell<V1Group>>>,
-    /// Array of weak references to the children
-    pub children: Vec<Weak<RefCell<V1Group>>>,
-    /// Array of weak references to the entries
-    pub entries: Vec<Weak<RefCell<V1Entry>>>, // db: Box<Option<V1Kpdb>>,
+    /// The parent group
+    pub parent: Option<GroupId>,
+    /// The subgroups
+    pub children: Vec<GroupId>,
+    /// The entries of the group
+    pub entries: Vec<EntryId>,
 }
 
 impl V1Group {
@@ -54,27 +56,9 @@ impl V1Group {
             flags: 0,
             parent: None,
             children: vec![],
-            entries: vec![], // db: box None,
+            entries: vec![],
         }
     }
-
-    pub fn drop_weak_child_reference(&mut self,
-                                     child: &Rc<RefCell<V1Group>>)
-                                     -> Result<(), V1KpdbError> {
-        let index = try!(self.children.get_index(child));
-        let weak_group_reference = self.children.remove(index);
-        drop(weak_group_reference);
-        Ok(())
-    }
-
-    pub fn drop_weak_entry_reference(&mut self,
-                                     entry: &Rc<RefCell<V1Entry>>)
-                                     -> Result<(), V1KpdbError> {
-        let index = try!(self.entries.get_index(entry));
-        let weak_entry_reference = self.entries.remove(index);
-        drop(weak_entry_reference);
-        Ok(())
-    }
 }
 
 impl PartialEq for V1Group {
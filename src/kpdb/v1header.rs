@@ -4,6 +4,18 @@ use kpdb::v1error::V1KpdbError;
 // * Drop for critical data
 // * Parsing into LoadParser
 
+#[doc = "
+The block ciphers a KeePass v1.x database can be encrypted with.
+Which one is used is stored in the enc_flag of the header.
+"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum V1Cipher {
+    /// AES-256 in CBC mode (enc_flag bit 0b10)
+    Aes,
+    /// Twofish-256 in CBC mode (enc_flag bit 0b1000)
+    Twofish,
+}
+
 #[doc = "
 V1Header implements the header of a KeePass v1.x database.
 Normally you don't need to mess with this yourself.
@@ -15,14 +27,13 @@ pub struct V1Header {
     /// File signature
     pub signature2: u32,
     /// Describes which encryption algorithm was used.
-    /// 0b10 is for AES, 0b1000 is for Twofish (not
-    /// supported, yet)
+    /// 0b10 is for AES, 0b1000 is for Twofish
     pub enc_flag: u32,
     /// Version of the database. 0x00030002 is for v1.x
     pub version: u32,
     /// A seed used to create the final key
     pub final_randomseed: Vec<u8>,
-    /// IV for AEC_CBC to de-/encrypt the database
+    /// IV for AES_CBC or Twofish_CBC to de-/encrypt the database
     pub iv: Vec<u8>,
     /// Total number of groups in database
     pub num_groups: u32,
@@ -66,12 +77,31 @@ impl V1Header {
 
     // Checks encryption flag
     pub fn check_enc_flag(&self) -> Result<(), V1KpdbError> {
-        if self.enc_flag & 2 != 2 {
-            return Err(V1KpdbError::EncFlagErr);
-        }
+        try!(self.cipher());
         Ok(())
     }
 
+    /// Returns the cipher the database is encrypted with
+    pub fn cipher(&self) -> Result<V1Cipher, V1KpdbError> {
+        if self.enc_flag & 2 == 2 {
+            Ok(V1Cipher::Aes)
+        } else if self.enc_flag & 8 == 8 {
+            Ok(V1Cipher::Twofish)
+        } else {
+            Err(V1KpdbError::EncFlagErr)
+        }
+    }
+
+    /// Set the cipher which should be used on the next save.
+    /// Other bits of enc_flag (e.g. 0b1 for SHA-2) are kept.
+    pub fn set_cipher(&mut self, cipher: V1Cipher) {
+        self.enc_flag &= !(2 | 8);
+        match cipher {
+            V1Cipher::Aes => self.enc_flag |= 2,
+            V1Cipher::Twofish => self.enc_flag |= 8,
+        }
+    }
+
     // Checks database version
     pub fn check_version(&self) -> Result<(), V1KpdbError> {
         if self.version != 0x00030002u32 {
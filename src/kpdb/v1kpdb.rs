@@ -1,31 +1,46 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::mem;
 use std::io::{Read, Write};
 use std::fs::File;
+use std::ops::{Index, IndexMut};
 
 use chrono::{DateTime, Local};
 use rand;
+use uuid::Uuid;
 
-use kpdb::GetIndex;
+use common::common_error::CommonError;
+use common::file::{save_atomically, Fingerprint};
+use common::lock::{lock_holder, FileLock, LockInfo, LockMode};
+use kpdb::arena::Arena;
+use kpdb::compositekey::CompositeKey;
 use kpdb::crypter::Crypter;
+use kpdb::merge::{merge, MergeReport};
+use kpdb::path::{escape_title, join_path, split_path};
 use kpdb::parser::{HeaderLoadParser, HeaderSaveParser, LoadParser, SaveParser};
 use kpdb::v1error::V1KpdbError;
-use kpdb::v1group::V1Group;
-use kpdb::v1entry::V1Entry;
+use kpdb::v1group::{GroupId, V1Group};
+use kpdb::v1entry::{EntryId, V1Entry};
 use kpdb::v1header::V1Header;
 use super::super::sec_str::SecureString;
 
 #[doc = "
 V1Kpdb implements a KeePass v1.x database. Some notes on the file format:
 
-* Database is encrypted with AES (Twofish currently not supported by this
-  module) with a password and/or a keyfile.
+* Database is encrypted with AES or Twofish with a password and/or a keyfile.
+  A loaded database is saved with the same cipher again, use
+  header.set_cipher() to choose another one.
 * Database holds entries which describes the credentials (username, password
   URL...) and are sorted in groups
 * The groups themselves can hold subgroups
 * Entries have titles for better identification by the user and expiration
   dates to remind that the password should be changed after some period
 
+Groups and entries are owned by the database and addressed by handles
+(GroupId and EntryId), which stay valid until the item is removed.
+Index the database with a handle to get the item, e.g.
+`db[db.groups[0]].title`, or use group and entry, which return None
+for removed items instead of panicking.
+
 TODO:
 
 * saving
@@ -40,16 +55,36 @@ pub struct V1Kpdb {
     /// Holds the header. Normally you don't need
     /// to manipulate this yourself
     pub header: V1Header,
-    /// The groups which hold the entries
-    pub groups: Vec<Rc<RefCell<V1Group>>>,
-    /// The entries of the whole database
-    pub entries: Vec<Rc<RefCell<V1Entry>>>,
+    /// The groups which hold the entries, in the order of the
+    /// file (every group is followed by its subgroups)
+    pub groups: Vec<GroupId>,
+    /// The entries of the whole database, in the order of the file
+    pub entries: Vec<EntryId>,
     /// A group which holds all groups of level 0
     /// as a subgroup (all groups which are not a
     /// subgroup of another group )
-    pub root_group: Rc<RefCell<V1Group>>,
+    pub root_group: GroupId,
+    /// Number of timestamped backups of previous versions which
+    /// save keeps next to the database (path.YYYYmmdd-HHMMSS.fff.bak).
+    /// 0 keeps none
+    pub backups: usize,
+    /// Fingerprint of the file at path when the database was loaded
+    /// or saved the last time. None if the database wasn't loaded
+    /// from a file
+    pub fingerprint: Option<Fingerprint>,
+    /// Lock the database on load against concurrent editing and
+    /// release the lock on drop. None (default) doesn't lock
+    pub lock_mode: Option<LockMode>,
     // Used to de- and encrypt the database
     crypter: Crypter,
+    // Lock of the database, see lock_mode
+    lock: Option<FileLock>,
+    // Owners of the groups (including the root group) and entries
+    group_arena: Arena<V1Group>,
+    entry_arena: Arena<V1Entry>,
+    // Handles of the groups by id and of the entries by UUID
+    group_index: HashMap<u32, GroupId>,
+    entry_index: HashMap<Uuid, EntryId>,
 }
 
 impl V1Kpdb {
@@ -64,19 +99,252 @@ impl V1Kpdb {
                password: Option<String>,
                keyfile: Option<String>)
                -> Result<V1Kpdb, V1KpdbError> {
+        V1Kpdb::with_crypter(path, try!(Crypter::new(password, keyfile)))
+    }
+
+    /// Same as new, but the database is encrypted with a composite
+    /// key of arbitrary components, e.g. a password and a key from
+    /// a custom KeyProvider
+    pub fn with_key(path: String, key: CompositeKey) -> Result<V1Kpdb, V1KpdbError> {
+        V1Kpdb::with_crypter(path, try!(Crypter::with_key(key)))
+    }
+
+    fn with_crypter(path: String, crypter: Crypter) -> Result<V1Kpdb, V1KpdbError> {
+        let mut group_arena = Arena::new();
+        let root_group = GroupId(group_arena.insert(V1Group::new()));
         Ok(V1Kpdb {
             path: path,
             header: V1Header::new(),
             groups: vec![],
             entries: vec![],
-            root_group: Rc::new(RefCell::new(V1Group::new())),
-            crypter: try!(Crypter::new(password, keyfile)),
+            root_group: root_group,
+            backups: 0,
+            fingerprint: None,
+            lock_mode: None,
+            crypter: crypter,
+            lock: None,
+            group_arena: group_arena,
+            entry_arena: Arena::new(),
+            group_index: HashMap::new(),
+            entry_index: HashMap::new(),
         })
     }
 
-    /// Decrypt and parse the database.
+    /// The group of a handle, None if it was removed
+    pub fn group(&self, group: GroupId) -> Option<&V1Group> {
+        self.group_arena.get(group.0)
+    }
+
+    /// Same as group, but the group can be changed
+    pub fn group_mut(&mut self, group: GroupId) -> Option<&mut V1Group> {
+        self.group_arena.get_mut(group.0)
+    }
+
+    /// The entry of a handle, None if it was removed
+    pub fn entry(&self, entry: EntryId) -> Option<&V1Entry> {
+        self.entry_arena.get(entry.0)
+    }
+
+    /// Same as entry, but the entry can be changed
+    pub fn entry_mut(&mut self, entry: EntryId) -> Option<&mut V1Entry> {
+        self.entry_arena.get_mut(entry.0)
+    }
+
+    /// The group with the given id
+    pub fn group_by_id(&self, id: u32) -> Option<GroupId> {
+        match self.group_index.get(&id) {
+            Some(&group) if self.group(group).map(|g| g.id) == Some(id) => Some(group),
+            // The id was changed after the group was added
+            _ => self.groups.iter().cloned().find(|&group| self[group].id == id),
+        }
+    }
+
+    /// The entry with the given UUID
+    pub fn entry_by_uuid(&self, uuid: &Uuid) -> Option<EntryId> {
+        match self.entry_index.get(uuid) {
+            Some(&entry) if self.entry(entry).map(|e| e.uuid) == Some(*uuid) => Some(entry),
+            _ => self.entries.iter().cloned().find(|&entry| self[entry].uuid == *uuid),
+        }
+    }
+
+    /// The path of a group, e.g. "Internet/Email". A "/" in a title
+    /// is escaped as "\/" and a backslash as "\\", see kpdb::path.
+    pub fn group_path(&self, group: GroupId) -> String {
+        let mut titles: Vec<&str> = vec![];
+        let mut current = Some(group);
+        while let Some(group) = current {
+            if group == self.root_group {
+                break;
+            }
+            titles.push(&self[group].title);
+            current = self[group].parent;
+        }
+        titles.reverse();
+        join_path(&titles)
+    }
+
+    /// The path of an entry, the path of its group followed by its
+    /// title, e.g. "Internet/Email/Gmail"
+    pub fn entry_path(&self, entry: EntryId) -> String {
+        let entry = &self[entry];
+        match entry.group {
+            Some(group) if group != self.root_group => {
+                format!("{}/{}", self.group_path(group), escape_title(&entry.title))
+            }
+            _ => escape_title(&entry.title),
+        }
+    }
+
+    /// The group with the given path, e.g. "Internet/Email"
+    ///
+    /// Returns PathErr if there is no such group and AmbiguousErr if
+    /// a group on the way has several subgroups with the title.
+    pub fn group_by_path(&self, path: &str) -> Result<GroupId, V1KpdbError> {
+        let titles = split_path(path);
+        if titles.is_empty() {
+            return Err(V1KpdbError::PathErr);
+        }
+        let mut group = self.root_group;
+        for title in titles.iter() {
+            group = try!(try!(self.subgroup(group, title)).ok_or(V1KpdbError::PathErr));
+        }
+        Ok(group)
+    }
+
+    /// The entry with the given path, e.g. "Internet/Email/Gmail"
+    ///
+    /// Returns PathErr if there is no such entry and AmbiguousErr if
+    /// several entries of the group or several subgroups on the way
+    /// have the title.
+    pub fn entry_by_path(&self, path: &str) -> Result<EntryId, V1KpdbError> {
+        let mut titles = split_path(path);
+        let title = try!(titles.pop().ok_or(V1KpdbError::PathErr));
+        let mut group = self.root_group;
+        for parent in titles.iter() {
+            group = try!(try!(self.subgroup(group, parent)).ok_or(V1KpdbError::PathErr));
+        }
+        let mut found = self[group].entries.iter().filter(|&&entry| self[entry].title == title);
+        match (found.next(), found.next()) {
+            (Some(&entry), None) => Ok(entry),
+            (None, _) => Err(V1KpdbError::PathErr),
+            _ => Err(V1KpdbError::AmbiguousErr),
+        }
+    }
+
+    /// Create a group by its path like mkdir -p: missing parent groups
+    /// are created with create_group, existing ones are used.
+    ///
+    /// Returns the group at path, whether it was created or not,
+    /// PathErr for an empty path and AmbiguousErr if a group on the
+    /// way has several subgroups with the title.
+    pub fn create_group_path(&mut self, path: &str) -> Result<GroupId, V1KpdbError> {
+        let titles = split_path(path);
+        if titles.is_empty() {
+            return Err(V1KpdbError::PathErr);
+        }
+        let mut group = self.root_group;
+        for title in titles.into_iter() {
+            group = match try!(self.subgroup(group, &title)) {
+                Some(subgroup) => subgroup,
+                None => {
+                    let parent = if group == self.root_group { None } else { Some(group) };
+                    try!(self.create_group(title, None, None, parent))
+                }
+            };
+        }
+        Ok(group)
+    }
+
+    // The subgroup of group with the title if there is exactly one
+    fn subgroup(&self, group: GroupId, title: &str) -> Result<Option<GroupId>, V1KpdbError> {
+        let mut found = self[group].children.iter().filter(|&&child| self[child].title == title);
+        match (found.next(), found.next()) {
+            (Some(&child), None) => Ok(Some(child)),
+            (None, _) => Ok(None),
+            _ => Err(V1KpdbError::AmbiguousErr),
+        }
+    }
+
+    /// Decrypt and parse the database. Takes the lock first if
+    /// lock_mode is set and returns LockErr if someone else holds it.
+    /// A lock taken by load is released again if loading fails.
     pub fn load(&mut self) -> Result<(), V1KpdbError> {
-        let (header, encrypted_database) = try!(self.read_in_file());
+        let take_lock = self.lock_mode.is_some() && self.lock.is_none();
+        if take_lock {
+            try!(self.lock(false));
+        }
+        let result = self.load_file();
+        if take_lock && result.is_err() {
+            self.lock = None;
+        }
+        result
+    }
+
+    fn load_file(&mut self) -> Result<(), V1KpdbError> {
+        let mut file = try!(File::open(&self.path).map_err(|_| V1KpdbError::FileErr));
+        let mut raw: Vec<u8> = vec![];
+        try!(file.read_to_end(&mut raw).map_err(|_| V1KpdbError::ReadErr));
+        let fingerprint = Fingerprint::new(&self.path, &raw);
+        try!(self.load_raw(raw));
+        self.fingerprint = Some(fingerprint);
+        Ok(())
+    }
+
+    /// Lock the database like load does with lock_mode (LockFile if
+    /// it's None). steal takes over a lock file someone else left
+    /// behind, check lock_holder before.
+    pub fn lock(&mut self, steal: bool) -> Result<(), V1KpdbError> {
+        let mode = self.lock_mode.unwrap_or(LockMode::LockFile);
+        // Release our old lock first, otherwise a flock would conflict
+        self.lock = None;
+        let lock = try!(FileLock::acquire(&self.path, mode, steal).map_err(|e| {
+            match e {
+                CommonError::LockErr => V1KpdbError::LockErr,
+                CommonError::FileErr => V1KpdbError::FileErr,
+                _ => V1KpdbError::WriteErr,
+            }
+        }));
+        self.lock = Some(lock);
+        Ok(())
+    }
+
+    /// Release the lock of the database
+    pub fn unlock(&mut self) {
+        self.lock = None;
+    }
+
+    /// True if this database holds its lock
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    /// Who holds the lock of the database, None if nobody does
+    pub fn lock_holder(&self) -> Option<LockInfo> {
+        lock_holder(&self.path)
+    }
+
+    /// Same as load, but the encrypted database is read from reader
+    /// instead of the file at path
+    pub fn load_from_reader<R: Read>(&mut self, reader: &mut R) -> Result<(), V1KpdbError> {
+        let mut raw: Vec<u8> = vec![];
+        try!(reader.read_to_end(&mut raw).map_err(|_| V1KpdbError::ReadErr));
+        self.fingerprint = None;
+        self.load_raw(raw)
+    }
+
+    /// Same as load, but the encrypted database is taken from bytes,
+    /// so no filesystem is involved
+    pub fn load_from_bytes(&mut self, bytes: &[u8]) -> Result<(), V1KpdbError> {
+        self.fingerprint = None;
+        self.load_raw(bytes.to_vec())
+    }
+
+    fn load_raw(&mut self, mut raw: Vec<u8>) -> Result<(), V1KpdbError> {
+        if raw.len() < 124 {
+            return Err(V1KpdbError::FileErr);
+        }
+        let encrypted_database = raw.split_off(124);
+        let header = raw;
 
         // First read header and decrypt the database
         let header_parser = HeaderLoadParser::new(header);
@@ -91,23 +359,15 @@ impl V1Kpdb {
                                          self.header.num_groups,
                                          self.header.num_entries);
         let (groups, levels) = try!(parser.parse_groups());
-        self.groups = groups;
-        self.entries = try!(parser.parse_entries());
+        let entries = try!(parser.parse_entries());
         parser.delete_decrypted_content();
 
         // Now create the group tree and sort the entries to their groups
-        try!(LoadParser::create_group_tree(self, levels));
+        self.clear();
+        try!(LoadParser::create_group_tree(self, groups, levels, entries));
         Ok(())
     }
 
-    fn read_in_file(&self) -> Result<(Vec<u8>, Vec<u8>), V1KpdbError> {
-        let mut file = try!(File::open(&self.path).map_err(|_| V1KpdbError::FileErr));
-        let mut raw: Vec<u8> = vec![];
-        try!(file.read_to_end(&mut raw).map_err(|_| V1KpdbError::ReadErr));
-        let encrypted_database = raw.split_off(124);
-        Ok((raw, encrypted_database))
-    }
-
     fn check_header(&self) -> Result<(), V1KpdbError> {
         try!(self.header.check_signatures());
         try!(self.header.check_enc_flag());
@@ -115,12 +375,108 @@ impl V1Kpdb {
         Ok(())
     }
     
+    /// Save the database. The file is written to a temporary file
+    /// first and renamed over the old one, so a crash never leaves a
+    /// half written database behind. Returns ModifiedErr if the file
+    /// was changed by someone else since it was loaded, use
+    /// reload_and_merge or force_save then.
+    ///
+    /// * path: new filepath of the database. None means the database is
+    ///         saved where it was loaded from
+    ///
+    /// * password: new password. None keeps the old one, an empty
+    ///             string removes it
+    ///
+    /// * keyfile: new keyfile. None keeps the old one, an empty
+    ///            string removes it
+    ///
+    /// Returns PassErr without saving if neither a password nor a
+    /// keyfile would be left.
     pub fn save(&mut self,
                 path: Option<String>,
                 password: Option<String>,
                 keyfile: Option<String>) -> Result<(), V1KpdbError> {
+        let same_file = path.is_none() || path.as_ref() == Some(&self.path);
+        if same_file && self.modified_on_disk() {
+            return Err(V1KpdbError::ModifiedErr);
+        }
+        self.force_save(path, password, keyfile)
+    }
+
+    /// Same as save, but the file is overwritten even if someone
+    /// else changed it in the meantime
+    pub fn force_save(&mut self,
+                      path: Option<String>,
+                      password: Option<String>,
+                      keyfile: Option<String>) -> Result<(), V1KpdbError> {
+        let raw = try!(self.encrypt(password, keyfile));
+
+        if let Some(new_path) = path {
+            self.path = new_path
+        }
+        try!(save_atomically(&self.path, &raw, self.backups).map_err(|e| {
+            match e {
+                CommonError::FileErr => V1KpdbError::FileErr,
+                _ => V1KpdbError::WriteErr,
+            }
+        }));
+        self.fingerprint = Some(Fingerprint::new(&self.path, &raw));
+
+        Ok(())
+    }
+
+    /// True if the file at path was changed (or removed) by someone
+    /// else since the database was loaded or saved the last time
+    pub fn modified_on_disk(&self) -> bool {
+        match self.fingerprint {
+            Some(ref fingerprint) => {
+                match Fingerprint::of_file(&self.path) {
+                    Some(ref current) => !fingerprint.same_content(current),
+                    None => true,
+                }
+            }
+            None => false,
+        }
+    }
+
+    /// Load the file at path again and merge it into this database
+    /// (see merge::merge without base), so that a following save keeps
+    /// the changes of both. Nothing is deleted as KeePass 1.x doesn't
+    /// record deletions, i.e. groups and entries deleted on one side
+    /// reappear. The header of this database is kept. On error this
+    /// database stays as it was.
+    pub fn reload_and_merge(&mut self) -> Result<MergeReport, V1KpdbError> {
+        let mut disk = try!(V1Kpdb::new(self.path.clone(), Some("".to_string()), None));
+        // Decrypt the file with our own key
+        mem::swap(&mut self.crypter, &mut disk.crypter);
+        let loaded = disk.load();
+        mem::swap(&mut self.crypter, &mut disk.crypter);
+        try!(loaded);
+
+        let report = try!(merge(None, self, &disk));
+        self.fingerprint = disk.fingerprint.take();
+        Ok(report)
+    }
+
+    /// Same as save, but the encrypted database is written to writer
+    /// instead of a file. path stays untouched.
+    pub fn save_to_writer<W: Write>(&mut self,
+                                    writer: &mut W,
+                                    password: Option<String>,
+                                    keyfile: Option<String>) -> Result<(), V1KpdbError> {
+        let raw = try!(self.encrypt(password, keyfile));
+        try!(writer.write_all(&raw).map_err(|_| V1KpdbError::WriteErr));
+        try!(writer.flush().map_err(|_| V1KpdbError::WriteErr));
+        Ok(())
+    }
+
+    // Encrypt the database with a new header and return the
+    // header followed by the encrypted database
+    fn encrypt(&mut self,
+               password: Option<String>,
+               keyfile: Option<String>) -> Result<Vec<u8>, V1KpdbError> {
         let mut parser = SaveParser::new();
-        parser.prepare(self);
+        try!(parser.prepare(self));
         
         let mut header = self.header.clone();
         header.final_randomseed = (0..16).map(|_| rand::random::<u8>()).collect();
@@ -128,38 +484,32 @@ impl V1Kpdb {
         header.content_hash = try!(Crypter::get_content_hash(&parser.database));
 
 
-        if let Some(new_password) = password {
-            if new_password == "".to_string() {
-                self.crypter.change_password(None);
+        // The keyfile first, so that the password can be removed when
+        // switching to a keyfile only
+        if let Some(new_keyfile) = keyfile {
+            if new_keyfile == "".to_string() {
+                try!(self.crypter.change_keyfile(None));
             }
             else {
-                self.crypter.change_password(Some(new_password));
+                try!(self.crypter.change_keyfile(Some(new_keyfile)));
             }
         }
 
-        if let Some(new_keyfile) = keyfile {
-            if new_keyfile == "".to_string() {
-                self.crypter.change_keyfile(None);
+        if let Some(new_password) = password {
+            if new_password == "".to_string() {
+                try!(self.crypter.change_password(None));
             }
             else {
-                self.crypter.change_keyfile(Some(new_keyfile));
+                try!(self.crypter.change_password(Some(new_password)));
             }
         }
 
         let encrypted_database = try!(self.crypter.encrypt_database(&header, parser.database));
 
         let mut header_parser = HeaderSaveParser::new(header);
-        let header_raw = header_parser.parse_header();
-
-        if let Some(new_path) = path {
-            self.path = new_path
-        }
-        let mut file = try!(File::create(&self.path).map_err(|_| V1KpdbError::FileErr));
-        try!(file.write_all(&header_raw).map_err(|_| V1KpdbError::WriteErr));
-        try!(file.write_all(&encrypted_database).map_err(|_| V1KpdbError::WriteErr));
-        try!(file.flush().map_err(|_| V1KpdbError::WriteErr));            
-
-        Ok(())
+        let mut raw = header_parser.parse_header();
+        raw.extend_from_slice(&encrypted_database);
+        Ok(raw)
     }
     
     /// Create a new group
@@ -173,54 +523,50 @@ impl V1Kpdb {
     /// * image: an image number, used in KeePass and KeePassX for the group
     ///          icon. None means 0
     ///
-    /// * parent: a group inside the groups vector which should be the parent in
+    /// * parent: a group of the database which should be the parent in
     ///           the group tree. None means that the root group is the parent
+    ///
+    /// Returns the handle of the new group, IndexErr if parent isn't
+    /// in the database.
     pub fn create_group(&mut self,
                         title: String,
                         expire: Option<DateTime<Local>>,
                         image: Option<u32>,
-                        parent: Option<Rc<RefCell<V1Group>>>)
-                        -> Result<(), V1KpdbError> {
+                        parent: Option<GroupId>)
+                        -> Result<GroupId, V1KpdbError> {
         let mut new_id: u32 = 1;
-        for group in self.groups.iter() {
-            let id = group.borrow().id;
+        for &group in self.groups.iter() {
+            let id = self[group].id;
             if id >= new_id {
                 new_id = id + 1;
             }
         }
 
-        let new_group = Rc::new(RefCell::new(V1Group::new()));
-        new_group.borrow_mut().id = new_id;
-        new_group.borrow_mut().title = title;
-        new_group.borrow_mut().creation = Local::now();
-        new_group.borrow_mut().last_mod = Local::now();
-        new_group.borrow_mut().last_access = Local::now();
+        let mut new_group = V1Group::new();
+        new_group.id = new_id;
+        new_group.title = title;
+        new_group.creation = Local::now();
+        new_group.last_mod = Local::now();
+        new_group.last_access = Local::now();
         match expire {
-            Some(s) => new_group.borrow_mut().expire = s,
+            Some(s) => new_group.expire = s,
             None => {} // is 12-28-2999 23:59:59 through V1Group::new
         }
         match image {
-            Some(s) => new_group.borrow_mut().image = s,
+            Some(s) => new_group.image = s,
             None => {} // is 0 through V1Group::new
         }
         match parent {
             Some(s) => {
-                let index = try!(self.groups.get_index(&s));
-                new_group.borrow_mut().parent = Some(s.clone());
-                s.borrow_mut().children.push(Rc::downgrade(&new_group.clone()));
-                self.groups.insert(index + 1, new_group);
-
+                let index = try!(self.groups.iter().position(|&g| g == s).ok_or(V1KpdbError::IndexErr));
+                Ok(self.insert_group(new_group, s, index + 1))
             }
             None => {
-                new_group.borrow_mut().parent = Some(self.root_group
-                                                         .clone());
-                self.root_group.borrow_mut().children.push(Rc::downgrade(&new_group.clone()));
-                self.groups.push(new_group);
+                let root = self.root_group;
+                let index = self.groups.len();
+                Ok(self.insert_group(new_group, root, index))
             }
         }
-
-        self.header.num_groups += 1;
-        Ok(())
     }
 
     /// Create a new entry
@@ -244,6 +590,9 @@ impl V1Kpdb {
     ///
     /// * password: password for the URL
     ///
+    /// Returns the handle of the new entry, IndexErr if group isn't in
+    /// the database.
+    ///
     /// Note: username and password should be of type String at creation. If you have a
     /// &str which you convert into a String with to_string() the plaintext will remain
     /// in memory as the new created String is a copy of the original &str. If you use
@@ -251,124 +600,322 @@ impl V1Kpdb {
     /// created.
     ///
     pub fn create_entry(&mut self,
-                        group: Rc<RefCell<V1Group>>,
+                        group: GroupId,
                         title: String,
                         expire: Option<DateTime<Local>>,
                         image: Option<u32>,
                         url: Option<String>,
                         comment: Option<String>,
                         username: Option<String>,
-                        password: Option<String>) {
+                        password: Option<String>)
+                        -> Result<EntryId, V1KpdbError> {
+        if self.group(group).is_none() {
+            return Err(V1KpdbError::IndexErr);
+        }
         // Automatically creates a UUID for the entry
-        let new_entry = Rc::new(RefCell::new(V1Entry::new()));
-        new_entry.borrow_mut().title = title;
-        new_entry.borrow_mut().group = Some(group.clone());
-        group.borrow_mut().entries.push(Rc::downgrade(&new_entry.clone()));
-        new_entry.borrow_mut().group_id = group.borrow().id;
-        new_entry.borrow_mut().creation = Local::now();
-        new_entry.borrow_mut().last_mod = Local::now();
-        new_entry.borrow_mut().last_access = Local::now();
+        let mut new_entry = V1Entry::new();
+        new_entry.title = title;
+        new_entry.creation = Local::now();
+        new_entry.last_mod = Local::now();
+        new_entry.last_access = Local::now();
         match expire {
-            Some(s) => new_entry.borrow_mut().expire = s,
+            Some(s) => new_entry.expire = s,
             None => {} // is 12-28-2999 23:59:59 through V1Entry::new()
         };
         match image {
-            Some(s) => new_entry.borrow_mut().image = s,
+            Some(s) => new_entry.image = s,
             None => {} // is 0 through V1Entry::new()
         }
-        new_entry.borrow_mut().url = url;
-        new_entry.borrow_mut().comment = comment;
+        new_entry.url = url;
+        new_entry.comment = comment;
         match username {
-            Some(s) => new_entry.borrow_mut().username = Some(SecureString::new(s)),
+            Some(s) => new_entry.username = Some(SecureString::new(s)),
             None => {}
         };
         match password {
-            Some(s) => new_entry.borrow_mut().password = Some(SecureString::new(s)),
+            Some(s) => new_entry.password = Some(SecureString::new(s)),
             None => {}
         };
 
-        self.entries.push(new_entry);
-        self.header.num_entries += 1;
+        Ok(self.push_entry(new_entry, Some(group)))
     }
 
     /// Remove a group
     ///
     /// * group: The group to remove
     ///
-    /// Note: Entries and children of the group are deleted, too.
+    /// Note: Entries and children of the group are deleted, too. Their
+    /// sensitive data is overwritten when they are dropped at the end
+    /// of this function.
     ///
-    /// The group should be given to the function as a move. If this is done, the rc counter
-    /// is 0 at the end of the function and therefore sensitive data is deleted correctly.
-    pub fn remove_group(&mut self, group: Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
-        // Sensitive data (e.g. SecureString) is automatically dropped at the end of this
-        // function as Rc is 0 then
-        try!(self.remove_group_from_db(&group));
-        try!(self.remove_entries(&group));
-        if let Some(ref parent) = group.borrow().parent {
-            try!(parent.borrow_mut().drop_weak_child_reference(&group));
-            drop(parent);
+    /// The group and its subtree are found through the arena, but
+    /// closing the gaps in groups and entries, which have to stay in
+    /// the order of the file, is linear in their length.
+    pub fn remove_group(&mut self, group: GroupId) -> Result<(), V1KpdbError> {
+        if group == self.root_group || self.group(group).is_none() {
+            return Err(V1KpdbError::IndexErr);
         }
-        try!(self.remove_children(&group));
+        let mut removed_groups: Vec<GroupId> = vec![];
+        let mut removed_entries: HashSet<EntryId> = HashSet::new();
+        let mut stack = vec![group];
+        while let Some(current) = stack.pop() {
+            let current_group = &self[current];
+            stack.extend(current_group.children.iter().cloned());
+            removed_entries.extend(current_group.entries.iter().cloned());
+            removed_groups.push(current);
+        }
+
+        if let Some(parent) = self[group].parent {
+            self[parent].children.retain(|&child| child != group);
+        }
+        // Every group is followed by its subgroups, so the subtree is
+        // a single range of groups
+        let start = try!(self.groups.iter().position(|&g| g == group).ok_or(V1KpdbError::IndexErr));
+        self.groups.drain(start..start + removed_groups.len());
+        self.entries.retain(|entry| !removed_entries.contains(entry));
+        for removed in removed_groups {
+            self.drop_group(removed);
+        }
+        for removed in removed_entries {
+            self.drop_entry(removed);
+        }
+        self.header.num_groups = self.groups.len() as u32;
+        self.header.num_entries = self.entries.len() as u32;
         Ok(())
     }
 
-    fn remove_group_from_db(&mut self, group: &Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
-        let index = try!(self.groups.get_index(group));
-        let db_reference = self.groups.remove(index);
-        drop(db_reference);
-        self.header.num_groups -= 1;
+    /// Remove an entry
+    ///
+    /// * entry: The entry to remove.
+    ///
+    /// Note: Sensitive data of the entry is overwritten when it's
+    /// dropped at the end of this function.
+    ///
+    /// The entry is found through the arena, but removing it from
+    /// entries, which has to stay in the order of the file, is linear
+    /// in the number of entries.
+    pub fn remove_entry(&mut self, entry: EntryId) -> Result<(), V1KpdbError> {
+        let group = match self.entry(entry) {
+            Some(removed) => removed.group,
+            None => return Err(V1KpdbError::IndexErr),
+        };
+        self.entries.retain(|&e| e != entry);
+        if let Some(group) = group.and_then(|group| self.group_mut(group)) {
+            group.entries.retain(|&e| e != entry);
+        }
+        self.drop_entry(entry);
+        self.header.num_entries = self.entries.len() as u32;
         Ok(())
     }
 
-    fn remove_entry_from_db(&mut self, entry: &Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
-        let index = try!(self.entries.get_index(entry));
-        let db_reference = self.entries.remove(index);
-        drop(db_reference);
-        self.header.num_entries -= 1;
+    /// Move an entry into another group. The entry keeps its UUID
+    /// and creation date, its last_mod is set to now.
+    ///
+    /// * entry: the entry to move
+    ///
+    /// * group: the new group of the entry
+    ///
+    /// Returns IndexErr if the entry or the group isn't in the
+    /// database.
+    pub fn move_entry(&mut self, entry: EntryId, group: GroupId) -> Result<(), V1KpdbError> {
+        if self.entry(entry).is_none() || group == self.root_group || self.group(group).is_none() {
+            return Err(V1KpdbError::IndexErr);
+        }
+        if let Some(old) = self[entry].group.and_then(|old| self.group_mut(old)) {
+            old.entries.retain(|&e| e != entry);
+        }
+        let group_id = self[group].id;
+        self[group].entries.push(entry);
+        let entry = &mut self[entry];
+        entry.group_id = group_id;
+        entry.group = Some(group);
+        entry.last_mod = Local::now();
         Ok(())
     }
 
-    fn remove_entries(&mut self, group: &Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
-        // Clone needed to prevent thread panning through borrowing
-        let entries = group.borrow().entries.clone();
-        for entry in entries {
-            if let Some(entry_strong) = entry.upgrade() {
-                try!(self.remove_entry(entry_strong));
-            } else {
-                return Err(V1KpdbError::WeakErr);
+    /// Move a group with its subgroups and entries to another parent.
+    /// The levels of the subtree are recomputed and the groups are
+    /// reordered so that every group is still followed by its
+    /// subgroups. The group keeps its id and creation date, its
+    /// last_mod is set to now.
+    ///
+    /// * group: the group to move
+    ///
+    /// * parent: the new parent group, None means the root group
+    ///
+    /// * position: index of the group among the subgroups of parent
+    ///             (not counting the group itself), larger values
+    ///             append it
+    ///
+    /// Returns IndexErr if a group isn't in the database and MoveErr
+    /// if parent is the group itself or one of its subgroups.
+    pub fn move_group(&mut self,
+                      group: GroupId,
+                      parent: Option<GroupId>,
+                      position: usize)
+                      -> Result<(), V1KpdbError> {
+        let parent = parent.unwrap_or(self.root_group);
+        if group == self.root_group || self.group(group).is_none() || self.group(parent).is_none() {
+            return Err(V1KpdbError::IndexErr);
+        }
+        let mut ancestor = Some(parent);
+        while let Some(current) = ancestor {
+            if current == group {
+                return Err(V1KpdbError::MoveErr);
             }
+            ancestor = self[current].parent;
+        }
+
+        // The subtree follows the group in the file order
+        let start = try!(self.groups.iter().position(|&g| g == group).ok_or(V1KpdbError::IndexErr));
+        let level = self[group].level;
+        let end = start + 1 +
+                  self.groups[start + 1..].iter().take_while(|&&g| self[g].level > level).count();
+        let subtree: Vec<GroupId> = self.groups.drain(start..end).collect();
+
+        if let Some(old) = self[group].parent {
+            self[old].children.retain(|&child| child != group);
+        }
+        let new_level = if parent == self.root_group { 0 } else { self[parent].level + 1 };
+        for &moved in subtree.iter() {
+            let moved = &mut self[moved];
+            moved.level = moved.level + new_level - level;
+        }
+
+        // Insert after the subtree of the previous sibling, or right
+        // after the parent for the first one
+        let siblings: Vec<GroupId> = self.groups.iter().cloned().filter(|&g| self[g].parent == Some(parent)).collect();
+        let position = position.min(siblings.len());
+        let index = if position == 0 {
+            self.groups.iter().position(|&g| g == parent).map_or(0, |index| index + 1)
+        } else {
+            let previous = siblings[position - 1];
+            let previous_level = self[previous].level;
+            let index = self.groups.iter().position(|&g| g == previous).unwrap() + 1;
+            index + self.groups[index..].iter().take_while(|&&g| self[g].level > previous_level).count()
+        };
+        for (offset, moved) in subtree.into_iter().enumerate() {
+            self.groups.insert(index + offset, moved);
         }
+
+        let mut children = siblings;
+        children.insert(position, group);
+        self[parent].children = children;
+        let group = &mut self[group];
+        group.parent = Some(parent);
+        group.last_mod = Local::now();
         Ok(())
     }
 
-    fn remove_children(&mut self, group: &Rc<RefCell<V1Group>>) -> Result<(), V1KpdbError> {
-        // Clone needed to prevent thread panning through borrowing
-        let children = group.borrow().children.clone();
-        for child in children {
-            if let Some(child_strong) = child.upgrade() {
-                try!(self.remove_group(child_strong));
-            } else {
-                return Err(V1KpdbError::WeakErr);
-            }
+    // Drop all groups and entries
+    pub(crate) fn clear(&mut self) {
+        self.group_arena = Arena::new();
+        self.entry_arena = Arena::new();
+        self.root_group = GroupId(self.group_arena.insert(V1Group::new()));
+        self.groups.clear();
+        self.entries.clear();
+        self.group_index.clear();
+        self.entry_index.clear();
+        self.header.num_groups = 0;
+        self.header.num_entries = 0;
+    }
+
+    // Append a group to groups as the last child of parent. The caller
+    // has to add the groups in the order of the tree.
+    pub(crate) fn push_group(&mut self, group: V1Group, parent: GroupId) -> GroupId {
+        let index = self.groups.len();
+        self.insert_group(group, parent, index)
+    }
+
+    // Add a group as the last child of parent at index of groups
+    fn insert_group(&mut self, mut group: V1Group, parent: GroupId, index: usize) -> GroupId {
+        group.children.clear();
+        group.entries.clear();
+        let group_id = group.id;
+        let id = GroupId(self.group_arena.insert(group));
+        self.group_index.insert(group_id, id);
+        self.link_group(id, parent, index);
+        id
+    }
+
+    // Append an entry to entries and the entries of group. An entry
+    // without group keeps its group_id.
+    pub(crate) fn push_entry(&mut self, entry: V1Entry, group: Option<GroupId>) -> EntryId {
+        let uuid = entry.uuid;
+        let id = EntryId(self.entry_arena.insert(entry));
+        self.entry_index.insert(uuid, id);
+        self.link_entry(id, group);
+        id
+    }
+
+    // Make a group of the arena the last child of parent and insert it
+    // at index of groups
+    pub(crate) fn link_group(&mut self, group: GroupId, parent: GroupId, index: usize) {
+        let level = if parent == self.root_group { 0 } else { self[parent].level + 1 };
+        self[group].level = level;
+        self[group].parent = Some(parent);
+        self[parent].children.push(group);
+        self.groups.insert(index, group);
+        self.header.num_groups = self.groups.len() as u32;
+    }
+
+    // Append an entry of the arena to entries and the entries of group
+    pub(crate) fn link_entry(&mut self, entry: EntryId, group: Option<GroupId>) {
+        if let Some(group) = group {
+            let group_id = self[group].id;
+            self[group].entries.push(entry);
+            self[entry].group_id = group_id;
         }
-        Ok(())
+        self[entry].group = group;
+        self.entries.push(entry);
+        self.header.num_entries = self.entries.len() as u32;
     }
 
-    /// Remove a group
-    ///
-    /// * entry: The entry to remove.
-    ///
-    /// Note: The entry should be given to the function as a move. If this is done, the rc counter
-    /// is 0 at the end of the function and therefore sensitive data is deleted correctly.
-    pub fn remove_entry(&mut self, entry: Rc<RefCell<V1Entry>>) -> Result<(), V1KpdbError> {
-        // Sensitive data (e.g. SecureString) is automatically dropped at the end of this
-        // function as Rc is 0 then
-        try!(self.remove_entry_from_db(&entry));
+    // Drop a group which isn't linked anymore
+    pub(crate) fn drop_group(&mut self, group: GroupId) {
+        if let Some(removed) = self.group_arena.remove(group.0) {
+            if self.group_index.get(&removed.id) == Some(&group) {
+                self.group_index.remove(&removed.id);
+            }
+        }
+    }
 
-        if let Some(ref group) = entry.borrow().group {
-            try!(group.borrow_mut().drop_weak_entry_reference(&entry));
-            drop(group);
+    // Drop an entry which isn't linked anymore
+    pub(crate) fn drop_entry(&mut self, entry: EntryId) {
+        if let Some(removed) = self.entry_arena.remove(entry.0) {
+            if self.entry_index.get(&removed.uuid) == Some(&entry) {
+                self.entry_index.remove(&removed.uuid);
+            }
         }
-        Ok(())
+    }
+}
+
+impl Index<GroupId> for V1Kpdb {
+    type Output = V1Group;
+
+    /// Panics if the group was removed
+    fn index(&self, group: GroupId) -> &V1Group {
+        self.group(group).expect("group was removed")
+    }
+}
+
+impl IndexMut<GroupId> for V1Kpdb {
+    fn index_mut(&mut self, group: GroupId) -> &mut V1Group {
+        self.group_mut(group).expect("group was removed")
+    }
+}
+
+impl Index<EntryId> for V1Kpdb {
+    type Output = V1Entry;
+
+    /// Panics if the entry was removed
+    fn index(&self, entry: EntryId) -> &V1Entry {
+        self.entry(entry).expect("entry was removed")
+    }
+}
+
+impl IndexMut<EntryId> for V1Kpdb {
+    fn index_mut(&mut self, entry: EntryId) -> &mut V1Entry {
+        self.entry_mut(entry).expect("entry was removed")
     }
 }
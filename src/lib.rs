@@ -8,8 +8,23 @@ extern crate rustc_serialize;
 extern crate chrono;
 extern crate rand;
 extern crate uuid;
+extern crate twofish;
+extern crate salsa20;
+extern crate chacha20;
+extern crate argon2;
+extern crate flate2;
+extern crate xml;
+extern crate regex;
+#[cfg(feature = "secret-service")]
+extern crate dbus;
 
 pub mod sec_str;
 pub mod kpdb;
+pub mod kdbx;
 pub mod common;
+pub mod agent;
+pub mod ssh_agent;
+pub mod fuse;
+#[cfg(feature = "secret-service")]
+pub mod secret_service;
 
@@ -1,6 +1,7 @@
 use libc::{c_void, mlock, munlock, size_t};
 use openssl::symm;
 use rand;
+use std::str;
 
 use common::common::write_array_volatile;
 
@@ -83,6 +84,36 @@ impl SecureString {
                                                       Some(&self.iv),
                                                       &self.encrypted_string).expect("Can't decrypt string!?")).unwrap();
     }
+
+    /// Call f with the decrypted string. Other than unlock this works on
+    /// a shared reference, the string is decrypted into a locked copy
+    /// which is overwritten with zeroes as soon as f returns.
+    /// Returns None without calling f if the string can't be decrypted,
+    /// which only happens if the encrypted string was corrupted.
+    pub fn with_unlocked<T, F: FnOnce(&str) -> T>(&self, f: F) -> Option<T> {
+        let cipher = symm::Cipher::aes_256_cbc();
+        // Lock the copy before anything is decrypted into it. It doesn't
+        // grow, the plain text is never longer than the encrypted one.
+        let mut plain = vec![0u8; self.encrypted_string.len() + cipher.block_size()];
+        unsafe {
+            mlock(plain.as_ptr() as *const c_void, plain.len() as size_t);
+        }
+        let length = symm::Crypter::new(cipher, symm::Mode::Decrypt, &self.password, Some(&self.iv))
+            .and_then(|mut crypter| {
+                let count = try!(crypter.update(&self.encrypted_string, &mut plain));
+                let rest = try!(crypter.finalize(&mut plain[count..]));
+                Ok(count + rest)
+            });
+        let result = match length {
+            Ok(length) => str::from_utf8(&plain[..length]).ok().map(f),
+            Err(_) => None,
+        };
+        unsafe {
+            write_array_volatile(plain.as_mut_ptr(), 0u8, plain.len());
+            munlock(plain.as_ptr() as *const c_void, plain.len() as size_t);
+        }
+        result
+    }
 }
 
 // string value and encrypted_string value will be overwritten with zeroes after drop of struct
@@ -157,6 +188,22 @@ mod tests {
         assert_eq!(sec_str.string, "delete");
     }
 
+    #[test]
+    fn test_with_unlocked() {
+        let str = "unlocked".to_string();
+        let sec_str = SecureString::new(str);
+        assert_eq!(sec_str.with_unlocked(|string| string.to_string()), Some("unlocked".to_string()));
+        assert_eq!(sec_str.string, "\0\0\0\0\0\0\0\0");
+    }
+
+    #[test]
+    fn test_with_unlocked_corrupted() {
+        let str = "corrupted".to_string();
+        let mut sec_str = SecureString::new(str);
+        sec_str.encrypted_string = vec![1u8; 5];
+        assert_eq!(sec_str.with_unlocked(|string| string.to_string()), None);
+    }
+
     #[test]
     fn test_encryption() {
         let str = "delete".to_string();
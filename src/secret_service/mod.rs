@@ -0,0 +1,6 @@
+pub mod secret_error;
+pub mod service;
+mod session;
+
+#[cfg(test)]
+mod tests_secret_service;
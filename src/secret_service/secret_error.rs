@@ -0,0 +1,32 @@
+use std::fmt;
+use std::error;
+
+pub use self::SecretServiceError::*;
+
+#[doc = "
+Use this for catching various errors that
+can happen when running a SecretService.
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum SecretServiceError {
+    /// Couldn't connect to the bus
+    BusErr,
+    /// Another program owns org.freedesktop.secrets already,
+    /// e.g. gnome-keyring or KeePassXC
+    NameErr,
+}
+
+impl fmt::Display for SecretServiceError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for SecretServiceError {
+    fn description(&self) -> &str {
+        match *self {
+            BusErr => "Couldn't connect to the D-Bus session bus",
+            NameErr => "Another Secret Service is running already",
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::Path as FilePath;
+use std::time::Duration;
+
+use dbus::arg::{ArgType, PropMap, RefArg, Variant};
+use dbus::blocking::LocalConnection;
+use dbus::message::MessageType;
+use dbus::strings::{ErrorName, Path};
+use dbus::Message;
+
+use common::common::write_array_volatile;
+use kpdb::v1entry::{EntryId, V1Entry};
+use kpdb::v1kpdb::V1Kpdb;
+use secret_service::secret_error::SecretServiceError;
+use secret_service::session::Session;
+use super::super::sec_str::SecureString;
+
+pub const BUS_NAME: &'static str = "org.freedesktop.secrets";
+pub const SERVICE_PATH: &'static str = "/org/freedesktop/secrets";
+pub const COLLECTION_PATH: &'static str = "/org/freedesktop/secrets/collection/keepass";
+pub const DEFAULT_ALIAS_PATH: &'static str = "/org/freedesktop/secrets/aliases/default";
+const SESSION_PREFIX: &'static str = "/org/freedesktop/secrets/session/";
+
+const SERVICE: &'static str = "org.freedesktop.Secret.Service";
+const COLLECTION: &'static str = "org.freedesktop.Secret.Collection";
+const ITEM: &'static str = "org.freedesktop.Secret.Item";
+const SESSION: &'static str = "org.freedesktop.Secret.Session";
+const PROPERTIES: &'static str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE: &'static str = "org.freedesktop.DBus.Introspectable";
+
+const NO_SESSION: &'static str = "org.freedesktop.Secret.Error.NoSession";
+const NO_SUCH_OBJECT: &'static str = "org.freedesktop.Secret.Error.NoSuchObject";
+const NOT_SUPPORTED: &'static str = "org.freedesktop.DBus.Error.NotSupported";
+const INVALID_ARGS: &'static str = "org.freedesktop.DBus.Error.InvalidArgs";
+const UNKNOWN_METHOD: &'static str = "org.freedesktop.DBus.Error.UnknownMethod";
+
+// Attribute searched by libsecret for its schemas, our items have none
+const SCHEMA_ATTRIBUTE: &'static str = "xdg:schema";
+
+// An error name and message for the caller
+type Reply = Result<Message, (&'static str, &'static str)>;
+
+// Session, parameters, value and content type of a secret
+type Secret = (Path<'static>, Vec<u8>, Vec<u8>, String);
+
+enum Object {
+    Service,
+    Collection,
+    Item(EntryId),
+    Session(String),
+}
+
+#[doc = "
+Offers a loaded V1Kpdb as the default collection of the freedesktop.org
+Secret Service API, so that programs using libsecret find their
+passwords in it. Every entry is an item labelled with its title, with
+the attributes title, url, username, group (the titles of its groups
+separated by slashes) and uuid; its secret is the password.
+
+The collection is read-only and always unlocked. Secrets go out either
+plain or encrypted with AES-128 using a key agreed on with the
+client by Diffie-Hellman (dh-ietf1024-sha256-aes128-cbc-pkcs7).
+"]
+pub struct SecretService {
+    /// Label of the collection, the file name of the database by default
+    pub label: String,
+    db: V1Kpdb,
+    sessions: HashMap<String, Session>,
+    next_session: u32,
+}
+
+impl SecretService {
+    /// Create a provider for a loaded database
+    pub fn new(db: V1Kpdb) -> SecretService {
+        let label = FilePath::new(&db.path)
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| "KeePass".to_string());
+        SecretService {
+            label: label,
+            db: db,
+            sessions: HashMap::new(),
+            next_session: 0,
+        }
+    }
+
+    /// Connect to the session bus, or the bus at address, and claim
+    /// org.freedesktop.secrets. Returns NameErr if another Secret
+    /// Service owns the name.
+    pub fn connect(address: Option<&str>) -> Result<LocalConnection, SecretServiceError> {
+        let conn = try!(match address {
+                            Some(address) => LocalConnection::new_address(address),
+                            None => LocalConnection::new_session(),
+                        }
+                        .map_err(|_| SecretServiceError::BusErr));
+        match conn.request_name(BUS_NAME, false, false, true) {
+            Ok(::dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply::PrimaryOwner) => Ok(conn),
+            Ok(_) => Err(SecretServiceError::NameErr),
+            Err(_) => Err(SecretServiceError::BusErr),
+        }
+    }
+
+    /// Connect to the session bus and answer requests forever
+    pub fn run(&mut self) -> Result<(), SecretServiceError> {
+        let conn = try!(SecretService::connect(None));
+        loop {
+            try!(self.process(&conn, Duration::from_secs(3600)));
+        }
+    }
+
+    /// Answer the next request on conn, waiting up to timeout for it.
+    /// Returns false if none came.
+    pub fn process(&mut self, conn: &LocalConnection, timeout: Duration) -> Result<bool, SecretServiceError> {
+        let msg = match conn.channel().blocking_pop_message(timeout) {
+            Ok(Some(msg)) => msg,
+            Ok(None) => return Ok(false),
+            Err(_) => return Err(SecretServiceError::BusErr),
+        };
+        if msg.msg_type() != MessageType::MethodCall {
+            return Ok(true);
+        }
+        let reply = match self.answer(&msg) {
+            Ok(reply) => reply,
+            Err((name, text)) => {
+                msg.error(&ErrorName::from(name), &CString::new(text).expect("Invalid error message!?"))
+            }
+        };
+        if !msg.get_no_reply() {
+            try!(conn.channel().send(reply).map_err(|_| SecretServiceError::BusErr));
+        }
+        Ok(true)
+    }
+
+    fn answer(&mut self, msg: &Message) -> Reply {
+        let path = msg.path().map(|path| path.to_string()).unwrap_or_default();
+        let interface = msg.interface().map(|interface| interface.to_string()).unwrap_or_default();
+        let member = msg.member().map(|member| member.to_string()).unwrap_or_default();
+        let object = try!(self.object(&path).ok_or((NO_SUCH_OBJECT, "No such object")));
+
+        match (&interface[..], &member[..], object) {
+            (INTROSPECTABLE, "Introspect", object) => Ok(msg.method_return().append1(self.introspect(&path, &object))),
+            (PROPERTIES, "Get", object) => {
+                let (_, name): (&str, &str) = try!(msg.read2().map_err(|_| (INVALID_ARGS, "Expected (ss)")));
+                let mut properties = self.properties(&object);
+                let value = try!(properties.remove(name).ok_or((INVALID_ARGS, "No such property")));
+                Ok(msg.method_return().append1(value))
+            }
+            (PROPERTIES, "GetAll", object) => Ok(msg.method_return().append1(self.properties(&object))),
+            (PROPERTIES, "Set", _) => Err((NOT_SUPPORTED, "The collection is read-only")),
+
+            (SERVICE, "OpenSession", Object::Service) => self.open_session(msg),
+            (SERVICE, "SearchItems", Object::Service) |
+            (COLLECTION, "SearchItems", Object::Collection) => {
+                let attributes: HashMap<String, String> = try!(msg.read1()
+                                                                  .map_err(|_| (INVALID_ARGS, "Expected a{ss}")));
+                let found = self.search(&attributes);
+                if interface == SERVICE {
+                    Ok(msg.method_return().append2(found, Vec::<Path>::new()))
+                } else {
+                    Ok(msg.method_return().append1(found))
+                }
+            }
+            (SERVICE, "Unlock", Object::Service) => {
+                // Everything is unlocked all the time
+                let objects: Vec<Path> = try!(msg.read1().map_err(|_| (INVALID_ARGS, "Expected ao")));
+                Ok(msg.method_return().append2(objects, Path::from("/")))
+            }
+            (SERVICE, "Lock", Object::Service) => Ok(msg.method_return().append2(Vec::<Path>::new(), Path::from("/"))),
+            (SERVICE, "GetSecrets", Object::Service) => {
+                let (items, session): (Vec<Path>, Path) = try!(msg.read2()
+                                                                   .map_err(|_| (INVALID_ARGS, "Expected (aoo)")));
+                let mut secrets = HashMap::new();
+                for item in items {
+                    if let Some(Object::Item(entry)) = self.object(&item) {
+                        secrets.insert(item.clone(), try!(self.secret(&session, entry)));
+                    }
+                }
+                Ok(msg.method_return().append1(secrets))
+            }
+            (SERVICE, "ReadAlias", Object::Service) => {
+                let alias: &str = try!(msg.read1().map_err(|_| (INVALID_ARGS, "Expected s")));
+                let path = if alias == "default" { COLLECTION_PATH } else { "/" };
+                Ok(msg.method_return().append1(Path::from(path)))
+            }
+            (SERVICE, "CreateCollection", _) |
+            (SERVICE, "SetAlias", _) |
+            (COLLECTION, "Delete", _) |
+            (COLLECTION, "CreateItem", _) |
+            (ITEM, "Delete", _) |
+            (ITEM, "SetSecret", _) => Err((NOT_SUPPORTED, "The collection is read-only")),
+
+            (ITEM, "GetSecret", Object::Item(entry)) => {
+                let session: Path = try!(msg.read1().map_err(|_| (INVALID_ARGS, "Expected o")));
+                let secret = try!(self.secret(&session, entry));
+                Ok(msg.method_return().append1(secret))
+            }
+            (SESSION, "Close", Object::Session(name)) => {
+                self.sessions.remove(&name);
+                Ok(msg.method_return())
+            }
+            _ => Err((UNKNOWN_METHOD, "Unknown method")),
+        }
+    }
+
+    fn object(&self, path: &str) -> Option<Object> {
+        if path == SERVICE_PATH {
+            return Some(Object::Service);
+        }
+        if path == COLLECTION_PATH || path == DEFAULT_ALIAS_PATH {
+            return Some(Object::Collection);
+        }
+        if let Some(name) = path.strip_prefix(SESSION_PREFIX) {
+            let name = name.to_string();
+            return if self.sessions.contains_key(&name) { Some(Object::Session(name)) } else { None };
+        }
+        let collection = format!("{}/", COLLECTION_PATH);
+        if path.starts_with(&collection) {
+            let uuid = &path[collection.len()..];
+            return self.db
+                       .entries
+                       .iter()
+                       .find(|&&entry| self.db[entry].uuid.simple().to_string() == uuid)
+                       .map(|&entry| Object::Item(entry));
+        }
+        None
+    }
+
+    fn open_session(&mut self, msg: &Message) -> Reply {
+        let mut args = msg.iter_init();
+        let algorithm: String = try!(args.read().map_err(|_| (INVALID_ARGS, "Expected (sv)")));
+        let input: Vec<u8> = args.recurse(ArgType::Variant).and_then(|mut variant| variant.get()).unwrap_or_default();
+        let (session, output) = try!(Session::open(&algorithm, &input)
+                                         .ok_or((NOT_SUPPORTED, "Algorithm isn't supported")));
+        let output: Box<dyn RefArg> = match session {
+            Session::Plain => Box::new(String::new()),
+            Session::Aes { .. } => Box::new(output),
+        };
+        self.next_session += 1;
+        let name = self.next_session.to_string();
+        self.sessions.insert(name.clone(), session);
+        let path = Path::from(format!("{}{}", SESSION_PREFIX, name));
+        Ok(msg.method_return().append2(Variant(output), path))
+    }
+
+    fn search(&self, attributes: &HashMap<String, String>) -> Vec<Path<'static>> {
+        self.db
+            .entries
+            .iter()
+            .filter(|&&entry| {
+                let mut values = item_attributes(&self.db, &self.db[entry]);
+                let found = attributes.iter()
+                                      .all(|(name, value)| {
+                                          name == SCHEMA_ATTRIBUTE || values.get(name) == Some(value)
+                                      });
+                for (_, value) in values.drain() {
+                    wipe(value);
+                }
+                found
+            })
+            .map(|&entry| item_path(&self.db[entry]))
+            .collect()
+    }
+
+    // Sensitive data in this function:
+    //
+    // * the password of the entry
+    //
+    // At the end of this function:
+    //
+    // * the password is overwritten, only the secret for the session
+    //   (encrypted if the session isn't plain) is returned
+    fn secret(&self,
+              session: &Path,
+              entry: EntryId)
+              -> Result<Secret, (&'static str, &'static str)> {
+        let session_obj = try!(session.strip_prefix(SESSION_PREFIX)
+                                      .and_then(|name| self.sessions.get(name))
+                                      .ok_or((NO_SESSION, "No such session")));
+        let password = reveal(&self.db[entry].password);
+        let (parameters, value) = session_obj.encrypt(password.as_bytes());
+        wipe(password);
+        Ok((Path::from(session.to_string()), parameters, value, "text/plain; charset=utf8".to_string()))
+    }
+
+    fn properties(&self, object: &Object) -> PropMap {
+        let mut properties = PropMap::new();
+        let mut add = |name: &str, value: Box<dyn RefArg>| {
+            properties.insert(name.to_string(), Variant(value));
+        };
+        match *object {
+            Object::Service => {
+                add("Collections", Box::new(vec![Path::from(COLLECTION_PATH)]));
+            }
+            Object::Collection => {
+                let items: Vec<Path> = self.db.entries.iter().map(|&entry| item_path(&self.db[entry])).collect();
+                let created = self.db.entries.iter().map(|&entry| self.db[entry].creation.timestamp()).min();
+                let modified = self.db.entries.iter().map(|&entry| self.db[entry].last_mod.timestamp()).max();
+                add("Items", Box::new(items));
+                add("Label", Box::new(self.label.clone()));
+                add("Locked", Box::new(false));
+                add("Created", Box::new(created.unwrap_or(0) as u64));
+                add("Modified", Box::new(modified.unwrap_or(0) as u64));
+            }
+            Object::Item(entry) => {
+                let entry = &self.db[entry];
+                let attributes = item_attributes(&self.db, entry);
+                add("Locked", Box::new(false));
+                add("Attributes", Box::new(attributes));
+                add("Label", Box::new(entry.title.clone()));
+                add("Created", Box::new(entry.creation.timestamp() as u64));
+                add("Modified", Box::new(entry.last_mod.timestamp() as u64));
+            }
+            Object::Session(_) => {}
+        }
+        properties
+    }
+
+    fn introspect(&self, path: &str, object: &Object) -> String {
+        let interface = match *object {
+            Object::Service => SERVICE,
+            Object::Collection => COLLECTION,
+            Object::Item(_) => ITEM,
+            Object::Session(_) => SESSION,
+        };
+        let mut xml = format!("<node><interface name=\"{}\"/><interface name=\"{}\"/><interface name=\"{}\"/>",
+                              interface,
+                              PROPERTIES,
+                              INTROSPECTABLE);
+        let children: Vec<String> = match *object {
+            Object::Service => vec!["collection".to_string(), "aliases".to_string()],
+            Object::Collection if path == COLLECTION_PATH => {
+                self.db.entries.iter().map(|&entry| self.db[entry].uuid.simple().to_string()).collect()
+            }
+            _ => vec![],
+        };
+        for child in children {
+            xml.push_str(&format!("<node name=\"{}\"/>", child));
+        }
+        xml.push_str("</node>");
+        xml
+    }
+}
+
+fn item_path(entry: &V1Entry) -> Path<'static> {
+    Path::from(format!("{}/{}", COLLECTION_PATH, entry.uuid.simple()))
+}
+
+fn item_attributes(db: &V1Kpdb, entry: &V1Entry) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    attributes.insert("title".to_string(), entry.title.clone());
+    attributes.insert("url".to_string(), entry.url.clone().unwrap_or_default());
+    attributes.insert("username".to_string(), reveal(&entry.username));
+    attributes.insert("group".to_string(),
+                      entry.group.map(|group| db.group_path(group)).unwrap_or_default());
+    attributes.insert("uuid".to_string(), entry.uuid.simple().to_string());
+    attributes
+}
+
+fn wipe(string: String) {
+    unsafe { write_array_volatile(string.as_ptr() as *mut u8, 0u8, string.len()) };
+}
+
+fn reveal(string: &Option<SecureString>) -> String {
+    match *string {
+        Some(ref string) => string.with_unlocked(|string| string.to_string()).unwrap_or_default(),
+        None => "".to_string(),
+    }
+}
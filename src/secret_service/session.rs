@@ -0,0 +1,91 @@
+use openssl::bn::BigNum;
+use openssl::dh::Dh;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm;
+use rand;
+
+pub const PLAIN: &'static str = "plain";
+pub const DH_AES: &'static str = "dh-ietf1024-sha256-aes128-cbc-pkcs7";
+
+// Second Oakley group of RFC 2409, the group the Secret Service API
+// prescribes for dh-ietf1024-sha256-aes128-cbc-pkcs7
+pub const OAKLEY_GROUP_2: &'static str = "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD1\
+                                          29024E088A67CC74020BBEA63B139B22514A08798E3404DD\
+                                          EF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245\
+                                          E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+                                          EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE65381\
+                                          FFFFFFFFFFFFFFFF";
+pub const PRIME_LEN: usize = 128;
+
+// How secrets are transferred to a client, negotiated in OpenSession
+pub enum Session {
+    // Unencrypted, the bus is trusted
+    Plain,
+    // AES-128 with a key agreed on by Diffie-Hellman
+    Aes { key: Vec<u8> },
+}
+
+impl Session {
+    // Start a session with the algorithm and input of the client.
+    // Returns the session and the output for the client, None if the
+    // algorithm isn't supported.
+    pub fn open(algorithm: &str, input: &[u8]) -> Option<(Session, Vec<u8>)> {
+        match algorithm {
+            PLAIN => Some((Session::Plain, vec![])),
+            DH_AES => agree(input).map(|(key, public_key)| (Session::Aes { key: key }, public_key)),
+            _ => None,
+        }
+    }
+
+    // Returns the parameters (the IV) and the encrypted secret
+    pub fn encrypt(&self, secret: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        match *self {
+            Session::Plain => (vec![], secret.to_vec()),
+            Session::Aes { ref key } => {
+                let iv: Vec<u8> = (0..16).map(|_| rand::random::<u8>()).collect();
+                let value = symm::encrypt(symm::Cipher::aes_128_cbc(), key, Some(&iv), secret)
+                                .expect("Can't encrypt secret!?");
+                (iv, value)
+            }
+        }
+    }
+}
+
+// Diffie-Hellman with the public key of the client, returns the AES key
+// and our public key
+fn agree(client_key: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let prime = BigNum::from_hex_str(OAKLEY_GROUP_2).expect("Invalid prime!?");
+    let generator = BigNum::from_u32(2).expect("Invalid generator!?");
+    let dh = match Dh::from_pqg(prime, None, generator).and_then(|dh| dh.generate_key()) {
+        Ok(dh) => dh,
+        Err(_) => return None,
+    };
+    let client_key = match BigNum::from_slice(client_key) {
+        Ok(key) => key,
+        Err(_) => return None,
+    };
+    let shared = match dh.compute_key(&client_key) {
+        Ok(shared) => shared,
+        Err(_) => return None,
+    };
+    // The shared secret is used with the full length of the prime
+    let mut padded = vec![0u8; PRIME_LEN - shared.len()];
+    padded.extend_from_slice(&shared);
+    Some((hkdf_sha256(&padded, 16), dh.public_key().to_vec()))
+}
+
+// HKDF (RFC 5869) with SHA-256, no salt and no info
+pub fn hkdf_sha256(input: &[u8], len: usize) -> Vec<u8> {
+    let hmac = |key: &[u8], data: &[u8]| {
+        let key = PKey::hmac(key).expect("Can't derive key!?");
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).expect("Can't derive key!?");
+        signer.update(data).expect("Can't derive key!?");
+        signer.sign_to_vec().expect("Can't derive key!?")
+    };
+    let prk = hmac(&[0u8; 32], input);
+    let mut okm = hmac(&prk, &[1u8]);
+    okm.truncate(len);
+    okm
+}
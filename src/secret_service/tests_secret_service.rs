@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::strings::Path;
+use openssl::bn::BigNum;
+use openssl::dh::Dh;
+use openssl::symm;
+use rustc_serialize::hex::FromHex;
+
+use kpdb::v1kpdb::V1Kpdb;
+use secret_service::service::{SecretService, BUS_NAME, COLLECTION_PATH, SERVICE_PATH};
+use secret_service::session::{hkdf_sha256, DH_AES, OAKLEY_GROUP_2, PRIME_LEN};
+
+const SERVICE: &'static str = "org.freedesktop.Secret.Service";
+const ITEM: &'static str = "org.freedesktop.Secret.Item";
+
+type Secret = (Path<'static>, Vec<u8>, Vec<u8>, String);
+
+fn load() -> V1Kpdb {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    db
+}
+
+#[test]
+fn test_hkdf_sha256() {
+    // Test case 3 of RFC 5869
+    let okm = hkdf_sha256(&[0x0bu8; 22], 16);
+    assert_eq!(okm, "8da4e775a563c18f715f802a063c5a31".from_hex().unwrap());
+}
+
+#[test]
+fn test_secret_service() {
+    // A private session bus, so that we don't meet a real Secret Service
+    let mut daemon = match Command::new("dbus-daemon")
+                               .args(["--session", "--nofork", "--print-address=1"])
+                               .stdout(Stdio::piped())
+                               .spawn() {
+        Ok(daemon) => daemon,
+        Err(_) => {
+            println!("dbus-daemon not found, skipping test_secret_service");
+            return;
+        }
+    };
+    let mut address = String::new();
+    BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+    let address = address.trim().to_string();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (ready_tx, ready_rx) = mpsc::channel();
+    let service = {
+        let address = address.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            // V1Kpdb isn't Send, so load it in the thread of the service
+            let mut service = SecretService::new(load());
+            let conn = SecretService::connect(Some(&address)).ok().unwrap();
+            ready_tx.send(()).unwrap();
+            while !stop.load(Ordering::SeqCst) {
+                service.process(&conn, Duration::from_millis(100)).ok().unwrap();
+            }
+        })
+    };
+    ready_rx.recv().unwrap();
+
+    let mut db = load();
+    let expected = {
+        let entry = db.entries[2];
+        let entry = &mut db[entry];
+        assert_eq!(entry.title, "test3");
+        let password = entry.password.as_mut().unwrap();
+        password.unlock();
+        password.string.clone()
+    };
+
+    let conn = Connection::new_address(&address).unwrap();
+    let timeout = Duration::from_secs(5);
+    let secrets = conn.with_proxy(BUS_NAME, SERVICE_PATH, timeout);
+    let (alias,): (Path,) = secrets.method_call(SERVICE, "ReadAlias", ("default",)).unwrap();
+    assert_eq!(&*alias, COLLECTION_PATH);
+
+    // Search by attributes, libsecret adds its schema
+    let mut attributes = HashMap::new();
+    attributes.insert("group", "Internet/12");
+    attributes.insert("xdg:schema", "org.freedesktop.Secret.Generic");
+    let (unlocked, locked): (Vec<Path>, Vec<Path>) = secrets.method_call(SERVICE, "SearchItems", (attributes,))
+                                                            .unwrap();
+    assert_eq!(unlocked.len(), 1);
+    assert!(locked.is_empty());
+    let item = conn.with_proxy(BUS_NAME, unlocked[0].clone(), timeout);
+    let (label,): (Variant<String>,) =
+        item.method_call("org.freedesktop.DBus.Properties", "Get", (ITEM, "Label")).unwrap();
+    assert_eq!(label.0, "test3");
+
+    // Plain session
+    let (_, session): (Variant<Box<dyn RefArg>>, Path) =
+        secrets.method_call(SERVICE, "OpenSession", ("plain", Variant(""))).unwrap();
+    let (secret,): (Secret,) = item.method_call(ITEM, "GetSecret", (session.clone(),)).unwrap();
+    assert_eq!(String::from_utf8(secret.2).unwrap(), expected);
+    let session_proxy = conn.with_proxy(BUS_NAME, session.clone(), timeout);
+    let closed: Result<(), _> = session_proxy.method_call("org.freedesktop.Secret.Session", "Close", ());
+    assert!(closed.is_ok());
+    let closed: Result<(Secret,), _> = item.method_call(ITEM, "GetSecret", (session,));
+    assert!(closed.is_err());
+
+    // Encrypted session
+    let prime = BigNum::from_hex_str(OAKLEY_GROUP_2).unwrap();
+    let dh = Dh::from_pqg(prime, None, BigNum::from_u32(2).unwrap()).unwrap().generate_key().unwrap();
+    let (output, session): (Variant<Vec<u8>>, Path) =
+        secrets.method_call(SERVICE, "OpenSession", (DH_AES, Variant(dh.public_key().to_vec()))).unwrap();
+    let shared = dh.compute_key(&BigNum::from_slice(&output.0).unwrap()).unwrap();
+    let mut padded = vec![0u8; PRIME_LEN - shared.len()];
+    padded.extend_from_slice(&shared);
+    let key = hkdf_sha256(&padded, 16);
+    let (found,): (HashMap<Path, Secret>,) =
+        secrets.method_call(SERVICE, "GetSecrets", (unlocked.clone(), session)).unwrap();
+    let secret = &found[&unlocked[0]];
+    assert!(secret.2 != expected.as_bytes());
+    let decrypted = symm::decrypt(symm::Cipher::aes_128_cbc(), &key, Some(&secret.1), &secret.2).unwrap();
+    assert_eq!(String::from_utf8(decrypted).unwrap(), expected);
+
+    // Unknown algorithms and writes are refused
+    let refused: Result<(Variant<Box<dyn RefArg>>, Path), _> =
+        secrets.method_call(SERVICE, "OpenSession", ("rot13", Variant("")));
+    assert!(refused.is_err());
+    let refused: Result<(), _> = item.method_call(ITEM, "Delete", ());
+    assert!(refused.is_err());
+
+    stop.store(true, Ordering::SeqCst);
+    service.join().unwrap();
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+}
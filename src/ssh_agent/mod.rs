@@ -0,0 +1,7 @@
+pub mod server;
+pub mod key;
+pub mod ssh_error;
+mod wire;
+
+#[cfg(test)]
+mod tests_ssh_agent;
@@ -0,0 +1,324 @@
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use agent::server::{bind, peer_uid, protect_memory, wait_for};
+use common::common::write_array_volatile;
+use kpdb::v1entry::V1Entry;
+use kpdb::v1kpdb::V1Kpdb;
+use ssh_agent::key::SshKey;
+use ssh_agent::ssh_error::SshAgentError;
+use ssh_agent::wire::{put_string, put_u32, Reader};
+
+// Messages of the OpenSSH agent protocol
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENT_SUCCESS: u8 = 6;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_AGENTC_LOCK: u8 = 22;
+const SSH_AGENTC_UNLOCK: u8 = 23;
+
+// Same limit as in OpenSSH
+const MAX_MESSAGE: usize = 256 * 1024;
+
+// Line in the comment of an entry whose attachment is offered
+const MARKER: &'static str = "ssh-agent:";
+
+#[doc = "
+An SSH agent which offers the private keys stored as attachments of
+entries to ssh, ssh-add and everything else speaking the OpenSSH agent
+protocol. An entry is used if a line of its comment says
+
+```text
+ssh-agent: yes
+```
+
+or `ssh-agent: confirm` to ask the user through askpass before every
+signature with its key. Only the keys are kept, the database itself is
+dropped once they are loaded.
+
+Like the Agent, it only talks to allowed_uids and locks itself after
+timeout without requests, dropping all keys. `ssh-add -x` locks it as
+well, `ssh-add -X` unlocks it with the password of the database (the
+passphrase given to lock it doesn't matter).
+"]
+pub struct SshAgent {
+    /// Filepath of the socket
+    pub socket_path: String,
+    /// Lock after this time without requests, None means never
+    pub timeout: Option<Duration>,
+    /// Users allowed to connect, only the owner of the agent by default
+    pub allowed_uids: Vec<u32>,
+    /// Lock all memory of the process with mlockall and disable
+    /// core dumps when the agent starts (true by default)
+    pub protect_memory: bool,
+    /// Ask before every signature, not only for the keys of entries
+    /// marked with confirm
+    pub confirm_all: bool,
+    /// Program asking the user, like ssh-askpass of OpenSSH it gets
+    /// the question as argument and SSH_ASKPASS_PROMPT=confirm in its
+    /// environment and has to exit successfully to allow the signature.
+    /// SSH_ASKPASS or ssh-askpass by default.
+    pub askpass: String,
+    path: String,
+    keyfile: Option<String>,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    // None while the agent is locked
+    keys: Option<Vec<SshKey>>,
+    last_request: Instant,
+}
+
+// What the threads answering clients need
+struct Handler {
+    confirm_all: bool,
+    askpass: String,
+    path: String,
+    keyfile: Option<String>,
+    state: Arc<Mutex<State>>,
+}
+
+/// Path of the socket if none is given: keepass-ssh-agent.sock in
+/// XDG_RUNTIME_DIR, otherwise keepass-ssh-agent-<uid>.sock in /tmp
+pub fn default_socket_path() -> String {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => format!("{}/keepass-ssh-agent.sock", dir),
+        Err(_) => format!("/tmp/keepass-ssh-agent-{}.sock", unsafe { libc::geteuid() }),
+    }
+}
+
+/// The keys of all entries marked for the SshAgent, the comment of a
+/// key is the path of its entry
+pub fn load_keys(db: &V1Kpdb) -> Result<Vec<SshKey>, SshAgentError> {
+    let mut keys = vec![];
+    for &id in &db.entries {
+        let entry = &db[id];
+        let confirm = match marker(entry) {
+            Some(confirm) => confirm,
+            None => continue,
+        };
+        let binary = try!(entry.binary.as_ref().ok_or(SshAgentError::KeyErr));
+        let mut key = try!(SshKey::from_bytes(binary, db.entry_path(id)));
+        key.confirm = confirm;
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+impl SshAgent {
+    /// Create an agent with the keys of a loaded database, which is
+    /// dropped afterwards. keyfile is used together with the password
+    /// given to unlock the agent.
+    pub fn new(db: V1Kpdb, keyfile: Option<String>, socket_path: String) -> Result<SshAgent, SshAgentError> {
+        let keys = try!(load_keys(&db));
+        Ok(SshAgent {
+            socket_path: socket_path,
+            timeout: None,
+            allowed_uids: vec![unsafe { libc::geteuid() }],
+            protect_memory: true,
+            confirm_all: false,
+            askpass: env::var("SSH_ASKPASS").unwrap_or_else(|_| "ssh-askpass".to_string()),
+            path: db.path.clone(),
+            keyfile: keyfile,
+            state: Arc::new(Mutex::new(State {
+                keys: Some(keys),
+                last_request: Instant::now(),
+            })),
+        })
+    }
+
+    /// Comments of the keys offered, i.e. the paths of their entries,
+    /// none while the agent is locked
+    pub fn key_comments(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        match state.keys {
+            Some(ref keys) => keys.iter().map(|key| key.comment.clone()).collect(),
+            None => vec![],
+        }
+    }
+
+    /// True if the keys are dropped until the agent is unlocked
+    pub fn is_locked(&self) -> bool {
+        self.state.lock().unwrap().keys.is_none()
+    }
+
+    /// Listen on socket_path and answer requests, every client in its
+    /// own thread. Returns only if the agent can't start, with
+    /// RunningErr if another agent listens there.
+    pub fn run(&mut self) -> Result<(), SshAgentError> {
+        if self.protect_memory {
+            try!(protect_memory());
+        }
+        let listener = try!(bind(&self.socket_path));
+        let handler = Arc::new(Handler {
+            confirm_all: self.confirm_all,
+            askpass: self.askpass.clone(),
+            path: self.path.clone(),
+            keyfile: self.keyfile.clone(),
+            state: self.state.clone(),
+        });
+        self.state.lock().unwrap().last_request = Instant::now();
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                match self.timeout {
+                    Some(timeout) if state.keys.is_some() => {
+                        let elapsed = state.last_request.elapsed();
+                        if elapsed >= timeout {
+                            state.keys = None;
+                            continue;
+                        }
+                        Some(timeout - elapsed)
+                    }
+                    _ => None,
+                }
+            };
+            if !wait_for(listener.as_raw_fd(), wait) {
+                continue;
+            }
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(_) => continue,
+            };
+            if !peer_uid(&stream).is_some_and(|uid| self.allowed_uids.contains(&uid)) {
+                continue;
+            }
+            let handler = handler.clone();
+            thread::spawn(move || handler.serve(stream));
+        }
+    }
+}
+
+impl Handler {
+    fn serve(&self, mut stream: UnixStream) {
+        loop {
+            let mut len = [0u8; 4];
+            if stream.read_exact(&mut len).is_err() {
+                return;
+            }
+            let len = Reader::new(&len).u32().unwrap() as usize;
+            if len == 0 || len > MAX_MESSAGE {
+                return;
+            }
+            let mut request = vec![0u8; len];
+            if stream.read_exact(&mut request).is_err() {
+                return;
+            }
+            let response = self.answer(&request);
+            // The request may contain the password for unlocking
+            unsafe { write_array_volatile(request.as_mut_ptr(), 0u8, request.len()) };
+            let mut message = vec![];
+            put_string(&mut message, &response);
+            if stream.write_all(&message).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn answer(&self, request: &[u8]) -> Vec<u8> {
+        let mut reader = Reader::new(request);
+        let result = match reader.byte() {
+            Ok(SSH_AGENTC_REQUEST_IDENTITIES) => self.identities(),
+            Ok(SSH_AGENTC_SIGN_REQUEST) => self.sign(&mut reader),
+            Ok(SSH_AGENTC_LOCK) => {
+                self.state.lock().unwrap().keys = None;
+                Ok(vec![SSH_AGENT_SUCCESS])
+            }
+            Ok(SSH_AGENTC_UNLOCK) => self.unlock(&mut reader),
+            _ => Err(()),
+        };
+        result.unwrap_or_else(|_| vec![SSH_AGENT_FAILURE])
+    }
+
+    fn identities(&self) -> Result<Vec<u8>, ()> {
+        let mut state = self.state.lock().unwrap();
+        state.last_request = Instant::now();
+        let mut response = vec![SSH_AGENT_IDENTITIES_ANSWER];
+        // A locked agent has no keys, just like ssh-agent
+        let keys = state.keys.as_ref().map(|keys| &keys[..]).unwrap_or(&[]);
+        put_u32(&mut response, keys.len() as u32);
+        for key in keys {
+            put_string(&mut response, key.public_blob());
+            put_string(&mut response, key.comment.as_bytes());
+        }
+        Ok(response)
+    }
+
+    fn sign(&self, reader: &mut Reader) -> Result<Vec<u8>, ()> {
+        let blob = try!(reader.string());
+        let data = try!(reader.string());
+        let flags = try!(reader.u32());
+        let key = {
+            let mut state = self.state.lock().unwrap();
+            state.last_request = Instant::now();
+            let keys = try!(state.keys.as_ref().ok_or(()));
+            try!(keys.iter().find(|key| key.public_blob() == blob).cloned().ok_or(()))
+        };
+        if (key.confirm || self.confirm_all) && !self.confirm(&key) {
+            return Err(());
+        }
+        let signature = try!(key.sign(data, flags).map_err(|_| ()));
+        let mut response = vec![SSH_AGENT_SIGN_RESPONSE];
+        put_string(&mut response, &signature);
+        Ok(response)
+    }
+
+    fn confirm(&self, key: &SshKey) -> bool {
+        Command::new(&self.askpass)
+            .arg(format!("Allow use of key {}?", key.comment))
+            .env("SSH_ASKPASS_PROMPT", "confirm")
+            .stdin(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+
+    // Sensitive data in this function:
+    //
+    // * the password of the database
+    //
+    // At the end of this function:
+    //
+    // * the password is moved into the database, which is dropped
+    //   after the keys are loaded
+    fn unlock(&self, reader: &mut Reader) -> Result<Vec<u8>, ()> {
+        let password = try!(String::from_utf8(try!(reader.string()).to_vec()).map_err(|_| ()));
+        let password = if password.is_empty() { None } else { Some(password) };
+        let mut db = try!(V1Kpdb::new(self.path.clone(), password, self.keyfile.clone()).map_err(|_| ()));
+        try!(db.load().map_err(|_| ()));
+        let keys = try!(load_keys(&db).map_err(|_| ()));
+        let mut state = self.state.lock().unwrap();
+        state.keys = Some(keys);
+        state.last_request = Instant::now();
+        Ok(vec![SSH_AGENT_SUCCESS])
+    }
+}
+
+// Some(confirm) if the comment of the entry has a marker line
+fn marker(entry: &V1Entry) -> Option<bool> {
+    let comment = match entry.comment {
+        Some(ref comment) => comment,
+        None => return None,
+    };
+    for line in comment.lines() {
+        if let Some(value) = line.trim().strip_prefix(MARKER) {
+            match value.trim() {
+                "yes" => return Some(false),
+                "confirm" => return Some(true),
+                _ => {}
+            }
+        }
+    }
+    None
+}
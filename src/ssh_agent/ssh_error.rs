@@ -0,0 +1,60 @@
+use std::fmt;
+use std::error;
+
+use agent::agent_error::AgentError;
+
+pub use self::SshAgentError::*;
+
+#[doc = "
+Use this for catching various errors that
+can happen when loading SSH keys or running an SshAgent.
+"]
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum SshAgentError {
+    /// Couldn't create the socket, e.g. the directory
+    /// doesn't exist
+    SocketErr,
+    /// Another agent listens on the socket already
+    RunningErr,
+    /// Couldn't lock the memory of the agent, probably
+    /// RLIMIT_MEMLOCK is too low
+    MlockErr,
+    /// The attachment of a marked entry isn't a private key
+    KeyErr,
+    /// The private key is protected by a passphrase
+    EncryptedKeyErr,
+    /// The type of the private key isn't supported
+    UnsupportedKeyErr,
+    /// Wrong password and/or keyfile while unlocking
+    UnlockErr,
+}
+
+impl From<AgentError> for SshAgentError {
+    fn from(e: AgentError) -> SshAgentError {
+        match e {
+            AgentError::RunningErr => RunningErr,
+            AgentError::MlockErr => MlockErr,
+            _ => SocketErr,
+        }
+    }
+}
+
+impl fmt::Display for SshAgentError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for SshAgentError {
+    fn description(&self) -> &str {
+        match *self {
+            SocketErr => "Couldn't create the socket of the agent",
+            RunningErr => "Another agent is running already",
+            MlockErr => "Couldn't lock the memory of the agent",
+            KeyErr => "Attachment isn't a valid SSH private key",
+            EncryptedKeyErr => "SSH private key is protected by a passphrase",
+            UnsupportedKeyErr => "Type of SSH private key isn't supported",
+            UnlockErr => "Couldn't unlock the agent, wrong password or keyfile",
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::Duration;
+
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey};
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+use rustc_serialize::base64::FromBase64;
+
+use kpdb::v1kpdb::V1Kpdb;
+use ssh_agent::key::SshKey;
+use ssh_agent::server::SshAgent;
+use ssh_agent::ssh_error::SshAgentError;
+use ssh_agent::wire::{put_string, put_u32, Reader};
+
+fn read_file(path: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+    bytes
+}
+
+// The key blob of a .pub file
+fn public_blob(path: &str) -> Vec<u8> {
+    let text = String::from_utf8(read_file(path)).unwrap();
+    text.split(' ').nth(1).unwrap().from_base64().unwrap()
+}
+
+#[test]
+fn test_keys() {
+    for &(path, public) in [("test/ssh_ed25519", "test/ssh_ed25519.pub"),
+                            ("test/ssh_rsa", "test/ssh_rsa.pub"),
+                            ("test/ssh_rsa.pem", "test/ssh_rsa.pem.pub"),
+                            ("test/ssh_ecdsa", "test/ssh_ecdsa.pub")]
+                               .iter() {
+        let key = SshKey::from_bytes(&read_file(path), path.to_string()).ok().unwrap();
+        assert_eq!(key.public_blob(), &public_blob(public)[..]);
+        assert!(key.sign(b"data", 0).is_ok());
+    }
+    assert_eq!(SshKey::from_bytes(&read_file("test/ssh_encrypted"), "".to_string()).err(),
+               Some(SshAgentError::EncryptedKeyErr));
+    assert_eq!(SshKey::from_bytes(b"no key", "".to_string()).err(),
+               Some(SshAgentError::KeyErr));
+}
+
+#[test]
+fn test_signatures() {
+    let data = b"session identifier and userauth request";
+
+    let key = SshKey::from_bytes(&read_file("test/ssh_ed25519"), "".to_string()).ok().unwrap();
+    let signature = key.sign(data, 0).ok().unwrap();
+    let mut reader = Reader::new(&signature);
+    assert_eq!(reader.string(), Ok(&b"ssh-ed25519"[..]));
+    let raw = reader.string().unwrap();
+    let blob = key.public_blob();
+    let public = PKey::public_key_from_raw_bytes(&blob[blob.len() - 32..], Id::ED25519).unwrap();
+    let mut verifier = Verifier::new_without_digest(&public).unwrap();
+    assert!(verifier.verify_oneshot(raw, data).unwrap());
+
+    let key = SshKey::from_bytes(&read_file("test/ssh_rsa"), "".to_string()).ok().unwrap();
+    let mut blob = Reader::new(key.public_blob());
+    assert_eq!(blob.string(), Ok(&b"ssh-rsa"[..]));
+    let e = blob.mpint().unwrap();
+    let n = blob.mpint().unwrap();
+    let public = PKey::from_rsa(Rsa::from_public_components(n, e).unwrap()).unwrap();
+    for &(flags, name, digest) in [(2, "rsa-sha2-256", MessageDigest::sha256()),
+                                   (4, "rsa-sha2-512", MessageDigest::sha512())]
+                                      .iter() {
+        let signature = key.sign(data, flags).ok().unwrap();
+        let mut reader = Reader::new(&signature);
+        assert_eq!(reader.string(), Ok(name.as_bytes()));
+        let mut verifier = Verifier::new(digest, &public).unwrap();
+        verifier.update(data).unwrap();
+        assert!(verifier.verify(reader.string().unwrap()).unwrap());
+    }
+}
+
+fn request(stream: &mut UnixStream, message: &[u8]) -> Vec<u8> {
+    let mut framed = vec![];
+    put_string(&mut framed, message);
+    stream.write_all(&framed).unwrap();
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut response = vec![0u8; Reader::new(&len).u32().unwrap() as usize];
+    stream.read_exact(&mut response).unwrap();
+    response
+}
+
+fn identities(stream: &mut UnixStream) -> Vec<String> {
+    let response = request(stream, &[11]);
+    let mut reader = Reader::new(&response);
+    assert_eq!(reader.byte(), Ok(12));
+    let count = reader.u32().unwrap();
+    (0..count)
+        .map(|_| {
+            reader.string().unwrap();
+            String::from_utf8(reader.string().unwrap().to_vec()).unwrap()
+        })
+        .collect()
+}
+
+fn sign(stream: &mut UnixStream, blob: &[u8]) -> u8 {
+    let mut message = vec![13];
+    put_string(&mut message, blob);
+    put_string(&mut message, b"data");
+    put_u32(&mut message, 0);
+    request(stream, &message)[0]
+}
+
+#[test]
+fn test_ssh_agent() {
+    let mut db = V1Kpdb::new("test/test_parsing.kdb".to_string(), Some("test".to_string()), None)
+                     .ok()
+                     .unwrap();
+    assert!(db.load().is_ok());
+    let group = db.groups[0];
+    for &(title, path, comment) in [("ed25519", "test/ssh_ed25519", "ssh-agent: yes"),
+                                    ("ecdsa", "test/ssh_ecdsa", "Work\nssh-agent: confirm"),
+                                    ("unused", "test/ssh_rsa", "ssh-agent: no")]
+                                       .iter() {
+        let entry = db.create_entry(group,
+                                    title.to_string(),
+                                    None,
+                                    None,
+                                    None,
+                                    Some(comment.to_string()),
+                                    None,
+                                    None)
+                      .ok()
+                      .unwrap();
+        db[entry].binary_desc = Some(path.to_string());
+        db[entry].binary = Some(read_file(path));
+    }
+    let path = "test/new_ssh_agent.kdb".to_string();
+    assert!(db.save(Some(path.clone()), Some("test".to_string()), None).is_ok());
+    let mut db = V1Kpdb::new(path, Some("test".to_string()), None).ok().unwrap();
+    assert!(db.load().is_ok());
+
+    let socket = "test/new_ssh_agent.sock";
+    let mut agent = SshAgent::new(db, None, socket.to_string()).ok().unwrap();
+    assert_eq!(agent.key_comments(),
+               vec!["Internet/ed25519".to_string(), "Internet/ecdsa".to_string()]);
+    agent.protect_memory = false;
+    agent.askpass = "false".to_string();
+    // The agent runs until the tests end
+    thread::spawn(move || agent.run());
+
+    let mut stream = None;
+    for _ in 0..50 {
+        if let Ok(connected) = UnixStream::connect(socket) {
+            stream = Some(connected);
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let mut stream = stream.unwrap();
+    assert_eq!(identities(&mut stream).len(), 2);
+    assert_eq!(sign(&mut stream, &public_blob("test/ssh_ed25519.pub")), 14);
+    // askpass refuses
+    assert_eq!(sign(&mut stream, &public_blob("test/ssh_ecdsa.pub")), 5);
+    assert_eq!(sign(&mut stream, &public_blob("test/ssh_rsa.pub")), 5);
+
+    let mut lock = vec![22];
+    put_string(&mut lock, b"anything");
+    assert_eq!(request(&mut stream, &lock), vec![6]);
+    assert!(identities(&mut stream).is_empty());
+    assert_eq!(sign(&mut stream, &public_blob("test/ssh_ed25519.pub")), 5);
+    let mut unlock = vec![23];
+    put_string(&mut unlock, b"wrong");
+    assert_eq!(request(&mut stream, &unlock), vec![5]);
+    let mut unlock = vec![23];
+    put_string(&mut unlock, b"test");
+    assert_eq!(request(&mut stream, &unlock), vec![6]);
+    assert_eq!(identities(&mut stream),
+               vec!["Internet/ed25519".to_string(), "Internet/ecdsa".to_string()]);
+
+    let _ = fs::remove_file(socket);
+}
@@ -0,0 +1,64 @@
+// Encoding of the SSH protocol (RFC 4251, section 5), used for keys,
+// signatures and the messages of the agent protocol
+
+use openssl::bn::{BigNum, BigNumRef};
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data: data }
+    }
+
+    pub fn byte(&mut self) -> Result<u8, ()> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, ()> {
+        let bytes = try!(self.bytes(4));
+        Ok((bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32)
+    }
+
+    pub fn string(&mut self) -> Result<&'a [u8], ()> {
+        let len = try!(self.u32()) as usize;
+        self.bytes(len)
+    }
+
+    pub fn mpint(&mut self) -> Result<BigNum, ()> {
+        let bytes = try!(self.string());
+        // Only positive numbers appear in keys
+        if !bytes.is_empty() && bytes[0] & 0x80 != 0 {
+            return Err(());
+        }
+        BigNum::from_slice(bytes).map_err(|_| ())
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], ()> {
+        if self.data.len() < len {
+            return Err(());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+}
+
+pub fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+pub fn put_string(out: &mut Vec<u8>, value: &[u8]) {
+    put_u32(out, value.len() as u32);
+    out.extend_from_slice(value);
+}
+
+pub fn put_mpint(out: &mut Vec<u8>, value: &BigNumRef) {
+    let mut bytes = value.to_vec();
+    // A set high bit would make the number negative
+    if !bytes.is_empty() && bytes[0] & 0x80 != 0 {
+        bytes.insert(0, 0);
+    }
+    put_string(out, &bytes);
+}